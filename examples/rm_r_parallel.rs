@@ -0,0 +1,63 @@
+// Copyright 2020 Jared Forth.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Times `fsutils::try_rm_r` against `fsutils::try_rm_r_parallel`.
+//!
+//! ```text
+//! cargo run --release --example rm_r_parallel -- [dir] [files] [threads]
+//! ```
+//!
+//! Each run builds a tree of `files` empty files, 100 to a directory, under
+//! `dir`, which defaults to a directory in the system temporary directory,
+//! and removes it. Point `dir` at the disk you care about, as the results
+//! depend on it more than on anything else.
+
+use std::env;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+const RUNS: usize = 3;
+const PER_DIR: usize = 100;
+
+fn build(root: &Path, files: usize) {
+    for dir in 0..files.div_ceil(PER_DIR) {
+        let dir = root.join(format!("{:05}", dir));
+        fsutils::try_mkdir(&dir).unwrap();
+        for file in 0..PER_DIR {
+            fsutils::try_create_file(dir.join(format!("{:03}", file))).unwrap();
+        }
+    }
+}
+
+/// Builds a fresh tree for each run and returns the median time `remove` took.
+fn time(root: &Path, files: usize, remove: impl Fn(&Path) -> fsutils::Result<()>) -> Duration {
+    let mut times: Vec<Duration> = (0..RUNS)
+        .map(|_| {
+            build(root, files);
+            let start = Instant::now();
+            remove(root).unwrap();
+            start.elapsed()
+        })
+        .collect();
+    times.sort();
+    times[RUNS / 2]
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let dir = args.next().map_or_else(|| env::temp_dir().join("fsutils_rm_r_parallel"), PathBuf::from);
+    let files = args.next().map_or(100_000, |n| n.parse().expect("files must be a number"));
+    let threads = args.next().map_or(0, |n| n.parse().expect("threads must be a number"));
+
+    let serial = time(&dir, files, |root| fsutils::try_rm_r(root));
+    let parallel = time(&dir, files, |root| fsutils::try_rm_r_parallel(root, threads));
+    println!("{} files in {}, median of {} runs", files, dir.display(), RUNS);
+    println!("try_rm_r:          {:?}", serial);
+    println!("try_rm_r_parallel: {:?} ({} threads)", parallel, threads);
+    println!("speedup:           {:.2}x", serial.as_secs_f64() / parallel.as_secs_f64());
+}
@@ -0,0 +1,292 @@
+// Copyright 2020 Jared Forth.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! POSIX access control lists on Linux, behind the `acl` feature.
+
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::error::{FsError, IoResultExt, Result};
+use crate::op::Op;
+use crate::sys;
+
+/// The extended attribute the kernel keeps the access ACL of a file in.
+const ACCESS_ACL: &str = "system.posix_acl_access";
+/// The version of the format of `ACCESS_ACL`.
+const ACL_VERSION: u32 = 2;
+/// The id stored for entries that do not name a user or group.
+const NO_ID: u32 = u32::MAX;
+
+/// Who an entry of an access control list applies to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum AclTag {
+    /// The owner of the file, the `u::` entry.
+    UserObj,
+    /// The user with this id, a `u:<id>:` entry.
+    User(u32),
+    /// The group of the file, the `g::` entry.
+    GroupObj,
+    /// The group with this id, a `g:<id>:` entry.
+    Group(u32),
+    /// The most any `User`, `Group` or `GroupObj` entry may grant, the `m::` entry.
+    Mask,
+    /// Everyone else, the `o::` entry.
+    Other,
+}
+
+impl AclTag {
+    /// The tag and id as the kernel stores them.
+    fn raw(self) -> (u16, u32) {
+        match self {
+            AclTag::UserObj => (0x01, NO_ID),
+            AclTag::User(uid) => (0x02, uid),
+            AclTag::GroupObj => (0x04, NO_ID),
+            AclTag::Group(gid) => (0x08, gid),
+            AclTag::Mask => (0x10, NO_ID),
+            AclTag::Other => (0x20, NO_ID),
+        }
+    }
+
+    fn from_raw(tag: u16, id: u32) -> Option<AclTag> {
+        Some(match tag {
+            0x01 => AclTag::UserObj,
+            0x02 => AclTag::User(id),
+            0x04 => AclTag::GroupObj,
+            0x08 => AclTag::Group(id),
+            0x10 => AclTag::Mask,
+            0x20 => AclTag::Other,
+            _ => return None,
+        })
+    }
+}
+
+/// One entry of an access control list, as returned by `fsutils::get_acl`.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::{AclEntry, AclTag};
+///
+/// // Read and execute for the user with id 1000, like `setfacl -m u:1000:r-x`
+/// let entry = AclEntry::new(AclTag::User(1000), 0o5);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct AclEntry {
+    /// Who the entry applies to.
+    pub tag: AclTag,
+    /// The permissions granted, as one octal digit of a mode: 4 for read,
+    /// 2 for write and 1 for execute.
+    pub perms: u32,
+}
+
+impl AclEntry {
+    /// Creates an entry granting `perms` to `tag`.
+    pub fn new(tag: AclTag, perms: u32) -> AclEntry {
+        AclEntry { tag, perms }
+    }
+}
+
+/// Returns the access control list of a file or directory, or `None` if it
+/// cannot be read.
+///
+/// Requires the `acl` feature, on Linux.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::AclTag;
+///
+/// fsutils::write_file("get_acl.txt", "text");
+///
+/// let acl = fsutils::get_acl("get_acl.txt").unwrap();
+/// assert!(acl.iter().any(|entry| entry.tag == AclTag::UserObj));
+///
+/// # // Cleanup
+/// # fsutils::rm("get_acl.txt");
+/// ```
+pub fn get_acl<P: AsRef<Path>>(path: P) -> Option<Vec<AclEntry>> {
+    match try_get_acl(path) {
+        Ok(acl) => Some(acl),
+        Err(e) => {
+            error!("Cannot read access control list {}", e);
+            None
+        }
+    }
+}
+
+/// Returns the access control list of a file or directory, like `getfacl`.
+///
+/// A file without extended entries has the three entries its mode bits
+/// describe, `UserObj`, `GroupObj` and `Other`, and those are returned.
+/// Entries come in the order the kernel keeps them: by tag, then by id.
+/// Symbolic links are followed. Default ACLs of directories are not covered.
+///
+/// Requires the `acl` feature, on Linux.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::{AclEntry, AclTag};
+///
+/// fsutils::write_file("try_get_acl.txt", "text");
+/// fsutils::try_chmod("try_get_acl.txt", 0o640).unwrap();
+///
+/// let acl = fsutils::try_get_acl("try_get_acl.txt").unwrap();
+/// assert_eq!(acl, [
+///     AclEntry::new(AclTag::UserObj, 0o6),
+///     AclEntry::new(AclTag::GroupObj, 0o4),
+///     AclEntry::new(AclTag::Other, 0o0),
+/// ]);
+/// assert!(fsutils::try_get_acl("a_very_1234_unlikely_9876_filename").is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm("try_get_acl.txt");
+/// ```
+pub fn try_get_acl<P: AsRef<Path>>(path: P) -> Result<Vec<AclEntry>> {
+    let path = path.as_ref();
+    match sys::getxattr(path, OsStr::new(ACCESS_ACL)).at(path)? {
+        Some(data) => decode(&data).ok_or_else(|| {
+            FsError::from_io(path, io::Error::new(io::ErrorKind::InvalidData, "malformed access control list"))
+        }),
+        None => {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(path).at(path)?.permissions().mode();
+            Ok(vec![
+                AclEntry::new(AclTag::UserObj, (mode >> 6) & 0o7),
+                AclEntry::new(AclTag::GroupObj, (mode >> 3) & 0o7),
+                AclEntry::new(AclTag::Other, mode & 0o7),
+            ])
+        }
+    }
+}
+
+/// Replaces the access control list of a file or directory
+/// and returns a boolean based on success or failure.
+///
+/// Requires the `acl` feature, on Linux.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::{AclEntry, AclTag};
+///
+/// fsutils::write_file("set_acl.txt", "text");
+///
+/// let acl = [
+///     AclEntry::new(AclTag::UserObj, 0o6),
+///     AclEntry::new(AclTag::GroupObj, 0o0),
+///     AclEntry::new(AclTag::Other, 0o0),
+/// ];
+/// assert!(fsutils::set_acl("set_acl.txt", &acl));
+///
+/// # // Cleanup
+/// # fsutils::rm("set_acl.txt");
+/// ```
+pub fn set_acl<P: AsRef<Path>>(path: P, entries: &[AclEntry]) -> bool {
+    match try_set_acl(path, entries) {
+        Ok(_) => true,
+        Err(e) => {
+            error!("Cannot set access control list {}", e);
+            false
+        }
+    }
+}
+
+/// Replaces the access control list of a file or directory, like
+/// `setfacl --set`.
+///
+/// `entries` may be in any order. It must have exactly one `UserObj`,
+/// `GroupObj` and `Other` entry, at most one entry for each user and group,
+/// and a `Mask` entry if there are any `User` or `Group` entries; otherwise
+/// this fails with an error of kind `io::ErrorKind::InvalidInput` before
+/// anything is changed. The file's mode bits change along with the list, as
+/// they always mirror it. The filesystem must support ACLs, which most Linux
+/// filesystems do.
+///
+/// Requires the `acl` feature, on Linux.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::{AclEntry, AclTag};
+///
+/// fsutils::write_file("try_set_acl.txt", "text");
+/// let uid = fsutils::stat("try_set_acl.txt").unwrap().uid.unwrap();
+///
+/// let acl = [
+///     AclEntry::new(AclTag::Other, 0o0),
+///     AclEntry::new(AclTag::User(uid + 1), 0o4),
+///     AclEntry::new(AclTag::UserObj, 0o6),
+///     AclEntry::new(AclTag::GroupObj, 0o4),
+///     AclEntry::new(AclTag::Mask, 0o4),
+/// ];
+/// if fsutils::try_set_acl("try_set_acl.txt", &acl).is_ok() {
+///     let read = fsutils::try_get_acl("try_set_acl.txt").unwrap();
+///     assert_eq!(read.len(), 5);
+///     assert_eq!(read[1], AclEntry::new(AclTag::User(uid + 1), 0o4));
+/// }
+/// assert!(fsutils::try_set_acl("try_set_acl.txt", &acl[..2]).is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm("try_set_acl.txt");
+/// ```
+pub fn try_set_acl<P: AsRef<Path>>(path: P, entries: &[AclEntry]) -> Result<()> {
+    let path = path.as_ref();
+    let data = encode(entries).map_err(|reason| FsError::from_io(path, io::Error::new(io::ErrorKind::InvalidInput, reason)))?;
+    Op::new("set_acl", path).run((), || {
+        sys::setxattr(path, OsStr::new(ACCESS_ACL), &data).at(path)?;
+        info!("Set access control list of {}", path.display());
+        Ok(())
+    })
+}
+
+fn decode(data: &[u8]) -> Option<Vec<AclEntry>> {
+    if data.len() < 4 || !(data.len() - 4).is_multiple_of(8) || data[..4] != ACL_VERSION.to_le_bytes() {
+        return None;
+    }
+    data[4..]
+        .chunks(8)
+        .map(|raw| {
+            let tag = u16::from_le_bytes([raw[0], raw[1]]);
+            let perms = u16::from_le_bytes([raw[2], raw[3]]);
+            let id = u32::from_le_bytes([raw[4], raw[5], raw[6], raw[7]]);
+            AclTag::from_raw(tag, id).map(|tag| AclEntry::new(tag, u32::from(perms)))
+        })
+        .collect()
+}
+
+/// Checks that `entries` form a valid list and returns them in the kernel's format.
+fn encode(entries: &[AclEntry]) -> std::result::Result<Vec<u8>, String> {
+    let mut raw: Vec<_> = entries.iter().map(|entry| (entry.tag.raw(), entry.perms)).collect();
+    raw.sort_by_key(|&(tag, _)| tag);
+    if let Some(pair) = raw.windows(2).find(|pair| pair[0].0 == pair[1].0) {
+        return Err(format!("duplicate entry for {:?}", AclTag::from_raw(pair[0].0 .0, pair[0].0 .1)));
+    }
+    let has = |tag: u16| raw.iter().any(|&((t, _), _)| t == tag);
+    for (tag, name) in [(0x01, "UserObj"), (0x04, "GroupObj"), (0x20, "Other")] {
+        if !has(tag) {
+            return Err(format!("missing {} entry", name));
+        }
+    }
+    if (has(0x02) || has(0x08)) && !has(0x10) {
+        return Err("a Mask entry is needed with User or Group entries".to_string());
+    }
+    if let Some(&(_, perms)) = raw.iter().find(|&&(_, perms)| perms > 0o7) {
+        return Err(format!("permissions {:o} are more than rwx", perms));
+    }
+    let mut data = ACL_VERSION.to_le_bytes().to_vec();
+    for ((tag, id), perms) in raw {
+        data.extend_from_slice(&tag.to_le_bytes());
+        data.extend_from_slice(&(perms as u16).to_le_bytes());
+        data.extend_from_slice(&id.to_le_bytes());
+    }
+    Ok(data)
+}
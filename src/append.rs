@@ -0,0 +1,176 @@
+// Copyright 2020 Jared Forth.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Appends that keep a file below a maximum size.
+
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::atomic::replace_file;
+use crate::error::{IoResultExt, Result};
+use crate::op::Op;
+
+/// What `fsutils::append_capped` does once a file would grow past its cap.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::CapPolicy;
+///
+/// assert_eq!(CapPolicy::default(), CapPolicy::DropOldest);
+/// let rotate = CapPolicy::Rotate { keep: 3 };
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum CapPolicy {
+    /// Drops whole lines from the start of the file until the new data fits,
+    /// so the file always holds the most recent output.
+    #[default]
+    DropOldest,
+    /// Renames the full file to `name.1`, shifting older ones to `name.2` and
+    /// so on up to `name.<keep>`, and starts a new file. With `keep: 0` the
+    /// old contents are simply discarded.
+    Rotate { keep: usize },
+}
+
+/// Appends data to a file, keeping it within `max_len` bytes according to `policy`,
+/// and returns a boolean based on success or failure.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::CapPolicy;
+///
+/// for i in 0..100 {
+///     fsutils::append_capped("append_capped.log", &format!("event {:02}\n", i), 36, CapPolicy::DropOldest);
+/// }
+/// assert_eq!(fsutils::read_file("append_capped.log"), "event 96\nevent 97\nevent 98\nevent 99\n");
+///
+/// # // Cleanup
+/// # fsutils::rm("append_capped.log");
+/// ```
+pub fn append_capped<P: AsRef<Path>>(path: P, contents: &str, max_len: u64, policy: CapPolicy) -> bool {
+    match try_append_capped(path, contents, max_len, policy) {
+        Ok(_) => true,
+        Err(e) => {
+            error!("Cannot write file {}", e);
+            false
+        }
+    }
+}
+
+/// Appends data to a file, creating it if it does not exist, and keeps it
+/// within `max_len` bytes according to `policy`.
+///
+/// This bounds the disk space used by debug logs and similar output from
+/// long-running processes. While the file has room this is a plain append.
+/// Once it does not, `CapPolicy::DropOldest` rewrites the file through a
+/// temporary file with its oldest lines removed, and `CapPolicy::Rotate`
+/// moves it aside as `name.1` first. Data that is larger than `max_len` on
+/// its own is cut down to its last `max_len` bytes when dropping, and
+/// written whole when rotating.
+///
+/// Making room discards data, so the confirm hook set with `fsutils::set_confirm`
+/// is asked first, and `FsError::Declined` is returned if it refuses.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::{CapPolicy, FsError};
+///
+/// let policy = CapPolicy::Rotate { keep: 2 };
+/// fsutils::try_append_capped("try_capped.log", "first run\n", 16, policy).unwrap();
+/// fsutils::try_append_capped("try_capped.log", "second run\n", 16, policy).unwrap();
+/// fsutils::try_append_capped("try_capped.log", "third run\n", 16, policy).unwrap();
+///
+/// assert_eq!(fsutils::read_file("try_capped.log"), "third run\n");
+/// assert_eq!(fsutils::read_file("try_capped.log.1"), "second run\n");
+/// assert_eq!(fsutils::read_file("try_capped.log.2"), "first run\n");
+///
+/// fsutils::set_confirm(|_, _| false);
+/// let result = fsutils::try_append_capped("try_capped.log", "fourth run\n", 16, policy);
+/// assert!(matches!(result, Err(FsError::Declined { .. })));
+/// assert_eq!(fsutils::read_file("try_capped.log"), "third run\n");
+/// fsutils::clear_confirm();
+///
+/// # // Cleanup
+/// # fsutils::rm("try_capped.log");
+/// # fsutils::rm("try_capped.log.1");
+/// # fsutils::rm("try_capped.log.2");
+/// ```
+pub fn try_append_capped<P: AsRef<Path>>(path: P, contents: &str, max_len: u64, policy: CapPolicy) -> Result<()> {
+    let path = path.as_ref();
+    let len = match path.metadata() {
+        Ok(meta) => meta.len(),
+        Err(_) => 0,
+    };
+    let full = len + contents.len() as u64 > max_len;
+    Op::new("append_capped", path).bytes(contents.len() as u64).destructive(full).run((), || {
+        if !full {
+            OpenOptions::new().create(true).append(true).open(path).at(path)?.write_all(contents.as_bytes()).at(path)?;
+            info!("Appended to file {}", path.display());
+            return Ok(());
+        }
+        match policy {
+            CapPolicy::DropOldest => drop_oldest(path, len, contents.as_bytes(), max_len)?,
+            CapPolicy::Rotate { keep } => {
+                if len > 0 {
+                    rotate(path, keep)?;
+                }
+                File::create(path).at(path)?.write_all(contents.as_bytes()).at(path)?;
+            }
+        }
+        info!("Appended to file {} after making room", path.display());
+        Ok(())
+    })
+}
+
+/// Rewrites the file at `path`, `len` bytes long, as its newest lines
+/// followed by `contents`, in at most `max_len` bytes.
+fn drop_oldest(path: &Path, len: u64, contents: &[u8], max_len: u64) -> Result<()> {
+    let excess = len + contents.len() as u64 - max_len;
+    let mut kept = Vec::new();
+    if excess < len {
+        // Read from one byte early, so that a cut landing exactly on the start
+        // of a line is not mistaken for one partway through it
+        let mut file = File::open(path).at(path)?;
+        file.seek(SeekFrom::Start(excess - 1)).at(path)?;
+        file.read_to_end(&mut kept).at(path)?;
+        let cut = kept.iter().position(|&b| b == b'\n').unwrap_or(0);
+        kept.drain(..=cut);
+        kept.extend_from_slice(contents);
+    } else {
+        kept.extend_from_slice(&contents[(excess - len) as usize..]);
+    }
+    replace_file(path, |temp| temp.write_all(&kept).at(path))
+}
+
+/// Shifts `path.1` to `path.2` and so on, dropping `path.<keep>`, then moves
+/// `path` to `path.1`.
+fn rotate(path: &Path, keep: usize) -> Result<()> {
+    if keep == 0 {
+        return fs::remove_file(path).at(path);
+    }
+    for n in (1..keep).rev() {
+        let from = numbered(path, n);
+        if from.exists() {
+            let to = numbered(path, n + 1);
+            fs::rename(&from, &to).at2(&from, &to)?;
+        }
+    }
+    let to = numbered(path, 1);
+    fs::rename(path, &to).at2(path, &to)
+}
+
+fn numbered(path: &Path, n: usize) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
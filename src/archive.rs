@@ -0,0 +1,263 @@
+// Copyright 2020 Jared Forth.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! What creating and extracting zip and tar archives have in common.
+
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::error::{FsError, IoResultExt, Result};
+use crate::glob::Pattern;
+use crate::link::symlink;
+use crate::meta::{file_id, open_for_times, FileKind};
+use crate::walk::{walk_with, WalkOptions};
+
+pub(crate) const MODE_TYPE: u32 = 0o170000;
+#[cfg(not(unix))]
+pub(crate) const MODE_FILE: u32 = 0o100000;
+#[cfg(any(feature = "zip", not(unix)))]
+pub(crate) const MODE_DIR: u32 = 0o040000;
+#[cfg(any(feature = "zip", not(unix)))]
+pub(crate) const MODE_SYMLINK: u32 = 0o120000;
+
+/// Something to put in an archive, found before it is written.
+pub(crate) struct Source {
+    pub(crate) path: PathBuf,
+    /// The name in the archive, with `/` separators and a trailing `/` for
+    /// directories.
+    pub(crate) name: String,
+    pub(crate) kind: FileKind,
+}
+
+/// Finds everything to archive from `source`, leaving out what matches
+/// `exclude` and the file with the id `archive`, which is the archive itself.
+///
+/// A source is named after its last component, and one without a name of its
+/// own, such as `.`, contributes only what is below it. Symbolic links below
+/// a source are kept as links, while a source that is a link is followed.
+pub(crate) fn collect(
+    source: &Path,
+    exclude: &[Pattern],
+    archive: Option<(u64, u64)>,
+    found: &mut Vec<Source>,
+) -> Result<()> {
+    let prefix = source.file_name().map(|name| entry_name(source, Path::new(name))).transpose()?;
+    let mut walk = walk_with(source, &WalkOptions::new());
+    while let Some(entry) = walk.next() {
+        let entry = entry?;
+        let relative = match entry.path.strip_prefix(source) {
+            Ok(relative) if entry.depth > 0 => relative.to_path_buf(),
+            _ => PathBuf::from(prefix.clone().unwrap_or_default()),
+        };
+        if entry.depth > 0 && exclude.iter().any(|pattern| pattern.matches_path(&relative)) {
+            if entry.kind == FileKind::Dir && !entry.is_symlink {
+                walk.skip_dir();
+            }
+            continue;
+        }
+        if entry.kind == FileKind::File && archive.is_some() && file_id(&entry.path).ok() == archive {
+            continue;
+        }
+        let mut name = match (&prefix, entry.depth) {
+            (None, 0) => continue,
+            (Some(prefix), 0) => prefix.clone(),
+            (Some(prefix), _) => format!("{}/{}", prefix, entry_name(&entry.path, &relative)?),
+            (None, _) => entry_name(&entry.path, &relative)?,
+        };
+        let kind = if entry.is_symlink && entry.depth > 0 { FileKind::Symlink } else { entry.kind };
+        match kind {
+            FileKind::Dir => name.push('/'),
+            FileKind::Other => {
+                warn!("Skipping {}, which is not a file, directory or link", entry.path.display());
+                continue;
+            }
+            _ => {}
+        }
+        found.push(Source { path: entry.path, name, kind });
+    }
+    Ok(())
+}
+
+/// `name` with `/` separators, as it is stored in an archive for the file at
+/// `path`.
+///
+/// Archive names are kept to UTF-8, which is what zip and pax mark them as
+/// and other tools expect, so a name that is not valid UTF-8 fails with
+/// `FsError::InvalidPath` rather than being stored with replacement
+/// characters.
+pub(crate) fn entry_name(path: &Path, name: &Path) -> Result<String> {
+    let parts: Option<Vec<&str>> = name.components().map(|c| c.as_os_str().to_str()).collect();
+    parts.map(|parts| parts.join("/")).ok_or_else(|| FsError::invalid_path(path, "name is not valid UTF-8"))
+}
+
+/// The text of an entry name or link target read from an archive, which must
+/// be valid UTF-8.
+pub(crate) fn read_name(bytes: &[u8]) -> std::io::Result<String> {
+    String::from_utf8(bytes.to_vec()).map_err(|_| {
+        let name = String::from_utf8_lossy(bytes);
+        std::io::Error::new(std::io::ErrorKind::InvalidData, format!("entry name {:?} is not valid UTF-8", name))
+    })
+}
+
+/// The Unix mode recorded for an entry, made up from the read-only flag on
+/// other platforms.
+pub(crate) fn mode_of(meta: &fs::Metadata, kind: FileKind) -> u32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let _ = kind;
+        meta.mode()
+    }
+    #[cfg(not(unix))]
+    {
+        let write = if meta.permissions().readonly() { 0 } else { 0o200 };
+        match kind {
+            FileKind::Dir => MODE_DIR | 0o555 | write,
+            FileKind::Symlink => MODE_SYMLINK | 0o777,
+            _ => MODE_FILE | 0o444 | write,
+        }
+    }
+}
+
+/// The path of an entry below the destination, or `None` for a name that
+/// would leave it, such as `../x` or `/etc/x`.
+pub(crate) fn safe_relative(name: &str) -> Option<PathBuf> {
+    let mut relative = PathBuf::new();
+    if name.starts_with(['/', '\\']) {
+        return None;
+    }
+    for part in name.split(['/', '\\']) {
+        match part {
+            "" | "." => {}
+            ".." => return None,
+            _ if cfg!(windows) && part.contains(':') => return None,
+            _ => relative.push(part),
+        }
+    }
+    Some(relative)
+}
+
+/// The error for an entry named `name` that would be extracted outside
+/// `dst`.
+pub(crate) fn leaves_destination(dst: &Path, name: &str) -> FsError {
+    FsError::invalid_path(dst.join(name.trim_start_matches(['/', '\\'])), "archive entry leaves the destination")
+}
+
+/// Checks that every link to be extracted, given as its path below `dst` and
+/// its target, resolves to somewhere below `dst`.
+///
+/// Judging each link by its own path would let one lead through another, as
+/// `c -> a/b/..` does through `a/b -> ..`, so a link whose path or target
+/// passes through another link in the archive is refused as well.
+pub(crate) fn check_links(dst: &Path, links: &[(PathBuf, PathBuf)]) -> Result<()> {
+    let paths: HashSet<&Path> = links.iter().map(|(relative, _)| relative.as_path()).collect();
+    for (relative, target) in links {
+        if !link_stays_inside(relative, target, &paths) {
+            return Err(FsError::invalid_path(dst.join(relative), "archive link points outside the destination"));
+        }
+    }
+    Ok(())
+}
+
+/// Whether a link at `relative` to `target` resolves to somewhere below the
+/// destination without passing through any of `links`.
+fn link_stays_inside(relative: &Path, target: &Path, links: &HashSet<&Path>) -> bool {
+    let mut resolved = PathBuf::new();
+    let parent = relative.parent().unwrap_or_else(|| Path::new(""));
+    for component in parent.components().chain(target.components()) {
+        match component {
+            Component::Normal(name) => {
+                resolved.push(name);
+                if links.contains(resolved.as_path()) {
+                    return false;
+                }
+            }
+            Component::CurDir => {}
+            Component::ParentDir if resolved.pop() => {}
+            _ => return false,
+        }
+    }
+    true
+}
+
+/// Creates or overwrites an extracted file, replacing a link in its place
+/// rather than writing through it.
+pub(crate) fn create_file(path: &Path, contents: &[u8], modified: SystemTime) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).at(parent)?;
+    }
+    if path.symlink_metadata().is_ok_and(|meta| meta.file_type().is_symlink()) {
+        fs::remove_file(path).at(path)?;
+    }
+    let mut file = File::create(path).at(path)?;
+    file.write_all(contents).at(path)?;
+    file.set_times(fs::FileTimes::new().set_modified(modified)).at(path)
+}
+
+/// Asks the confirm hook about each of `paths` that already exists, before
+/// anything is extracted, and returns those it declined, which are left as
+/// they are.
+pub(crate) fn declined<'a, I>(name: &str, paths: I) -> HashSet<PathBuf>
+where
+    I: IntoIterator<Item = &'a PathBuf>,
+{
+    paths
+        .into_iter()
+        .filter(|path| path.symlink_metadata().is_ok() && !crate::op::confirm_each(name, path))
+        .cloned()
+        .collect()
+}
+
+/// Creates an extracted symbolic link, replacing whatever file is in its
+/// place.
+pub(crate) fn create_symlink(path: &Path, target: &Path) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).at(parent)?;
+    }
+    if path.symlink_metadata().is_ok() {
+        fs::remove_file(path).at(path)?;
+    }
+    let is_dir = path.parent().is_some_and(|parent| parent.join(target).is_dir());
+    symlink(target, path, is_dir).at(path)
+}
+
+/// Restores the times and permission bits of extracted directories, once
+/// everything inside them has been written.
+pub(crate) fn finish_dirs(mut dirs: Vec<(PathBuf, SystemTime, Option<u32>)>) -> Result<()> {
+    // Deepest first, so a read-only directory is not closed before what is
+    // below it
+    dirs.sort_by(|a, b| b.0.cmp(&a.0));
+    for (path, modified, mode) in dirs {
+        if let Ok(file) = open_for_times(&path) {
+            let _ = file.set_times(fs::FileTimes::new().set_modified(modified));
+        }
+        if let Some(mode) = mode {
+            set_mode(&path, mode)?;
+        }
+    }
+    Ok(())
+}
+
+/// Restores permission bits, without setuid, setgid and sticky bits, or only
+/// whether the entry is writable on platforms without them.
+pub(crate) fn set_mode(path: &Path, mode: u32) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(mode & 0o777)).at(path)
+    }
+    #[cfg(not(unix))]
+    {
+        let mut permissions = fs::metadata(path).at(path)?.permissions();
+        permissions.set_readonly(mode & 0o200 == 0);
+        fs::set_permissions(path, permissions).at(path)
+    }
+}
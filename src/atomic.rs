@@ -0,0 +1,199 @@
+// Copyright 2020 Jared Forth.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Writes that replace a file in a single step.
+
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::error::{FsError, IoResultExt, Result};
+use crate::op::Op;
+use crate::temp::TempBuilder;
+
+/// Writes data to a file through a temporary file that is renamed over it
+/// and returns a boolean based on success or failure.
+///
+/// ## Usage:
+///
+/// ```
+/// assert!(fsutils::write_file_atomic("atomic.json", "{}"));
+///
+/// assert_eq!(fsutils::read_file("atomic.json"), "{}");
+///
+/// # // Cleanup
+/// # fsutils::rm("atomic.json");
+/// ```
+pub fn write_file_atomic<P: AsRef<Path>>(path: P, contents: &str) -> bool {
+    let path = path.as_ref();
+    match try_write_file_atomic(path, contents) {
+        Ok(_) => true,
+        Err(e) => {
+            error!("Cannot write file to location '{}' {}", path.display(), e);
+            false
+        }
+    }
+}
+
+/// Writes data to a file through a temporary file that is renamed over it.
+///
+/// The data is written and flushed to disk under a temporary name in the same
+/// directory, which is then renamed to `path`. Because a rename within a
+/// directory is atomic, anything reading `path` sees either the old contents
+/// or the new ones, never a partly written file, even if the process or the
+/// machine crashes halfway. An existing file keeps its permissions. If `path`
+/// is a symlink, the file it points to is replaced and the link is left alone.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("try_atomic.conf", "port = 80");
+///
+/// assert!(fsutils::try_write_file_atomic("try_atomic.conf", "port = 8080").is_ok());
+/// assert_eq!(fsutils::read_file("try_atomic.conf"), "port = 8080");
+/// assert!(fsutils::try_write_file_atomic("a_very_1234_unlikely_9876_dirname/file", "").is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm("try_atomic.conf");
+/// ```
+pub fn try_write_file_atomic<P: AsRef<Path>>(path: P, contents: &str) -> Result<()> {
+    let path = path.as_ref();
+    Op::new("write_file_atomic", path).bytes(contents.len() as u64).destructive(path.exists()).run((), || {
+        replace_file(path, |file| file.write_all(contents.as_bytes()).at(path))?;
+        info!("Atomically wrote file to {}", path.display());
+        Ok(())
+    })
+}
+
+/// Writes data to a file so that it survives a crash or power loss
+/// and returns a boolean based on success or failure.
+///
+/// ## Usage:
+///
+/// ```
+/// assert!(fsutils::write_file_durable("durable.wal", "commit 1"));
+///
+/// assert_eq!(fsutils::read_file("durable.wal"), "commit 1");
+///
+/// # // Cleanup
+/// # fsutils::rm("durable.wal");
+/// ```
+pub fn write_file_durable<P: AsRef<Path>>(path: P, contents: &str) -> bool {
+    let path = path.as_ref();
+    match try_write_file_durable(path, contents) {
+        Ok(_) => true,
+        Err(e) => {
+            error!("Cannot write file to location '{}' {}", path.display(), e);
+            false
+        }
+    }
+}
+
+/// Writes data to a file so that it survives a crash or power loss.
+///
+/// This works like `fsutils::try_write_file_atomic`, and once it returns,
+/// the directory holding the file has been flushed to disk as well. Without
+/// that last step the new contents can be safely on disk while the rename
+/// that put them in place is not, and the old file comes back after a power
+/// cut. Nothing is returned until both are stored, which is what write-ahead
+/// logs, queues and anything else that promises not to lose acknowledged
+/// data need. It is also a lot slower than a plain write.
+///
+/// On Windows, where directories cannot be flushed, the rename is left to
+/// NTFS's own metadata journal.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::mkdir("try_durable_queue");
+///
+/// assert!(fsutils::try_write_file_durable("try_durable_queue/0001.msg", "payload").is_ok());
+/// assert_eq!(fsutils::read_file("try_durable_queue/0001.msg"), "payload");
+///
+/// # // Cleanup
+/// # fsutils::rm_r("try_durable_queue");
+/// ```
+pub fn try_write_file_durable<P: AsRef<Path>>(path: P, contents: &str) -> Result<()> {
+    let path = path.as_ref();
+    Op::new("write_file_durable", path).bytes(contents.len() as u64).destructive(path.exists()).run((), || {
+        replace_file(path, |file| file.write_all(contents.as_bytes()).at(path))?;
+        sync_parent(&resolve_link(path)?)?;
+        info!("Durably wrote file to {}", path.display());
+        Ok(())
+    })
+}
+
+/// Replaces `path` with whatever `write` puts into a fresh temporary file,
+/// renaming it into place once it is safely on disk.
+///
+/// `write` attaches its own path context, since it may also read from other
+/// files, such as the one being replaced.
+pub(crate) fn replace_file<F>(path: &Path, write: F) -> Result<()>
+where
+    F: FnOnce(&mut File) -> Result<()>,
+{
+    replace_file_if(path, |file| write(file).map(|_| true)).map(|_| ())
+}
+
+/// Like `replace_file`, but leaves `path` alone if `write` returns `false`,
+/// and returns whether it was replaced.
+pub(crate) fn replace_file_if<F>(path: &Path, write: F) -> Result<bool>
+where
+    F: FnOnce(&mut File) -> Result<bool>,
+{
+    let path = resolve_link(path)?;
+    let parent = parent_dir(&path);
+    let mut prefix = std::ffi::OsString::from(".");
+    prefix.push(path.file_name().unwrap_or_default());
+    prefix.push(".");
+
+    let mut temp = TempBuilder::new().prefix(prefix).suffix(".tmp").parent(parent).tempfile()?;
+    let temp_path = temp.path().to_path_buf();
+    if !write(temp.as_file())? {
+        return Ok(false);
+    }
+    temp.as_file().sync_all().at(&temp_path)?;
+    if let Ok(meta) = path.metadata() {
+        fs::set_permissions(&temp_path, meta.permissions()).at(&temp_path)?;
+    }
+    // Closes the handle, which Windows needs before the rename
+    let temp_path = temp.keep();
+    fs::rename(&temp_path, &path).map_err(|e| {
+        let _ = fs::remove_file(&temp_path);
+        FsError::from_io(&path, e)
+    })?;
+    Ok(true)
+}
+
+/// Follows `path` to the file it refers to if it is a symlink.
+fn resolve_link(path: &Path) -> Result<PathBuf> {
+    match path.symlink_metadata() {
+        Ok(meta) if meta.file_type().is_symlink() => fs::canonicalize(path).at(path),
+        _ => Ok(path.to_path_buf()),
+    }
+}
+
+/// Flushes the directory entry for `path` to disk.
+#[cfg(unix)]
+fn sync_parent(path: &Path) -> Result<()> {
+    let parent = parent_dir(path);
+    File::open(parent).at(parent)?.sync_all().at(parent)
+}
+
+#[cfg(not(unix))]
+fn sync_parent(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+/// Returns the directory holding `path`, which is `.` for a bare file name.
+fn parent_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    }
+}
@@ -0,0 +1,209 @@
+// Copyright 2020 Jared Forth.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Reading files that may be compressed, whatever their names.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+#[cfg(any(feature = "zstd", feature = "lz4"))]
+use crate::compress;
+use crate::deflate;
+use crate::error::{FsError, IoResultExt, Result};
+#[cfg(feature = "xz")]
+use crate::xz;
+
+/// A compressed format, told apart by the magic number it starts with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Format {
+    Gzip,
+    Zstd,
+    Lz4,
+    Xz,
+}
+
+impl Format {
+    fn of(data: &[u8]) -> Option<Format> {
+        if deflate::is_gzip(data) {
+            Some(Format::Gzip)
+        } else if data.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Format::Zstd)
+        } else if data.starts_with(&[0x04, 0x22, 0x4d, 0x18]) || data.starts_with(&[0x02, 0x21, 0x4c, 0x18]) {
+            Some(Format::Lz4)
+        } else if data.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0]) {
+            Some(Format::Xz)
+        } else {
+            None
+        }
+    }
+
+    /// The feature that decompresses the format.
+    fn feature(self) -> &'static str {
+        match self {
+            Format::Gzip => "gzip",
+            Format::Zstd => "zstd",
+            Format::Lz4 => "lz4",
+            Format::Xz => "xz",
+        }
+    }
+}
+
+/// Decompresses `data` if it starts like a compressed format, and returns
+/// it as it is otherwise.
+fn decompress(data: Vec<u8>) -> io::Result<Vec<u8>> {
+    let format = match Format::of(&data) {
+        Some(format) => format,
+        None => return Ok(data),
+    };
+    match format {
+        Format::Gzip => deflate::gunzip(&data),
+        #[cfg(feature = "zstd")]
+        Format::Zstd => decompress_frames(&data),
+        #[cfg(feature = "lz4")]
+        Format::Lz4 => decompress_frames(&data),
+        #[cfg(feature = "xz")]
+        Format::Xz => xz::decompress(&data),
+        #[allow(unreachable_patterns)]
+        format => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("reading {} files requires the `{}` feature", format.feature(), format.feature()),
+        )),
+    }
+}
+
+#[cfg(any(feature = "zstd", feature = "lz4"))]
+fn decompress_frames(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    compress::decompress(data, &mut out)?;
+    Ok(out)
+}
+
+/// Reads data from a file, decompressing it if it is compressed,
+/// and returns a `String` with the file's contents
+///
+/// An empty `String` is returned if the file cannot be read, cannot be
+/// decompressed or is not valid UTF-8. Use `fsutils::try_read_file_auto` to
+/// find out why.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::create_file_bytes(
+///     "read_file_auto.log.gz",
+///     b"\x1f\x8b\x08\x00\x00\x00\x00\x00\x02\x03\x73\x77\x0d\x51\xd0\xcf\xcc\x4b\x49\xad\
+///       \xd0\xcb\x28\xc9\xcd\x51\x30\x32\x30\xe0\x02\x00\xf8\xf1\x32\x42\x14\x00\x00\x00",
+/// );
+/// fsutils::write_file("read_file_auto.log", "GET /index.html 200\n");
+///
+/// assert_eq!(fsutils::read_file_auto("read_file_auto.log.gz"), "GET /index.html 200\n");
+/// assert_eq!(fsutils::read_file_auto("read_file_auto.log"), "GET /index.html 200\n");
+///
+/// # // Cleanup
+/// # fsutils::rm("read_file_auto.log.gz");
+/// # fsutils::rm("read_file_auto.log");
+/// ```
+pub fn read_file_auto<P: AsRef<Path>>(path: P) -> String {
+    match try_read_file_auto(path) {
+        Ok(contents) => contents,
+        Err(e) => {
+            error!("Cannot read file {}", e);
+            String::new()
+        }
+    }
+}
+
+/// Reads data from a file into a `String`, decompressing it if it is
+/// compressed.
+///
+/// This is `fsutils::try_read_file_auto_bytes` followed by a check that the
+/// contents are UTF-8, which fails with an error of kind
+/// `io::ErrorKind::InvalidData` if they are not.
+///
+/// ## Usage:
+///
+/// ```
+/// # #[cfg(feature = "zstd")]
+/// # {
+/// use fsutils::Codec;
+///
+/// fsutils::write_file("try_read_file_auto.log", "GET /index.html 200\n");
+/// fsutils::compress_file("try_read_file_auto.log", "try_read_file_auto.zst", Codec::Zstd, 0);
+///
+/// assert_eq!(fsutils::try_read_file_auto("try_read_file_auto.zst").unwrap(), "GET /index.html 200\n");
+/// assert!(fsutils::try_read_file_auto("a_very_1234_unlikely_9876_filename").is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm("try_read_file_auto.log");
+/// # fsutils::rm("try_read_file_auto.zst");
+/// # }
+/// ```
+pub fn try_read_file_auto<P: AsRef<Path>>(path: P) -> Result<String> {
+    let path = path.as_ref();
+    let bytes = try_read_file_auto_bytes(path)?;
+    String::from_utf8(bytes).map_err(|e| FsError::from_io(path, io::Error::new(io::ErrorKind::InvalidData, e)))
+}
+
+/// Reads data from a file, decompressing it if it is compressed,
+/// and returns a `Vec<u8>` with the file's contents
+///
+/// An empty `Vec` is returned if the file cannot be read or cannot be
+/// decompressed.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::create_file_bytes("read_file_auto_bytes.bin", b"\x00\x01\x02");
+///
+/// assert_eq!(fsutils::read_file_auto_bytes("read_file_auto_bytes.bin"), b"\x00\x01\x02");
+///
+/// # // Cleanup
+/// # fsutils::rm("read_file_auto_bytes.bin");
+/// ```
+pub fn read_file_auto_bytes<P: AsRef<Path>>(path: P) -> Vec<u8> {
+    match try_read_file_auto_bytes(path) {
+        Ok(contents) => contents,
+        Err(e) => {
+            error!("Cannot read file {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// Reads data from a file into a `Vec<u8>`, decompressing it if it is
+/// compressed.
+///
+/// The format is told by the magic number the file starts with, not by its
+/// extension, so code that reads logs need not care which of them were
+/// rotated and compressed. gzip files are always recognised, Zstandard and
+/// LZ4 files with the `zstd` and `lz4` features, and xz files with the `xz`
+/// feature. A file in one of those formats whose feature is not enabled fails
+/// with an error of kind `io::ErrorKind::Unsupported`, and one that is corrupt
+/// with `io::ErrorKind::InvalidData`. Files made of several compressed
+/// streams, as `cat a.gz b.gz` gives, read as their contents one after
+/// another, and any other file is read as it is. The whole file is
+/// decompressed in memory.
+///
+/// ## Usage:
+///
+/// ```
+/// use std::io::ErrorKind;
+///
+/// fsutils::create_file_bytes("try_read_file_auto_bytes.gz", b"\x1f\x8b\x08\x00 not really gzip");
+///
+/// let e = fsutils::try_read_file_auto_bytes("try_read_file_auto_bytes.gz").unwrap_err();
+/// assert_eq!(e.kind(), ErrorKind::InvalidData);
+///
+/// # // Cleanup
+/// # fsutils::rm("try_read_file_auto_bytes.gz");
+/// ```
+pub fn try_read_file_auto_bytes<P: AsRef<Path>>(path: P) -> Result<Vec<u8>> {
+    let path = path.as_ref();
+    let data = fs::read(path).at(path)?;
+    decompress(data).at(path)
+}
@@ -0,0 +1,495 @@
+// Copyright 2020 Jared Forth.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Integrity baselines of trees, and the drift from them.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::atomic::try_write_file_atomic;
+use crate::error::{FsError, IoResultExt, Result};
+use crate::glob::to_slash;
+use crate::hash::{self, Sha256};
+use crate::meta::FileKind;
+use crate::walk::{walk_with, WalkOptions};
+
+/// The first line of every baseline file.
+const HEADER: &str = "# fsutils baseline 1";
+
+/// What a `Baseline` records about one entry of the tree.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct BaselineEntry {
+    /// What the entry is. Symbolic links are not followed.
+    pub kind: FileKind,
+    /// The length in bytes of a file, or `0` for anything else.
+    pub size: u64,
+    /// The SHA-256 digest of a file as lowercase hex.
+    pub sha256: Option<String>,
+    /// The permission bits, on Unix.
+    pub mode: Option<u32>,
+    /// The owning user's id, on Unix.
+    pub uid: Option<u32>,
+    /// The owning group's id, on Unix.
+    pub gid: Option<u32>,
+    /// Where a symbolic link points.
+    pub target: Option<PathBuf>,
+}
+
+impl BaselineEntry {
+    fn read(path: &Path, kind: FileKind) -> Result<BaselineEntry> {
+        let meta = fs::symlink_metadata(path).at(path)?;
+        #[cfg(unix)]
+        let (mode, uid, gid) = {
+            use std::os::unix::fs::MetadataExt;
+            (Some(meta.mode() & 0o7777), Some(meta.uid()), Some(meta.gid()))
+        };
+        #[cfg(not(unix))]
+        let (mode, uid, gid) = (None, None, None);
+        let (size, sha256) = match kind {
+            FileKind::File => (meta.len(), Some(hash::hex(&hash::digest_file(path, Sha256::new())?))),
+            _ => (0, None),
+        };
+        let target = if kind == FileKind::Symlink { Some(fs::read_link(path).at(path)?) } else { None };
+        Ok(BaselineEntry { kind, size, sha256, mode, uid, gid, target })
+    }
+
+    /// The differences from `other`, an earlier record of the same path.
+    fn changes_from(&self, other: &BaselineEntry) -> Vec<Change> {
+        let mut changes = Vec::new();
+        if self.kind != other.kind {
+            changes.push(Change::Kind);
+        }
+        if self.size != other.size {
+            changes.push(Change::Size);
+        }
+        if self.sha256 != other.sha256 {
+            changes.push(Change::Content);
+        }
+        if self.mode != other.mode {
+            changes.push(Change::Permissions);
+        }
+        if (self.uid, self.gid) != (other.uid, other.gid) {
+            changes.push(Change::Owner);
+        }
+        if self.target != other.target {
+            changes.push(Change::Target);
+        }
+        changes
+    }
+}
+
+/// What changed about a path between a `Baseline` and the tree.
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Change {
+    /// It is now a different kind of entry, such as a link instead of a file.
+    Kind,
+    /// The file has a different length.
+    Size,
+    /// The file has different contents.
+    Content,
+    /// The permission bits differ.
+    Permissions,
+    /// The owning user or group differs.
+    Owner,
+    /// The symbolic link points somewhere else.
+    Target,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Change::Kind => "kind",
+            Change::Size => "size",
+            Change::Content => "content",
+            Change::Permissions => "permissions",
+            Change::Owner => "owner",
+            Change::Target => "target",
+        })
+    }
+}
+
+/// A path that is in both a `Baseline` and the tree, but differs.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Modified {
+    /// The path, relative to the root.
+    pub path: PathBuf,
+    /// Everything that differs, never empty.
+    pub changes: Vec<Change>,
+}
+
+/// How a tree has drifted from a `Baseline`, with the paths relative to the
+/// root and sorted.
+///
+/// Displaying it gives one line per path, such as `added etc/new.conf` or
+/// `modified bin/app: size, content`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Drift {
+    /// Paths in the tree that the baseline does not have.
+    pub added: Vec<PathBuf>,
+    /// Paths in the baseline that are no longer in the tree.
+    pub removed: Vec<PathBuf>,
+    /// Paths in both that differ.
+    pub modified: Vec<Modified>,
+}
+
+impl Drift {
+    /// Returns whether the tree is exactly as the baseline recorded it.
+    pub fn is_clean(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+}
+
+impl fmt::Display for Drift {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for path in &self.added {
+            writeln!(f, "added {}", to_slash(path))?;
+        }
+        for path in &self.removed {
+            writeln!(f, "removed {}", to_slash(path))?;
+        }
+        for modified in &self.modified {
+            let changes: Vec<String> = modified.changes.iter().map(Change::to_string).collect();
+            writeln!(f, "modified {}: {}", to_slash(&modified.path), changes.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// A record of every entry below a root, with the digests of files and their
+/// permissions, owners and link targets, to check the tree against later.
+///
+/// Times are not recorded, since deploying or restoring the same files
+/// changes them. Symbolic links are recorded as links and not followed.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::{Baseline, Change};
+///
+/// fsutils::mkdir("baseline_dir/etc");
+/// fsutils::write_file("baseline_dir/etc/app.conf", "port = 80\n");
+///
+/// let baseline = Baseline::capture("baseline_dir").unwrap();
+/// assert_eq!(baseline.len(), 2);
+///
+/// fsutils::write_file("baseline_dir/etc/app.conf", "port = 8080\n");
+/// fsutils::write_file("baseline_dir/etc/extra.conf", "");
+///
+/// let drift = baseline.check("baseline_dir").unwrap();
+/// assert_eq!(drift.added, [std::path::Path::new("etc/extra.conf")]);
+/// assert_eq!(drift.modified[0].changes, [Change::Size, Change::Content]);
+/// assert_eq!(drift.to_string(), "added etc/extra.conf\nmodified etc/app.conf: size, content\n");
+///
+/// # // Cleanup
+/// # fsutils::rm_r("baseline_dir");
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Baseline {
+    entries: BTreeMap<PathBuf, BaselineEntry>,
+}
+
+impl Baseline {
+    /// Records the tree at `root`, hashing every file in it.
+    pub fn capture<P: AsRef<Path>>(root: P) -> Result<Baseline> {
+        let root = root.as_ref();
+        let mut entries = BTreeMap::new();
+        for entry in walk_with(root, &WalkOptions::new()) {
+            let entry = entry?;
+            if entry.depth == 0 {
+                continue;
+            }
+            let relative = entry.path.strip_prefix(root).unwrap_or(&entry.path).to_path_buf();
+            entries.insert(relative, BaselineEntry::read(&entry.path, entry.kind)?);
+        }
+        Ok(Baseline { entries })
+    }
+
+    /// Reads a baseline written by `Baseline::save`.
+    pub fn load<P: AsRef<Path>>(file: P) -> Result<Baseline> {
+        let file = file.as_ref();
+        let text = fs::read_to_string(file).at(file)?;
+        let invalid = |number: usize, reason: &str| {
+            let message = format!("line {}: {}", number, reason);
+            FsError::from_io(file, io::Error::new(io::ErrorKind::InvalidData, message))
+        };
+        let mut lines = text.lines().enumerate();
+        if lines.next().map(|(_, line)| line) != Some(HEADER) {
+            return Err(invalid(1, "not an fsutils baseline"));
+        }
+        let mut entries = BTreeMap::new();
+        for (i, line) in lines {
+            let (path, entry) = parse_line(line).ok_or_else(|| invalid(i + 1, "malformed entry"))?;
+            entries.insert(path, entry);
+        }
+        Ok(Baseline { entries })
+    }
+
+    /// Writes the baseline to `file`, replacing it atomically.
+    ///
+    /// The file is text, one tab-separated line per entry sorted by path, so
+    /// it can be kept in version control and reviewed like any other.
+    pub fn save<P: AsRef<Path>>(&self, file: P) -> Result<()> {
+        let mut text = format!("{}\n", HEADER);
+        for (path, entry) in &self.entries {
+            let kind = match entry.kind {
+                FileKind::File => "file",
+                FileKind::Dir => "dir",
+                FileKind::Symlink => "symlink",
+                FileKind::Other => "other",
+            };
+            let optional = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+            let fields = [
+                kind.to_string(),
+                entry.size.to_string(),
+                optional(entry.sha256.clone()),
+                optional(entry.mode.map(|mode| format!("{:o}", mode))),
+                optional(entry.uid.map(|uid| uid.to_string())),
+                optional(entry.gid.map(|gid| gid.to_string())),
+                escape(&to_slash(path)),
+                optional(entry.target.as_ref().map(|target| escape(&target.to_string_lossy()))),
+            ];
+            text.push_str(&fields.join("\t"));
+            text.push('\n');
+        }
+        try_write_file_atomic(file, &text)
+    }
+
+    /// Compares the tree at `root` with the baseline.
+    pub fn check<P: AsRef<Path>>(&self, root: P) -> Result<Drift> {
+        Ok(self.compare(&Baseline::capture(root)?))
+    }
+
+    /// Compares a later baseline of the same tree with this one.
+    pub fn compare(&self, later: &Baseline) -> Drift {
+        let mut drift = Drift::default();
+        for (path, entry) in &later.entries {
+            match self.entries.get(path) {
+                None => drift.added.push(path.clone()),
+                Some(earlier) => {
+                    let changes = entry.changes_from(earlier);
+                    if !changes.is_empty() {
+                        drift.modified.push(Modified { path: path.clone(), changes });
+                    }
+                }
+            }
+        }
+        drift.removed = self.entries.keys().filter(|path| !later.entries.contains_key(*path)).cloned().collect();
+        drift
+    }
+
+    /// The record of `path`, relative to the root.
+    pub fn get<P: AsRef<Path>>(&self, path: P) -> Option<&BaselineEntry> {
+        self.entries.get(path.as_ref())
+    }
+
+    /// The paths and records in the baseline, sorted by path.
+    pub fn iter(&self) -> impl Iterator<Item = (&Path, &BaselineEntry)> {
+        self.entries.iter().map(|(path, entry)| (path.as_path(), entry))
+    }
+
+    /// How many entries the baseline records, not counting the root.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns whether the baseline records nothing, as for an empty directory.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Drops `file` from the baseline if it is inside `root`, so a baseline
+    /// kept in the tree it describes is not reported as drift.
+    fn without(mut self, root: &Path, file: &Path) -> Baseline {
+        let parent = file.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        let (root, parent) = (fs::canonicalize(root), fs::canonicalize(parent));
+        if let (Ok(root), Ok(parent), Some(name)) = (root, parent, file.file_name()) {
+            if let Ok(relative) = parent.join(name).strip_prefix(root) {
+                self.entries.remove(relative);
+            }
+        }
+        self
+    }
+}
+
+/// Parses one entry line of a baseline file.
+fn parse_line(line: &str) -> Option<(PathBuf, BaselineEntry)> {
+    let fields: Vec<&str> = line.split('\t').collect();
+    if fields.len() != 8 {
+        return None;
+    }
+    fn optional(field: &str) -> Option<&str> {
+        if field == "-" {
+            None
+        } else {
+            Some(field)
+        }
+    }
+    let kind = match fields[0] {
+        "file" => FileKind::File,
+        "dir" => FileKind::Dir,
+        "symlink" => FileKind::Symlink,
+        "other" => FileKind::Other,
+        _ => return None,
+    };
+    let mode = optional(fields[3]).map(|mode| u32::from_str_radix(mode, 8)).transpose().ok()?;
+    let uid = optional(fields[4]).map(str::parse).transpose().ok()?;
+    let gid = optional(fields[5]).map(str::parse).transpose().ok()?;
+    let entry = BaselineEntry {
+        kind,
+        size: fields[1].parse().ok()?,
+        sha256: optional(fields[2]).map(str::to_string),
+        mode,
+        uid,
+        gid,
+        target: optional(fields[7]).map(|target| PathBuf::from(unescape(target))),
+    };
+    Some((PathBuf::from(unescape(fields[6])), entry))
+}
+
+/// Escapes the characters that separate fields and lines.
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n").replace('\r', "\\r")
+}
+
+fn unescape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => out.push('\t'),
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some(other) => out.push(other),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+/// Records the tree at `root` in the baseline file `file`, and returns
+/// whether that succeeded.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::mkdir("create_baseline_dir");
+/// fsutils::write_file("create_baseline_dir/a.txt", "a");
+///
+/// assert!(fsutils::create_baseline("create_baseline_dir", "create_baseline.txt"));
+/// assert!(!fsutils::create_baseline("a_very_1234_unlikely_9876_dirname", "create_baseline.txt"));
+///
+/// # // Cleanup
+/// # fsutils::rm_r("create_baseline_dir");
+/// # fsutils::rm("create_baseline.txt");
+/// ```
+pub fn create_baseline<P: AsRef<Path>, Q: AsRef<Path>>(root: P, file: Q) -> bool {
+    match try_create_baseline(root, file) {
+        Ok(_) => true,
+        Err(e) => {
+            error!("Cannot create baseline {}", e);
+            false
+        }
+    }
+}
+
+/// Records the tree at `root` in the baseline file `file`, for
+/// `fsutils::try_check_baseline` to compare the tree with later, and returns
+/// the baseline.
+///
+/// The baseline file may be inside the tree; it is left out of the record.
+/// See `fsutils::Baseline` for what is recorded.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::mkdir("try_create_baseline_dir/bin");
+/// fsutils::write_file("try_create_baseline_dir/bin/app", "v1");
+///
+/// let baseline = fsutils::try_create_baseline("try_create_baseline_dir", "try_create_baseline_dir/BASELINE").unwrap();
+/// assert_eq!(baseline.len(), 2);
+/// assert_eq!(baseline.get("bin/app").unwrap().size, 2);
+/// assert!(fsutils::read_file("try_create_baseline_dir/BASELINE").contains("bin/app"));
+///
+/// # // Cleanup
+/// # fsutils::rm_r("try_create_baseline_dir");
+/// ```
+pub fn try_create_baseline<P: AsRef<Path>, Q: AsRef<Path>>(root: P, file: Q) -> Result<Baseline> {
+    let (root, file) = (root.as_ref(), file.as_ref());
+    let baseline = Baseline::capture(root)?.without(root, file);
+    baseline.save(file)?;
+    Ok(baseline)
+}
+
+/// Compares the tree at `root` with the baseline file `file`, or returns
+/// `None` if either cannot be read.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::mkdir("check_baseline_dir");
+/// fsutils::create_baseline("check_baseline_dir", "check_baseline.txt");
+///
+/// if let Some(drift) = fsutils::check_baseline("check_baseline_dir", "check_baseline.txt") {
+///     print!("{}", drift);
+/// }
+///
+/// # // Cleanup
+/// # fsutils::rm_r("check_baseline_dir");
+/// # fsutils::rm("check_baseline.txt");
+/// ```
+pub fn check_baseline<P: AsRef<Path>, Q: AsRef<Path>>(root: P, file: Q) -> Option<Drift> {
+    match try_check_baseline(root, file) {
+        Ok(drift) => Some(drift),
+        Err(e) => {
+            error!("Cannot check baseline {}", e);
+            None
+        }
+    }
+}
+
+/// Compares the tree at `root` with the baseline file `file` written by
+/// `fsutils::try_create_baseline`, and reports the paths added, removed and
+/// modified since, like tripwire.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::Change;
+///
+/// fsutils::mkdir("try_check_baseline_dir");
+/// fsutils::write_file("try_check_baseline_dir/a.txt", "a");
+/// fsutils::write_file("try_check_baseline_dir/b.txt", "b");
+/// fsutils::try_create_baseline("try_check_baseline_dir", "try_check_baseline_dir/.baseline").unwrap();
+///
+/// let drift = fsutils::try_check_baseline("try_check_baseline_dir", "try_check_baseline_dir/.baseline").unwrap();
+/// assert!(drift.is_clean());
+///
+/// fsutils::rm("try_check_baseline_dir/a.txt");
+/// fsutils::write_file("try_check_baseline_dir/b.txt", "B");
+///
+/// let drift = fsutils::try_check_baseline("try_check_baseline_dir", "try_check_baseline_dir/.baseline").unwrap();
+/// assert_eq!(drift.removed, [std::path::Path::new("a.txt")]);
+/// assert_eq!(drift.modified[0].changes, [Change::Content]);
+/// assert!(fsutils::try_check_baseline("try_check_baseline_dir", "a_very_1234_unlikely_9876_filename").is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm_r("try_check_baseline_dir");
+/// ```
+pub fn try_check_baseline<P: AsRef<Path>, Q: AsRef<Path>>(root: P, file: Q) -> Result<Drift> {
+    let (root, file) = (root.as_ref(), file.as_ref());
+    let baseline = Baseline::load(file)?;
+    Ok(baseline.compare(&Baseline::capture(root)?.without(root, file)))
+}
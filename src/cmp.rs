@@ -0,0 +1,174 @@
+// Copyright 2020 Jared Forth.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Comparing the contents of files byte by byte.
+
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+
+use crate::error::{IoResultExt, Result};
+use crate::meta::file_id;
+
+/// How much of each file is read and compared at a time.
+const BLOCK_SIZE: usize = 64 * 1024;
+
+/// Returns whether two files have the same contents, or `false` if either
+/// cannot be read.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("files_identical_a.txt", "same");
+/// fsutils::write_file("files_identical_b.txt", "same");
+///
+/// assert!(fsutils::files_identical("files_identical_a.txt", "files_identical_b.txt"));
+/// assert!(!fsutils::files_identical("files_identical_a.txt", "a_very_1234_unlikely_9876_filename"));
+///
+/// # // Cleanup
+/// # fsutils::rm("files_identical_a.txt");
+/// # fsutils::rm("files_identical_b.txt");
+/// ```
+pub fn files_identical<P: AsRef<Path>, Q: AsRef<Path>>(a: P, b: Q) -> bool {
+    match try_files_identical(a, b) {
+        Ok(identical) => identical,
+        Err(e) => {
+            error!("Cannot compare files {}", e);
+            false
+        }
+    }
+}
+
+/// Returns whether two files have the same contents, like `cmp -s`.
+///
+/// Files of different sizes are told apart from their metadata without
+/// reading them, and two paths to the same file are identical without
+/// reading it either. Otherwise both are read a block at a time, stopping
+/// at the first difference.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("try_files_identical_a.txt", "abc");
+/// fsutils::write_file("try_files_identical_b.txt", "abd");
+/// fsutils::write_file("try_files_identical_c.txt", "abcd");
+///
+/// assert!(fsutils::try_files_identical("try_files_identical_a.txt", "try_files_identical_a.txt").unwrap());
+/// assert!(!fsutils::try_files_identical("try_files_identical_a.txt", "try_files_identical_b.txt").unwrap());
+/// assert!(!fsutils::try_files_identical("try_files_identical_a.txt", "try_files_identical_c.txt").unwrap());
+/// assert!(fsutils::try_files_identical("try_files_identical_a.txt", "a_very_1234_unlikely_9876_filename").is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm("try_files_identical_a.txt");
+/// # fsutils::rm("try_files_identical_b.txt");
+/// # fsutils::rm("try_files_identical_c.txt");
+/// ```
+pub fn try_files_identical<P: AsRef<Path>, Q: AsRef<Path>>(a: P, b: Q) -> Result<bool> {
+    let (a, b) = (a.as_ref(), b.as_ref());
+    if fs::metadata(a).at(a)?.len() != fs::metadata(b).at(b)?.len() {
+        return Ok(false);
+    }
+    if file_id(a).at(a)? == file_id(b).at(b)? {
+        return Ok(true);
+    }
+    Ok(first_difference(a, b)?.is_none())
+}
+
+/// Returns the offset of the first byte where two files differ, or `None`
+/// if they are identical or either cannot be read.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("cmp_a.txt", "abc");
+/// fsutils::write_file("cmp_b.txt", "abd");
+///
+/// assert_eq!(fsutils::cmp("cmp_a.txt", "cmp_b.txt"), Some(2));
+/// assert_eq!(fsutils::cmp("cmp_a.txt", "cmp_a.txt"), None);
+///
+/// # // Cleanup
+/// # fsutils::rm("cmp_a.txt");
+/// # fsutils::rm("cmp_b.txt");
+/// ```
+pub fn cmp<P: AsRef<Path>, Q: AsRef<Path>>(a: P, b: Q) -> Option<u64> {
+    match try_cmp(a, b) {
+        Ok(offset) => offset,
+        Err(e) => {
+            error!("Cannot compare files {}", e);
+            None
+        }
+    }
+}
+
+/// Returns the offset of the first byte where two files differ, counting
+/// from `0`, or `None` if they are identical.
+///
+/// When one file is a prefix of the other, they differ at the length of the
+/// shorter one. This is the byte that `cmp` reports, less one, since `cmp`
+/// counts from `1`. Both files are read a block at a time, stopping at the
+/// first difference.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("try_cmp_a.txt", "hello world");
+/// fsutils::write_file("try_cmp_b.txt", "hello there");
+/// fsutils::write_file("try_cmp_c.txt", "hello");
+///
+/// assert_eq!(fsutils::try_cmp("try_cmp_a.txt", "try_cmp_b.txt").unwrap(), Some(6));
+/// assert_eq!(fsutils::try_cmp("try_cmp_a.txt", "try_cmp_c.txt").unwrap(), Some(5));
+/// assert_eq!(fsutils::try_cmp("try_cmp_a.txt", "try_cmp_a.txt").unwrap(), None);
+/// assert!(fsutils::try_cmp("try_cmp_a.txt", "a_very_1234_unlikely_9876_filename").is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm("try_cmp_a.txt");
+/// # fsutils::rm("try_cmp_b.txt");
+/// # fsutils::rm("try_cmp_c.txt");
+/// ```
+pub fn try_cmp<P: AsRef<Path>, Q: AsRef<Path>>(a: P, b: Q) -> Result<Option<u64>> {
+    first_difference(a.as_ref(), b.as_ref())
+}
+
+/// Reads both files in blocks until they differ or both end.
+fn first_difference(a: &Path, b: &Path) -> Result<Option<u64>> {
+    let mut file_a = fs::File::open(a).at(a)?;
+    let mut file_b = fs::File::open(b).at(b)?;
+    let mut buf_a = vec![0; BLOCK_SIZE];
+    let mut buf_b = vec![0; BLOCK_SIZE];
+    let mut offset = 0;
+    loop {
+        let n_a = fill(&mut file_a, &mut buf_a).at(a)?;
+        let n_b = fill(&mut file_b, &mut buf_b).at(b)?;
+        let common = n_a.min(n_b);
+        if let Some(i) = buf_a[..common].iter().zip(&buf_b[..common]).position(|(x, y)| x != y) {
+            return Ok(Some(offset + i as u64));
+        }
+        if n_a != n_b {
+            return Ok(Some(offset + common as u64));
+        }
+        if n_a < BLOCK_SIZE {
+            return Ok(None);
+        }
+        offset += BLOCK_SIZE as u64;
+    }
+}
+
+/// Reads until `buf` is full or the reader ends, and returns how much was
+/// read, so blocks of both files line up however the reads are split.
+pub(crate) fn fill<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
@@ -0,0 +1,286 @@
+// Copyright 2020 Jared Forth.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Compressing and decompressing single files with Zstandard and LZ4.
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Seek, Write};
+use std::path::Path;
+
+use crate::atomic::replace_file;
+use crate::cmp::fill;
+use crate::error::{IoResultExt, Result};
+#[cfg(feature = "lz4")]
+use crate::lz4;
+use crate::op::Op;
+#[cfg(feature = "zstd")]
+use crate::zstd;
+
+/// A compression format for `fsutils::compress_file`.
+///
+/// `Zstd` and `Lz4` need the features of the same names. Both are encoded
+/// and decoded by this crate, without the reference C libraries, and the
+/// files they write can be read by the `zstd` and `lz4` tools. The encoders
+/// aim for compatibility and ratio rather than speed: they are several times
+/// slower than those tools, and at its default level Zstandard is not
+/// reliably faster than gzip. LZ4 files are larger than Zstandard's.
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Codec {
+    #[cfg(feature = "zstd")]
+    Zstd,
+    #[cfg(feature = "lz4")]
+    Lz4,
+}
+
+impl Codec {
+    /// The usual file extension, without the dot.
+    pub fn extension(self) -> &'static str {
+        match self {
+            #[cfg(feature = "zstd")]
+            Codec::Zstd => "zst",
+            #[cfg(feature = "lz4")]
+            Codec::Lz4 => "lz4",
+        }
+    }
+
+    /// The level a level of `0` stands for, which is `3` for Zstandard and
+    /// `1` for LZ4, as with their command line tools.
+    pub fn default_level(self) -> u32 {
+        match self {
+            #[cfg(feature = "zstd")]
+            Codec::Zstd => zstd::DEFAULT_LEVEL,
+            #[cfg(feature = "lz4")]
+            Codec::Lz4 => lz4::DEFAULT_LEVEL,
+        }
+    }
+
+    /// The highest level, which is `22` for Zstandard and `12` for LZ4.
+    pub fn max_level(self) -> u32 {
+        match self {
+            #[cfg(feature = "zstd")]
+            Codec::Zstd => zstd::MAX_LEVEL,
+            #[cfg(feature = "lz4")]
+            Codec::Lz4 => lz4::MAX_LEVEL,
+        }
+    }
+}
+
+/// Compresses a file into another with `codec` at `level`
+/// and returns a boolean based on success or failure.
+///
+/// ## Usage:
+///
+/// ```
+/// # #[cfg(feature = "zstd")]
+/// # {
+/// use fsutils::Codec;
+///
+/// fsutils::write_file("compress_file.log", "GET /index.html 200\n");
+///
+/// assert!(fsutils::compress_file("compress_file.log", "compress_file.log.zst", Codec::Zstd, 0));
+///
+/// # // Cleanup
+/// # fsutils::rm("compress_file.log");
+/// # fsutils::rm("compress_file.log.zst");
+/// # }
+/// ```
+pub fn compress_file<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dst: Q, codec: Codec, level: u32) -> bool {
+    let (src, dst) = (src.as_ref(), dst.as_ref());
+    match try_compress_file(src, dst, codec, level) {
+        Ok(_) => true,
+        Err(e) => {
+            error!("Cannot compress {} to {} {}", src.display(), dst.display(), e);
+            false
+        }
+    }
+}
+
+/// Compresses a file into another with `codec` at `level`, and returns the
+/// size of the compressed file.
+///
+/// Higher levels look harder for repeated data, which is slower and gives a
+/// smaller file. `0` stands for the codec's default level, and levels above
+/// `Codec::max_level` are treated as it. The file is read and written a
+/// block at a time, so it can be larger than memory, and `dst` is replaced
+/// only once it is complete. What is written is a standard `.zst` or `.lz4`
+/// file with a checksum of the contents, which the `zstd` and `lz4` tools
+/// read. Requires the `zstd` or `lz4` feature.
+///
+/// ## Usage:
+///
+/// ```
+/// # #[cfg(feature = "lz4")]
+/// # {
+/// use fsutils::Codec;
+///
+/// fsutils::write_file("try_compress_file.log", &"GET /index.html 200\n".repeat(1000));
+///
+/// let size = fsutils::try_compress_file("try_compress_file.log", "try_compress_file.lz4", Codec::Lz4, 9).unwrap();
+/// assert!(size < 20_000);
+/// assert!(fsutils::try_compress_file("a_very_1234_unlikely_9876_filename", "out.lz4", Codec::Lz4, 0).is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm("try_compress_file.log");
+/// # fsutils::rm("try_compress_file.lz4");
+/// # }
+/// ```
+pub fn try_compress_file<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dst: Q, codec: Codec, level: u32) -> Result<u64> {
+    let (src, dst) = (src.as_ref(), dst.as_ref());
+    let level = if level == 0 { codec.default_level() } else { level.min(codec.max_level()) };
+    Op::new("compress_file", src).to(dst).destructive(dst.exists()).run(0, || {
+        let input = BufReader::new(File::open(src).at(src)?);
+        let mut size = 0;
+        replace_file(dst, |file| {
+            let mut output = BufWriter::new(&mut *file);
+            match codec {
+                #[cfg(feature = "zstd")]
+                Codec::Zstd => zstd::compress(input, &mut output, level),
+                #[cfg(feature = "lz4")]
+                Codec::Lz4 => lz4::compress(input, &mut output, level),
+            }
+            .and_then(|_| output.flush())
+            .at2(src, dst)?;
+            drop(output);
+            size = file.stream_position().at(dst)?;
+            Ok(())
+        })?;
+        info!("Compressed {} to {} with {:?} at level {}", src.display(), dst.display(), codec, level);
+        Ok(size)
+    })
+}
+
+/// Decompresses a Zstandard or LZ4 file into another
+/// and returns a boolean based on success or failure.
+///
+/// ## Usage:
+///
+/// ```
+/// # #[cfg(feature = "zstd")]
+/// # {
+/// use fsutils::Codec;
+///
+/// fsutils::write_file("decompress_file.log", "GET /index.html 200\n");
+/// fsutils::compress_file("decompress_file.log", "decompress_file.log.zst", Codec::Zstd, 19);
+///
+/// assert!(fsutils::decompress_file("decompress_file.log.zst", "decompress_file.out"));
+/// assert_eq!(fsutils::read_file("decompress_file.out"), "GET /index.html 200\n");
+///
+/// # // Cleanup
+/// # fsutils::rm("decompress_file.log");
+/// # fsutils::rm("decompress_file.log.zst");
+/// # fsutils::rm("decompress_file.out");
+/// # }
+/// ```
+pub fn decompress_file<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dst: Q) -> bool {
+    let (src, dst) = (src.as_ref(), dst.as_ref());
+    match try_decompress_file(src, dst) {
+        Ok(_) => true,
+        Err(e) => {
+            error!("Cannot decompress {} to {} {}", src.display(), dst.display(), e);
+            false
+        }
+    }
+}
+
+/// Decompresses a Zstandard or LZ4 file into another, and returns the size
+/// of the decompressed file.
+///
+/// The format is told by the magic number the file starts with, not by its
+/// extension. Several frames one after another, as `cat a.zst b.zst` gives,
+/// decompress to their contents one after another, and skippable frames are
+/// skipped. The checksums in the file are verified, and `dst` is replaced
+/// only once all of it has decompressed. Frames that need a dictionary are
+/// not supported. Requires the `zstd` or `lz4` feature.
+///
+/// ## Usage:
+///
+/// ```
+/// # #[cfg(feature = "lz4")]
+/// # {
+/// use fsutils::Codec;
+///
+/// fsutils::write_file("try_decompress_file.log", &"GET /index.html 200\n".repeat(1000));
+/// fsutils::compress_file("try_decompress_file.log", "try_decompress_file.lz4", Codec::Lz4, 0);
+///
+/// assert_eq!(fsutils::try_decompress_file("try_decompress_file.lz4", "try_decompress_file.out").unwrap(), 20_000);
+/// assert!(fsutils::try_decompress_file("try_decompress_file.log", "try_decompress_file.out").is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm("try_decompress_file.log");
+/// # fsutils::rm("try_decompress_file.lz4");
+/// # fsutils::rm("try_decompress_file.out");
+/// # }
+/// ```
+pub fn try_decompress_file<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dst: Q) -> Result<u64> {
+    let (src, dst) = (src.as_ref(), dst.as_ref());
+    Op::new("decompress_file", src).to(dst).destructive(dst.exists()).run(0, || {
+        let mut input = BufReader::new(File::open(src).at(src)?);
+        let mut size = 0;
+        replace_file(dst, |file| {
+            let mut output = BufWriter::new(file);
+            size = decompress(&mut input, &mut output).and_then(|size| output.flush().map(|_| size)).at2(src, dst)?;
+            Ok(())
+        })?;
+        info!("Decompressed {} to {}", src.display(), dst.display());
+        Ok(size)
+    })
+}
+
+/// Decompresses a Zstandard or LZ4 stream, told apart by its magic number,
+/// and returns how many bytes it held.
+pub(crate) fn decompress<R: Read, W: Write>(mut input: R, output: &mut W) -> io::Result<u64> {
+    let mut magic = read_u32_or_end(&mut input)?;
+    let mut skipped = false;
+    // Both formats share skippable frames, so look past them for the format
+    while let Some(0x184d_2a50..=0x184d_2a5f) = magic {
+        let size = u64::from(read_u32(&mut input, "skippable frame")?);
+        if io::copy(&mut input.by_ref().take(size), &mut io::sink())? != size {
+            return Err(truncated("skippable frame"));
+        }
+        skipped = true;
+        magic = read_u32_or_end(&mut input)?;
+    }
+    match magic {
+        None if skipped => Ok(0),
+        #[cfg(feature = "zstd")]
+        Some(zstd::MAGIC) => zstd::decompress(zstd::MAGIC, input, output),
+        #[cfg(feature = "lz4")]
+        Some(magic @ (lz4::MAGIC | lz4::LEGACY_MAGIC)) => lz4::decompress(magic, input, output),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidData, "not a zstd or lz4 file")),
+    }
+}
+
+fn truncated(format: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("{} stream is truncated", format))
+}
+
+/// Reads exactly `count` bytes of a `format` stream.
+pub(crate) fn read_vec<R: Read>(input: &mut R, count: usize, format: &str) -> io::Result<Vec<u8>> {
+    let mut buf = vec![0; count];
+    if fill(input, &mut buf)? < count {
+        return Err(truncated(format));
+    }
+    Ok(buf)
+}
+
+/// Reads a little-endian `u32` of a `format` stream.
+pub(crate) fn read_u32<R: Read>(input: &mut R, format: &str) -> io::Result<u32> {
+    let bytes = read_vec(input, 4, format)?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// Reads a little-endian `u32`, or `None` if the stream has ended.
+pub(crate) fn read_u32_or_end<R: Read>(input: &mut R) -> io::Result<Option<u32>> {
+    let mut bytes = [0; 4];
+    match fill(input, &mut bytes)? {
+        0 => Ok(None),
+        4 => Ok(Some(u32::from_le_bytes(bytes))),
+        _ => Err(truncated("compressed")),
+    }
+}
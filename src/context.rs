@@ -0,0 +1,224 @@
+// Copyright 2020 Jared Forth.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Operations confined to a single directory tree.
+
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use crate::error::{FsError, IoResultExt, Result};
+
+/// A handle that runs operations on paths relative to a root directory,
+/// refusing any path that would reach outside of it.
+///
+/// Absolute paths, `..` components that climb above the root and symlinks
+/// that point outside the root are all rejected with `FsError::InvalidPath`.
+/// The check happens before each operation, so it does not protect against
+/// another process swapping in a symlink between the check and the operation.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::FsContext;
+///
+/// fsutils::mkdir("context_root");
+/// let ctx = FsContext::new("context_root").unwrap();
+///
+/// ctx.mkdir("a/b").unwrap();
+/// ctx.write_file("a/b/file.txt", "inside").unwrap();
+/// assert_eq!(ctx.read_file("a/b/../b/file.txt").unwrap(), "inside");
+///
+/// assert!(ctx.write_file("../escaped.txt", "outside").is_err());
+/// assert!(ctx.rm("/etc/passwd").is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm_r("context_root");
+/// ```
+#[derive(Clone, Debug)]
+pub struct FsContext {
+    root: PathBuf,
+}
+
+impl FsContext {
+    /// Creates a context rooted at an existing directory.
+    pub fn new<P: AsRef<Path>>(root: P) -> Result<FsContext> {
+        let root = root.as_ref();
+        let root = fs::canonicalize(root).at(root)?;
+        if !root.is_dir() {
+            let source = std::io::Error::new(std::io::ErrorKind::NotADirectory, "sandbox root is not a directory");
+            return Err(FsError::from_io(root, source));
+        }
+        Ok(FsContext { root })
+    }
+
+    /// Returns the canonical root directory.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Maps a path relative to the root onto the real filesystem path.
+    ///
+    /// ## Usage:
+    ///
+    /// ```
+    /// # #[cfg(unix)] {
+    /// use fsutils::FsContext;
+    ///
+    /// fsutils::mkdir("resolve_root");
+    /// std::os::unix::fs::symlink("/tmp", "resolve_root/link").unwrap();
+    /// let ctx = FsContext::new("resolve_root").unwrap();
+    ///
+    /// assert_eq!(ctx.resolve("x/./y").unwrap(), ctx.root().join("x/y"));
+    /// assert!(ctx.resolve("link/file").is_err());
+    ///
+    /// # // Cleanup
+    /// # fsutils::rm_r("resolve_root");
+    /// # }
+    /// ```
+    pub fn resolve<P: AsRef<Path>>(&self, path: P) -> Result<PathBuf> {
+        let path = path.as_ref();
+        self.follow(path, &normalize(path)?)
+    }
+
+    /// Like `resolve`, but leaves a symlink in the final component unresolved,
+    /// for operations such as `rm` that act on the link itself.
+    fn resolve_entry(&self, path: &Path) -> Result<PathBuf> {
+        let relative = normalize(path)?;
+        match (relative.parent(), relative.file_name()) {
+            (Some(parent), Some(name)) => Ok(self.follow(path, parent)?.join(name)),
+            _ => Ok(self.root.clone()),
+        }
+    }
+
+    /// Follows an already normalized relative path from the root.
+    fn follow(&self, path: &Path, relative: &Path) -> Result<PathBuf> {
+        // Follow the path one component at a time, resolving symlinks as they appear
+        let mut resolved = self.root.clone();
+        let mut components = relative.components();
+        while let Some(component) = components.next() {
+            let next = resolved.join(component);
+            match next.symlink_metadata() {
+                Ok(meta) if meta.file_type().is_symlink() => {
+                    let target = fs::canonicalize(&next)
+                        .map_err(|_| FsError::invalid_path(path, "path contains a dangling symlink"))?;
+                    if !target.starts_with(&self.root) {
+                        return Err(FsError::invalid_path(path, "symlink points outside the sandbox root"));
+                    }
+                    resolved = target;
+                }
+                Ok(_) => resolved = next,
+                Err(_) => {
+                    // Nothing below a missing component can be a symlink
+                    resolved = next;
+                    let rest = components.as_path();
+                    if !rest.as_os_str().is_empty() {
+                        resolved.push(rest);
+                    }
+                    break;
+                }
+            }
+        }
+        Ok(resolved)
+    }
+
+    /// Creates a directory and its parents. See `fsutils::try_mkdir`.
+    pub fn mkdir<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        crate::try_mkdir(self.resolve(path)?)
+    }
+
+    /// Removes a file. See `fsutils::try_rm`.
+    pub fn rm<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        crate::try_rm(self.resolve_entry(path.as_ref())?)
+    }
+
+    /// Removes an empty directory. See `fsutils::try_rmdir`.
+    pub fn rmdir<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        crate::try_rmdir(self.resolve_entry(path.as_ref())?)
+    }
+
+    /// Removes a directory recursively. See `fsutils::try_rm_r`.
+    ///
+    /// The root itself cannot be removed.
+    pub fn rm_r<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let resolved = self.resolve_entry(path.as_ref())?;
+        if resolved == self.root {
+            return Err(FsError::invalid_path(path, "refusing to remove the sandbox root"));
+        }
+        crate::try_rm_r(resolved)
+    }
+
+    /// Checks whether a path exists.
+    pub fn path_exists<P: AsRef<Path>>(&self, path: P) -> Result<bool> {
+        Ok(crate::path_exists(self.resolve(path)?))
+    }
+
+    /// Checks if a directory is empty. See `fsutils::try_directory_is_empty`.
+    pub fn directory_is_empty<P: AsRef<Path>>(&self, path: P) -> Result<bool> {
+        crate::try_directory_is_empty(self.resolve(path)?)
+    }
+
+    /// Moves a file within the sandbox. See `fsutils::try_mv`.
+    pub fn mv<P: AsRef<Path>, Q: AsRef<Path>>(&self, path_one: P, path_two: Q) -> Result<()> {
+        crate::try_mv(self.resolve_entry(path_one.as_ref())?, self.resolve_entry(path_two.as_ref())?)
+    }
+
+    /// Copies a file within the sandbox. See `fsutils::try_cp`.
+    pub fn cp<P: AsRef<Path>, Q: AsRef<Path>>(&self, src: P, dst: Q) -> Result<u64> {
+        crate::try_cp(self.resolve(src)?, self.resolve(dst)?)
+    }
+
+    /// Creates a file. See `fsutils::try_create_file`.
+    pub fn create_file<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        crate::try_create_file(self.resolve(path)?)
+    }
+
+    /// Creates a file from bytes. See `fsutils::try_create_file_bytes`.
+    pub fn create_file_bytes<P: AsRef<Path>>(&self, path: P, bytes_to_write: &[u8]) -> Result<()> {
+        crate::try_create_file_bytes(self.resolve(path)?, bytes_to_write)
+    }
+
+    /// Writes data to a file. See `fsutils::try_write_file`.
+    pub fn write_file<P: AsRef<Path>>(&self, path: P, contents: &str) -> Result<()> {
+        crate::try_write_file(self.resolve(path)?, contents)
+    }
+
+    /// Appends data to a file. See `fsutils::try_write_file_append`.
+    pub fn write_file_append<P: AsRef<Path>>(&self, path: P, contents: &str) -> Result<()> {
+        crate::try_write_file_append(self.resolve(path)?, contents)
+    }
+
+    /// Reads a file into a `String`. See `fsutils::try_read_file`.
+    pub fn read_file<P: AsRef<Path>>(&self, path: P) -> Result<String> {
+        crate::try_read_file(self.resolve(path)?)
+    }
+
+    /// Lists directory contents. See `fsutils::try_ls`.
+    pub fn ls<P: AsRef<Path>>(&self, path: P) -> Result<Vec<PathBuf>> {
+        crate::try_ls(self.resolve(path)?)
+    }
+}
+
+/// Lexically normalizes a sandbox-relative path so that `..` can never climb above the root.
+fn normalize(path: &Path) -> Result<PathBuf> {
+    let mut relative = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => relative.push(name),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !relative.pop() {
+                    return Err(FsError::invalid_path(path, "path escapes the sandbox root"));
+                }
+            }
+            Component::RootDir | Component::Prefix(_) => {
+                return Err(FsError::invalid_path(path, "absolute paths are not allowed in a sandbox"));
+            }
+        }
+    }
+    Ok(relative)
+}
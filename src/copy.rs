@@ -0,0 +1,1592 @@
+// Copyright 2020 Jared Forth.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Copying files and directory trees.
+
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::cmp;
+use crate::error::{FsError, IoResultExt, Result};
+use crate::glob::Pattern;
+use crate::hash::{self, Sha256};
+use crate::link::{Ancestors, FollowSymlinks};
+use crate::meta::FileKind;
+use crate::op::{confirm_each, Op};
+use crate::overwrite::OverwritePolicy;
+
+/// Copies a file from `src` to `dst`
+/// and returns the number of bytes copied.
+///
+/// If `dst` is an existing directory, the file is copied into it under its
+/// own name, like the Bash `cp` command.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("cp_source.txt", "Hello, world!");
+/// fsutils::mkdir("cp_directory");
+///
+/// assert_eq!(fsutils::cp("cp_source.txt", "cp_copy.txt"), Some(13));
+/// assert_eq!(fsutils::cp("cp_source.txt", "cp_directory"), Some(13));
+/// assert_eq!(fsutils::read_file("cp_directory/cp_source.txt"), "Hello, world!");
+///
+/// # // Cleanup
+/// # fsutils::rm("cp_source.txt");
+/// # fsutils::rm("cp_copy.txt");
+/// # fsutils::rm_r("cp_directory");
+/// ```
+pub fn cp<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dst: Q) -> Option<u64> {
+    match try_cp(src, dst) {
+        Ok(bytes) => Some(bytes),
+        Err(e) => {
+            error!("File copying error: {}", e);
+            None
+        }
+    }
+}
+
+/// Copies a file from `src` to `dst` and returns the number of bytes copied.
+///
+/// Fails if `src` is a directory, or if `src` and `dst` are the same file.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("try_cp_source.txt", "Hello");
+///
+/// assert_eq!(fsutils::try_cp("try_cp_source.txt", "try_cp_copy.txt").unwrap(), 5);
+/// assert!(fsutils::try_cp("try_cp_source.txt", "try_cp_source.txt").is_err());
+/// assert!(fsutils::try_cp("src", "try_cp_directory_copy").is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm("try_cp_source.txt");
+/// # fsutils::rm("try_cp_copy.txt");
+/// ```
+pub fn try_cp<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dst: Q) -> Result<u64> {
+    let mut opts = CopyOptions::new();
+    opts.overwrite(OverwritePolicy::Overwrite);
+    Ok(cp_with(src, dst, &opts)?.unwrap_or(0))
+}
+
+/// Copies a file from `src` to `dst` as a copy-on-write clone where the
+/// filesystem supports it, and returns the number of bytes in the file.
+///
+/// Clones share their data blocks with the original until either is modified,
+/// so even very large files copy instantly. This uses `FICLONE` on Linux (btrfs,
+/// XFS) and `clonefile` on macOS (APFS), and falls back to a regular copy on
+/// other filesystems and platforms.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("cp_reflink_source.txt", "Hello, world!");
+///
+/// assert_eq!(fsutils::cp_reflink("cp_reflink_source.txt", "cp_reflink_copy.txt"), Some(13));
+/// assert_eq!(fsutils::read_file("cp_reflink_copy.txt"), "Hello, world!");
+///
+/// # // Cleanup
+/// # fsutils::rm("cp_reflink_source.txt");
+/// # fsutils::rm("cp_reflink_copy.txt");
+/// ```
+pub fn cp_reflink<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dst: Q) -> Option<u64> {
+    match try_cp_reflink(src, dst) {
+        Ok(bytes) => Some(bytes),
+        Err(e) => {
+            error!("File cloning error: {}", e);
+            None
+        }
+    }
+}
+
+/// Copies a file from `src` to `dst` as a copy-on-write clone where the
+/// filesystem supports it, and returns the number of bytes in the file.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("try_cp_reflink_source.txt", "Hello");
+///
+/// assert_eq!(fsutils::try_cp_reflink("try_cp_reflink_source.txt", "try_cp_reflink_copy.txt").unwrap(), 5);
+/// assert!(fsutils::try_cp_reflink("src", "try_cp_reflink_directory_copy").is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm("try_cp_reflink_source.txt");
+/// # fsutils::rm("try_cp_reflink_copy.txt");
+/// ```
+pub fn try_cp_reflink<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dst: Q) -> Result<u64> {
+    let mut opts = CopyOptions::new();
+    opts.overwrite(OverwritePolicy::Overwrite).reflink(true);
+    Ok(cp_with(src, dst, &opts)?.unwrap_or(0))
+}
+
+/// Copies a file from `src` to `dst`, continuing a previous copy that was
+/// interrupted, and returns the number of bytes copied by this call.
+///
+/// See `fsutils::try_cp_resume`.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("cp_resume_source.txt", "Hello, world!");
+/// fsutils::write_file("cp_resume_copy.txt", "Hello");
+///
+/// assert_eq!(fsutils::cp_resume("cp_resume_source.txt", "cp_resume_copy.txt"), Some(8));
+/// assert_eq!(fsutils::read_file("cp_resume_copy.txt"), "Hello, world!");
+///
+/// # // Cleanup
+/// # fsutils::rm("cp_resume_source.txt");
+/// # fsutils::rm("cp_resume_copy.txt");
+/// ```
+pub fn cp_resume<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dst: Q) -> Option<u64> {
+    match try_cp_resume(src, dst) {
+        Ok(bytes) => Some(bytes),
+        Err(e) => {
+            error!("File copying error: {}", e);
+            None
+        }
+    }
+}
+
+/// Copies a file from `src` to `dst`, continuing a previous copy that was
+/// interrupted, and returns the number of bytes copied by this call.
+///
+/// While copying, progress is recorded every few megabytes in a state file
+/// named after the destination with `.cp_resume` appended. It holds the
+/// source's size and modification time and a SHA-256 digest of the bytes
+/// written so far, and is removed once the copy completes. A later call
+/// continues from the last recorded point if the source is unchanged and the
+/// destination still starts with those bytes. Without a state file, an
+/// existing destination counts as a partial copy only if all of it matches
+/// the start of the source, so a complete copy copies nothing.
+///
+/// Any other existing destination fails with an error of kind
+/// `io::ErrorKind::AlreadyExists` rather than being copied over, and the
+/// confirm hook set with `fsutils::set_confirm` is asked before an existing
+/// destination is written to.
+///
+/// ## Usage:
+///
+/// ```
+/// use std::io::ErrorKind;
+///
+/// fsutils::write_file("try_cp_resume_source.txt", "Hello, world!");
+/// fsutils::write_file("try_cp_resume_copy.txt", "Hello");
+///
+/// assert_eq!(fsutils::try_cp_resume("try_cp_resume_source.txt", "try_cp_resume_copy.txt").unwrap(), 8);
+/// assert_eq!(fsutils::try_cp_resume("try_cp_resume_source.txt", "try_cp_resume_copy.txt").unwrap(), 0);
+///
+/// // Same length, but not a copy of the source, so it is left alone
+/// fsutils::write_file("try_cp_resume_copy.txt", "Hello, World!");
+/// let e = fsutils::try_cp_resume("try_cp_resume_source.txt", "try_cp_resume_copy.txt").unwrap_err();
+/// assert_eq!(e.kind(), ErrorKind::AlreadyExists);
+/// assert_eq!(fsutils::read_file("try_cp_resume_copy.txt"), "Hello, World!");
+///
+/// # // Cleanup
+/// # fsutils::rm("try_cp_resume_source.txt");
+/// # fsutils::rm("try_cp_resume_copy.txt");
+/// ```
+pub fn try_cp_resume<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dst: Q) -> Result<u64> {
+    let src = src.as_ref();
+    let dst = destination(src, dst.as_ref())?;
+    let meta = src.metadata().at(src)?;
+    if meta.is_dir() {
+        let source = io::Error::new(io::ErrorKind::IsADirectory, "use cp_r to copy directories");
+        return Err(FsError::from_io(src, source));
+    }
+    if is_same(src, &dst) {
+        return Err(FsError::invalid_path(&dst, "source and destination are the same file"));
+    }
+    let state = resume_state_path(&dst);
+    let existing = dst.symlink_metadata().is_ok();
+    let (offset, digest) = match resume_point(src, &meta, &dst, &state)? {
+        Some(point) => point,
+        None => {
+            let reason = "destination is not a partial copy of the source";
+            let source = io::Error::new(io::ErrorKind::AlreadyExists, reason);
+            return Err(FsError::from_io(&dst, source));
+        }
+    };
+    let remaining = meta.len() - offset;
+    Op::new("cp_resume", src).to(&dst).bytes(remaining).destructive(existing).run(remaining, || {
+        let mut from = fs::File::open(src).at(src)?;
+        let mut to = fs::OpenOptions::new().write(true).create(true).truncate(offset == 0).open(&dst).at(&dst)?;
+        from.seek(SeekFrom::Start(offset)).at(src)?;
+        to.set_len(offset).and_then(|_| to.seek(SeekFrom::Start(offset))).at(&dst)?;
+        let mut from = from.take(remaining);
+        let mut progress = ResumeState { size: meta.len(), modified: modified_nanos(&meta), copied: offset, digest };
+        let mut buf = vec![0; PROGRESS_CHUNK];
+        let mut unrecorded = 0;
+        loop {
+            let n = cmp::fill(&mut from, &mut buf).at(src)?;
+            if n == 0 {
+                break;
+            }
+            to.write_all(&buf[..n]).at(&dst)?;
+            progress.digest.update(&buf[..n]);
+            progress.copied += n as u64;
+            unrecorded += n as u64;
+            if unrecorded >= RESUME_CHECKPOINT && progress.modified.is_some() {
+                // The data must be on disk before the state file says it is
+                to.sync_data().at(&dst)?;
+                fs::write(&state, progress.to_string()).at(&state)?;
+                unrecorded = 0;
+            }
+        }
+        to.set_permissions(meta.permissions()).at(&dst)?;
+        match fs::remove_file(&state) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(FsError::from_io(&state, e)),
+            _ => {}
+        }
+        if offset > 0 {
+            info!("Resumed copying {} to {} at byte {}", src.display(), dst.display(), offset);
+        } else {
+            info!("Copied {} to {}", src.display(), dst.display());
+        }
+        Ok(progress.copied - offset)
+    })
+}
+
+/// How many bytes `fsutils::try_cp_resume` copies between updates of its state
+/// file.
+const RESUME_CHECKPOINT: u64 = 8 * 1024 * 1024;
+
+/// How far a copy made by `fsutils::try_cp_resume` got, as kept in its state
+/// file.
+struct ResumeState<D> {
+    size: u64,
+    modified: Option<u128>,
+    copied: u64,
+    digest: D,
+}
+
+impl fmt::Display for ResumeState<Sha256> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "size {}", self.size)?;
+        if let Some(modified) = self.modified {
+            writeln!(f, "modified {}", modified)?;
+        }
+        writeln!(f, "copied {}", self.copied)?;
+        writeln!(f, "sha256 {}", hash::hex(&self.digest.clone().finish()))
+    }
+}
+
+impl ResumeState<String> {
+    /// Reads a state file, returning `None` if it is missing or malformed.
+    fn read(path: &Path) -> Option<ResumeState<String>> {
+        let text = fs::read_to_string(path).ok()?;
+        let mut state = ResumeState { size: 0, modified: None, copied: 0, digest: String::new() };
+        for line in text.lines() {
+            let (key, value) = line.split_once(' ')?;
+            match key {
+                "size" => state.size = value.parse().ok()?,
+                "modified" => state.modified = Some(value.parse().ok()?),
+                "copied" => state.copied = value.parse().ok()?,
+                "sha256" => state.digest = value.to_string(),
+                _ => return None,
+            }
+        }
+        Some(state)
+    }
+}
+
+/// The state file `fsutils::try_cp_resume` keeps next to `dst`.
+fn resume_state_path(dst: &Path) -> PathBuf {
+    let mut name = dst.as_os_str().to_owned();
+    name.push(".cp_resume");
+    PathBuf::from(name)
+}
+
+/// A file's modification time in nanoseconds since the Unix epoch.
+fn modified_nanos(meta: &fs::Metadata) -> Option<u128> {
+    let modified = meta.modified().ok()?;
+    modified.duration_since(std::time::UNIX_EPOCH).ok().map(|d| d.as_nanos())
+}
+
+/// Works out how much of `src` an earlier copy already wrote to `dst`, and the
+/// digest of those bytes, or returns `None` if `dst` is not a partial copy of
+/// `src`.
+fn resume_point(src: &Path, meta: &fs::Metadata, dst: &Path, state: &Path) -> Result<Option<(u64, Sha256)>> {
+    let existing = match dst.metadata() {
+        Ok(existing) if existing.is_file() => existing.len(),
+        Ok(_) => return Ok(None),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Some((0, Sha256::new()))),
+        Err(e) => return Err(FsError::from_io(dst, e)),
+    };
+    if let Some(recorded) = ResumeState::read(state) {
+        let unchanged = recorded.size == meta.len() && recorded.modified == modified_nanos(meta);
+        if unchanged && recorded.modified.is_some() && recorded.copied <= existing.min(meta.len()) {
+            let digest = prefix_digest(dst, recorded.copied)?;
+            if hash::hex(&digest.clone().finish()) == recorded.digest {
+                return Ok(Some((recorded.copied, digest)));
+            }
+        }
+    }
+    if existing > meta.len() {
+        return Ok(None);
+    }
+    Ok(matching_prefix(src, dst, existing)?.map(|digest| (existing, digest)))
+}
+
+/// Digests the first `len` bytes of the file at `path`.
+fn prefix_digest(path: &Path, len: u64) -> Result<Sha256> {
+    let mut file = fs::File::open(path).at(path)?.take(len);
+    let mut digest = Sha256::new();
+    let mut buf = vec![0; PROGRESS_CHUNK];
+    loop {
+        let n = cmp::fill(&mut file, &mut buf).at(path)?;
+        if n == 0 {
+            return Ok(digest);
+        }
+        digest.update(&buf[..n]);
+    }
+}
+
+/// Checks that the first `len` bytes of `src` and `dst` are the same, and
+/// returns their digest if they are.
+fn matching_prefix(src: &Path, dst: &Path, len: u64) -> Result<Option<Sha256>> {
+    let mut a = fs::File::open(src).at(src)?.take(len);
+    let mut b = fs::File::open(dst).at(dst)?.take(len);
+    let mut digest = Sha256::new();
+    let (mut x, mut y) = (vec![0; PROGRESS_CHUNK], vec![0; PROGRESS_CHUNK]);
+    loop {
+        let n = cmp::fill(&mut a, &mut x).at(src)?;
+        let m = cmp::fill(&mut b, &mut y).at(dst)?;
+        if x[..n] != y[..m] {
+            return Ok(None);
+        }
+        if n == 0 {
+            return Ok(Some(digest));
+        }
+        digest.update(&x[..n]);
+    }
+}
+
+/// Copies a file from `src` to `dst` according to `opts`
+/// and returns the number of bytes copied, or `None` if the overwrite policy
+/// skipped the copy.
+///
+/// Only the overwrite, metadata, reflink, progress, verify and sparse options
+/// apply to single files.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::CopyOptions;
+///
+/// fsutils::write_file("cp_with_source.txt", "new");
+/// fsutils::write_file("cp_with_copy.txt", "old");
+///
+/// assert!(fsutils::cp_with("cp_with_source.txt", "cp_with_copy.txt", &CopyOptions::new()).is_err());
+/// assert_eq!(fsutils::cp_with("cp_with_source.txt", "cp_with_copy.txt", CopyOptions::new().skip_existing(true)).unwrap(), None);
+/// assert_eq!(fsutils::read_file("cp_with_copy.txt"), "old");
+///
+/// # // Cleanup
+/// # fsutils::rm("cp_with_source.txt");
+/// # fsutils::rm("cp_with_copy.txt");
+/// ```
+pub fn cp_with<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dst: Q, opts: &CopyOptions) -> Result<Option<u64>> {
+    let src = src.as_ref();
+    let dst = destination(src, dst.as_ref())?;
+    if !opts.follow.follows(true) && src.symlink_metadata().at(src)?.file_type().is_symlink() {
+        return copy_root_link("cp", src, &dst, opts);
+    }
+    let meta = src.metadata().at(src)?;
+    if meta.is_dir() {
+        let source = io::Error::new(io::ErrorKind::IsADirectory, "use cp_r to copy directories");
+        return Err(FsError::from_io(src, source));
+    }
+    if is_same(src, &dst) {
+        return Err(FsError::invalid_path(&dst, "source and destination are the same file"));
+    }
+    let dst = match target(&meta, &dst, opts)? {
+        Some(dst) => dst,
+        None => return Ok(None),
+    };
+    opts.overwrite.back_up(&dst)?;
+    let op = Op::new("cp", src).to(&dst).bytes(meta.len());
+    op.destructive(dst.symlink_metadata().is_ok()).run(Some(meta.len()), || {
+        let bytes = copy_file(src, &dst, &meta, opts, &Progress::new(meta.len()))?;
+        info!("Copied {} to {}", src.display(), dst.display());
+        Ok(Some(bytes))
+    })
+}
+
+/// Options for `fsutils::cp_r_with`.
+///
+/// By default existing destination files are an error, symlinks below the
+/// source are copied as symlinks and the whole tree is copied.
+///
+/// Include and exclude patterns use shell wildcards (`*`, `?`, `**`, `[...]`).
+/// A pattern without a `/` is matched against the entry's file name, anything
+/// else against its path relative to the source directory. Excludes apply to
+/// files and directories, includes only to files.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::CopyOptions;
+///
+/// fsutils::mkdir("cp_r_opts/src/deep/deeper");
+/// fsutils::write_file("cp_r_opts/src/main.rs", "fn main() {}");
+/// fsutils::write_file("cp_r_opts/src/main.o", "");
+/// fsutils::write_file("cp_r_opts/src/deep/lib.rs", "");
+/// fsutils::write_file("cp_r_opts/src/deep/deeper/mod.rs", "");
+///
+/// let mut opts = CopyOptions::new();
+/// opts.exclude("*.o").unwrap().max_depth(2);
+/// fsutils::cp_r_with("cp_r_opts/src", "cp_r_opts/dst", &opts).unwrap();
+///
+/// assert!(fsutils::path_exists("cp_r_opts/dst/main.rs"));
+/// assert!(fsutils::path_exists("cp_r_opts/dst/deep/lib.rs"));
+/// assert!(!fsutils::path_exists("cp_r_opts/dst/main.o"));
+/// assert!(!fsutils::path_exists("cp_r_opts/dst/deep/deeper/mod.rs"));
+///
+/// # // Cleanup
+/// # fsutils::rm_r("cp_r_opts");
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct CopyOptions {
+    overwrite: OverwritePolicy,
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+    follow: FollowSymlinks,
+    max_depth: Option<usize>,
+    preserve: bool,
+    preserve_ownership: bool,
+    reflink: bool,
+    progress: Option<ProgressFn>,
+    verify: bool,
+    remove_unverified: bool,
+    sparse: bool,
+    update: bool,
+    rate_limit: Option<u64>,
+    threads: Option<usize>,
+}
+
+/// A shareable progress callback, wrapped so `CopyOptions` can stay `Debug`.
+#[derive(Clone)]
+struct ProgressFn(Arc<dyn Fn(u64, u64) + Send + Sync>);
+
+impl fmt::Debug for ProgressFn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ProgressFn(..)")
+    }
+}
+
+impl CopyOptions {
+    /// Creates the default options.
+    pub fn new() -> CopyOptions {
+        CopyOptions::default()
+    }
+
+    /// What to do with destination files that already exist.
+    pub fn overwrite(&mut self, policy: OverwritePolicy) -> &mut CopyOptions {
+        self.overwrite = policy;
+        self
+    }
+
+    /// Shorthand for `overwrite(OverwritePolicy::Skip)` or back to the default.
+    ///
+    /// This is the no-clobber mode of `cp -n`.
+    pub fn skip_existing(&mut self, skip: bool) -> &mut CopyOptions {
+        self.overwrite = if skip { OverwritePolicy::Skip } else { OverwritePolicy::Error };
+        self
+    }
+
+    /// Only copy files that are newer than the destination, or missing from it,
+    /// like `cp -u`.
+    ///
+    /// Older destination files are replaced whatever the overwrite policy, and
+    /// newer or equally old ones are skipped.
+    ///
+    /// ## Usage:
+    ///
+    /// ```
+    /// use std::fs::{File, FileTimes};
+    /// use std::time::{Duration, SystemTime};
+    /// use fsutils::CopyOptions;
+    ///
+    /// fsutils::write_file("update_source.txt", "new");
+    /// fsutils::write_file("update_copy.txt", "newer");
+    /// let mut opts = CopyOptions::new();
+    /// opts.update(true);
+    ///
+    /// let last_year = SystemTime::now() - Duration::from_secs(365 * 24 * 60 * 60);
+    /// File::options().write(true).open("update_source.txt").unwrap()
+    ///     .set_times(FileTimes::new().set_modified(last_year)).unwrap();
+    /// assert_eq!(fsutils::cp_with("update_source.txt", "update_copy.txt", &opts).unwrap(), None);
+    ///
+    /// File::options().write(true).open("update_copy.txt").unwrap()
+    ///     .set_times(FileTimes::new().set_modified(last_year - Duration::from_secs(60))).unwrap();
+    /// assert_eq!(fsutils::cp_with("update_source.txt", "update_copy.txt", &opts).unwrap(), Some(3));
+    ///
+    /// # // Cleanup
+    /// # fsutils::rm("update_source.txt");
+    /// # fsutils::rm("update_copy.txt");
+    /// ```
+    pub fn update(&mut self, update: bool) -> &mut CopyOptions {
+        self.update = update;
+        self
+    }
+
+    /// Only copy files matching `pattern`. May be given several times.
+    pub fn include(&mut self, pattern: &str) -> Result<&mut CopyOptions> {
+        self.include.push(Pattern::new(pattern)?);
+        Ok(self)
+    }
+
+    /// Skip files and directories matching `pattern`. May be given several times.
+    pub fn exclude(&mut self, pattern: &str) -> Result<&mut CopyOptions> {
+        self.exclude.push(Pattern::new(pattern)?);
+        Ok(self)
+    }
+
+    /// Copy what symlinks point to instead of recreating the links.
+    ///
+    /// Shorthand for `follow(FollowSymlinks::Always)` or back to the default.
+    pub fn follow_symlinks(&mut self, follow: bool) -> &mut CopyOptions {
+        self.follow = if follow { FollowSymlinks::Always } else { FollowSymlinks::CommandLineOnly };
+        self
+    }
+
+    /// Which symlinks to copy what they point to for, rather than recreating
+    /// the links. By default only a source that is itself a link is followed.
+    ///
+    /// With `FollowSymlinks::Never`, even a source that is a link is copied
+    /// as a link, to a file or directory alike.
+    ///
+    /// ## Usage:
+    ///
+    /// ```
+    /// use fsutils::{CopyOptions, FollowSymlinks};
+    ///
+    /// fsutils::mkdir("copy_follow/data");
+    /// fsutils::write_file("copy_follow/data/file.txt", "text");
+    ///
+    /// if fsutils::try_ln_s("data", "copy_follow/latest").is_ok() {
+    ///     fsutils::try_ln_s("file.txt", "copy_follow/data/alias.txt").unwrap();
+    ///     let mut opts = CopyOptions::new();
+    ///     opts.follow(FollowSymlinks::Always);
+    ///     fsutils::cp_r_with("copy_follow/latest", "copy_follow/deref", &opts).unwrap();
+    ///     assert_eq!(fsutils::readlink("copy_follow/deref/alias.txt"), None);
+    ///
+    ///     opts.follow(FollowSymlinks::Never);
+    ///     assert_eq!(fsutils::cp_r_with("copy_follow/latest", "copy_follow/link", &opts).unwrap(), 0);
+    ///     assert_eq!(fsutils::readlink("copy_follow/link").unwrap().to_str(), Some("data"));
+    /// }
+    ///
+    /// # // Cleanup
+    /// # fsutils::rm_r("copy_follow");
+    /// ```
+    pub fn follow(&mut self, follow: FollowSymlinks) -> &mut CopyOptions {
+        self.follow = follow;
+        self
+    }
+
+    /// Only descend `depth` levels; `1` copies just the direct children of the source.
+    pub fn max_depth(&mut self, depth: usize) -> &mut CopyOptions {
+        self.max_depth = Some(depth);
+        self
+    }
+
+    /// Keep permissions and access/modification times, like `cp -p`.
+    ///
+    /// ## Usage:
+    ///
+    /// ```
+    /// use std::fs::{File, FileTimes};
+    /// use std::time::{Duration, SystemTime};
+    /// use fsutils::CopyOptions;
+    ///
+    /// fsutils::write_file("preserve_source.txt", "old news");
+    /// let last_year = SystemTime::now() - Duration::from_secs(365 * 24 * 60 * 60);
+    /// File::options().write(true).open("preserve_source.txt").unwrap()
+    ///     .set_times(FileTimes::new().set_modified(last_year)).unwrap();
+    ///
+    /// fsutils::cp_with("preserve_source.txt", "preserve_copy.txt", CopyOptions::new().preserve(true)).unwrap();
+    ///
+    /// let modified = |p| std::fs::metadata(p).unwrap().modified().unwrap();
+    /// assert_eq!(modified("preserve_copy.txt"), modified("preserve_source.txt"));
+    ///
+    /// # // Cleanup
+    /// # fsutils::rm("preserve_source.txt");
+    /// # fsutils::rm("preserve_copy.txt");
+    /// ```
+    ///
+    /// Read-only files keep their times too:
+    ///
+    /// ```
+    /// # #[cfg(unix)]
+    /// # {
+    /// use std::os::unix::fs::PermissionsExt;
+    /// use fsutils::CopyOptions;
+    ///
+    /// fsutils::write_file("preserve_read_only.txt", "fixed");
+    /// fsutils::chmod("preserve_read_only.txt", 0o444);
+    ///
+    /// let mut opts = CopyOptions::new();
+    /// opts.preserve(true);
+    /// fsutils::cp_with("preserve_read_only.txt", "preserve_read_only_copy.txt", &opts).unwrap();
+    ///
+    /// let meta = std::fs::metadata("preserve_read_only_copy.txt").unwrap();
+    /// assert_eq!(meta.permissions().mode() & 0o777, 0o444);
+    /// assert_eq!(meta.modified().unwrap(), std::fs::metadata("preserve_read_only.txt").unwrap().modified().unwrap());
+    ///
+    /// # // Cleanup
+    /// # fsutils::rm("preserve_read_only.txt");
+    /// # fsutils::rm("preserve_read_only_copy.txt");
+    /// # }
+    /// ```
+    pub fn preserve(&mut self, preserve: bool) -> &mut CopyOptions {
+        self.preserve = preserve;
+        self
+    }
+
+    /// Also keep the owning user and group, which usually requires root.
+    ///
+    /// Ignored on platforms without unix ownership.
+    pub fn preserve_ownership(&mut self, preserve: bool) -> &mut CopyOptions {
+        self.preserve_ownership = preserve;
+        self
+    }
+
+    /// Clone files copy-on-write where the filesystem supports it, like `cp --reflink=auto`.
+    ///
+    /// See `fsutils::cp_reflink`.
+    pub fn reflink(&mut self, reflink: bool) -> &mut CopyOptions {
+        self.reflink = reflink;
+        self
+    }
+
+    /// Calls `f` with `(bytes_copied, total_bytes)` as data is copied.
+    ///
+    /// Files are then streamed in 64 KiB chunks, with a call
+    /// after each one. For `cp_r_with` both counts cover the whole tree, so the
+    /// source is walked once up front to add up its size.
+    ///
+    /// ## Usage:
+    ///
+    /// ```
+    /// use std::sync::{Arc, Mutex};
+    /// use fsutils::CopyOptions;
+    ///
+    /// fsutils::write_file("progress_source.txt", &"x".repeat(200_000));
+    ///
+    /// let calls = Arc::new(Mutex::new(Vec::new()));
+    /// let log = Arc::clone(&calls);
+    /// let mut opts = CopyOptions::new();
+    /// opts.progress(move |copied, total| log.lock().unwrap().push((copied, total)));
+    /// fsutils::cp_with("progress_source.txt", "progress_copy.txt", &opts).unwrap();
+    ///
+    /// let calls = calls.lock().unwrap();
+    /// assert!(calls.len() > 1);
+    /// assert_eq!(calls.last(), Some(&(200_000, 200_000)));
+    ///
+    /// # // Cleanup
+    /// # fsutils::rm("progress_source.txt");
+    /// # fsutils::rm("progress_copy.txt");
+    /// ```
+    pub fn progress<F>(&mut self, f: F) -> &mut CopyOptions
+    where
+        F: Fn(u64, u64) + Send + Sync + 'static,
+    {
+        self.progress = Some(ProgressFn(Arc::new(f)));
+        self
+    }
+
+    /// Hash each file while copying it and check the copy against that hash
+    /// afterwards, failing with `std::io::ErrorKind::InvalidData` on a mismatch.
+    ///
+    /// The copy is flushed to disk before it is read back, so this catches
+    /// corruption on flaky network mounts and removable media.
+    ///
+    /// ## Usage:
+    ///
+    /// ```
+    /// use fsutils::CopyOptions;
+    ///
+    /// fsutils::write_file("verify_source.txt", "checked");
+    /// let mut opts = CopyOptions::new();
+    /// opts.verify(true).remove_unverified(true);
+    ///
+    /// assert_eq!(fsutils::cp_with("verify_source.txt", "verify_copy.txt", &opts).unwrap(), Some(7));
+    ///
+    /// # // Cleanup
+    /// # fsutils::rm("verify_source.txt");
+    /// # fsutils::rm("verify_copy.txt");
+    /// ```
+    pub fn verify(&mut self, verify: bool) -> &mut CopyOptions {
+        self.verify = verify;
+        self
+    }
+
+    /// With `verify`, delete a copy that fails verification instead of leaving it behind.
+    pub fn remove_unverified(&mut self, remove: bool) -> &mut CopyOptions {
+        self.remove_unverified = remove;
+        self
+    }
+
+    /// Skip the holes in sparse files, so disk images and database files are not
+    /// inflated to their apparent size.
+    ///
+    /// Uses `SEEK_DATA` and `SEEK_HOLE` on Linux, macOS and FreeBSD. Elsewhere,
+    /// or on filesystems without hole reporting, files are copied normally.
+    ///
+    /// ## Usage:
+    ///
+    /// ```
+    /// use std::io::{Seek, SeekFrom, Write};
+    /// use fsutils::CopyOptions;
+    ///
+    /// // One byte of data after a 16 MiB hole
+    /// let mut image = std::fs::File::create("sparse_source.img").unwrap();
+    /// image.seek(SeekFrom::Start(16 << 20)).unwrap();
+    /// image.write_all(b"x").unwrap();
+    ///
+    /// fsutils::cp_with("sparse_source.img", "sparse_copy.img", CopyOptions::new().sparse(true)).unwrap();
+    /// assert_eq!(std::fs::read("sparse_copy.img").unwrap(), std::fs::read("sparse_source.img").unwrap());
+    ///
+    /// # // Cleanup
+    /// # fsutils::rm("sparse_source.img");
+    /// # fsutils::rm("sparse_copy.img");
+    /// ```
+    pub fn sparse(&mut self, sparse: bool) -> &mut CopyOptions {
+        self.sparse = sparse;
+        self
+    }
+
+    /// Copy at most `bytes_per_sec` bytes per second, averaged over the whole
+    /// operation, so background jobs do not saturate a disk or network mount.
+    ///
+    /// ## Usage:
+    ///
+    /// ```
+    /// use std::time::{Duration, Instant};
+    /// use fsutils::CopyOptions;
+    ///
+    /// fsutils::write_file("throttled_source.txt", &"x".repeat(50_000));
+    ///
+    /// let start = Instant::now();
+    /// fsutils::cp_with("throttled_source.txt", "throttled_copy.txt", CopyOptions::new().rate_limit(100_000)).unwrap();
+    /// assert!(start.elapsed() >= Duration::from_millis(400));
+    ///
+    /// # // Cleanup
+    /// # fsutils::rm("throttled_source.txt");
+    /// # fsutils::rm("throttled_copy.txt");
+    /// ```
+    pub fn rate_limit(&mut self, bytes_per_sec: u64) -> &mut CopyOptions {
+        self.rate_limit = Some(bytes_per_sec.max(1));
+        self
+    }
+
+    /// Copy files on `threads` threads in `cp_r_with`, or one per CPU for `0`.
+    ///
+    /// Speeds up trees of many small files, where a single thread spends most
+    /// of its time waiting on system calls. With several threads, the progress
+    /// callback may be called from any of them.
+    ///
+    /// ## Usage:
+    ///
+    /// ```
+    /// use fsutils::CopyOptions;
+    ///
+    /// fsutils::mkdir("threads_source");
+    /// for i in 0..100 {
+    ///     fsutils::write_file(format!("threads_source/{}.txt", i), "data");
+    /// }
+    ///
+    /// assert_eq!(fsutils::cp_r_with("threads_source", "threads_copy", CopyOptions::new().threads(4)).unwrap(), 400);
+    /// assert_eq!(fsutils::read_file("threads_copy/99.txt"), "data");
+    ///
+    /// # // Cleanup
+    /// # fsutils::rm_r("threads_source");
+    /// # fsutils::rm_r("threads_copy");
+    /// ```
+    pub fn threads(&mut self, threads: usize) -> &mut CopyOptions {
+        self.threads = Some(threads);
+        self
+    }
+
+    fn excluded(&self, relative: &Path) -> bool {
+        self.exclude.iter().any(|p| p.matches_path(relative))
+    }
+
+    fn included(&self, relative: &Path) -> bool {
+        self.include.is_empty() || self.include.iter().any(|p| p.matches_path(relative))
+    }
+}
+
+/// Copies a directory tree from `src` to `dst`
+/// and returns the number of bytes copied.
+///
+/// If `dst` is an existing directory, the tree is copied into it under its own
+/// name, like `cp -r`. Existing destination files are not overwritten.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::mkdir("cp_r_source/nested");
+/// fsutils::write_file("cp_r_source/nested/file.txt", "Hello");
+///
+/// assert_eq!(fsutils::cp_r("cp_r_source", "cp_r_copy"), Some(5));
+/// assert_eq!(fsutils::read_file("cp_r_copy/nested/file.txt"), "Hello");
+///
+/// # // Cleanup
+/// # fsutils::rm_r("cp_r_source");
+/// # fsutils::rm_r("cp_r_copy");
+/// ```
+pub fn cp_r<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dst: Q) -> Option<u64> {
+    match try_cp_r(src, dst) {
+        Ok(bytes) => Some(bytes),
+        Err(e) => {
+            error!("Directory copying error: {}", e);
+            None
+        }
+    }
+}
+
+/// Copies a directory tree from `src` to `dst` with the default `CopyOptions`
+/// and returns the number of bytes copied.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::mkdir("try_cp_r_source");
+/// fsutils::write_file("try_cp_r_source/file.txt", "Hello");
+///
+/// assert_eq!(fsutils::try_cp_r("try_cp_r_source", "try_cp_r_copy").unwrap(), 5);
+/// assert!(fsutils::try_cp_r("try_cp_r_source", "try_cp_r_source/inside").is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm_r("try_cp_r_source");
+/// # fsutils::rm_r("try_cp_r_copy");
+/// ```
+pub fn try_cp_r<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dst: Q) -> Result<u64> {
+    cp_r_with(src, dst, &CopyOptions::new())
+}
+
+/// Copies a directory tree from `src` to `dst` according to `opts`
+/// and returns the number of bytes copied.
+pub fn cp_r_with<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dst: Q, opts: &CopyOptions) -> Result<u64> {
+    let src = src.as_ref();
+    let dst = destination(src, dst.as_ref())?;
+    if !opts.follow.follows(true) && src.symlink_metadata().at(src)?.file_type().is_symlink() {
+        return copy_root_link("cp_r", src, &dst, opts).map(|_| 0);
+    }
+    if !src.metadata().at(src)?.is_dir() {
+        let source = io::Error::new(io::ErrorKind::NotADirectory, "use cp to copy files");
+        return Err(FsError::from_io(src, source));
+    }
+    if is_inside(src, &dst) {
+        return Err(FsError::invalid_path(&dst, "cannot copy a directory into itself"));
+    }
+    Op::new("cp_r", src).to(&dst).run(0, || {
+        let bytes = copy_tree(src, &dst, opts)?;
+        info!("Copied {} to {}", src.display(), dst.display());
+        Ok(bytes)
+    })
+}
+
+/// Recreates the directories of the tree at `src_root` under `dst_root`,
+/// without any files, and returns the number of directories created, or
+/// `None` if it fails.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::mkdir("mirror_dirs_src/raw/2020");
+/// fsutils::write_file("mirror_dirs_src/raw/2020/img.png", "");
+///
+/// assert_eq!(fsutils::mirror_dirs("mirror_dirs_src", "mirror_dirs_dst"), Some(3));
+/// assert!(fsutils::path_exists("mirror_dirs_dst/raw/2020"));
+/// assert!(!fsutils::path_exists("mirror_dirs_dst/raw/2020/img.png"));
+///
+/// # // Cleanup
+/// # fsutils::rm_r("mirror_dirs_src");
+/// # fsutils::rm_r("mirror_dirs_dst");
+/// ```
+pub fn mirror_dirs<P: AsRef<Path>, Q: AsRef<Path>>(src_root: P, dst_root: Q) -> Option<usize> {
+    match try_mirror_dirs(src_root, dst_root) {
+        Ok(created) => Some(created),
+        Err(e) => {
+            error!("Cannot mirror directories {}", e);
+            None
+        }
+    }
+}
+
+/// Recreates the directories of the tree at `src_root` under `dst_root`,
+/// without any files, and returns the number of directories created.
+///
+/// `dst_root` itself stands for `src_root`, so `src_root/a/b` becomes
+/// `dst_root/a/b`; it is created if needed and counted if it is. Directories
+/// that already exist are left as they are, and new ones get the default
+/// permissions. Symbolic links to directories are not followed. The whole
+/// tree is read before anything is created, so `dst_root` may be inside
+/// `src_root`.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::mkdir("try_mirror_dirs_src/a/b");
+/// fsutils::mkdir("try_mirror_dirs_src/c");
+/// fsutils::mkdir("try_mirror_dirs_dst/a");
+///
+/// assert_eq!(fsutils::try_mirror_dirs("try_mirror_dirs_src", "try_mirror_dirs_dst").unwrap(), 2);
+/// assert_eq!(fsutils::try_mirror_dirs("try_mirror_dirs_src", "try_mirror_dirs_dst").unwrap(), 0);
+/// assert!(fsutils::try_mirror_dirs("a_very_1234_unlikely_9876_dirname", "try_mirror_dirs_dst").is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm_r("try_mirror_dirs_src");
+/// # fsutils::rm_r("try_mirror_dirs_dst");
+/// ```
+pub fn try_mirror_dirs<P: AsRef<Path>, Q: AsRef<Path>>(src_root: P, dst_root: Q) -> Result<usize> {
+    let src_root = src_root.as_ref();
+    let dst_root = dst_root.as_ref();
+    if !src_root.metadata().at(src_root)?.is_dir() {
+        return Err(FsError::from_io(src_root, io::ErrorKind::NotADirectory.into()));
+    }
+    let mut dirs = Vec::new();
+    for entry in crate::walk::walk(src_root) {
+        let entry = entry?;
+        if entry.kind == FileKind::Dir {
+            dirs.push(dst_root.join(entry.path.strip_prefix(src_root).unwrap_or(&entry.path)));
+        }
+    }
+    Op::new("mirror_dirs", src_root).to(dst_root).run(0, || {
+        let mut created = 0;
+        for dir in dirs.iter().filter(|dir| !dir.is_dir()) {
+            // Only the parents of `dst_root` can be missing, the rest come in order
+            fs::create_dir_all(dir).at(dir)?;
+            created += 1;
+        }
+        info!("Mirrored the directories of {} to {}", src_root.display(), dst_root.display());
+        Ok(created)
+    })
+}
+
+/// Recreates the symlink `src` as `dst`, for a copy that does not follow it,
+/// and returns the bytes copied, which are none, or `None` if it was skipped.
+fn copy_root_link(name: &'static str, src: &Path, dst: &Path, opts: &CopyOptions) -> Result<Option<u64>> {
+    let dst = match target(&src.symlink_metadata().at(src)?, dst, opts)? {
+        Some(dst) => dst,
+        None => return Ok(None),
+    };
+    opts.overwrite.back_up(&dst)?;
+    Op::new(name, src).to(&dst).destructive(dst.symlink_metadata().is_ok()).run(Some(0), || {
+        copy_symlink(src, &dst)?;
+        info!("Copied link {} to {}", src.display(), dst.display());
+        Ok(Some(0))
+    })
+}
+
+/// Copies the file, directory tree or symlink at `src` to exactly `dst` for a
+/// move between filesystems, keeping metadata and checking file contents.
+///
+/// This is part of a `mv`, so it is not recorded as an operation of its own.
+pub(crate) fn copy_for_move(src: &Path, dst: &Path) -> Result<()> {
+    let mut opts = CopyOptions::new();
+    opts.overwrite(OverwritePolicy::Overwrite).preserve(true).verify(true).remove_unverified(true);
+    let meta = src.symlink_metadata().at(src)?;
+    if meta.file_type().is_symlink() {
+        copy_symlink(src, dst)
+    } else if meta.is_dir() {
+        copy_tree(src, dst, &opts).map(|_| ())
+    } else {
+        copy_file(src, dst, &meta, &opts, &Progress::new(meta.len())).map(|_| ())
+    }
+}
+
+/// Copies the directory `src` to `dst` and returns the number of bytes copied.
+fn copy_tree(src: &Path, dst: &Path, opts: &CopyOptions) -> Result<u64> {
+    let total = match opts.progress {
+        Some(_) => tree_size(src, Path::new(""), opts, &mut Ancestors::new(opts.follow))?,
+        None => 0,
+    };
+    let progress = Progress::new(total);
+    if opts.include.is_empty() {
+        fs::create_dir_all(dst).at(dst)?;
+    }
+    let mut plan = Plan { ancestors: Ancestors::new(opts.follow), ..Plan::default() };
+    plan.ancestors.enter(src)?;
+    plan_dir(src, dst, Path::new(""), opts, &mut plan)?;
+    copy_files(&plan.files, opts, &progress)?;
+    // Directories last, since adding entries bumps their modification time
+    for (meta, dir) in &plan.dirs {
+        preserve_metadata(meta, dir, opts)?;
+    }
+    if opts.preserve && dst.is_dir() {
+        preserve_metadata(&src.metadata().at(src)?, dst, opts)?;
+    }
+    Ok(progress.copied())
+}
+
+/// The file copies and directory metadata left to do once a tree has been walked.
+#[derive(Default)]
+struct Plan {
+    files: Vec<(PathBuf, PathBuf, fs::Metadata)>,
+    dirs: Vec<(fs::Metadata, PathBuf)>,
+    ancestors: Ancestors,
+}
+
+/// Walks `src`, creating the directories and symlinks of the copy in `dst` and
+/// adding the rest to `plan`, where `relative` is the path of `src` below the
+/// root of the copy.
+fn plan_dir(src: &Path, dst: &Path, relative: &Path, opts: &CopyOptions, plan: &mut Plan) -> Result<()> {
+    let depth = relative.components().count() + 1;
+    for entry in fs::read_dir(src).at(src)? {
+        let entry = entry.at(src)?;
+        let from = entry.path();
+        let to = dst.join(entry.file_name());
+        let rel = relative.join(entry.file_name());
+        if opts.excluded(&rel) {
+            continue;
+        }
+
+        let mut file_type = entry.file_type().at(&from)?;
+        if file_type.is_symlink() && opts.follow.follows(false) {
+            file_type = opts.follow.metadata(&from, false).at(&from)?.file_type();
+        }
+
+        if file_type.is_dir() {
+            if opts.max_depth.is_none_or(|max| depth < max) {
+                if !plan.ancestors.enter(&from)? {
+                    // A link back to a directory being copied, kept as a link to end the loop
+                    fs::create_dir_all(dst).at(dst)?;
+                    copy_symlink(&from, &to)?;
+                    continue;
+                }
+                if opts.include.is_empty() {
+                    fs::create_dir_all(&to).at(&to)?;
+                }
+                plan_dir(&from, &to, &rel, opts, plan)?;
+                plan.ancestors.leave();
+                if opts.preserve && to.is_dir() {
+                    plan.dirs.push((from.metadata().at(&from)?, to));
+                }
+            }
+        } else if opts.included(&rel) {
+            let meta = if file_type.is_symlink() { from.symlink_metadata() } else { from.metadata() };
+            let meta = meta.at(&from)?;
+            let to = match target(&meta, &to, opts)? {
+                Some(to) => to,
+                None => continue,
+            };
+            if to.symlink_metadata().is_ok() && !confirm_each("cp_r", &to) {
+                continue;
+            }
+            opts.overwrite.back_up(&to)?;
+            if let Some(parent) = to.parent() {
+                fs::create_dir_all(parent).at(parent)?;
+            }
+            if file_type.is_symlink() {
+                copy_symlink(&from, &to)?;
+            } else {
+                plan.files.push((from, to, meta));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Copies each `(src, dst, meta)` in `files`, on several threads if `opts` asks for it.
+fn copy_files(files: &[(PathBuf, PathBuf, fs::Metadata)], opts: &CopyOptions, progress: &Progress) -> Result<()> {
+    let threads = match opts.threads {
+        None => 1,
+        Some(0) => thread::available_parallelism().map_or(1, |n| n.get()),
+        Some(n) => n,
+    };
+    if threads <= 1 || files.len() <= 1 {
+        for (src, dst, meta) in files {
+            copy_file(src, dst, meta, opts, progress)?;
+        }
+        return Ok(());
+    }
+
+    let next = AtomicUsize::new(0);
+    let failure = Mutex::new(None);
+    thread::scope(|scope| {
+        for _ in 0..threads.min(files.len()) {
+            scope.spawn(|| {
+                while let Some((src, dst, meta)) = files.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    if let Err(e) = copy_file(src, dst, meta, opts, progress) {
+                        failure.lock().unwrap_or_else(|e| e.into_inner()).get_or_insert(e);
+                        // Stop the other workers picking up new files
+                        next.store(files.len(), Ordering::Relaxed);
+                    }
+                }
+            });
+        }
+    });
+    match failure.into_inner().unwrap_or_else(|e| e.into_inner()) {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+/// Adds up the size of the files `plan_dir` would copy from `src`.
+fn tree_size(src: &Path, relative: &Path, opts: &CopyOptions, ancestors: &mut Ancestors) -> Result<u64> {
+    let depth = relative.components().count() + 1;
+    let mut size = 0;
+    for entry in fs::read_dir(src).at(src)? {
+        let entry = entry.at(src)?;
+        let from = entry.path();
+        let rel = relative.join(entry.file_name());
+        if opts.excluded(&rel) {
+            continue;
+        }
+        let mut file_type = entry.file_type().at(&from)?;
+        if file_type.is_symlink() && opts.follow.follows(false) {
+            file_type = opts.follow.metadata(&from, false).at(&from)?.file_type();
+        }
+        if file_type.is_dir() {
+            if opts.max_depth.is_none_or(|max| depth < max) && ancestors.enter(&from)? {
+                size += tree_size(&from, &rel, opts, ancestors)?;
+                ancestors.leave();
+            }
+        } else if !file_type.is_symlink() && opts.included(&rel) {
+            size += from.metadata().at(&from)?.len();
+        }
+    }
+    Ok(size)
+}
+
+/// How many bytes are read and written at a time when reporting progress.
+const PROGRESS_CHUNK: usize = 64 * 1024;
+
+/// Running totals for one copy operation, shared by all of its threads.
+struct Progress {
+    copied: AtomicU64,
+    total: u64,
+    /// Bytes actually read and written, which excludes skipped holes and clones.
+    transferred: AtomicU64,
+    started: Instant,
+}
+
+impl Progress {
+    fn new(total: u64) -> Progress {
+        Progress {
+            copied: AtomicU64::new(0),
+            total,
+            transferred: AtomicU64::new(0),
+            started: Instant::now(),
+        }
+    }
+
+    fn copied(&self) -> u64 {
+        self.copied.load(Ordering::Relaxed)
+    }
+
+    /// Sleeps until `bytes` more are allowed under the rate limit.
+    fn throttle(&self, bytes: u64, opts: &CopyOptions) {
+        let transferred = self.transferred.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        if let Some(rate) = opts.rate_limit {
+            let due = Duration::from_secs_f64(transferred as f64 / rate as f64);
+            if let Some(wait) = due.checked_sub(self.started.elapsed()) {
+                thread::sleep(wait);
+            }
+        }
+    }
+
+    fn advance(&self, bytes: u64, opts: &CopyOptions) {
+        if bytes == 0 {
+            return;
+        }
+        let copied = self.copied.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        if let Some(ProgressFn(f)) = &opts.progress {
+            f(copied, self.total.max(copied));
+        }
+    }
+}
+
+/// Copies the contents of a single file, where `meta` is the metadata of `src`,
+/// and returns the number of bytes copied.
+fn copy_file(src: &Path, dst: &Path, meta: &fs::Metadata, opts: &CopyOptions, progress: &Progress) -> Result<u64> {
+    let mut hasher = if opts.verify { Some(Sha256::new()) } else { None };
+    let cloned = opts.reflink && reflink(src, dst, meta)?;
+    let sparse_copy = if opts.sparse && !cloned { sparse(src, dst, meta, opts, progress)? } else { None };
+    if cloned || sparse_copy.is_some() {
+        // The data never passed through here, so it is read back for verification
+        hasher = None;
+    }
+    let bytes = if cloned {
+        progress.advance(meta.len(), opts);
+        meta.len()
+    } else if let Some(bytes) = sparse_copy {
+        bytes
+    } else if opts.progress.is_some() || opts.rate_limit.is_some() || hasher.is_some() {
+        stream(src, dst, meta, opts, progress, hasher.as_mut())?
+    } else {
+        let bytes = fs::copy(src, dst).at2(src, dst)?;
+        progress.advance(bytes, opts);
+        bytes
+    };
+    if opts.verify {
+        let expected = match hasher {
+            Some(hasher) => hasher.finish(),
+            None => hash::digest_file(src, Sha256::new())?,
+        };
+        let actual = hash::digest_file(dst, Sha256::new())?;
+        if actual != expected {
+            if opts.remove_unverified {
+                fs::remove_file(dst).at(dst)?;
+            }
+            let reason = format!(
+                "checksum mismatch copying from {}: expected {}, found {}",
+                src.display(),
+                hash::hex(&expected),
+                hash::hex(&actual)
+            );
+            return Err(FsError::from_io(dst, io::Error::new(io::ErrorKind::InvalidData, reason)));
+        }
+    }
+    if opts.preserve {
+        preserve_metadata(meta, dst, opts)?;
+    }
+    Ok(bytes)
+}
+
+/// Copies `src` to `dst` chunk by chunk, reporting each one to `progress` and
+/// feeding it to `hasher`.
+fn stream(
+    src: &Path,
+    dst: &Path,
+    meta: &fs::Metadata,
+    opts: &CopyOptions,
+    progress: &Progress,
+    mut hasher: Option<&mut Sha256>,
+) -> Result<u64> {
+    let mut from = fs::File::open(src).at(src)?;
+    let mut to = fs::File::create(dst).at(dst)?;
+    let bytes = pump(&mut from, &mut to, u64::MAX, opts, progress, hasher.as_deref_mut()).at2(src, dst)?;
+    to.set_permissions(meta.permissions()).at(dst)?;
+    if hasher.is_some() {
+        to.sync_all().at(dst)?;
+    }
+    Ok(bytes)
+}
+
+/// Copies up to `limit` bytes from the current position of `from` to the
+/// current position of `to`.
+///
+/// Unless the data has to be hashed on the way, this first tries to have the
+/// kernel copy it without passing it through user space, which also lets
+/// network filesystems copy on the server.
+fn pump(
+    from: &mut fs::File,
+    to: &mut fs::File,
+    limit: u64,
+    opts: &CopyOptions,
+    progress: &Progress,
+    mut hasher: Option<&mut Sha256>,
+) -> io::Result<u64> {
+    // Smaller chunks under a low rate limit, so it sleeps little and often
+    let chunk = match opts.rate_limit {
+        Some(rate) => (rate / 10).clamp(1, PROGRESS_CHUNK as u64) as usize,
+        None => PROGRESS_CHUNK,
+    };
+    if hasher.is_none() {
+        if let Some(bytes) = offload(from, to, limit, chunk, opts, progress)? {
+            return Ok(bytes);
+        }
+    }
+    let mut buf = vec![0; chunk];
+    let mut bytes = 0;
+    while bytes < limit {
+        let want = (limit - bytes).min(buf.len() as u64) as usize;
+        let n = match from.read(&mut buf[..want]) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        to.write_all(&buf[..n])?;
+        if let Some(hasher) = hasher.as_mut() {
+            hasher.update(&buf[..n]);
+        }
+        bytes += n as u64;
+        progress.advance(n as u64, opts);
+        progress.throttle(n as u64, opts);
+    }
+    Ok(bytes)
+}
+
+/// Copies like `pump` with `copy_file_range`, returning `None`, having copied
+/// nothing, if it is not supported between these two files.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn offload(
+    from: &fs::File,
+    to: &fs::File,
+    limit: u64,
+    chunk: usize,
+    opts: &CopyOptions,
+    progress: &Progress,
+) -> io::Result<Option<u64>> {
+    // Without anything to report between chunks, let the kernel take big bites
+    let chunk = if opts.progress.is_none() && opts.rate_limit.is_none() { 1 << 30 } else { chunk };
+    let mut bytes = 0;
+    while bytes < limit {
+        let want = (limit - bytes).min(chunk as u64) as usize;
+        let n = match crate::sys::copy_file_range(from, to, want) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(ref e) if bytes == 0 && crate::sys::is_unsupported(e) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        bytes += n as u64;
+        progress.advance(n as u64, opts);
+        progress.throttle(n as u64, opts);
+    }
+    Ok(Some(bytes))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn offload(
+    _from: &fs::File,
+    _to: &fs::File,
+    _limit: u64,
+    _chunk: usize,
+    _opts: &CopyOptions,
+    _progress: &Progress,
+) -> io::Result<Option<u64>> {
+    Ok(None)
+}
+
+/// Copies only the data regions of `src`, leaving holes in `dst` where `src`
+/// has them. Returns `None`, having written nothing, if the filesystem cannot
+/// report holes.
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd"
+))]
+fn sparse(src: &Path, dst: &Path, meta: &fs::Metadata, opts: &CopyOptions, progress: &Progress) -> Result<Option<u64>> {
+    use crate::sys;
+
+    let mut from = fs::File::open(src).at(src)?;
+    let len = meta.len();
+    let mut to = None;
+    let mut pos = 0;
+    while pos < len {
+        let start = match sys::seek_data(&from, pos) {
+            Ok(Some(start)) => start.min(len),
+            Ok(None) => len,
+            Err(e) if pos == 0 => {
+                debug!("Cannot find holes in {}: {}", src.display(), e);
+                return Ok(None);
+            }
+            Err(e) => return Err(FsError::from_io(src, e)),
+        };
+        let end = if start < len { sys::seek_hole(&from, start).at(src)?.min(len) } else { len };
+        progress.advance(start - pos, opts);
+
+        let to = match to.as_mut() {
+            Some(to) => to,
+            None => to.insert(fs::File::create(dst).at(dst)?),
+        };
+        from.seek(SeekFrom::Start(start)).at(src)?;
+        to.seek(SeekFrom::Start(start)).at(dst)?;
+        pump(&mut from, to, end - start, opts, progress, None).at2(src, dst)?;
+        pos = end;
+    }
+    let to = match to {
+        Some(to) => to,
+        None => fs::File::create(dst).at(dst)?,
+    };
+    // Extends the file over a trailing hole without writing it
+    to.set_len(len).at(dst)?;
+    to.set_permissions(meta.permissions()).at(dst)?;
+    Ok(Some(len))
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd"
+)))]
+fn sparse(_src: &Path, _dst: &Path, _meta: &fs::Metadata, _opts: &CopyOptions, _progress: &Progress) -> Result<Option<u64>> {
+    Ok(None)
+}
+
+/// Clones `src` to `dst`, returning `false` if the filesystem cannot share blocks.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn reflink(src: &Path, dst: &Path, meta: &fs::Metadata) -> Result<bool> {
+    let from = fs::File::open(src).at(src)?;
+    let to = fs::File::create(dst).at(dst)?;
+    match crate::sys::ficlone(&from, &to) {
+        Ok(()) => {
+            to.set_permissions(meta.permissions()).at(dst)?;
+            Ok(true)
+        }
+        Err(e) => {
+            debug!("Cannot clone {} to {}: {}", src.display(), dst.display(), e);
+            Ok(false)
+        }
+    }
+}
+
+/// Clones `src` to `dst`, returning `false` if the filesystem cannot share blocks.
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+fn reflink(src: &Path, dst: &Path, _meta: &fs::Metadata) -> Result<bool> {
+    // `clonefile` refuses to replace an existing file
+    if dst.symlink_metadata().is_ok() {
+        fs::remove_file(dst).at(dst)?;
+    }
+    match crate::sys::clonefile(src, dst) {
+        Ok(()) => Ok(true),
+        Err(e) => {
+            debug!("Cannot clone {} to {}: {}", src.display(), dst.display(), e);
+            Ok(false)
+        }
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "macos", target_os = "ios")))]
+fn reflink(_src: &Path, _dst: &Path, _meta: &fs::Metadata) -> Result<bool> {
+    Ok(false)
+}
+
+/// Applies the ownership, times and permissions in `meta` to `dst`.
+fn preserve_metadata(meta: &fs::Metadata, dst: &Path, opts: &CopyOptions) -> Result<()> {
+    if opts.preserve_ownership {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            std::os::unix::fs::chown(dst, Some(meta.uid()), Some(meta.gid())).at(dst)?;
+        }
+        #[cfg(not(unix))]
+        info!("Ignoring ownership of {} on this platform", dst.display());
+    }
+
+    let mut times = fs::FileTimes::new();
+    if let Ok(modified) = meta.modified() {
+        times = times.set_modified(modified);
+    }
+    if let Ok(accessed) = meta.accessed() {
+        times = times.set_accessed(accessed);
+    }
+    // The copy may already have the source's read-only mode, so the handle
+    // must not need write access: a read-only one on unix, where that is
+    // enough, and one that only writes attributes on Windows
+    #[cfg(unix)]
+    fs::File::open(dst)
+        .or_else(|_| fs::File::options().write(true).open(dst))
+        .and_then(|f| f.set_times(times))
+        .at(dst)?;
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::OpenOptionsExt;
+        const FILE_WRITE_ATTRIBUTES: u32 = 0x100;
+        if meta.is_dir() {
+            info!("Ignoring times of directory {} on this platform", dst.display());
+        } else {
+            fs::File::options()
+                .access_mode(FILE_WRITE_ATTRIBUTES)
+                .open(dst)
+                .and_then(|f| f.set_times(times))
+                .at(dst)?;
+        }
+    }
+
+    fs::set_permissions(dst, meta.permissions()).at(dst)
+}
+
+/// Recreates the symlink at `src` as `dst`, replacing whatever is at `dst`.
+fn copy_symlink(src: &Path, dst: &Path) -> Result<()> {
+    let target = fs::read_link(src).at(src)?;
+    if dst.symlink_metadata().is_ok() {
+        fs::remove_file(dst).at(dst)?;
+    }
+    #[cfg(unix)]
+    let result = std::os::unix::fs::symlink(&target, dst);
+    #[cfg(windows)]
+    let result = if src.metadata().map(|m| m.is_dir()).unwrap_or(false) {
+        std::os::windows::fs::symlink_dir(&target, dst)
+    } else {
+        std::os::windows::fs::symlink_file(&target, dst)
+    };
+    #[cfg(not(any(unix, windows)))]
+    let result = Err(io::Error::new(io::ErrorKind::Unsupported, "symlinks are not supported"));
+    result.at2(src, dst)
+}
+
+/// Whether `dst` is `src` or somewhere below it.
+fn is_inside(src: &Path, dst: &Path) -> bool {
+    let src = match fs::canonicalize(src) {
+        Ok(src) => src,
+        Err(_) => return false,
+    };
+    // `dst` usually does not exist yet, so canonicalize its closest existing ancestor
+    let mut existing = dst;
+    let mut rest = Vec::new();
+    while fs::symlink_metadata(existing).is_err() {
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                rest.push(name);
+                existing = if parent.as_os_str().is_empty() { Path::new(".") } else { parent };
+            }
+            _ => return false,
+        }
+    }
+    match fs::canonicalize(existing) {
+        Ok(mut dst) => {
+            dst.extend(rest.iter().rev());
+            dst.starts_with(&src)
+        }
+        Err(_) => false,
+    }
+}
+
+/// Appends the file name of `src` if `dst` is an existing directory.
+fn destination(src: &Path, dst: &Path) -> Result<PathBuf> {
+    if dst.is_dir() {
+        match src.file_name() {
+            Some(name) => Ok(dst.join(name)),
+            None => Err(FsError::invalid_path(src, "source has no file name")),
+        }
+    } else {
+        Ok(dst.to_path_buf())
+    }
+}
+
+/// Works out where to copy a file with metadata `meta` given the requested
+/// destination, or `None` to skip it.
+fn target(meta: &fs::Metadata, dst: &Path, opts: &CopyOptions) -> Result<Option<PathBuf>> {
+    if opts.update {
+        if let Ok(existing) = dst.symlink_metadata() {
+            match (meta.modified(), existing.modified()) {
+                (Ok(source), Ok(destination)) if source <= destination => {
+                    info!("Skipping up-to-date {}", dst.display());
+                    return Ok(None);
+                }
+                _ => return Ok(Some(dst.to_path_buf())),
+            }
+        }
+    }
+    opts.overwrite.resolve(dst)
+}
+
+/// Whether both paths exist and resolve to the same location.
+fn is_same(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
@@ -0,0 +1,652 @@
+// Copyright 2020 Jared Forth.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! DEFLATE compression as in RFC 1951, the gzip format of RFC 1952 around
+//! it, and the CRC-32 of the containers that use it.
+
+use std::io;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145,
+    8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+/// The order code length code lengths are stored in.
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+#[cfg(any(feature = "tar", feature = "zip"))]
+const WINDOW: usize = 32 * 1024;
+#[cfg(any(feature = "tar", feature = "zip"))]
+const MIN_MATCH: usize = 3;
+#[cfg(any(feature = "tar", feature = "zip"))]
+const MAX_MATCH: usize = 258;
+/// How many earlier positions with the same three bytes are tried per match.
+#[cfg(any(feature = "tar", feature = "zip"))]
+const MAX_CHAIN: usize = 128;
+#[cfg(any(feature = "tar", feature = "zip"))]
+const HASH_BITS: u32 = 15;
+/// How many literals and matches go in one block, each with its own codes.
+#[cfg(any(feature = "tar", feature = "zip"))]
+const BLOCK_SYMBOLS: usize = 1 << 14;
+
+/// The CRC-32 of `data` continued from `crc`, which is `0` to start, as zip
+/// and gzip use it.
+pub(crate) fn crc32(crc: u32, data: &[u8]) -> u32 {
+    static TABLE: std::sync::OnceLock<[u32; 256]> = std::sync::OnceLock::new();
+    let table = TABLE.get_or_init(|| {
+        let mut table = [0; 256];
+        for (i, entry) in table.iter_mut().enumerate() {
+            let mut c = i as u32;
+            for _ in 0..8 {
+                c = if c & 1 != 0 { 0xedb88320 ^ (c >> 1) } else { c >> 1 };
+            }
+            *entry = c;
+        }
+        table
+    });
+    !data.iter().fold(!crc, |c, &b| table[((c ^ u32::from(b)) & 0xff) as usize] ^ (c >> 8))
+}
+
+/// A literal byte, or a copy of earlier output.
+#[cfg(any(feature = "tar", feature = "zip"))]
+#[derive(Clone, Copy)]
+enum Symbol {
+    Literal(u8),
+    Match { length: u16, distance: u16 },
+}
+
+/// Writes bits starting from the least significant, as DEFLATE packs them.
+#[cfg(any(feature = "tar", feature = "zip"))]
+struct BitWriter {
+    out: Vec<u8>,
+    buf: u64,
+    count: u32,
+}
+
+#[cfg(any(feature = "tar", feature = "zip"))]
+impl BitWriter {
+    fn bits(&mut self, value: u32, count: u32) {
+        self.buf |= u64::from(value) << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.out.push(self.buf as u8);
+            self.buf >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Writes a Huffman code, which is stored most significant bit first.
+    fn code(&mut self, code: u16, length: u8) {
+        let reversed = code.reverse_bits() >> (16 - u32::from(length));
+        self.bits(u32::from(reversed), u32::from(length));
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.out.push(self.buf as u8);
+        }
+        self.out
+    }
+}
+
+/// Compresses `data` into a raw DEFLATE stream, stored as it is when it
+/// does not compress.
+#[cfg(any(feature = "tar", feature = "zip"))]
+pub(crate) fn compress(data: &[u8]) -> Vec<u8> {
+    let compressed = compress_blocks(data);
+    let blocks = data.len().div_ceil(65535).max(1);
+    if compressed.len() <= data.len() + 5 * blocks {
+        return compressed;
+    }
+    let mut out = Vec::with_capacity(data.len() + 5 * blocks);
+    for i in 0..blocks {
+        let block = &data[(i * 65535).min(data.len())..((i + 1) * 65535).min(data.len())];
+        out.push(u8::from(i + 1 == blocks));
+        out.extend_from_slice(&(block.len() as u16).to_le_bytes());
+        out.extend_from_slice(&(!(block.len() as u16)).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out
+}
+
+#[cfg(any(feature = "tar", feature = "zip"))]
+fn compress_blocks(data: &[u8]) -> Vec<u8> {
+    let mut out = BitWriter { out: Vec::with_capacity(data.len() / 2), buf: 0, count: 0 };
+    let symbols = find_matches(data);
+    if symbols.is_empty() {
+        // A final block with only the end-of-block code
+        out.bits(0b011, 3);
+        out.bits(0, 7);
+    }
+    let blocks = symbols.chunks(BLOCK_SYMBOLS).count();
+    for (i, block) in symbols.chunks(BLOCK_SYMBOLS).enumerate() {
+        write_block(&mut out, block, i + 1 == blocks);
+    }
+    out.finish()
+}
+
+/// The earlier positions of each three-byte sequence in the window.
+#[cfg(any(feature = "tar", feature = "zip"))]
+struct Chains<'a> {
+    data: &'a [u8],
+    head: Vec<usize>,
+    prev: Vec<usize>,
+}
+
+#[cfg(any(feature = "tar", feature = "zip"))]
+impl Chains<'_> {
+    fn hash(&self, i: usize) -> usize {
+        let word = u32::from(self.data[i]) << 16 | u32::from(self.data[i + 1]) << 8 | u32::from(self.data[i + 2]);
+        (word.wrapping_mul(0x9e37_79b1) >> (32 - HASH_BITS)) as usize
+    }
+
+    fn insert(&mut self, i: usize) {
+        if i + MIN_MATCH <= self.data.len() {
+            let h = self.hash(i);
+            self.prev[i % WINDOW] = self.head[h];
+            self.head[h] = i;
+        }
+    }
+
+    /// The longest earlier match for the bytes at `i`, as a length and a
+    /// distance, or a length of `0` if there is none.
+    fn longest(&self, i: usize) -> (usize, usize) {
+        let data = self.data;
+        if i + MIN_MATCH > data.len() {
+            return (0, 0);
+        }
+        let limit = (data.len() - i).min(MAX_MATCH);
+        let (mut best, mut best_distance) = (0, 0);
+        let mut candidate = self.head[self.hash(i)];
+        for _ in 0..MAX_CHAIN {
+            if candidate == usize::MAX || candidate >= i || i - candidate > WINDOW {
+                break;
+            }
+            let length = data[candidate..].iter().zip(&data[i..i + limit]).take_while(|(a, b)| a == b).count();
+            if length > best {
+                best = length;
+                best_distance = i - candidate;
+                if length == limit {
+                    break;
+                }
+            }
+            // Older positions only, since slots are reused as the window moves
+            let next = self.prev[candidate % WINDOW];
+            if next == usize::MAX || next >= candidate {
+                break;
+            }
+            candidate = next;
+        }
+        if best >= MIN_MATCH {
+            (best, best_distance)
+        } else {
+            (0, 0)
+        }
+    }
+}
+
+/// Splits `data` into literals and back-references, taking the longer match
+/// one byte later when there is one.
+#[cfg(any(feature = "tar", feature = "zip"))]
+fn find_matches(data: &[u8]) -> Vec<Symbol> {
+    let mut chains = Chains { data, head: vec![usize::MAX; 1 << HASH_BITS], prev: vec![usize::MAX; WINDOW] };
+    let mut symbols = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        let (length, distance) = chains.longest(i);
+        chains.insert(i);
+        if length == 0 || (length < MAX_MATCH && chains.longest(i + 1).0 > length) {
+            symbols.push(Symbol::Literal(data[i]));
+            i += 1;
+            continue;
+        }
+        symbols.push(Symbol::Match { length: length as u16, distance: distance as u16 });
+        for j in i + 1..i + length {
+            chains.insert(j);
+        }
+        i += length;
+    }
+    symbols
+}
+
+/// The length or distance code of `value`, with its extra bits.
+#[cfg(any(feature = "tar", feature = "zip"))]
+fn code_of(value: u16, base: &[u16], extra: &[u8]) -> (usize, u32, u32) {
+    let code = base.iter().rposition(|&b| b <= value).unwrap_or(0);
+    (code, u32::from(value - base[code]), u32::from(extra[code]))
+}
+
+/// Writes one block with dynamic Huffman codes.
+#[cfg(any(feature = "tar", feature = "zip"))]
+fn write_block(out: &mut BitWriter, symbols: &[Symbol], last: bool) {
+    let mut literal_freqs = [0u32; 286];
+    let mut distance_freqs = [0u32; 30];
+    literal_freqs[256] = 1;
+    for symbol in symbols {
+        match *symbol {
+            Symbol::Literal(byte) => literal_freqs[usize::from(byte)] += 1,
+            Symbol::Match { length, distance } => {
+                literal_freqs[257 + code_of(length, &LENGTH_BASE, &LENGTH_EXTRA).0] += 1;
+                distance_freqs[code_of(distance, &DIST_BASE, &DIST_EXTRA).0] += 1;
+            }
+        }
+    }
+    let literal_lengths = code_lengths(&literal_freqs, 15);
+    let distance_lengths = code_lengths(&distance_freqs, 15);
+    let literal_count = literal_lengths.iter().rposition(|&l| l > 0).map_or(257, |i| (i + 1).max(257));
+    let distance_count = distance_lengths.iter().rposition(|&l| l > 0).map_or(1, |i| i + 1);
+
+    // The code lengths of both codes, run-length encoded with codes 16 to 18
+    let all: Vec<u8> =
+        literal_lengths[..literal_count].iter().chain(&distance_lengths[..distance_count]).copied().collect();
+    let mut runs: Vec<(u8, u32)> = Vec::new();
+    let mut i = 0;
+    while i < all.len() {
+        let length = all[i];
+        let run = all[i..].iter().take_while(|&&l| l == length).count();
+        let mut left = run;
+        if length == 0 {
+            while left >= 11 {
+                let n = left.min(138);
+                runs.push((18, (n - 11) as u32));
+                left -= n;
+            }
+            if left >= 3 {
+                runs.push((17, (left - 3) as u32));
+                left = 0;
+            }
+        } else {
+            runs.push((length, 0));
+            left -= 1;
+            while left >= 3 {
+                let n = left.min(6);
+                runs.push((16, (n - 3) as u32));
+                left -= n;
+            }
+        }
+        runs.extend(std::iter::repeat_n((length, 0), left));
+        i += run;
+    }
+    let mut length_freqs = [0u32; 19];
+    for &(code, _) in &runs {
+        length_freqs[usize::from(code)] += 1;
+    }
+    let length_lengths = code_lengths(&length_freqs, 7);
+    let length_count = CODE_LENGTH_ORDER.iter().rposition(|&i| length_lengths[i] > 0).map_or(4, |i| (i + 1).max(4));
+
+    out.bits(u32::from(last), 1);
+    out.bits(0b10, 2);
+    out.bits((literal_count - 257) as u32, 5);
+    out.bits((distance_count - 1) as u32, 5);
+    out.bits((length_count - 4) as u32, 4);
+    for &i in &CODE_LENGTH_ORDER[..length_count] {
+        out.bits(u32::from(length_lengths[i]), 3);
+    }
+    let length_codes = canonical_codes(&length_lengths);
+    for &(code, extra) in &runs {
+        let code = usize::from(code);
+        out.code(length_codes[code], length_lengths[code]);
+        match code {
+            16 => out.bits(extra, 2),
+            17 => out.bits(extra, 3),
+            18 => out.bits(extra, 7),
+            _ => {}
+        }
+    }
+
+    let literal_codes = canonical_codes(&literal_lengths);
+    let distance_codes = canonical_codes(&distance_lengths);
+    for symbol in symbols {
+        match *symbol {
+            Symbol::Literal(byte) => {
+                let byte = usize::from(byte);
+                out.code(literal_codes[byte], literal_lengths[byte]);
+            }
+            Symbol::Match { length, distance } => {
+                let (code, extra, bits) = code_of(length, &LENGTH_BASE, &LENGTH_EXTRA);
+                out.code(literal_codes[257 + code], literal_lengths[257 + code]);
+                out.bits(extra, bits);
+                let (code, extra, bits) = code_of(distance, &DIST_BASE, &DIST_EXTRA);
+                out.code(distance_codes[code], distance_lengths[code]);
+                out.bits(extra, bits);
+            }
+        }
+    }
+    out.code(literal_codes[256], literal_lengths[256]);
+}
+
+/// Huffman code lengths for `freqs`, none longer than `limit`.
+///
+/// At least two symbols get a code, so the code is always complete, and
+/// frequencies are flattened until the longest code fits the limit.
+#[cfg(any(feature = "tar", feature = "zip"))]
+fn code_lengths(freqs: &[u32], limit: u8) -> Vec<u8> {
+    let mut freqs = freqs.to_vec();
+    while freqs.iter().filter(|&&f| f > 0).count() < 2 {
+        let unused = freqs.iter().position(|&f| f == 0).unwrap_or(0);
+        freqs[unused] = 1;
+    }
+    loop {
+        let lengths = huffman_lengths(&freqs);
+        if lengths.iter().all(|&l| l <= limit) {
+            return lengths;
+        }
+        for f in freqs.iter_mut().filter(|f| **f > 0) {
+            *f = (*f / 2).max(1);
+        }
+    }
+}
+
+/// Unlimited Huffman code lengths, from repeatedly joining the two lightest
+/// trees.
+#[cfg(any(feature = "tar", feature = "zip"))]
+fn huffman_lengths(freqs: &[u32]) -> Vec<u8> {
+    use std::cmp::Reverse;
+    use std::collections::BinaryHeap;
+    // Nodes are numbered with the leaves first, each pointing to its parent
+    let leaves: Vec<usize> = freqs.iter().enumerate().filter(|(_, &f)| f > 0).map(|(symbol, _)| symbol).collect();
+    let mut parents = vec![usize::MAX; leaves.len()];
+    let mut heap: BinaryHeap<_> =
+        leaves.iter().enumerate().map(|(node, &s)| Reverse((u64::from(freqs[s]), node))).collect();
+    while heap.len() > 1 {
+        let (Some(Reverse((a, node_a))), Some(Reverse((b, node_b)))) = (heap.pop(), heap.pop()) else {
+            break;
+        };
+        let node = parents.len();
+        parents.push(usize::MAX);
+        parents[node_a] = node;
+        parents[node_b] = node;
+        heap.push(Reverse((a + b, node)));
+    }
+    let mut lengths = vec![0u8; freqs.len()];
+    for (leaf, &symbol) in leaves.iter().enumerate() {
+        let mut depth = 0;
+        let mut node = leaf;
+        while parents[node] != usize::MAX {
+            node = parents[node];
+            depth += 1;
+        }
+        lengths[symbol] = depth.min(usize::from(u8::MAX)) as u8;
+    }
+    lengths
+}
+
+/// The canonical codes for the code lengths, as DEFLATE defines them.
+#[cfg(any(feature = "tar", feature = "zip"))]
+fn canonical_codes(lengths: &[u8]) -> Vec<u16> {
+    let mut counts = [0u16; 16];
+    for &l in lengths {
+        counts[usize::from(l)] += 1;
+    }
+    counts[0] = 0;
+    let mut next = [0u16; 16];
+    let mut code = 0u16;
+    for bits in 1..16 {
+        code = (code + counts[bits - 1]) << 1;
+        next[bits] = code;
+    }
+    lengths
+        .iter()
+        .map(|&l| {
+            if l == 0 {
+                return 0;
+            }
+            let code = next[usize::from(l)];
+            next[usize::from(l)] += 1;
+            code
+        })
+        .collect()
+}
+
+fn corrupt(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("corrupt deflate stream: {}", reason))
+}
+
+/// Reads bits starting from the least significant.
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    buf: u32,
+    count: u32,
+}
+
+impl BitReader<'_> {
+    fn bits(&mut self, count: u32) -> io::Result<u32> {
+        while self.count < count {
+            let byte = *self.data.get(self.pos).ok_or_else(|| corrupt("unexpected end"))?;
+            self.pos += 1;
+            self.buf |= u32::from(byte) << self.count;
+            self.count += 8;
+        }
+        let value = self.buf & ((1u64 << count) - 1) as u32;
+        self.buf >>= count;
+        self.count -= count;
+        Ok(value)
+    }
+}
+
+/// A Huffman code for decoding: how many codes there are of each length and
+/// the symbols in code order.
+struct Decoder {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Decoder {
+    fn new(lengths: &[u8]) -> io::Result<Decoder> {
+        let mut counts = [0u16; 16];
+        for &l in lengths {
+            counts[usize::from(l)] += 1;
+        }
+        let mut left = 1i32;
+        for &count in &counts[1..] {
+            left = (left << 1) - i32::from(count);
+            if left < 0 {
+                return Err(corrupt("oversubscribed code"));
+            }
+        }
+        let mut offsets = [0u16; 16];
+        for bits in 1..15 {
+            offsets[bits + 1] = offsets[bits] + counts[bits];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &l) in lengths.iter().enumerate() {
+            if l != 0 {
+                symbols[usize::from(offsets[usize::from(l)])] = symbol as u16;
+                offsets[usize::from(l)] += 1;
+            }
+        }
+        Ok(Decoder { counts, symbols })
+    }
+
+    fn decode(&self, input: &mut BitReader) -> io::Result<u16> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for &count in &self.counts[1..] {
+            code |= input.bits(1)? as i32;
+            let count = i32::from(count);
+            if code - count < first {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(corrupt("invalid code"))
+    }
+}
+
+/// Decompresses a raw DEFLATE stream, and returns the output and how many
+/// bytes of `data` the stream took.
+pub(crate) fn decompress(data: &[u8]) -> io::Result<(Vec<u8>, usize)> {
+    let mut input = BitReader { data, pos: 0, buf: 0, count: 0 };
+    let mut out = Vec::with_capacity(data.len() * 3);
+    loop {
+        let last = input.bits(1)? == 1;
+        match input.bits(2)? {
+            0 => {
+                input.buf = 0;
+                input.count = 0;
+                let header = data.get(input.pos..input.pos + 4).ok_or_else(|| corrupt("unexpected end"))?;
+                let len = usize::from(u16::from_le_bytes([header[0], header[1]]));
+                if u16::from_le_bytes([header[0], header[1]]) != !u16::from_le_bytes([header[2], header[3]]) {
+                    return Err(corrupt("stored block length mismatch"));
+                }
+                input.pos += 4;
+                let stored = data.get(input.pos..input.pos + len).ok_or_else(|| corrupt("unexpected end"))?;
+                out.extend_from_slice(stored);
+                input.pos += len;
+            }
+            1 => {
+                let mut lengths = [0u8; 288];
+                lengths[..144].fill(8);
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                lengths[280..].fill(8);
+                let literals = Decoder::new(&lengths)?;
+                let distances = Decoder::new(&[5; 30])?;
+                inflate_block(&mut input, &mut out, &literals, &distances)?;
+            }
+            2 => {
+                let literal_count = input.bits(5)? as usize + 257;
+                let distance_count = input.bits(5)? as usize + 1;
+                let length_count = input.bits(4)? as usize + 4;
+                let mut length_lengths = [0u8; 19];
+                for &i in &CODE_LENGTH_ORDER[..length_count] {
+                    length_lengths[i] = input.bits(3)? as u8;
+                }
+                let length_decoder = Decoder::new(&length_lengths)?;
+                let mut lengths = Vec::with_capacity(literal_count + distance_count);
+                while lengths.len() < literal_count + distance_count {
+                    let (value, repeat) = match length_decoder.decode(&mut input)? {
+                        code @ 0..=15 => (code as u8, 1),
+                        16 => (*lengths.last().ok_or_else(|| corrupt("repeat with no length"))?, 3 + input.bits(2)?),
+                        17 => (0, 3 + input.bits(3)?),
+                        _ => (0, 11 + input.bits(7)?),
+                    };
+                    lengths.extend(std::iter::repeat_n(value, repeat as usize));
+                }
+                if lengths.len() > literal_count + distance_count {
+                    return Err(corrupt("too many code lengths"));
+                }
+                let literals = Decoder::new(&lengths[..literal_count])?;
+                let distances = Decoder::new(&lengths[literal_count..])?;
+                inflate_block(&mut input, &mut out, &literals, &distances)?;
+            }
+            _ => return Err(corrupt("invalid block type")),
+        }
+        if last {
+            return Ok((out, input.pos));
+        }
+    }
+}
+
+fn inflate_block(input: &mut BitReader, out: &mut Vec<u8>, literals: &Decoder, distances: &Decoder) -> io::Result<()> {
+    loop {
+        let symbol = usize::from(literals.decode(input)?);
+        match symbol {
+            0..=255 => out.push(symbol as u8),
+            256 => return Ok(()),
+            257..=285 => {
+                let code = symbol - 257;
+                let length = usize::from(LENGTH_BASE[code]) + input.bits(u32::from(LENGTH_EXTRA[code]))? as usize;
+                let code = usize::from(distances.decode(input)?);
+                if code >= 30 {
+                    return Err(corrupt("invalid distance code"));
+                }
+                let distance = usize::from(DIST_BASE[code]) + input.bits(u32::from(DIST_EXTRA[code]))? as usize;
+                if distance > out.len() {
+                    return Err(corrupt("distance too far back"));
+                }
+                let start = out.len() - distance;
+                for i in 0..length {
+                    out.push(out[start + i]);
+                }
+            }
+            _ => return Err(corrupt("invalid literal or length code")),
+        }
+    }
+}
+
+/// Compresses `data` into a single gzip member with no name or time.
+#[cfg(feature = "tar")]
+pub(crate) fn gzip(data: &[u8]) -> Vec<u8> {
+    let os = if cfg!(windows) { 0 } else if cfg!(target_os = "macos") { 7 } else { 3 };
+    let mut out = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, os];
+    out.extend_from_slice(&compress(data));
+    out.extend_from_slice(&crc32(0, data).to_le_bytes());
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out
+}
+
+/// Whether `data` starts like a gzip stream.
+pub(crate) fn is_gzip(data: &[u8]) -> bool {
+    data.starts_with(&[0x1f, 0x8b])
+}
+
+/// Decompresses a gzip stream, joining the members of one made of several,
+/// as `gzip -d` does.
+pub(crate) fn gunzip(data: &[u8]) -> io::Result<Vec<u8>> {
+    fn invalid(reason: &str) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, format!("invalid gzip stream: {}", reason))
+    }
+    fn skip_string(data: &[u8], at: usize) -> io::Result<usize> {
+        let rest = data.get(at..).ok_or_else(|| invalid("truncated"))?;
+        let end = rest.iter().position(|&b| b == 0).ok_or_else(|| invalid("truncated"))?;
+        Ok(at + end + 1)
+    }
+
+    let mut out = Vec::new();
+    let mut at = 0;
+    loop {
+        let header = data.get(at..at + 10).ok_or_else(|| invalid("truncated"))?;
+        if !is_gzip(header) {
+            return Err(invalid("bad magic number"));
+        }
+        if header[2] != 8 {
+            return Err(invalid("unknown compression method"));
+        }
+        let flags = header[3];
+        at += 10;
+        if flags & 4 != 0 {
+            let len = data.get(at..at + 2).ok_or_else(|| invalid("truncated"))?;
+            at += 2 + usize::from(u16::from_le_bytes([len[0], len[1]]));
+        }
+        if flags & 8 != 0 {
+            at = skip_string(data, at)?;
+        }
+        if flags & 16 != 0 {
+            at = skip_string(data, at)?;
+        }
+        if flags & 2 != 0 {
+            at += 2;
+        }
+        let (member, used) = decompress(data.get(at..).ok_or_else(|| invalid("truncated"))?)?;
+        at += used;
+        let trailer = data.get(at..at + 8).ok_or_else(|| invalid("truncated"))?;
+        let crc = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+        let size = u32::from_le_bytes([trailer[4], trailer[5], trailer[6], trailer[7]]);
+        if crc != crc32(0, &member) || size != member.len() as u32 {
+            return Err(invalid("checksum mismatch"));
+        }
+        out.extend_from_slice(&member);
+        at += 8;
+        // Anything after the last member must be padding
+        if !is_gzip(&data[at..]) {
+            if data[at..].iter().any(|&b| b != 0) {
+                return Err(invalid("trailing garbage"));
+            }
+            return Ok(out);
+        }
+    }
+}
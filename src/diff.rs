@@ -0,0 +1,370 @@
+// Copyright 2020 Jared Forth.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Line-by-line differences between text files.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::error::{IoResultExt, Result};
+
+/// How many unchanged lines are shown around each change, as `diff -u` does.
+const CONTEXT: usize = 3;
+
+/// One line of a `Hunk`.
+///
+/// Each line keeps its line ending, so only the last line of a file can be
+/// without one.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum DiffLine {
+    /// A line both files have, shown for context.
+    Context(String),
+    /// A line only the old file has.
+    Removed(String),
+    /// A line only the new file has.
+    Added(String),
+}
+
+impl DiffLine {
+    /// The text of the line, with its line ending.
+    pub fn text(&self) -> &str {
+        match self {
+            DiffLine::Context(text) | DiffLine::Removed(text) | DiffLine::Added(text) => text,
+        }
+    }
+}
+
+/// A run of changed lines with the unchanged lines around them, one `@@`
+/// section of a unified diff.
+///
+/// The starts and lengths are the numbers of the `@@` line: starts count
+/// from `1`, and a range of no lines starts at the line before it.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("hunk_a.txt", "a\nb\nc\n");
+/// fsutils::write_file("hunk_b.txt", "a\nB\nc\n");
+///
+/// let hunks = fsutils::try_diff_hunks("hunk_a.txt", "hunk_b.txt").unwrap();
+/// assert_eq!(hunks[0].to_string(), "@@ -1,3 +1,3 @@\n a\n-b\n+B\n c\n");
+///
+/// # // Cleanup
+/// # fsutils::rm("hunk_a.txt");
+/// # fsutils::rm("hunk_b.txt");
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Hunk {
+    /// Where the hunk starts in the old file.
+    pub old_start: usize,
+    /// How many lines of the old file the hunk covers.
+    pub old_lines: usize,
+    /// Where the hunk starts in the new file.
+    pub new_start: usize,
+    /// How many lines of the new file the hunk covers.
+    pub new_lines: usize,
+    /// The lines, in order.
+    pub lines: Vec<DiffLine>,
+}
+
+impl fmt::Display for Hunk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let range = |start: usize, len: usize| if len == 1 { start.to_string() } else { format!("{},{}", start, len) };
+        writeln!(f, "@@ -{} +{} @@", range(self.old_start, self.old_lines), range(self.new_start, self.new_lines))?;
+        for line in &self.lines {
+            let prefix = match line {
+                DiffLine::Context(_) => ' ',
+                DiffLine::Removed(_) => '-',
+                DiffLine::Added(_) => '+',
+            };
+            write!(f, "{}{}", prefix, line.text())?;
+            if !line.text().ends_with('\n') {
+                f.write_str("\n\\ No newline at end of file\n")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns the differences between two text files as a unified diff, or an
+/// empty `String` if either cannot be read.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("diff_a.conf", "port = 80\n");
+/// fsutils::write_file("diff_b.conf", "port = 8080\n");
+///
+/// print!("{}", fsutils::diff("diff_a.conf", "diff_b.conf"));
+///
+/// # // Cleanup
+/// # fsutils::rm("diff_a.conf");
+/// # fsutils::rm("diff_b.conf");
+/// ```
+pub fn diff<P: AsRef<Path>, Q: AsRef<Path>>(a: P, b: Q) -> String {
+    match try_diff(a, b) {
+        Ok(text) => text,
+        Err(e) => {
+            error!("Cannot diff files {}", e);
+            String::new()
+        }
+    }
+}
+
+/// Returns the differences between two text files as a unified diff, like
+/// `diff -u` without the timestamps.
+///
+/// The diff is minimal, with three lines of context around each change, and
+/// empty if the files are identical. Both files must be UTF-8. See
+/// `fsutils::try_diff_hunks` for the changes as data.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("try_diff_a.conf", "host = localhost\nport = 80\nuser = www\n");
+/// fsutils::write_file("try_diff_b.conf", "host = localhost\nport = 8080\nuser = www\n");
+///
+/// assert_eq!(fsutils::try_diff("try_diff_a.conf", "try_diff_b.conf").unwrap(), "\
+/// --- try_diff_a.conf
+/// +++ try_diff_b.conf
+/// @@ -1,3 +1,3 @@
+///  host = localhost
+/// -port = 80
+/// +port = 8080
+///  user = www
+/// ");
+/// assert_eq!(fsutils::try_diff("try_diff_a.conf", "try_diff_a.conf").unwrap(), "");
+/// assert!(fsutils::try_diff("try_diff_a.conf", "a_very_1234_unlikely_9876_filename").is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm("try_diff_a.conf");
+/// # fsutils::rm("try_diff_b.conf");
+/// ```
+pub fn try_diff<P: AsRef<Path>, Q: AsRef<Path>>(a: P, b: Q) -> Result<String> {
+    let (a, b) = (a.as_ref(), b.as_ref());
+    let hunks = try_diff_hunks(a, b)?;
+    if hunks.is_empty() {
+        return Ok(String::new());
+    }
+    let mut out = format!("--- {}\n+++ {}\n", a.display(), b.display());
+    for hunk in hunks {
+        out.push_str(&hunk.to_string());
+    }
+    Ok(out)
+}
+
+/// Returns the differences between two text files as `Hunk`s, or an empty
+/// `Vec` if either cannot be read.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("diff_hunks_a.txt", "a\n");
+/// fsutils::write_file("diff_hunks_b.txt", "b\n");
+///
+/// for hunk in fsutils::diff_hunks("diff_hunks_a.txt", "diff_hunks_b.txt") {
+///     println!("{} lines changed to {}", hunk.old_lines, hunk.new_lines);
+/// }
+///
+/// # // Cleanup
+/// # fsutils::rm("diff_hunks_a.txt");
+/// # fsutils::rm("diff_hunks_b.txt");
+/// ```
+pub fn diff_hunks<P: AsRef<Path>, Q: AsRef<Path>>(a: P, b: Q) -> Vec<Hunk> {
+    match try_diff_hunks(a, b) {
+        Ok(hunks) => hunks,
+        Err(e) => {
+            error!("Cannot diff files {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// Returns the differences between two text files as the `Hunk`s of
+/// `fsutils::try_diff`, for tools that show or count changes their own way.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::DiffLine;
+///
+/// fsutils::write_file("try_diff_hunks_a.txt", "one\ntwo\n");
+/// fsutils::write_file("try_diff_hunks_b.txt", "one\ntwo\nthree");
+///
+/// let hunks = fsutils::try_diff_hunks("try_diff_hunks_a.txt", "try_diff_hunks_b.txt").unwrap();
+/// assert_eq!(hunks.len(), 1);
+/// assert_eq!((hunks[0].old_start, hunks[0].old_lines, hunks[0].new_start, hunks[0].new_lines), (1, 2, 1, 3));
+/// assert_eq!(hunks[0].lines[2], DiffLine::Added("three".to_string()));
+///
+/// # // Cleanup
+/// # fsutils::rm("try_diff_hunks_a.txt");
+/// # fsutils::rm("try_diff_hunks_b.txt");
+/// ```
+pub fn try_diff_hunks<P: AsRef<Path>, Q: AsRef<Path>>(a: P, b: Q) -> Result<Vec<Hunk>> {
+    let (a, b) = (a.as_ref(), b.as_ref());
+    let old = fs::read_to_string(a).at(a)?;
+    let new = fs::read_to_string(b).at(b)?;
+    Ok(hunks(&old, &new))
+}
+
+/// One step of turning the old lines into the new ones.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Edit {
+    Keep,
+    Remove,
+    Add,
+}
+
+/// Splits both texts into lines and groups the edits between them into
+/// hunks.
+fn hunks(old: &str, new: &str) -> Vec<Hunk> {
+    let old: Vec<&str> = old.split_inclusive('\n').collect();
+    let new: Vec<&str> = new.split_inclusive('\n').collect();
+    // Lines are compared by number, which is cheaper than comparing text
+    let mut ids = HashMap::new();
+    let mut id = |line: &str| {
+        let next = ids.len();
+        *ids.entry(line.to_string()).or_insert(next)
+    };
+    let old_ids: Vec<usize> = old.iter().map(|line| id(line)).collect();
+    let new_ids: Vec<usize> = new.iter().map(|line| id(line)).collect();
+    let mut edits = Vec::with_capacity(old.len().max(new.len()));
+    myers(&old_ids, &new_ids, &mut edits);
+
+    let mut hunks: Vec<Hunk> = Vec::new();
+    // The position in both files before each edit
+    let mut positions = Vec::with_capacity(edits.len() + 1);
+    let (mut i, mut j) = (0, 0);
+    for edit in &edits {
+        positions.push((i, j));
+        match edit {
+            Edit::Keep => {
+                i += 1;
+                j += 1;
+            }
+            Edit::Remove => i += 1,
+            Edit::Add => j += 1,
+        }
+    }
+    positions.push((i, j));
+
+    let mut at = 0;
+    while let Some(first) = edits[at..].iter().position(|edit| *edit != Edit::Keep).map(|n| at + n) {
+        // Extend the hunk while the next change is close enough that their
+        // context would touch
+        let mut last = first;
+        loop {
+            let mut next = last + 1;
+            while next < edits.len() && edits[next] == Edit::Keep {
+                next += 1;
+            }
+            if next < edits.len() && next - last - 1 <= 2 * CONTEXT {
+                last = next;
+            } else {
+                break;
+            }
+        }
+        let start = first.saturating_sub(CONTEXT);
+        let end = (last + 1 + CONTEXT).min(edits.len());
+        let (old_from, new_from) = positions[start];
+        let (old_to, new_to) = positions[end];
+        let lines = edits[start..end]
+            .iter()
+            .zip(&positions[start..end])
+            .map(|(edit, &(i, j))| match edit {
+                Edit::Keep => DiffLine::Context(old[i].to_string()),
+                Edit::Remove => DiffLine::Removed(old[i].to_string()),
+                Edit::Add => DiffLine::Added(new[j].to_string()),
+            })
+            .collect();
+        let first_line = |from: usize, len: usize| if len == 0 { from } else { from + 1 };
+        hunks.push(Hunk {
+            old_start: first_line(old_from, old_to - old_from),
+            old_lines: old_to - old_from,
+            new_start: first_line(new_from, new_to - new_from),
+            new_lines: new_to - new_from,
+            lines,
+        });
+        at = end;
+    }
+    hunks
+}
+
+/// Appends the shortest edit script from `a` to `b` to `edits`, using
+/// Myers' algorithm in linear space: the middle of an optimal path is found
+/// searching from both ends at once, and the halves on either side of it are
+/// solved the same way.
+fn myers(a: &[usize], b: &[usize], edits: &mut Vec<Edit>) {
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let (a, b) = (&a[prefix..], &b[prefix..]);
+    let suffix = a.iter().rev().zip(b.iter().rev()).take_while(|(x, y)| x == y).count();
+    let (a, b) = (&a[..a.len() - suffix], &b[..b.len() - suffix]);
+    edits.extend(std::iter::repeat_n(Edit::Keep, prefix));
+    if a.is_empty() {
+        edits.extend(std::iter::repeat_n(Edit::Add, b.len()));
+    } else if b.is_empty() {
+        edits.extend(std::iter::repeat_n(Edit::Remove, a.len()));
+    } else {
+        let (x, y, u, v) = middle_snake(a, b);
+        myers(&a[..x], &b[..y], edits);
+        edits.extend(std::iter::repeat_n(Edit::Keep, u - x));
+        myers(&a[u..], &b[v..], edits);
+    }
+    edits.extend(std::iter::repeat_n(Edit::Keep, suffix));
+}
+
+/// Finds a run of matching lines from `(x, y)` to `(u, v)` that lies on a
+/// shortest edit path from `a` to `b`, splitting the edits about in half.
+fn middle_snake(a: &[usize], b: &[usize]) -> (usize, usize, usize, usize) {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let delta = n - m;
+    let odd = delta % 2 != 0;
+    let max = (n + m + 1) / 2 + 1;
+    let offset = max + 1;
+    // The furthest `x` reached on each diagonal `x - y`, searching forwards
+    // from the start and backwards from the end
+    let mut forward = vec![0isize; 2 * offset as usize + 1];
+    let mut backward = vec![0isize; 2 * offset as usize + 1];
+    let at = |k: isize| (k + offset) as usize;
+    for d in 0..max {
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && forward[at(k - 1)] < forward[at(k + 1)]) {
+                forward[at(k + 1)]
+            } else {
+                forward[at(k - 1)] + 1
+            };
+            let (x0, y0) = (x, x - k);
+            while x < n && x - k < m && a[x as usize] == b[(x - k) as usize] {
+                x += 1;
+            }
+            forward[at(k)] = x;
+            if odd && (delta - k).abs() < d && x + backward[at(delta - k)] >= n {
+                return (x0 as usize, y0 as usize, x as usize, (x - k) as usize);
+            }
+        }
+        for k in (-d..=d).step_by(2) {
+            // In reversed coordinates, counting from the ends of both slices
+            let mut x = if k == -d || (k != d && backward[at(k - 1)] < backward[at(k + 1)]) {
+                backward[at(k + 1)]
+            } else {
+                backward[at(k - 1)] + 1
+            };
+            let (x0, y0) = (x, x - k);
+            while x < n && x - k < m && a[(n - x - 1) as usize] == b[(m - x + k - 1) as usize] {
+                x += 1;
+            }
+            backward[at(k)] = x;
+            if !odd && (delta - k).abs() <= d && x + forward[at(delta - k)] >= n {
+                return ((n - x) as usize, (m - x + k) as usize, (n - x0) as usize, (m - y0) as usize);
+            }
+        }
+    }
+    unreachable!("a shortest edit path has at most {} edits", n + m)
+}
@@ -0,0 +1,650 @@
+// Copyright 2020 Jared Forth.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Reading and changing part of a file's contents.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::atomic::{replace_file, replace_file_if};
+use crate::error::{FsError, IoResultExt, Result};
+use crate::op::Op;
+
+/// Inserts data at the start of a file
+/// and returns a boolean based on success or failure.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("prepend.rs", "fn main() {}\n");
+///
+/// assert!(fsutils::prepend_file("prepend.rs", "// SPDX-License-Identifier: MIT\n"));
+/// assert_eq!(fsutils::read_file("prepend.rs"), "// SPDX-License-Identifier: MIT\nfn main() {}\n");
+///
+/// # // Cleanup
+/// # fsutils::rm("prepend.rs");
+/// ```
+pub fn prepend_file<P: AsRef<Path>>(path: P, contents: &str) -> bool {
+    match try_prepend_file(path, contents) {
+        Ok(_) => true,
+        Err(e) => {
+            error!("Cannot write file {}", e);
+            false
+        }
+    }
+}
+
+/// Inserts data at the start of a file, creating it if it does not exist.
+///
+/// Files cannot grow at the front, so the new data and the old contents are
+/// streamed into a temporary file that then replaces the original, the same
+/// way as `fsutils::try_write_file_atomic`. Large files are never held in
+/// memory, and a crash leaves the original untouched.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::try_prepend_file("try_prepend.csv", "b,2\n").unwrap();
+/// fsutils::try_prepend_file("try_prepend.csv", "name,value\n").unwrap();
+///
+/// assert_eq!(fsutils::read_file("try_prepend.csv"), "name,value\nb,2\n");
+///
+/// # // Cleanup
+/// # fsutils::rm("try_prepend.csv");
+/// ```
+pub fn try_prepend_file<P: AsRef<Path>>(path: P, contents: &str) -> Result<()> {
+    let path = path.as_ref();
+    Op::new("prepend_file", path).bytes(contents.len() as u64).destructive(path.exists()).run((), || {
+        replace_file(path, |temp| {
+            temp.write_all(contents.as_bytes()).at(path)?;
+            match File::open(path) {
+                Ok(mut original) => io::copy(&mut original, temp).map(|_| ()).at(path),
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+                Err(e) => Err(e).at(path),
+            }
+        })?;
+        info!("Prepended to file {}", path.display());
+        Ok(())
+    })
+}
+
+/// Inserts `text` as line `n` of a file, counting from 1,
+/// and returns a boolean based on success or failure.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("insert_line.ini", "[server]\nport = 80\n");
+///
+/// assert!(fsutils::insert_line("insert_line.ini", 2, "host = localhost"));
+/// assert_eq!(fsutils::read_file("insert_line.ini"), "[server]\nhost = localhost\nport = 80\n");
+///
+/// # // Cleanup
+/// # fsutils::rm("insert_line.ini");
+/// ```
+pub fn insert_line<P: AsRef<Path>>(path: P, n: usize, text: &str) -> bool {
+    match try_insert_line(path, n, text) {
+        Ok(_) => true,
+        Err(e) => {
+            error!("Cannot insert line {}", e);
+            false
+        }
+    }
+}
+
+/// Inserts `text` as line `n` of a file, counting from 1, moving the line
+/// that was there and everything after it down by one.
+///
+/// `n` may be one past the last line to append. The new line gets the same
+/// terminator as the lines around it, `\n` or `\r\n`. The file is rewritten
+/// through a temporary file, so a crash leaves the original untouched. Fails
+/// with an error of kind `io::ErrorKind::InvalidInput` if `n` is 0 or past
+/// the end of the file.
+///
+/// ## Usage:
+///
+/// ```
+/// use std::io::ErrorKind;
+///
+/// fsutils::write_file("try_insert_line.txt", "one\r\ntwo");
+///
+/// assert!(fsutils::try_insert_line("try_insert_line.txt", 3, "three").is_ok());
+/// assert_eq!(fsutils::read_file("try_insert_line.txt"), "one\r\ntwo\r\nthree\r\n");
+/// assert_eq!(fsutils::try_insert_line("try_insert_line.txt", 9, "nine").unwrap_err().kind(), ErrorKind::InvalidInput);
+///
+/// # // Cleanup
+/// # fsutils::rm("try_insert_line.txt");
+/// ```
+pub fn try_insert_line<P: AsRef<Path>>(path: P, n: usize, text: &str) -> Result<()> {
+    let path = path.as_ref();
+    Op::new("insert_line", path).destructive(true).run((), || {
+        rewrite_line(path, n, text, false)?;
+        info!("Inserted line {} in {}", n, path.display());
+        Ok(())
+    })
+}
+
+/// Replaces line `n` of a file, counting from 1, with `text`
+/// and returns a boolean based on success or failure.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("replace_line.ini", "[server]\nport = 80\n");
+///
+/// assert!(fsutils::replace_line("replace_line.ini", 2, "port = 8080"));
+/// assert_eq!(fsutils::read_file("replace_line.ini"), "[server]\nport = 8080\n");
+///
+/// # // Cleanup
+/// # fsutils::rm("replace_line.ini");
+/// ```
+pub fn replace_line<P: AsRef<Path>>(path: P, n: usize, text: &str) -> bool {
+    match try_replace_line(path, n, text) {
+        Ok(_) => true,
+        Err(e) => {
+            error!("Cannot replace line {}", e);
+            false
+        }
+    }
+}
+
+/// Replaces line `n` of a file, counting from 1, with `text`.
+///
+/// The line keeps its terminator. The file is rewritten through a temporary
+/// file, so a crash leaves the original untouched. Fails with an error of
+/// kind `io::ErrorKind::InvalidInput` if the file has no line `n`.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("try_replace_line.txt", "a\nb\nc");
+///
+/// assert!(fsutils::try_replace_line("try_replace_line.txt", 3, "z").is_ok());
+/// assert_eq!(fsutils::read_file("try_replace_line.txt"), "a\nb\nz");
+/// assert!(fsutils::try_replace_line("try_replace_line.txt", 4, "d").is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm("try_replace_line.txt");
+/// ```
+pub fn try_replace_line<P: AsRef<Path>>(path: P, n: usize, text: &str) -> Result<()> {
+    let path = path.as_ref();
+    Op::new("replace_line", path).destructive(true).run((), || {
+        rewrite_line(path, n, text, true)?;
+        info!("Replaced line {} in {}", n, path.display());
+        Ok(())
+    })
+}
+
+/// Copies the file at `path` line by line into a temporary file that replaces
+/// it, inserting `text` before line `n` or replacing line `n` with it.
+fn rewrite_line(path: &Path, n: usize, text: &str, replace: bool) -> Result<()> {
+    if n == 0 {
+        return Err(invalid_line(path, "line numbers start at 1"));
+    }
+    replace_file(path, |temp| {
+        let mut reader = BufReader::new(File::open(path).at(path)?);
+        let mut writer = BufWriter::new(temp);
+        let mut line = Vec::new();
+        let mut count = 0;
+        // The terminator of the most recent line that had one
+        let mut ending: &[u8] = b"\n";
+        let mut unterminated = false;
+        loop {
+            line.clear();
+            if reader.read_until(b'\n', &mut line).at(path)? == 0 {
+                break;
+            }
+            count += 1;
+            let own = terminator(&line);
+            if !own.is_empty() {
+                ending = own;
+            }
+            unterminated = own.is_empty();
+            if count == n {
+                writer.write_all(text.as_bytes()).at(path)?;
+                if replace {
+                    writer.write_all(own).at(path)?;
+                    continue;
+                }
+                writer.write_all(ending).at(path)?;
+            }
+            writer.write_all(&line).at(path)?;
+        }
+        if count < n {
+            if replace || count + 1 < n {
+                return Err(invalid_line(path, format!("line {} is past the end of the file, which has {} lines", n, count)));
+            }
+            if unterminated {
+                writer.write_all(ending).at(path)?;
+            }
+            writer.write_all(text.as_bytes()).at(path)?;
+            writer.write_all(ending).at(path)?;
+        }
+        writer.flush().at(path)
+    })
+}
+
+/// Returns the `\n` or `\r\n` at the end of `line`, or nothing for a last line without one.
+fn terminator(line: &[u8]) -> &'static [u8] {
+    if line.ends_with(b"\r\n") {
+        b"\r\n"
+    } else if line.ends_with(b"\n") {
+        b"\n"
+    } else {
+        b""
+    }
+}
+
+fn invalid_line<S: Into<String>>(path: &Path, reason: S) -> FsError {
+    FsError::from_io(path, io::Error::new(io::ErrorKind::InvalidInput, reason.into()))
+}
+
+/// Shrinks or extends a file to exactly `len` bytes
+/// and returns a boolean based on success or failure.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("truncate.txt", "Hello, world!");
+///
+/// assert!(fsutils::truncate("truncate.txt", 5));
+/// assert_eq!(fsutils::read_file("truncate.txt"), "Hello");
+///
+/// # // Cleanup
+/// # fsutils::rm("truncate.txt");
+/// ```
+pub fn truncate<P: AsRef<Path>>(path: P, len: u64) -> bool {
+    match try_truncate(path, len) {
+        Ok(_) => true,
+        Err(e) => {
+            error!("Cannot truncate file {}", e);
+            false
+        }
+    }
+}
+
+/// Shrinks or extends a file to exactly `len` bytes.
+///
+/// The file is changed in place, so it keeps its permissions, ownership and
+/// any handles other processes have open on it. Extending fills the new
+/// space with zeros. Unlike `fsutils::create_file`, a missing file is an
+/// error rather than being created.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("try_truncate.bin", "abc");
+///
+/// assert!(fsutils::try_truncate("try_truncate.bin", 5).is_ok());
+/// assert_eq!(fsutils::read_file("try_truncate.bin"), "abc\0\0");
+/// assert!(fsutils::try_truncate("a_very_1234_unlikely_9876_filename", 0).is_err());
+/// assert!(!fsutils::path_exists("a_very_1234_unlikely_9876_filename"));
+///
+/// # // Cleanup
+/// # fsutils::rm("try_truncate.bin");
+/// ```
+pub fn try_truncate<P: AsRef<Path>>(path: P, len: u64) -> Result<()> {
+    let path = path.as_ref();
+    let meta = path.metadata().at(path)?;
+    if meta.is_dir() {
+        return Err(FsError::from_io(path, io::ErrorKind::IsADirectory.into()));
+    }
+    Op::new("truncate", path).destructive(len < meta.len()).run((), || {
+        OpenOptions::new().write(true).open(path).at(path)?.set_len(len).at(path)?;
+        info!("Truncated {} to {} bytes", path.display(), len);
+        Ok(())
+    })
+}
+
+/// Empties a file in place
+/// and returns a boolean based on success or failure.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("truncate_to_zero.log", "old entries");
+///
+/// assert!(fsutils::truncate_to_zero("truncate_to_zero.log"));
+/// assert_eq!(fsutils::read_file("truncate_to_zero.log"), "");
+///
+/// # // Cleanup
+/// # fsutils::rm("truncate_to_zero.log");
+/// ```
+pub fn truncate_to_zero<P: AsRef<Path>>(path: P) -> bool {
+    truncate(path, 0)
+}
+
+/// Empties a file in place, e.g. to clear a log file that another process
+/// still has open for appending.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("try_truncate_to_zero.log", "old entries");
+///
+/// assert!(fsutils::try_truncate_to_zero("try_truncate_to_zero.log").is_ok());
+/// assert_eq!(fsutils::read_file("try_truncate_to_zero.log"), "");
+///
+/// # // Cleanup
+/// # fsutils::rm("try_truncate_to_zero.log");
+/// ```
+pub fn try_truncate_to_zero<P: AsRef<Path>>(path: P) -> Result<()> {
+    try_truncate(path, 0)
+}
+
+/// Reads up to `len` bytes starting `offset` bytes into a file
+/// and returns an empty `Vec` if the file cannot be read.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::create_file_bytes("read_range.bin", b"\x89PNG\r\n\x1a\n....");
+///
+/// assert_eq!(fsutils::read_range("read_range.bin", 1, 3), b"PNG");
+///
+/// # // Cleanup
+/// # fsutils::rm("read_range.bin");
+/// ```
+pub fn read_range<P: AsRef<Path>>(path: P, offset: u64, len: usize) -> Vec<u8> {
+    match try_read_range(path, offset, len) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            error!("Cannot read file {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// Reads up to `len` bytes starting `offset` bytes into a file.
+///
+/// Fewer bytes are returned if the file ends first, and none at all if
+/// `offset` is past the end.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("try_read_range.txt", "0123456789");
+///
+/// assert_eq!(fsutils::try_read_range("try_read_range.txt", 8, 5).unwrap(), b"89");
+/// assert!(fsutils::try_read_range("try_read_range.txt", 20, 5).unwrap().is_empty());
+///
+/// # // Cleanup
+/// # fsutils::rm("try_read_range.txt");
+/// ```
+pub fn try_read_range<P: AsRef<Path>>(path: P, offset: u64, len: usize) -> Result<Vec<u8>> {
+    let path = path.as_ref();
+    let mut file = File::open(path).at(path)?;
+    file.seek(SeekFrom::Start(offset)).at(path)?;
+    let mut bytes = Vec::with_capacity(len.min(1 << 20));
+    file.take(len as u64).read_to_end(&mut bytes).at(path)?;
+    Ok(bytes)
+}
+
+/// Overwrites the bytes of a file starting `offset` bytes in
+/// and returns a boolean based on success or failure.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("write_at.txt", "Hello, world!");
+///
+/// assert!(fsutils::write_at("write_at.txt", 7, b"there"));
+/// assert_eq!(fsutils::read_file("write_at.txt"), "Hello, there!");
+///
+/// # // Cleanup
+/// # fsutils::rm("write_at.txt");
+/// ```
+pub fn write_at<P: AsRef<Path>>(path: P, offset: u64, bytes: &[u8]) -> bool {
+    match try_write_at(path, offset, bytes) {
+        Ok(_) => true,
+        Err(e) => {
+            error!("Cannot write file {}", e);
+            false
+        }
+    }
+}
+
+/// Overwrites the bytes of a file starting `offset` bytes in, leaving the
+/// rest of the file as it is.
+///
+/// Writing past the end extends the file, filling any gap with zeros. The
+/// file must already exist.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("try_write_at.bin", "ab");
+///
+/// assert!(fsutils::try_write_at("try_write_at.bin", 4, b"ef").is_ok());
+/// assert_eq!(fsutils::read_file("try_write_at.bin"), "ab\0\0ef");
+/// assert!(fsutils::try_write_at("a_very_1234_unlikely_9876_filename", 0, b"x").is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm("try_write_at.bin");
+/// ```
+pub fn try_write_at<P: AsRef<Path>>(path: P, offset: u64, bytes: &[u8]) -> Result<()> {
+    let path = path.as_ref();
+    let meta = path.metadata().at(path)?;
+    Op::new("write_at", path).bytes(bytes.len() as u64).destructive(offset < meta.len()).run((), || {
+        let mut file = OpenOptions::new().write(true).open(path).at(path)?;
+        file.seek(SeekFrom::Start(offset)).at(path)?;
+        file.write_all(bytes).at(path)?;
+        info!("Wrote {} bytes at offset {} of {}", bytes.len(), offset, path.display());
+        Ok(())
+    })
+}
+
+/// How much `fsutils::try_replace_in_file` reads at a time.
+const REPLACE_CHUNK: usize = 64 * 1024;
+
+/// Replaces every occurrence of `from` in a file with `to`
+/// and returns the number of replacements, or `None` on failure.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("replace_in_file.toml", "version = \"0.1.6\"\n");
+///
+/// assert_eq!(fsutils::replace_in_file("replace_in_file.toml", "0.1.6", "0.1.7"), Some(1));
+/// assert_eq!(fsutils::read_file("replace_in_file.toml"), "version = \"0.1.7\"\n");
+///
+/// # // Cleanup
+/// # fsutils::rm("replace_in_file.toml");
+/// ```
+pub fn replace_in_file<P: AsRef<Path>>(path: P, from: &str, to: &str) -> Option<usize> {
+    match try_replace_in_file(path, from, to) {
+        Ok(count) => Some(count),
+        Err(e) => {
+            error!("Cannot replace in file {}", e);
+            None
+        }
+    }
+}
+
+/// Replaces every occurrence of `from` in a file with `to`, like
+/// `sed -i 's/from/to/g'` without the regular expressions, and returns the
+/// number of replacements.
+///
+/// The file is streamed through a temporary file that then replaces it, so
+/// large files are never held in memory and a crash leaves the original
+/// untouched. A file without any occurrences is not rewritten. Fails with an
+/// error of kind `io::ErrorKind::InvalidInput` if `from` is empty.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("try_replace_in_file.txt", "aaa");
+///
+/// assert_eq!(fsutils::try_replace_in_file("try_replace_in_file.txt", "aa", "b").unwrap(), 1);
+/// assert_eq!(fsutils::read_file("try_replace_in_file.txt"), "ba");
+/// assert_eq!(fsutils::try_replace_in_file("try_replace_in_file.txt", "zzz", "y").unwrap(), 0);
+/// assert!(fsutils::try_replace_in_file("try_replace_in_file.txt", "", "y").is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm("try_replace_in_file.txt");
+/// ```
+pub fn try_replace_in_file<P: AsRef<Path>>(path: P, from: &str, to: &str) -> Result<usize> {
+    let path = path.as_ref();
+    if from.is_empty() {
+        return Err(FsError::from_io(path, io::Error::new(io::ErrorKind::InvalidInput, "cannot replace an empty string")));
+    }
+    Op::new("replace_in_file", path).destructive(true).run(0, || {
+        let mut count = 0;
+        replace_file_if(path, |temp| {
+            let reader = File::open(path).at(path)?;
+            count = replace_stream(reader, BufWriter::new(temp), from.as_bytes(), to.as_bytes()).at(path)?;
+            Ok(count > 0)
+        })?;
+        info!("Made {} replacements in {}", count, path.display());
+        Ok(count)
+    })
+}
+
+/// Copies `reader` to `writer`, replacing each `from` with `to`, and returns
+/// the number of replacements.
+fn replace_stream<R: Read, W: Write>(mut reader: R, mut writer: W, from: &[u8], to: &[u8]) -> io::Result<usize> {
+    let mut count = 0;
+    let mut pending = Vec::new();
+    let mut chunk = vec![0; REPLACE_CHUNK];
+    loop {
+        let n = match reader.read(&mut chunk) {
+            Ok(n) => n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        pending.extend_from_slice(&chunk[..n]);
+        let mut start = 0;
+        while let Some(i) = pending[start..].windows(from.len()).position(|window| window == from) {
+            writer.write_all(&pending[start..start + i])?;
+            writer.write_all(to)?;
+            start += i + from.len();
+            count += 1;
+        }
+        // The end of the chunk may be the start of a match that the next one completes
+        let keep = if n == 0 { 0 } else { (from.len() - 1).min(pending.len() - start) };
+        let end = pending.len() - keep;
+        writer.write_all(&pending[start..end])?;
+        pending.drain(..end);
+        if n == 0 {
+            writer.flush()?;
+            return Ok(count);
+        }
+    }
+}
+
+/// Replaces every match of the regular expression `pattern` in a file
+/// and returns the number of replacements, or `None` on failure.
+///
+/// Requires the `regex` feature.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("replace_regex.ini", "timeout=30\nretries=3\n");
+///
+/// assert_eq!(fsutils::replace_regex_in_file("replace_regex.ini", r"=(\d+)", "= $1"), Some(2));
+/// assert_eq!(fsutils::read_file("replace_regex.ini"), "timeout= 30\nretries= 3\n");
+///
+/// # // Cleanup
+/// # fsutils::rm("replace_regex.ini");
+/// ```
+#[cfg(feature = "regex")]
+pub fn replace_regex_in_file<P: AsRef<Path>>(path: P, pattern: &str, replacement: &str) -> Option<usize> {
+    match try_replace_regex_in_file(path, pattern, replacement) {
+        Ok(count) => Some(count),
+        Err(e) => {
+            error!("Cannot replace in file {}", e);
+            None
+        }
+    }
+}
+
+/// Replaces every match of the regular expression `pattern` in a file and
+/// returns the number of replacements.
+///
+/// See `fsutils::replace_regex_in_file_with` for details.
+///
+/// Requires the `regex` feature.
+///
+/// ## Usage:
+///
+/// ```
+/// use std::io::ErrorKind;
+///
+/// fsutils::write_file("try_replace_regex.txt", "2020-01-31");
+///
+/// assert_eq!(fsutils::try_replace_regex_in_file("try_replace_regex.txt", r"(\d+)-(\d+)-(\d+)", "$3/$2/$1").unwrap(), 1);
+/// assert_eq!(fsutils::read_file("try_replace_regex.txt"), "31/01/2020");
+/// let invalid = fsutils::try_replace_regex_in_file("try_replace_regex.txt", "(", "");
+/// assert_eq!(invalid.unwrap_err().kind(), ErrorKind::InvalidInput);
+///
+/// # // Cleanup
+/// # fsutils::rm("try_replace_regex.txt");
+/// ```
+#[cfg(feature = "regex")]
+pub fn try_replace_regex_in_file<P: AsRef<Path>>(path: P, pattern: &str, replacement: &str) -> Result<usize> {
+    replace_regex_in_file_with(path, pattern, replacement, None)
+}
+
+/// Replaces every match of the regular expression `pattern` in a file,
+/// keeping the original under the same name plus `backup` if one is given,
+/// like `sed -i.bak`, and returns the number of replacements.
+///
+/// `replacement` may refer to capture groups as `$1` or `$name`; see
+/// [`regex::Regex::replace`](https://docs.rs/regex/1/regex/struct.Regex.html#method.replace)
+/// for the full syntax. Matching works on bytes, so files that are not valid
+/// UTF-8 are fine. The file is read into memory, and written back through a
+/// temporary file that replaces it, so a crash leaves the original untouched.
+/// A file without any matches is not rewritten and gets no backup. Fails with
+/// an error of kind `io::ErrorKind::InvalidInput` if `pattern` is not a valid
+/// regular expression.
+///
+/// Requires the `regex` feature.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::mkdir("replace_regex_with");
+/// fsutils::write_file("replace_regex_with/app.conf", "host = old.example.com");
+///
+/// let count = fsutils::replace_regex_in_file_with("replace_regex_with/app.conf", r"old\.(\w+)", "new.$1", Some(".bak"));
+/// assert_eq!(count.unwrap(), 1);
+/// assert_eq!(fsutils::read_file("replace_regex_with/app.conf"), "host = new.example.com");
+/// assert_eq!(fsutils::read_file("replace_regex_with/app.conf.bak"), "host = old.example.com");
+///
+/// # // Cleanup
+/// # fsutils::rm_r("replace_regex_with");
+/// ```
+#[cfg(feature = "regex")]
+pub fn replace_regex_in_file_with<P: AsRef<Path>>(
+    path: P,
+    pattern: &str,
+    replacement: &str,
+    backup: Option<&str>,
+) -> Result<usize> {
+    let path = path.as_ref();
+    let regex = regex::bytes::Regex::new(pattern)
+        .map_err(|e| FsError::from_io(path, io::Error::new(io::ErrorKind::InvalidInput, e)))?;
+    Op::new("replace_regex_in_file", path).destructive(true).run(0, || {
+        let contents = std::fs::read(path).at(path)?;
+        let count = regex.find_iter(&contents).count();
+        if count == 0 {
+            return Ok(0);
+        }
+        if let Some(suffix) = backup {
+            let mut name = path.as_os_str().to_os_string();
+            name.push(suffix);
+            std::fs::copy(path, &name).at2(path, &name)?;
+        }
+        let replaced = regex.replace_all(&contents, replacement.as_bytes());
+        replace_file(path, |temp| temp.write_all(&replaced).at(path))?;
+        info!("Made {} replacements in {}", count, path.display());
+        Ok(count)
+    })
+}
@@ -0,0 +1,203 @@
+// Copyright 2020 Jared Forth.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Reading text that is not UTF-8.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::error::{FsError, IoResultExt, Result};
+
+/// A text encoding understood by `fsutils::read_file_with_encoding`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Encoding {
+    /// UTF-8, with or without a byte order mark.
+    Utf8,
+    /// Little-endian UTF-16, as written by most Windows tools.
+    Utf16Le,
+    /// Big-endian UTF-16.
+    Utf16Be,
+    /// ISO-8859-1, where every byte is the code point of the same value.
+    Latin1,
+    /// Windows-1252, Latin-1 with printable characters such as `€` and `“`
+    /// in place of the C1 control codes.
+    Windows1252,
+}
+
+/// The characters Windows-1252 puts at 0x80 to 0x9F. The five unassigned
+/// bytes keep their Latin-1 meaning, as browsers do.
+const WINDOWS_1252: [char; 32] = [
+    '\u{20AC}', '\u{81}', '\u{201A}', '\u{0192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{02C6}', '\u{2030}', '\u{0160}', '\u{2039}', '\u{0152}', '\u{8D}', '\u{017D}', '\u{8F}',
+    '\u{90}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{02DC}', '\u{2122}', '\u{0161}', '\u{203A}', '\u{0153}', '\u{9D}', '\u{017E}', '\u{0178}',
+];
+
+impl Encoding {
+    /// Decodes `bytes`, dropping a leading byte order mark.
+    ///
+    /// Fails with an error of kind `io::ErrorKind::InvalidData` if `bytes` are
+    /// not valid in this encoding. Latin-1 and Windows-1252 accept any input.
+    ///
+    /// ## Usage:
+    ///
+    /// ```
+    /// use fsutils::Encoding;
+    ///
+    /// assert_eq!(Encoding::Utf16Le.decode(b"h\0i\0").unwrap(), "hi");
+    /// assert_eq!(Encoding::Windows1252.decode(b"\x80 5").unwrap(), "€ 5");
+    /// assert!(Encoding::Utf8.decode(b"\xff").is_err());
+    /// ```
+    pub fn decode(self, bytes: &[u8]) -> io::Result<String> {
+        match self {
+            Encoding::Utf8 => {
+                let bytes = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(bytes);
+                String::from_utf8(bytes.to_vec()).map_err(|e| invalid_data(e.to_string()))
+            }
+            Encoding::Utf16Le => decode_utf16(bytes.strip_prefix(b"\xFF\xFE").unwrap_or(bytes), u16::from_le_bytes),
+            Encoding::Utf16Be => decode_utf16(bytes.strip_prefix(b"\xFE\xFF").unwrap_or(bytes), u16::from_be_bytes),
+            Encoding::Latin1 => Ok(bytes.iter().map(|&b| b as char).collect()),
+            Encoding::Windows1252 => Ok(bytes
+                .iter()
+                .map(|&b| match b {
+                    0x80..=0x9F => WINDOWS_1252[(b - 0x80) as usize],
+                    _ => b as char,
+                })
+                .collect()),
+        }
+    }
+}
+
+fn decode_utf16(bytes: &[u8], unit: fn([u8; 2]) -> u16) -> io::Result<String> {
+    if !bytes.len().is_multiple_of(2) {
+        return Err(invalid_data("UTF-16 text with an odd number of bytes"));
+    }
+    let units = bytes.chunks(2).map(|pair| unit([pair[0], pair[1]]));
+    char::decode_utf16(units).collect::<std::result::Result<String, _>>().map_err(|e| invalid_data(e.to_string()))
+}
+
+fn invalid_data<E: Into<Box<dyn std::error::Error + Send + Sync>>>(error: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+/// Guesses the encoding of `bytes`.
+///
+/// A byte order mark decides outright. Otherwise text where nearly every other
+/// byte is zero is taken to be UTF-16 without a mark, which catches
+/// ASCII-heavy files saved by Windows tools, and text that is valid UTF-8 is
+/// taken to be UTF-8. Anything else is assumed to be a single-byte legacy
+/// encoding: Windows-1252 if it uses the 0x80 to 0x9F range, Latin-1 if not.
+/// Since single-byte encodings accept any input, the guess can be wrong for
+/// text in, say, Cyrillic code pages, but it is never rejected.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::{detect_encoding, Encoding};
+///
+/// assert_eq!(detect_encoding(b"\xFF\xFEh\0i\0"), Encoding::Utf16Le);
+/// assert_eq!(detect_encoding("naïve".as_bytes()), Encoding::Utf8);
+/// assert_eq!(detect_encoding(b"\0h\0i"), Encoding::Utf16Be);
+/// assert_eq!(detect_encoding(b"na\xefve"), Encoding::Latin1);
+/// assert_eq!(detect_encoding(b"\x93quoted\x94"), Encoding::Windows1252);
+/// ```
+pub fn detect_encoding(bytes: &[u8]) -> Encoding {
+    if bytes.starts_with(b"\xEF\xBB\xBF") {
+        return Encoding::Utf8;
+    }
+    if bytes.starts_with(b"\xFF\xFE") {
+        return Encoding::Utf16Le;
+    }
+    if bytes.starts_with(b"\xFE\xFF") {
+        return Encoding::Utf16Be;
+    }
+    if let Some(encoding) = looks_like_utf16(bytes) {
+        return encoding;
+    }
+    if std::str::from_utf8(bytes).is_ok() {
+        return Encoding::Utf8;
+    }
+    if bytes.iter().any(|b| (0x80..=0x9F).contains(b)) {
+        Encoding::Windows1252
+    } else {
+        Encoding::Latin1
+    }
+}
+
+/// Recognises UTF-16 without a byte order mark by its zero bytes: in mostly
+/// ASCII text one byte of almost every pair is zero, always the same one.
+fn looks_like_utf16(bytes: &[u8]) -> Option<Encoding> {
+    if bytes.len() < 2 || !bytes.len().is_multiple_of(2) {
+        return None;
+    }
+    let pairs = bytes.len() / 2;
+    let (mut even, mut odd) = (0, 0);
+    for pair in bytes.chunks(2) {
+        even += (pair[0] == 0) as usize;
+        odd += (pair[1] == 0) as usize;
+    }
+    // At least three quarters of the pairs, and the other position almost never
+    if odd * 4 >= pairs * 3 && even * 10 <= pairs {
+        Some(Encoding::Utf16Le)
+    } else if even * 4 >= pairs * 3 && odd * 10 <= pairs {
+        Some(Encoding::Utf16Be)
+    } else {
+        None
+    }
+}
+
+/// Reads a file in the given encoding and returns its contents as a `String`.
+///
+/// A byte order mark at the start of the file is dropped. Fails with an error
+/// of kind `io::ErrorKind::InvalidData` if the file is not valid in `encoding`.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::Encoding;
+///
+/// fsutils::create_file_bytes("read_with_encoding.txt", b"caf\xe9");
+///
+/// assert_eq!(fsutils::read_file_with_encoding("read_with_encoding.txt", Encoding::Latin1).unwrap(), "café");
+/// assert!(fsutils::read_file_with_encoding("read_with_encoding.txt", Encoding::Utf8).is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm("read_with_encoding.txt");
+/// ```
+pub fn read_file_with_encoding<P: AsRef<Path>>(path: P, encoding: Encoding) -> Result<String> {
+    let path = path.as_ref();
+    let bytes = fs::read(path).at(path)?;
+    encoding.decode(&bytes).map_err(|e| FsError::from_io(path, e))
+}
+
+/// Reads a file in whatever encoding `fsutils::detect_encoding` settles on,
+/// and returns its contents together with that encoding.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::Encoding;
+///
+/// fsutils::create_file_bytes("read_detect_encoding.txt", b"\xFF\xFEo\0k\0");
+///
+/// let (text, encoding) = fsutils::read_file_detect_encoding("read_detect_encoding.txt").unwrap();
+/// assert_eq!(text, "ok");
+/// assert_eq!(encoding, Encoding::Utf16Le);
+///
+/// # // Cleanup
+/// # fsutils::rm("read_detect_encoding.txt");
+/// ```
+pub fn read_file_detect_encoding<P: AsRef<Path>>(path: P) -> Result<(String, Encoding)> {
+    let path = path.as_ref();
+    let bytes = fs::read(path).at(path)?;
+    let encoding = detect_encoding(&bytes);
+    let text = encoding.decode(&bytes).map_err(|e| FsError::from_io(path, e))?;
+    Ok((text, encoding))
+}
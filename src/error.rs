@@ -0,0 +1,181 @@
+// Copyright 2020 Jared Forth.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! The error type returned by the `try_` family of functions.
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A specialized `Result` type for fsutils operations.
+pub type Result<T> = std::result::Result<T, FsError>;
+
+/// An error from a filesystem operation, carrying the path(s) involved.
+///
+/// Errors reported by the operating system are classified into the variants
+/// below; anything that does not fit ends up in `FsError::Io`. The original
+/// `io::Error` is always available through `Error::source`.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::FsError;
+///
+/// match fsutils::try_rm("a_very_1234_unlikely_9876_filename") {
+///     Err(FsError::NotFound { path, .. }) => assert_eq!(path.to_str(), Some("a_very_1234_unlikely_9876_filename")),
+///     _ => unreachable!(),
+/// }
+/// ```
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum FsError {
+    /// The path does not exist.
+    NotFound { path: PathBuf, source: io::Error },
+    /// The process lacks permission to access the path.
+    PermissionDenied { path: PathBuf, source: io::Error },
+    /// The path already exists.
+    AlreadyExists { path: PathBuf, source: io::Error },
+    /// The directory is not empty.
+    NotEmpty { path: PathBuf, source: io::Error },
+    /// A directory was expected but the path is something else.
+    NotADirectory { path: PathBuf, source: io::Error },
+    /// A rename was attempted across filesystems.
+    CrossDevice { from: PathBuf, to: PathBuf, source: io::Error },
+    /// The path is not acceptable for the requested operation.
+    InvalidPath { path: PathBuf, reason: String },
+    /// The confirm hook installed with `fsutils::set_confirm` declined the operation.
+    Declined { path: PathBuf },
+    /// Any other I/O error.
+    Io { path: PathBuf, source: io::Error },
+}
+
+impl FsError {
+    /// Classifies an `io::Error` that occurred while operating on `path`.
+    pub(crate) fn from_io<P: AsRef<Path>>(path: P, source: io::Error) -> FsError {
+        let path = path.as_ref().to_path_buf();
+        match source.kind() {
+            io::ErrorKind::NotFound => FsError::NotFound { path, source },
+            io::ErrorKind::PermissionDenied => FsError::PermissionDenied { path, source },
+            io::ErrorKind::AlreadyExists => FsError::AlreadyExists { path, source },
+            io::ErrorKind::DirectoryNotEmpty => FsError::NotEmpty { path, source },
+            io::ErrorKind::NotADirectory => FsError::NotADirectory { path, source },
+            _ => FsError::Io { path, source },
+        }
+    }
+
+    /// Classifies an `io::Error` from an operation with a source and a destination.
+    ///
+    /// Conflicts are attributed to the destination, everything else to the source.
+    pub(crate) fn from_io2<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q, source: io::Error) -> FsError {
+        match source.kind() {
+            io::ErrorKind::CrossesDevices => FsError::CrossDevice {
+                from: from.as_ref().to_path_buf(),
+                to: to.as_ref().to_path_buf(),
+                source,
+            },
+            io::ErrorKind::AlreadyExists | io::ErrorKind::DirectoryNotEmpty => FsError::from_io(to, source),
+            _ => FsError::from_io(from, source),
+        }
+    }
+
+    /// Builds an `AlreadyExists` error for checks done before touching the disk.
+    pub(crate) fn already_exists<P: AsRef<Path>>(path: P) -> FsError {
+        let source = io::Error::new(io::ErrorKind::AlreadyExists, "path already exists");
+        FsError::from_io(path, source)
+    }
+
+    /// Builds an `InvalidPath` error.
+    pub(crate) fn invalid_path<P: AsRef<Path>, S: Into<String>>(path: P, reason: S) -> FsError {
+        FsError::InvalidPath { path: path.as_ref().to_path_buf(), reason: reason.into() }
+    }
+
+    /// Returns the offending path, or the source path for two-path errors.
+    pub fn path(&self) -> &Path {
+        match self {
+            FsError::NotFound { path, .. }
+            | FsError::PermissionDenied { path, .. }
+            | FsError::AlreadyExists { path, .. }
+            | FsError::NotEmpty { path, .. }
+            | FsError::NotADirectory { path, .. }
+            | FsError::InvalidPath { path, .. }
+            | FsError::Declined { path }
+            | FsError::Io { path, .. } => path,
+            FsError::CrossDevice { from, .. } => from,
+        }
+    }
+
+    /// Returns the underlying `io::Error`, if there is one.
+    pub fn io_error(&self) -> Option<&io::Error> {
+        match self {
+            FsError::NotFound { source, .. }
+            | FsError::PermissionDenied { source, .. }
+            | FsError::AlreadyExists { source, .. }
+            | FsError::NotEmpty { source, .. }
+            | FsError::NotADirectory { source, .. }
+            | FsError::CrossDevice { source, .. }
+            | FsError::Io { source, .. } => Some(source),
+            FsError::InvalidPath { .. } | FsError::Declined { .. } => None,
+        }
+    }
+
+    /// Returns the `io::ErrorKind` that best describes this error.
+    pub fn kind(&self) -> io::ErrorKind {
+        match self {
+            FsError::InvalidPath { .. } => io::ErrorKind::InvalidInput,
+            _ => self.io_error().map_or(io::ErrorKind::Other, io::Error::kind),
+        }
+    }
+}
+
+impl fmt::Display for FsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FsError::NotFound { path, source } => write!(f, "{} not found: {}", path.display(), source),
+            FsError::PermissionDenied { path, source } => write!(f, "permission denied for {}: {}", path.display(), source),
+            FsError::AlreadyExists { path, source } => write!(f, "{} already exists: {}", path.display(), source),
+            FsError::NotEmpty { path, source } => write!(f, "directory {} is not empty: {}", path.display(), source),
+            FsError::NotADirectory { path, source } => write!(f, "{} is not a directory: {}", path.display(), source),
+            FsError::CrossDevice { from, to, source } => {
+                write!(f, "cannot move {} to {} across devices: {}", from.display(), to.display(), source)
+            }
+            FsError::InvalidPath { path, reason } => write!(f, "invalid path {}: {}", path.display(), reason),
+            FsError::Declined { path } => write!(f, "operation on {} was declined", path.display()),
+            FsError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+        }
+    }
+}
+
+impl Error for FsError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.io_error().map(|e| e as &(dyn Error + 'static))
+    }
+}
+
+impl From<FsError> for io::Error {
+    fn from(e: FsError) -> io::Error {
+        let kind = e.kind();
+        io::Error::new(kind, e)
+    }
+}
+
+/// Attaches path context to `io::Result`s.
+pub(crate) trait IoResultExt<T> {
+    fn at<P: AsRef<Path>>(self, path: P) -> Result<T>;
+    fn at2<P: AsRef<Path>, Q: AsRef<Path>>(self, from: P, to: Q) -> Result<T>;
+}
+
+impl<T> IoResultExt<T> for io::Result<T> {
+    fn at<P: AsRef<Path>>(self, path: P) -> Result<T> {
+        self.map_err(|e| FsError::from_io(path, e))
+    }
+
+    fn at2<P: AsRef<Path>, Q: AsRef<Path>>(self, from: P, to: Q) -> Result<T> {
+        self.map_err(|e| FsError::from_io2(from, to, e))
+    }
+}
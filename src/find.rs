@@ -0,0 +1,530 @@
+// Copyright 2020 Jared Forth.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Finding files by name, type, size and age.
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::error::{IoResultExt, Result};
+use crate::glob::Pattern;
+use crate::link::FollowSymlinks;
+use crate::meta::FileKind;
+use crate::walk::{walk_with, Walk, WalkEntry, WalkOptions};
+
+/// A search of a directory tree, like `find(1)`.
+///
+/// Every filter that is set must match for an entry to be found; none are
+/// set by default, so everything below the root and the root itself is
+/// found. `run` collects the paths and `iter` yields them as the walk goes.
+///
+/// ## Usage:
+///
+/// ```
+/// use std::path::Path;
+/// use std::time::Duration;
+/// use fsutils::{FileKind, Find};
+///
+/// fsutils::mkdir("find_dir/logs/old");
+/// fsutils::write_file("find_dir/logs/app.log", "0123456789");
+/// fsutils::write_file("find_dir/logs/old/app.log", "");
+/// fsutils::write_file("find_dir/logs/notes.txt", "0123456789");
+///
+/// let week = Duration::from_secs(7 * 24 * 60 * 60);
+/// let found = Find::new("find_dir")
+///     .name("*.log")
+///     .file_type(FileKind::File)
+///     .larger_than(5)
+///     .modified_within(week)
+///     .run()
+///     .unwrap();
+/// assert_eq!(found, [Path::new("find_dir/logs/app.log")]);
+///
+/// # // Cleanup
+/// # fsutils::rm_r("find_dir");
+/// ```
+#[derive(Clone, Debug)]
+pub struct Find {
+    root: PathBuf,
+    walk: WalkOptions,
+    min_depth: usize,
+    names: Vec<String>,
+    paths: Vec<String>,
+    kind: Option<FileKind>,
+    larger_than: Option<u64>,
+    smaller_than: Option<u64>,
+    modified_within: Option<Duration>,
+    older_than: Option<Duration>,
+}
+
+impl Find {
+    /// Creates a search of the tree at `root` without any filters.
+    pub fn new<P: AsRef<Path>>(root: P) -> Find {
+        Find {
+            root: root.as_ref().to_path_buf(),
+            walk: WalkOptions::new(),
+            min_depth: 0,
+            names: Vec::new(),
+            paths: Vec::new(),
+            kind: None,
+            larger_than: None,
+            smaller_than: None,
+            modified_within: None,
+            older_than: None,
+        }
+    }
+
+    /// Only find entries whose file name matches the glob `pattern`, like
+    /// `find -name`. When given several times, any of them may match.
+    ///
+    /// An invalid pattern is reported by `run` or `iter`.
+    pub fn name(&mut self, pattern: &str) -> &mut Find {
+        self.names.push(pattern.to_string());
+        self
+    }
+
+    /// Only find entries whose path relative to the root matches the glob
+    /// `pattern`, like `find -path` but without the root. When given several
+    /// times, any of them may match.
+    ///
+    /// An invalid pattern is reported by `run` or `iter`.
+    pub fn path(&mut self, pattern: &str) -> &mut Find {
+        self.paths.push(pattern.to_string());
+        self
+    }
+
+    /// Only find entries of this kind, like `find -type`. A symbolic link
+    /// that is followed has the kind of what it points to.
+    pub fn file_type(&mut self, kind: FileKind) -> &mut Find {
+        self.kind = Some(kind);
+        self
+    }
+
+    /// Only find entries of more than `bytes` bytes, like `find -size +N`.
+    ///
+    /// Directories have a size of their own too, so this is usually combined
+    /// with `file_type(FileKind::File)`.
+    pub fn larger_than(&mut self, bytes: u64) -> &mut Find {
+        self.larger_than = Some(bytes);
+        self
+    }
+
+    /// Only find entries of less than `bytes` bytes, like `find -size -N`.
+    pub fn smaller_than(&mut self, bytes: u64) -> &mut Find {
+        self.smaller_than = Some(bytes);
+        self
+    }
+
+    /// Only find entries modified less than `age` ago, like `find -mtime -N`.
+    pub fn modified_within(&mut self, age: Duration) -> &mut Find {
+        self.modified_within = Some(age);
+        self
+    }
+
+    /// Only find entries modified more than `age` ago, like `find -mtime +N`.
+    pub fn older_than(&mut self, age: Duration) -> &mut Find {
+        self.older_than = Some(age);
+        self
+    }
+
+    /// Do not find entries less than `depth` levels below the root, like
+    /// `find -mindepth`; `1` leaves out the root itself.
+    pub fn min_depth(&mut self, depth: usize) -> &mut Find {
+        self.min_depth = depth;
+        self
+    }
+
+    /// Only go `depth` levels down, like `find -maxdepth`.
+    pub fn max_depth(&mut self, depth: usize) -> &mut Find {
+        self.walk.max_depth(depth);
+        self
+    }
+
+    /// Which symbolic links to directories to walk into, like `find -L`.
+    pub fn follow(&mut self, follow: FollowSymlinks) -> &mut Find {
+        self.walk.follow(follow);
+        self
+    }
+
+    /// Skip what `.gitignore` and `.ignore` files say to ignore, as
+    /// described at `WalkOptions::gitignore`.
+    ///
+    /// ## Usage:
+    ///
+    /// ```
+    /// use fsutils::Find;
+    ///
+    /// fsutils::mkdir("find_gitignore_dir/node_modules/left-pad");
+    /// fsutils::write_file("find_gitignore_dir/node_modules/left-pad/index.js", "");
+    /// fsutils::write_file("find_gitignore_dir/index.js", "");
+    /// fsutils::write_file("find_gitignore_dir/.ignore", "node_modules\n");
+    ///
+    /// assert_eq!(Find::new("find_gitignore_dir").name("*.js").run().unwrap().len(), 2);
+    /// assert_eq!(Find::new("find_gitignore_dir").name("*.js").gitignore(true).run().unwrap().len(), 1);
+    ///
+    /// # // Cleanup
+    /// # fsutils::rm_r("find_gitignore_dir");
+    /// ```
+    pub fn gitignore(&mut self, gitignore: bool) -> &mut Find {
+        self.walk.gitignore(gitignore);
+        self
+    }
+
+    /// Runs the search and returns the paths found, in the order of
+    /// `fsutils::walk`.
+    ///
+    /// Fails on the first entry that cannot be read; use `iter` to go on past
+    /// such entries.
+    ///
+    /// ## Usage:
+    ///
+    /// ```
+    /// use fsutils::{FileKind, Find};
+    ///
+    /// fsutils::mkdir("find_run_dir/a");
+    /// fsutils::write_file("find_run_dir/a/big.bin", &"x".repeat(2048));
+    ///
+    /// assert_eq!(Find::new("find_run_dir").file_type(FileKind::File).larger_than(1024).run().unwrap().len(), 1);
+    /// assert_eq!(Find::new("find_run_dir").min_depth(1).max_depth(1).run().unwrap().len(), 1);
+    /// assert!(Find::new("find_run_dir").name("[").run().is_err());
+    /// assert!(Find::new("a_very_1234_unlikely_9876_dirname").run().is_err());
+    ///
+    /// # // Cleanup
+    /// # fsutils::rm_r("find_run_dir");
+    /// ```
+    pub fn run(&self) -> Result<Vec<PathBuf>> {
+        self.iter()?.collect()
+    }
+
+    /// Starts the search and returns an iterator over the paths found.
+    ///
+    /// Invalid patterns are reported up front. An entry that cannot be read
+    /// is yielded as an error and the search goes on.
+    ///
+    /// ## Usage:
+    ///
+    /// ```
+    /// use fsutils::Find;
+    ///
+    /// fsutils::mkdir("find_iter_dir/src");
+    /// fsutils::write_file("find_iter_dir/src/main.rs", "");
+    /// fsutils::write_file("find_iter_dir/src/lib.rs", "");
+    ///
+    /// let first = Find::new("find_iter_dir").path("src/*.rs").iter().unwrap().next().unwrap().unwrap();
+    /// assert!(first.ends_with("lib.rs"));
+    ///
+    /// # // Cleanup
+    /// # fsutils::rm_r("find_iter_dir");
+    /// ```
+    pub fn iter(&self) -> Result<FindIter> {
+        let compile = |patterns: &[String]| patterns.iter().map(|p| Pattern::new(p)).collect::<Result<Vec<_>>>();
+        Ok(FindIter {
+            names: compile(&self.names)?,
+            paths: compile(&self.paths)?,
+            now: SystemTime::now(),
+            walk: walk_with(&self.root, &self.walk),
+            find: self.clone(),
+        })
+    }
+}
+
+/// The iterator returned by `Find::iter`.
+#[derive(Debug)]
+pub struct FindIter {
+    find: Find,
+    names: Vec<Pattern>,
+    paths: Vec<Pattern>,
+    /// The time ages are measured from.
+    now: SystemTime,
+    walk: Walk,
+}
+
+impl FindIter {
+    fn matches(&self, entry: &WalkEntry) -> Result<bool> {
+        let find = &self.find;
+        if entry.depth < find.min_depth || find.kind.is_some_and(|kind| kind != entry.kind) {
+            return Ok(false);
+        }
+        if !self.names.is_empty() {
+            let name = entry.path.file_name().unwrap_or(entry.path.as_os_str()).to_string_lossy();
+            if !self.names.iter().any(|pattern| pattern.matches(&name)) {
+                return Ok(false);
+            }
+        }
+        if !self.paths.is_empty() {
+            let relative = entry.path.strip_prefix(&find.root).unwrap_or(&entry.path);
+            if !self.paths.iter().any(|pattern| pattern.matches(&crate::glob::to_slash(relative))) {
+                return Ok(false);
+            }
+        }
+        let needs_size = find.larger_than.is_some() || find.smaller_than.is_some();
+        let needs_time = find.modified_within.is_some() || find.older_than.is_some();
+        if !needs_size && !needs_time {
+            return Ok(true);
+        }
+        let path = &entry.path;
+        let meta = if entry.is_symlink && entry.kind != FileKind::Symlink {
+            path.metadata().at(path)?
+        } else {
+            path.symlink_metadata().at(path)?
+        };
+        let len = meta.len();
+        if find.larger_than.is_some_and(|bytes| len <= bytes) || find.smaller_than.is_some_and(|bytes| len >= bytes) {
+            return Ok(false);
+        }
+        if needs_time {
+            let age = self.now.duration_since(meta.modified().at(path)?).unwrap_or_default();
+            if find.modified_within.is_some_and(|max| age >= max) || find.older_than.is_some_and(|min| age <= min) {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
+
+impl Iterator for FindIter {
+    type Item = Result<PathBuf>;
+
+    fn next(&mut self) -> Option<Result<PathBuf>> {
+        loop {
+            let entry = match self.walk.next()? {
+                Ok(entry) => entry,
+                Err(e) => return Some(Err(e)),
+            };
+            match self.matches(&entry) {
+                Ok(true) => return Some(Ok(entry.path)),
+                Ok(false) => {}
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+/// Options for `fsutils::newest_file_with` and `fsutils::oldest_file_with`.
+///
+/// By default only the files directly in the directory are looked at, all of
+/// them.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::PickOptions;
+///
+/// let mut opts = PickOptions::new();
+/// opts.recursive(true).include("*.tar.gz").unwrap();
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct PickOptions {
+    recursive: bool,
+    include: Vec<Pattern>,
+}
+
+impl PickOptions {
+    /// Creates the default options.
+    pub fn new() -> PickOptions {
+        PickOptions::default()
+    }
+
+    /// Look at the files in subdirectories too.
+    pub fn recursive(&mut self, recursive: bool) -> &mut PickOptions {
+        self.recursive = recursive;
+        self
+    }
+
+    /// Only look at files matching `pattern`. May be given several times.
+    pub fn include(&mut self, pattern: &str) -> Result<&mut PickOptions> {
+        self.include.push(Pattern::new(pattern)?);
+        Ok(self)
+    }
+
+    /// The file whose modification time `better` prefers over all others.
+    fn pick(&self, dir: &Path, better: fn(SystemTime, SystemTime) -> bool) -> Result<Option<PathBuf>> {
+        let mut opts = WalkOptions::new();
+        if !self.recursive {
+            opts.max_depth(1);
+        }
+        let mut best: Option<(PathBuf, SystemTime)> = None;
+        for entry in walk_with(dir, &opts) {
+            let entry = entry?;
+            if entry.depth == 0 || entry.kind != FileKind::File {
+                continue;
+            }
+            let relative = entry.path.strip_prefix(dir).unwrap_or(&entry.path);
+            if !self.include.is_empty() && !self.include.iter().any(|p| p.matches_path(relative)) {
+                continue;
+            }
+            let modified = entry.path.symlink_metadata().and_then(|meta| meta.modified()).at(&entry.path)?;
+            if best.as_ref().is_none_or(|(_, time)| better(modified, *time)) {
+                best = Some((entry.path, modified));
+            }
+        }
+        Ok(best.map(|(path, _)| path))
+    }
+}
+
+/// Returns the most recently modified file directly in `dir`, or `None` if
+/// there is none or it cannot be read.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::mkdir("newest_file_dir");
+/// fsutils::write_file("newest_file_dir/backup.tar", "");
+///
+/// assert!(fsutils::newest_file("newest_file_dir").unwrap().ends_with("backup.tar"));
+///
+/// # // Cleanup
+/// # fsutils::rm_r("newest_file_dir");
+/// ```
+pub fn newest_file<P: AsRef<Path>>(dir: P) -> Option<PathBuf> {
+    match try_newest_file(dir) {
+        Ok(path) => path,
+        Err(e) => {
+            error!("Cannot find newest file {}", e);
+            None
+        }
+    }
+}
+
+/// Returns the most recently modified file directly in `dir`, or `None` if it
+/// has no files.
+///
+/// See `fsutils::newest_file_with` for the details.
+///
+/// ## Usage:
+///
+/// ```
+/// use std::time::{Duration, SystemTime};
+///
+/// fsutils::mkdir("try_newest_file_dir/sub");
+/// fsutils::write_file("try_newest_file_dir/old.log", "");
+/// fsutils::write_file("try_newest_file_dir/new.log", "");
+/// let hour_ago = SystemTime::now() - Duration::from_secs(60 * 60);
+/// fsutils::try_touch_at("try_newest_file_dir/old.log", hour_ago, hour_ago).unwrap();
+///
+/// let newest = fsutils::try_newest_file("try_newest_file_dir").unwrap().unwrap();
+/// assert!(newest.ends_with("new.log"));
+/// assert_eq!(fsutils::try_newest_file("try_newest_file_dir/sub").unwrap(), None);
+/// assert!(fsutils::try_newest_file("a_very_1234_unlikely_9876_dirname").is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm_r("try_newest_file_dir");
+/// ```
+pub fn try_newest_file<P: AsRef<Path>>(dir: P) -> Result<Option<PathBuf>> {
+    newest_file_with(dir, &PickOptions::new())
+}
+
+/// Returns the most recently modified file in `dir` that `opts` allow, or
+/// `None` if there is none.
+///
+/// Only regular files count; symbolic links below `dir` are not followed.
+/// Patterns are matched against the path relative to `dir`, or just the file
+/// name for patterns without a `/`. Among files modified at the same time,
+/// the first in the order of `fsutils::walk` is returned. Fails on the first
+/// entry that cannot be read.
+///
+/// ## Usage:
+///
+/// ```
+/// use std::time::{Duration, SystemTime};
+/// use fsutils::PickOptions;
+///
+/// fsutils::mkdir("newest_file_with_dir/2020");
+/// fsutils::write_file("newest_file_with_dir/2020/db.sql.gz", "");
+/// fsutils::write_file("newest_file_with_dir/notes.txt", "");
+/// let hour_ago = SystemTime::now() - Duration::from_secs(60 * 60);
+/// fsutils::try_touch_at("newest_file_with_dir/notes.txt", hour_ago, hour_ago).unwrap();
+///
+/// let mut opts = PickOptions::new();
+/// assert!(fsutils::newest_file_with("newest_file_with_dir", &opts).unwrap().unwrap().ends_with("notes.txt"));
+///
+/// opts.recursive(true).include("*.gz").unwrap();
+/// let newest = fsutils::newest_file_with("newest_file_with_dir", &opts).unwrap().unwrap();
+/// assert!(newest.ends_with("2020/db.sql.gz"));
+///
+/// # // Cleanup
+/// # fsutils::rm_r("newest_file_with_dir");
+/// ```
+pub fn newest_file_with<P: AsRef<Path>>(dir: P, opts: &PickOptions) -> Result<Option<PathBuf>> {
+    opts.pick(dir.as_ref(), |a, b| a > b)
+}
+
+/// Returns the least recently modified file directly in `dir`, or `None` if
+/// there is none or it cannot be read.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::mkdir("oldest_file_dir");
+/// fsutils::write_file("oldest_file_dir/backup.tar", "");
+///
+/// assert!(fsutils::oldest_file("oldest_file_dir").unwrap().ends_with("backup.tar"));
+///
+/// # // Cleanup
+/// # fsutils::rm_r("oldest_file_dir");
+/// ```
+pub fn oldest_file<P: AsRef<Path>>(dir: P) -> Option<PathBuf> {
+    match try_oldest_file(dir) {
+        Ok(path) => path,
+        Err(e) => {
+            error!("Cannot find oldest file {}", e);
+            None
+        }
+    }
+}
+
+/// Returns the least recently modified file directly in `dir`, or `None` if it
+/// has no files.
+///
+/// See `fsutils::newest_file_with` for the details, which are the same.
+///
+/// ## Usage:
+///
+/// ```
+/// use std::time::{Duration, SystemTime};
+///
+/// fsutils::mkdir("try_oldest_file_dir");
+/// fsutils::write_file("try_oldest_file_dir/old.log", "");
+/// fsutils::write_file("try_oldest_file_dir/new.log", "");
+/// let hour_ago = SystemTime::now() - Duration::from_secs(60 * 60);
+/// fsutils::try_touch_at("try_oldest_file_dir/old.log", hour_ago, hour_ago).unwrap();
+///
+/// let oldest = fsutils::try_oldest_file("try_oldest_file_dir").unwrap().unwrap();
+/// assert!(oldest.ends_with("old.log"));
+///
+/// # // Cleanup
+/// # fsutils::rm_r("try_oldest_file_dir");
+/// ```
+pub fn try_oldest_file<P: AsRef<Path>>(dir: P) -> Result<Option<PathBuf>> {
+    oldest_file_with(dir, &PickOptions::new())
+}
+
+/// Returns the least recently modified file in `dir` that `opts` allow, or
+/// `None` if there is none.
+///
+/// See `fsutils::newest_file_with` for the details, which are the same.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::PickOptions;
+///
+/// fsutils::mkdir("oldest_file_with_dir/keep");
+/// fsutils::write_file("oldest_file_with_dir/keep/a.bak", "");
+///
+/// let mut opts = PickOptions::new();
+/// opts.recursive(true).include("*.bak").unwrap();
+/// assert!(fsutils::oldest_file_with("oldest_file_with_dir", &opts).unwrap().unwrap().ends_with("a.bak"));
+/// assert!(opts.include("[").is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm_r("oldest_file_with_dir");
+/// ```
+pub fn oldest_file_with<P: AsRef<Path>>(dir: P, opts: &PickOptions) -> Result<Option<PathBuf>> {
+    opts.pick(dir.as_ref(), |a, b| a < b)
+}
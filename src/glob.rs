@@ -0,0 +1,494 @@
+// Copyright 2020 Jared Forth.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Shell-style wildcard patterns and expanding them.
+
+use std::path::{Component, Path, PathBuf};
+
+use crate::error::{FsError, Result};
+use crate::walk::{walk_with, Walk, WalkOptions};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+    /// A literal character.
+    Char(char),
+    /// `?`, any single character except `/`.
+    Any,
+    /// `*`, any run of characters except `/`.
+    Star,
+    /// `**`, any run of characters including `/`.
+    DoubleStar,
+    /// `**/`, zero or more whole directories.
+    AnyDirs,
+    /// `[...]`, one character from a set of ranges and named classes, or
+    /// outside it if negated.
+    Class { negated: bool, ranges: Vec<(char, char)>, named: Vec<NamedClass> },
+}
+
+/// A POSIX character class such as `[:digit:]`, inside brackets.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum NamedClass {
+    Alnum,
+    Alpha,
+    Digit,
+    Lower,
+    Upper,
+    Space,
+    Punct,
+    XDigit,
+}
+
+impl NamedClass {
+    fn parse(name: &str) -> Option<NamedClass> {
+        Some(match name {
+            "alnum" => NamedClass::Alnum,
+            "alpha" => NamedClass::Alpha,
+            "digit" => NamedClass::Digit,
+            "lower" => NamedClass::Lower,
+            "upper" => NamedClass::Upper,
+            "space" => NamedClass::Space,
+            "punct" => NamedClass::Punct,
+            "xdigit" => NamedClass::XDigit,
+            _ => return None,
+        })
+    }
+
+    fn contains(self, c: char) -> bool {
+        match self {
+            NamedClass::Alnum => c.is_alphanumeric(),
+            NamedClass::Alpha => c.is_alphabetic(),
+            NamedClass::Digit => c.is_ascii_digit(),
+            NamedClass::Lower => c.is_lowercase(),
+            NamedClass::Upper => c.is_uppercase(),
+            NamedClass::Space => c.is_whitespace(),
+            NamedClass::Punct => c.is_ascii_punctuation(),
+            NamedClass::XDigit => c.is_ascii_hexdigit(),
+        }
+    }
+}
+
+/// A compiled wildcard pattern.
+///
+/// Supports `?`, `*`, `**`, `[...]` character classes (`[!...]` or `[^...]`
+/// to negate, and POSIX classes such as `[[:digit:]]`) and `{a,b}`
+/// alternatives, which may nest. A pattern without a `/` is matched against
+/// the file name only, so `*.o` matches object files at any depth; otherwise
+/// it is matched against the whole relative path. That is decided for each
+/// alternative of a brace on its own.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Pattern {
+    source: String,
+    alternatives: Vec<Alternative>,
+}
+
+/// One of the patterns a `Pattern` with braces stands for.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Alternative {
+    tokens: Vec<Token>,
+    name_only: bool,
+}
+
+impl Pattern {
+    /// Compiles a pattern.
+    pub(crate) fn new(pattern: &str) -> Result<Pattern> {
+        let alternatives = expand_braces(pattern)
+            .iter()
+            .map(|alternative| {
+                Ok(Alternative { tokens: tokenize(alternative, pattern)?, name_only: !alternative.contains('/') })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Pattern { source: pattern.to_string(), alternatives })
+    }
+
+    /// Matches a relative path, or just its file name for patterns without a `/`.
+    pub(crate) fn matches_path(&self, path: &Path) -> bool {
+        let name = path.file_name().map(|name| name.to_string_lossy());
+        let full = to_slash(path);
+        self.alternatives.iter().any(|alternative| {
+            if alternative.name_only {
+                name.as_ref().is_some_and(|name| alternative.matches(name))
+            } else {
+                alternative.matches(&full)
+            }
+        })
+    }
+
+    /// Matches a `/`-separated string.
+    pub(crate) fn matches(&self, text: &str) -> bool {
+        self.alternatives.iter().any(|alternative| alternative.matches(text))
+    }
+}
+
+impl Alternative {
+    fn matches(&self, text: &str) -> bool {
+        let text: Vec<char> = text.chars().collect();
+        let mut memo = vec![None; (self.tokens.len() + 1) * (text.len() + 1)];
+        self.match_at(0, 0, &text, &mut memo)
+    }
+
+    fn match_at(&self, t: usize, s: usize, text: &[char], memo: &mut Vec<Option<bool>>) -> bool {
+        let key = t * (text.len() + 1) + s;
+        if let Some(result) = memo[key] {
+            return result;
+        }
+        let result = match self.tokens.get(t) {
+            None => s == text.len(),
+            Some(Token::Char(c)) => text.get(s) == Some(c) && self.match_at(t + 1, s + 1, text, memo),
+            Some(Token::Any) => text.get(s).is_some_and(|&c| c != '/') && self.match_at(t + 1, s + 1, text, memo),
+            Some(Token::Class { negated, ranges, named }) => match text.get(s) {
+                Some(&c) if c != '/' => {
+                    let in_range = ranges.iter().any(|&(lo, hi)| lo <= c && c <= hi);
+                    let inside = in_range || named.iter().any(|class| class.contains(c));
+                    inside != *negated && self.match_at(t + 1, s + 1, text, memo)
+                }
+                _ => false,
+            },
+            Some(Token::Star) => {
+                let mut end = s;
+                loop {
+                    if self.match_at(t + 1, end, text, memo) {
+                        break true;
+                    }
+                    if end == text.len() || text[end] == '/' {
+                        break false;
+                    }
+                    end += 1;
+                }
+            }
+            Some(Token::DoubleStar) => (s..=text.len()).any(|end| self.match_at(t + 1, end, text, memo)),
+            Some(Token::AnyDirs) => {
+                // Zero directories, or up to and including any later `/`
+                self.match_at(t + 1, s, text, memo)
+                    || (s..text.len()).any(|i| text[i] == '/' && self.match_at(t + 1, i + 1, text, memo))
+            }
+        };
+        memo[key] = Some(result);
+        result
+    }
+}
+
+/// Compiles one alternative of `pattern`, which has no braces left.
+fn tokenize(alternative: &str, pattern: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = alternative.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '?' => tokens.push(Token::Any),
+            '*' => {
+                if chars.peek() == Some(&'*') {
+                    chars.next();
+                    if chars.peek() == Some(&'/') {
+                        chars.next();
+                        tokens.push(Token::AnyDirs);
+                    } else {
+                        tokens.push(Token::DoubleStar);
+                    }
+                } else {
+                    tokens.push(Token::Star);
+                }
+            }
+            '[' => {
+                let negated = matches!(chars.peek(), Some('!') | Some('^'));
+                if negated {
+                    chars.next();
+                }
+                let mut ranges = Vec::new();
+                let mut named = Vec::new();
+                let mut closed = false;
+                let mut first = true;
+                while let Some(c) = chars.next() {
+                    if c == ']' && !first {
+                        closed = true;
+                        break;
+                    }
+                    first = false;
+                    if c == '[' && chars.peek() == Some(&':') {
+                        let rest: String = chars.clone().skip(1).collect();
+                        if let Some(end) = rest.find(":]") {
+                            let class = NamedClass::parse(&rest[..end]).ok_or_else(|| {
+                                let reason = format!("unknown character class [:{}:] in {:?}", &rest[..end], pattern);
+                                FsError::invalid_path(pattern, reason)
+                            })?;
+                            named.push(class);
+                            // Skip the `:`, the name and the closing `:]`
+                            for _ in 0..rest[..end].chars().count() + 3 {
+                                chars.next();
+                            }
+                            continue;
+                        }
+                    }
+                    let start = if c == '\\' { chars.next().unwrap_or('\\') } else { c };
+                    let mut lookahead = chars.clone();
+                    if lookahead.next() == Some('-') && lookahead.peek().is_some_and(|&c| c != ']') {
+                        chars.next();
+                        let end = chars.next().expect("peeked above");
+                        ranges.push((start, end));
+                    } else {
+                        ranges.push((start, start));
+                    }
+                }
+                if !closed {
+                    let reason = format!("unclosed character class in {:?}", pattern);
+                    return Err(FsError::invalid_path(pattern, reason));
+                }
+                tokens.push(Token::Class { negated, ranges, named });
+            }
+            '\\' => tokens.push(Token::Char(chars.next().unwrap_or('\\'))),
+            c => tokens.push(Token::Char(c)),
+        }
+    }
+    Ok(tokens)
+}
+
+/// Expands the braces of `pattern` as a shell does, so `{a,b}.{c,d}` gives
+/// `a.c`, `a.d`, `b.c` and `b.d`. Braces without a comma, unclosed ones and
+/// those inside `[...]` or escaped with `\` are kept as they are.
+fn expand_braces(pattern: &str) -> Vec<String> {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut i = 0;
+    let mut in_class = false;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            '[' if !in_class => in_class = true,
+            ']' if in_class => in_class = false,
+            '{' if !in_class => {
+                if let Some((commas, close)) = brace_at(&chars, i) {
+                    let prefix: String = chars[..i].iter().collect();
+                    let suffix: String = chars[close + 1..].iter().collect();
+                    let mut bounds = vec![i];
+                    bounds.extend(commas);
+                    bounds.push(close);
+                    let mut expanded = Vec::new();
+                    for pair in bounds.windows(2) {
+                        let middle: String = chars[pair[0] + 1..pair[1]].iter().collect();
+                        // The suffix may hold more braces, and so may each alternative
+                        expanded.extend(expand_braces(&format!("{}{}{}", prefix, middle, suffix)));
+                    }
+                    return expanded;
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    vec![pattern.to_string()]
+}
+
+/// The positions of the top-level commas and of the closing brace of the
+/// brace opened at `open`, if it is closed and has a comma.
+fn brace_at(chars: &[char], open: usize) -> Option<(Vec<usize>, usize)> {
+    let mut depth = 0;
+    let mut commas = Vec::new();
+    let mut i = open + 1;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            '{' => depth += 1,
+            ',' if depth == 0 => commas.push(i),
+            '}' if depth == 0 => return if commas.is_empty() { None } else { Some((commas, i)) },
+            '}' => depth -= 1,
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Renders a relative path with `/` separators on every platform.
+pub(crate) fn to_slash(path: &Path) -> String {
+    let parts: Vec<_> = path.components().map(|c| c.as_os_str().to_string_lossy()).collect();
+    parts.join("/")
+}
+
+/// Returns the existing paths matching a wildcard pattern, sorted, or an
+/// empty `Vec` if the pattern is invalid or the directory cannot be read.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::mkdir("glob_dir/src");
+/// fsutils::write_file("glob_dir/src/main.rs", "");
+/// fsutils::write_file("glob_dir/src/notes.md", "");
+///
+/// assert_eq!(fsutils::glob("glob_dir/**/*.rs").len(), 1);
+///
+/// # // Cleanup
+/// # fsutils::rm_r("glob_dir");
+/// ```
+pub fn glob(pattern: &str) -> Vec<PathBuf> {
+    match try_glob(pattern) {
+        Ok(paths) => paths,
+        Err(e) => {
+            error!("Cannot expand pattern {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// Returns the existing paths matching a wildcard pattern, sorted, like a
+/// shell expanding it.
+///
+/// Patterns support `?`, `*`, `[...]` and `**`, which matches any number of
+/// directories. Character classes may be negated with `[!...]` or `[^...]`
+/// and may hold POSIX classes such as `[[:digit:]]`. Braces give
+/// alternatives, as in `src/{lib,main}.rs` or `*.{jpg,png}`, and are
+/// expanded first, so each alternative is a pattern of its own; one without
+/// wildcards is returned if that path exists. The part before the first
+/// wildcard is the directory searched. Unlike in a shell, wildcards also match
+/// names that start with a dot, and `..` may not come after a wildcard.
+/// Symbolic links are returned but not walked into.
+///
+/// ## Usage:
+///
+/// ```
+/// use std::path::PathBuf;
+///
+/// fsutils::mkdir("try_glob_dir/src/bin");
+/// fsutils::write_file("try_glob_dir/src/lib.rs", "");
+/// fsutils::write_file("try_glob_dir/src/bin/tool.rs", "");
+/// fsutils::write_file("try_glob_dir/logo.png", "");
+/// fsutils::write_file("try_glob_dir/photo1.jpg", "");
+/// fsutils::write_file("try_glob_dir/photo.gif", "");
+///
+/// let paths = fsutils::try_glob("try_glob_dir/src/**/*.rs").unwrap();
+/// # #[cfg(unix)]
+/// assert_eq!(paths, [PathBuf::from("try_glob_dir/src/bin/tool.rs"), PathBuf::from("try_glob_dir/src/lib.rs")]);
+///
+/// let images = fsutils::try_glob("try_glob_dir/*.{jpg,png}").unwrap();
+/// assert_eq!(images.len(), 2);
+/// assert_eq!(fsutils::try_glob("try_glob_dir/photo[[:digit:]].*").unwrap().len(), 1);
+/// assert!(fsutils::try_glob("try_glob_dir/[").is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm_r("try_glob_dir");
+/// ```
+pub fn try_glob(pattern: &str) -> Result<Vec<PathBuf>> {
+    let mut found = glob_iter(pattern)?.collect::<Result<Vec<_>>>()?;
+    found.sort();
+    found.dedup();
+    Ok(found)
+}
+
+/// Returns an iterator over the existing paths matching a wildcard pattern,
+/// which finds them as it goes rather than all at once.
+///
+/// The pattern is checked up front. Paths come in the order of
+/// `fsutils::walk`, for each alternative of a brace in turn, so they are only
+/// sorted without braces and may repeat with them. An entry that cannot be
+/// read is yielded as an error and the search goes on. See
+/// `fsutils::try_glob` for the pattern syntax.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::mkdir("glob_iter_dir/logs");
+/// for day in 1..=3 {
+///     fsutils::write_file(format!("glob_iter_dir/logs/2020-01-0{}.log", day), "");
+/// }
+///
+/// let first = fsutils::glob_iter("glob_iter_dir/logs/*.log").unwrap().next().unwrap().unwrap();
+/// assert!(first.ends_with("2020-01-01.log"));
+///
+/// # // Cleanup
+/// # fsutils::rm_r("glob_iter_dir");
+/// ```
+pub fn glob_iter(pattern: &str) -> Result<GlobIter> {
+    let searches = expand_braces(pattern).iter().map(|alternative| Search::new(alternative)).collect::<Result<_>>()?;
+    Ok(GlobIter { searches, current: None })
+}
+
+/// The iterator returned by `fsutils::glob_iter`.
+#[derive(Debug)]
+pub struct GlobIter {
+    /// The searches left to start, the next one first.
+    searches: std::collections::VecDeque<Search>,
+    current: Option<(Search, Walk)>,
+}
+
+/// The search for one alternative of a pattern: the directory to look in
+/// and the pattern the paths below it must match.
+#[derive(Debug)]
+struct Search {
+    base: PathBuf,
+    /// `None` if the alternative has no wildcards, and is just a path.
+    matcher: Option<Pattern>,
+    max_depth: Option<usize>,
+}
+
+impl Search {
+    fn new(pattern: &str) -> Result<Search> {
+        // An escape is left to the matcher to undo
+        let is_wild = |s: &str| s.contains(['*', '?', '[', '\\']);
+        let mut base = PathBuf::new();
+        let mut rest = Vec::new();
+        for component in Path::new(pattern).components() {
+            let text = component.as_os_str().to_string_lossy();
+            if rest.is_empty() && !is_wild(&text) {
+                base.push(component);
+            } else {
+                if let Component::ParentDir = component {
+                    return Err(FsError::invalid_path(pattern, "`..` after a wildcard is not supported"));
+                }
+                rest.push(text.into_owned());
+            }
+        }
+        if rest.is_empty() {
+            return Ok(Search { base, matcher: None, max_depth: None });
+        }
+        let max_depth = if rest.iter().any(|part| part.contains("**")) { None } else { Some(rest.len()) };
+        Ok(Search { base, matcher: Some(Pattern::new(&rest.join("/"))?), max_depth })
+    }
+
+    fn root(&self) -> &Path {
+        if self.base.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            &self.base
+        }
+    }
+}
+
+impl Iterator for GlobIter {
+    type Item = Result<PathBuf>;
+
+    fn next(&mut self) -> Option<Result<PathBuf>> {
+        loop {
+            let (search, walk) = match &mut self.current {
+                Some(current) => (&current.0, &mut current.1),
+                None => {
+                    let search = self.searches.pop_front()?;
+                    if search.matcher.is_none() {
+                        if search.base.symlink_metadata().is_ok() {
+                            return Some(Ok(search.base));
+                        }
+                        continue;
+                    }
+                    let mut opts = WalkOptions::new();
+                    if let Some(depth) = search.max_depth {
+                        opts.max_depth(depth);
+                    }
+                    let walk = walk_with(search.root(), &opts);
+                    let current = self.current.insert((search, walk));
+                    (&current.0, &mut current.1)
+                }
+            };
+            match walk.next() {
+                None => self.current = None,
+                Some(Err(e)) => return Some(Err(e)),
+                Some(Ok(entry)) if entry.depth > 0 => {
+                    let relative = entry.path.strip_prefix(search.root()).unwrap_or(&entry.path);
+                    if search.matcher.as_ref().is_some_and(|matcher| matcher.matches(&to_slash(relative))) {
+                        return Some(Ok(search.base.join(relative)));
+                    }
+                }
+                Some(Ok(_)) => {}
+            }
+        }
+    }
+}
@@ -0,0 +1,1033 @@
+// Copyright 2020 Jared Forth.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Content hashing and file checksums.
+
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use crate::error::{FsError, IoResultExt, Result};
+
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+const INITIAL: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+/// An incremental SHA-256 digest.
+#[derive(Clone, Debug)]
+pub(crate) struct Sha256 {
+    state: [u32; 8],
+    block: [u8; 64],
+    filled: usize,
+    length: u64,
+}
+
+impl Sha256 {
+    pub(crate) fn new() -> Sha256 {
+        Sha256 { state: INITIAL, block: [0; 64], filled: 0, length: 0 }
+    }
+
+    /// Feeds more data into the digest.
+    pub(crate) fn update(&mut self, mut data: &[u8]) {
+        self.length = self.length.wrapping_add(data.len() as u64);
+        while !data.is_empty() {
+            let n = (64 - self.filled).min(data.len());
+            self.block[self.filled..self.filled + n].copy_from_slice(&data[..n]);
+            self.filled += n;
+            data = &data[n..];
+            if self.filled == 64 {
+                let block = self.block;
+                self.compress(&block);
+                self.filled = 0;
+            }
+        }
+    }
+
+    /// Returns the digest of everything fed in.
+    pub(crate) fn finish(mut self) -> [u8; 32] {
+        let bits = self.length.wrapping_mul(8);
+        self.update(&[0x80]);
+        while self.filled != 56 {
+            self.update(&[0]);
+        }
+        self.update(&bits.to_be_bytes());
+        let mut digest = [0; 32];
+        for (chunk, word) in digest.chunks_mut(4).zip(self.state.iter()) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+        digest
+    }
+
+    fn compress(&mut self, block: &[u8; 64]) {
+        let mut w = [0u32; 64];
+        for (i, chunk) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h.wrapping_add(s1).wrapping_add(ch).wrapping_add(K[i]).wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (state, value) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h].iter()) {
+            *state = state.wrapping_add(*value);
+        }
+    }
+}
+
+/// A digest that is fed data a piece at a time.
+pub(crate) trait Digest {
+    /// The finished digest.
+    type Output: AsRef<[u8]>;
+
+    /// Feeds more data into the digest.
+    fn update(&mut self, data: &[u8]);
+
+    /// Returns the digest of everything fed in.
+    fn finish(self) -> Self::Output;
+}
+
+impl Digest for Sha256 {
+    type Output = [u8; 32];
+
+    fn update(&mut self, data: &[u8]) {
+        Sha256::update(self, data)
+    }
+
+    fn finish(self) -> [u8; 32] {
+        Sha256::finish(self)
+    }
+}
+
+/// Feeds the file at `path` into `digest` in chunks and returns the result.
+pub(crate) fn digest_file<D: Digest>(path: &Path, mut digest: D) -> Result<D::Output> {
+    let mut file = fs::File::open(path).at(path)?;
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        match file.read(&mut buf) {
+            Ok(0) => return Ok(digest.finish()),
+            Ok(n) => digest.update(&buf[..n]),
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(FsError::from_io(path, e)),
+        }
+    }
+}
+
+/// Collects data into 64-byte blocks for the digests built on them.
+#[cfg(any(feature = "md5", feature = "sha1"))]
+#[derive(Clone, Debug)]
+struct Blocks {
+    block: [u8; 64],
+    filled: usize,
+    length: u64,
+}
+
+#[cfg(any(feature = "md5", feature = "sha1"))]
+impl Blocks {
+    fn new() -> Blocks {
+        Blocks { block: [0; 64], filled: 0, length: 0 }
+    }
+
+    /// Calls `compress` for each block that `data` completes.
+    fn update(&mut self, mut data: &[u8], mut compress: impl FnMut(&[u8; 64])) {
+        self.length = self.length.wrapping_add(data.len() as u64);
+        while !data.is_empty() {
+            let n = (64 - self.filled).min(data.len());
+            self.block[self.filled..self.filled + n].copy_from_slice(&data[..n]);
+            self.filled += n;
+            data = &data[n..];
+            if self.filled == 64 {
+                compress(&self.block);
+                self.filled = 0;
+            }
+        }
+    }
+
+    /// Pads the last block with the length in bits, as MD5 and SHA-1 do,
+    /// in the byte order they use.
+    fn pad(mut self, big_endian: bool, mut compress: impl FnMut(&[u8; 64])) {
+        let bits = self.length.wrapping_mul(8);
+        let length = self.length;
+        self.update(&[0x80], &mut compress);
+        while self.filled != 56 {
+            self.update(&[0], &mut compress);
+        }
+        self.update(&if big_endian { bits.to_be_bytes() } else { bits.to_le_bytes() }, &mut compress);
+        self.length = length;
+    }
+}
+
+#[cfg(feature = "md5")]
+const MD5_SHIFTS: [u32; 16] = [7, 12, 17, 22, 5, 9, 14, 20, 4, 11, 16, 23, 6, 10, 15, 21];
+
+#[cfg(feature = "md5")]
+const MD5_K: [u32; 64] = [
+    0xd76aa478, 0xe8c7b756, 0x242070db, 0xc1bdceee, 0xf57c0faf, 0x4787c62a, 0xa8304613, 0xfd469501,
+    0x698098d8, 0x8b44f7af, 0xffff5bb1, 0x895cd7be, 0x6b901122, 0xfd987193, 0xa679438e, 0x49b40821,
+    0xf61e2562, 0xc040b340, 0x265e5a51, 0xe9b6c7aa, 0xd62f105d, 0x02441453, 0xd8a1e681, 0xe7d3fbc8,
+    0x21e1cde6, 0xc33707d6, 0xf4d50d87, 0x455a14ed, 0xa9e3e905, 0xfcefa3f8, 0x676f02d9, 0x8d2a4c8a,
+    0xfffa3942, 0x8771f681, 0x6d9d6122, 0xfde5380c, 0xa4beea44, 0x4bdecfa9, 0xf6bb4b60, 0xbebfbc70,
+    0x289b7ec6, 0xeaa127fa, 0xd4ef3085, 0x04881d05, 0xd9d4d039, 0xe6db99e5, 0x1fa27cf8, 0xc4ac5665,
+    0xf4292244, 0x432aff97, 0xab9423a7, 0xfc93a039, 0x655b59c3, 0x8f0ccc92, 0xffeff47d, 0x85845dd1,
+    0x6fa87e4f, 0xfe2ce6e0, 0xa3014314, 0x4e0811a1, 0xf7537e82, 0xbd3af235, 0x2ad7d2bb, 0xeb86d391,
+];
+
+/// An incremental MD5 digest.
+#[cfg(feature = "md5")]
+#[derive(Clone, Debug)]
+pub(crate) struct Md5 {
+    state: [u32; 4],
+    blocks: Blocks,
+}
+
+#[cfg(feature = "md5")]
+impl Md5 {
+    pub(crate) fn new() -> Md5 {
+        Md5 { state: [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476], blocks: Blocks::new() }
+    }
+
+    fn compress(state: &mut [u32; 4], block: &[u8; 64]) {
+        let mut m = [0u32; 16];
+        for (i, chunk) in block.chunks(4).enumerate() {
+            m[i] = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+        let [mut a, mut b, mut c, mut d] = *state;
+        for i in 0..64 {
+            let (f, g) = match i / 16 {
+                0 => ((b & c) | (!b & d), i),
+                1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+                2 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16),
+            };
+            let f = f.wrapping_add(a).wrapping_add(MD5_K[i]).wrapping_add(m[g]);
+            a = d;
+            d = c;
+            c = b;
+            b = b.wrapping_add(f.rotate_left(MD5_SHIFTS[i / 16 * 4 + i % 4]));
+        }
+        for (state, value) in state.iter_mut().zip([a, b, c, d].iter()) {
+            *state = state.wrapping_add(*value);
+        }
+    }
+}
+
+#[cfg(feature = "md5")]
+impl Digest for Md5 {
+    type Output = [u8; 16];
+
+    fn update(&mut self, data: &[u8]) {
+        let state = &mut self.state;
+        self.blocks.update(data, |block| Md5::compress(state, block));
+    }
+
+    fn finish(mut self) -> [u8; 16] {
+        let state = &mut self.state;
+        self.blocks.pad(false, |block| Md5::compress(state, block));
+        let mut digest = [0; 16];
+        for (chunk, word) in digest.chunks_mut(4).zip(self.state.iter()) {
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+        digest
+    }
+}
+
+/// An incremental SHA-1 digest.
+#[cfg(feature = "sha1")]
+#[derive(Clone, Debug)]
+pub(crate) struct Sha1 {
+    state: [u32; 5],
+    blocks: Blocks,
+}
+
+#[cfg(feature = "sha1")]
+impl Sha1 {
+    pub(crate) fn new() -> Sha1 {
+        Sha1 { state: [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0], blocks: Blocks::new() }
+    }
+
+    fn compress(state: &mut [u32; 5], block: &[u8; 64]) {
+        let mut w = [0u32; 80];
+        for (i, chunk) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = *state;
+        for (i, word) in w.iter().enumerate() {
+            let (f, k) = match i / 20 {
+                0 => ((b & c) | (!b & d), 0x5a827999),
+                1 => (b ^ c ^ d, 0x6ed9eba1),
+                2 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6),
+            };
+            let t = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = t;
+        }
+        for (state, value) in state.iter_mut().zip([a, b, c, d, e].iter()) {
+            *state = state.wrapping_add(*value);
+        }
+    }
+}
+
+#[cfg(feature = "sha1")]
+impl Digest for Sha1 {
+    type Output = [u8; 20];
+
+    fn update(&mut self, data: &[u8]) {
+        let state = &mut self.state;
+        self.blocks.update(data, |block| Sha1::compress(state, block));
+    }
+
+    fn finish(mut self) -> [u8; 20] {
+        let state = &mut self.state;
+        self.blocks.pad(true, |block| Sha1::compress(state, block));
+        let mut digest = [0; 20];
+        for (chunk, word) in digest.chunks_mut(4).zip(self.state.iter()) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+        digest
+    }
+}
+
+#[cfg(feature = "blake3")]
+const BLAKE3_PERMUTATION: [usize; 16] = [2, 6, 3, 10, 7, 0, 4, 13, 1, 11, 12, 5, 9, 14, 15, 8];
+#[cfg(feature = "blake3")]
+const CHUNK_LEN: usize = 1024;
+#[cfg(feature = "blake3")]
+const CHUNK_START: u32 = 1;
+#[cfg(feature = "blake3")]
+const CHUNK_END: u32 = 2;
+#[cfg(feature = "blake3")]
+const PARENT: u32 = 4;
+#[cfg(feature = "blake3")]
+const ROOT: u32 = 8;
+
+/// The BLAKE3 compression function, returning the whole state. Its key is
+/// the SHA-256 initial state, which is also BLAKE3's.
+#[cfg(feature = "blake3")]
+fn blake3_compress(cv: &[u32; 8], block: &[u32; 16], counter: u64, block_len: u32, flags: u32) -> [u32; 16] {
+    fn g(s: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize, x: u32, y: u32) {
+        s[a] = s[a].wrapping_add(s[b]).wrapping_add(x);
+        s[d] = (s[d] ^ s[a]).rotate_right(16);
+        s[c] = s[c].wrapping_add(s[d]);
+        s[b] = (s[b] ^ s[c]).rotate_right(12);
+        s[a] = s[a].wrapping_add(s[b]).wrapping_add(y);
+        s[d] = (s[d] ^ s[a]).rotate_right(8);
+        s[c] = s[c].wrapping_add(s[d]);
+        s[b] = (s[b] ^ s[c]).rotate_right(7);
+    }
+    let mut s = [
+        cv[0], cv[1], cv[2], cv[3], cv[4], cv[5], cv[6], cv[7],
+        INITIAL[0], INITIAL[1], INITIAL[2], INITIAL[3],
+        counter as u32, (counter >> 32) as u32, block_len, flags,
+    ];
+    let mut m = *block;
+    for round in 0..7 {
+        g(&mut s, 0, 4, 8, 12, m[0], m[1]);
+        g(&mut s, 1, 5, 9, 13, m[2], m[3]);
+        g(&mut s, 2, 6, 10, 14, m[4], m[5]);
+        g(&mut s, 3, 7, 11, 15, m[6], m[7]);
+        g(&mut s, 0, 5, 10, 15, m[8], m[9]);
+        g(&mut s, 1, 6, 11, 12, m[10], m[11]);
+        g(&mut s, 2, 7, 8, 13, m[12], m[13]);
+        g(&mut s, 3, 4, 9, 14, m[14], m[15]);
+        if round < 6 {
+            let previous = m;
+            for (word, &from) in m.iter_mut().zip(BLAKE3_PERMUTATION.iter()) {
+                *word = previous[from];
+            }
+        }
+    }
+    for i in 0..8 {
+        s[i] ^= s[i + 8];
+        s[i + 8] ^= cv[i];
+    }
+    s
+}
+
+#[cfg(feature = "blake3")]
+fn first_8(words: [u32; 16]) -> [u32; 8] {
+    let mut out = [0; 8];
+    out.copy_from_slice(&words[..8]);
+    out
+}
+
+/// The last compression of a node, held back until it is known whether the
+/// node is the root.
+#[cfg(feature = "blake3")]
+#[derive(Clone, Debug)]
+struct Blake3Node {
+    cv: [u32; 8],
+    block: [u32; 16],
+    counter: u64,
+    block_len: u32,
+    flags: u32,
+}
+
+#[cfg(feature = "blake3")]
+impl Blake3Node {
+    fn parent(left: [u32; 8], right: [u32; 8]) -> Blake3Node {
+        let mut block = [0; 16];
+        block[..8].copy_from_slice(&left);
+        block[8..].copy_from_slice(&right);
+        Blake3Node { cv: INITIAL, block, counter: 0, block_len: 64, flags: PARENT }
+    }
+
+    fn chaining_value(&self) -> [u32; 8] {
+        first_8(blake3_compress(&self.cv, &self.block, self.counter, self.block_len, self.flags))
+    }
+
+    fn root(&self) -> [u8; 32] {
+        let words = blake3_compress(&self.cv, &self.block, 0, self.block_len, self.flags | ROOT);
+        let mut digest = [0; 32];
+        for (chunk, word) in digest.chunks_mut(4).zip(words.iter()) {
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+        digest
+    }
+}
+
+/// An incremental BLAKE3 digest, with the default 32 bytes of output.
+#[cfg(feature = "blake3")]
+#[derive(Clone, Debug)]
+pub(crate) struct Blake3 {
+    /// The chaining values of finished subtrees, as many as the set bits of
+    /// the number of chunks finished.
+    stack: Vec<[u32; 8]>,
+    /// The chunk being filled.
+    cv: [u32; 8],
+    chunk: u64,
+    block: [u8; 64],
+    block_len: usize,
+    blocks_compressed: usize,
+}
+
+#[cfg(feature = "blake3")]
+impl Blake3 {
+    pub(crate) fn new() -> Blake3 {
+        Blake3 { stack: Vec::new(), cv: INITIAL, chunk: 0, block: [0; 64], block_len: 0, blocks_compressed: 0 }
+    }
+
+    fn start_flag(&self) -> u32 {
+        if self.blocks_compressed == 0 {
+            CHUNK_START
+        } else {
+            0
+        }
+    }
+
+    fn block_words(&self) -> [u32; 16] {
+        let mut words = [0; 16];
+        for (word, chunk) in words.iter_mut().zip(self.block.chunks(4)) {
+            *word = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+        words
+    }
+
+    /// The final compression of the chunk being filled.
+    fn chunk_node(&self) -> Blake3Node {
+        Blake3Node {
+            cv: self.cv,
+            block: self.block_words(),
+            counter: self.chunk,
+            block_len: self.block_len as u32,
+            flags: self.start_flag() | CHUNK_END,
+        }
+    }
+}
+
+#[cfg(feature = "blake3")]
+impl Digest for Blake3 {
+    type Output = [u8; 32];
+
+    fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            if self.blocks_compressed * 64 + self.block_len == CHUNK_LEN {
+                // More data follows, so the full chunk is not the root
+                let mut cv = self.chunk_node().chaining_value();
+                self.chunk += 1;
+                let mut finished = self.chunk;
+                while finished & 1 == 0 {
+                    cv = Blake3Node::parent(self.stack.pop().unwrap_or_default(), cv).chaining_value();
+                    finished >>= 1;
+                }
+                self.stack.push(cv);
+                self.cv = INITIAL;
+                self.block = [0; 64];
+                self.block_len = 0;
+                self.blocks_compressed = 0;
+            }
+            if self.block_len == 64 {
+                let words = self.block_words();
+                self.cv = first_8(blake3_compress(&self.cv, &words, self.chunk, 64, self.start_flag()));
+                self.blocks_compressed += 1;
+                self.block = [0; 64];
+                self.block_len = 0;
+            }
+            let n = (64 - self.block_len).min(data.len());
+            self.block[self.block_len..self.block_len + n].copy_from_slice(&data[..n]);
+            self.block_len += n;
+            data = &data[n..];
+        }
+    }
+
+    fn finish(self) -> [u8; 32] {
+        let mut node = self.chunk_node();
+        for cv in self.stack.iter().rev() {
+            node = Blake3Node::parent(*cv, node.chaining_value());
+        }
+        node.root()
+    }
+}
+
+/// A digest algorithm for `fsutils::Hasher`.
+///
+/// `Sha1`, `Md5` and `Blake3` need the features of the same names.
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum HashAlgorithm {
+    #[default]
+    Sha256,
+    #[cfg(feature = "sha1")]
+    Sha1,
+    #[cfg(feature = "md5")]
+    Md5,
+    #[cfg(feature = "blake3")]
+    Blake3,
+}
+
+#[derive(Clone, Debug)]
+enum State {
+    Sha256(Sha256),
+    #[cfg(feature = "sha1")]
+    Sha1(Sha1),
+    #[cfg(feature = "md5")]
+    Md5(Md5),
+    #[cfg(feature = "blake3")]
+    Blake3(Blake3),
+}
+
+/// A digest fed from files, byte slices and readers in turn, for hashing
+/// several sources as one or data that is being copied anyway.
+///
+/// `finish` consumes the hasher, so it is finalized exactly once; clone it
+/// first to get the digest so far and keep going. It also implements
+/// `io::Write`, so it can be handed to `io::copy` or written to alongside
+/// another writer.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::{HashAlgorithm, Hasher};
+///
+/// fsutils::write_file("hasher_a.txt", "ab");
+///
+/// let mut hasher = Hasher::new(HashAlgorithm::Sha256);
+/// hasher.update_file("hasher_a.txt").unwrap();
+/// hasher.update(b"c");
+/// assert_eq!(hasher.bytes(), 3);
+/// assert_eq!(hasher.finish(), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+///
+/// # // Cleanup
+/// # fsutils::rm("hasher_a.txt");
+/// ```
+#[derive(Clone, Debug)]
+pub struct Hasher {
+    state: State,
+    bytes: u64,
+}
+
+impl Hasher {
+    /// Creates a hasher that has consumed nothing yet.
+    pub fn new(algorithm: HashAlgorithm) -> Hasher {
+        let state = match algorithm {
+            HashAlgorithm::Sha256 => State::Sha256(Sha256::new()),
+            #[cfg(feature = "sha1")]
+            HashAlgorithm::Sha1 => State::Sha1(Sha1::new()),
+            #[cfg(feature = "md5")]
+            HashAlgorithm::Md5 => State::Md5(Md5::new()),
+            #[cfg(feature = "blake3")]
+            HashAlgorithm::Blake3 => State::Blake3(Blake3::new()),
+        };
+        Hasher { state, bytes: 0 }
+    }
+
+    /// The algorithm the hasher was created with.
+    pub fn algorithm(&self) -> HashAlgorithm {
+        match self.state {
+            State::Sha256(_) => HashAlgorithm::Sha256,
+            #[cfg(feature = "sha1")]
+            State::Sha1(_) => HashAlgorithm::Sha1,
+            #[cfg(feature = "md5")]
+            State::Md5(_) => HashAlgorithm::Md5,
+            #[cfg(feature = "blake3")]
+            State::Blake3(_) => HashAlgorithm::Blake3,
+        }
+    }
+
+    /// The number of bytes consumed so far.
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// Consumes `data`.
+    pub fn update(&mut self, data: &[u8]) -> &mut Hasher {
+        self.bytes += data.len() as u64;
+        match &mut self.state {
+            State::Sha256(digest) => digest.update(data),
+            #[cfg(feature = "sha1")]
+            State::Sha1(digest) => Digest::update(digest, data),
+            #[cfg(feature = "md5")]
+            State::Md5(digest) => Digest::update(digest, data),
+            #[cfg(feature = "blake3")]
+            State::Blake3(digest) => Digest::update(digest, data),
+        }
+        self
+    }
+
+    /// Consumes the contents of the file at `path`, reading it in chunks.
+    ///
+    /// If reading fails part of the way through, what was read before the
+    /// error has still been consumed.
+    ///
+    /// ## Usage:
+    ///
+    /// ```
+    /// use fsutils::{HashAlgorithm, Hasher};
+    ///
+    /// fsutils::write_file("hasher_update_file_1.txt", "a");
+    /// fsutils::write_file("hasher_update_file_2.txt", "bc");
+    /// fsutils::write_file("hasher_update_file_3.txt", "abc");
+    ///
+    /// let mut hasher = Hasher::new(HashAlgorithm::default());
+    /// hasher.update_file("hasher_update_file_1.txt").unwrap();
+    /// hasher.update_file("hasher_update_file_2.txt").unwrap();
+    /// assert!(hasher.update_file("a_very_1234_unlikely_9876_filename").is_err());
+    /// assert_eq!(hasher.finish(), fsutils::sha256_file("hasher_update_file_3.txt").unwrap());
+    ///
+    /// # // Cleanup
+    /// # fsutils::rm("hasher_update_file_1.txt");
+    /// # fsutils::rm("hasher_update_file_2.txt");
+    /// # fsutils::rm("hasher_update_file_3.txt");
+    /// ```
+    pub fn update_file<P: AsRef<Path>>(&mut self, path: P) -> Result<&mut Hasher> {
+        let path = path.as_ref();
+        let file = fs::File::open(path).at(path)?;
+        self.update_reader(file).at(path)?;
+        Ok(self)
+    }
+
+    /// Consumes everything `reader` yields until its end, and returns how
+    /// many bytes that was.
+    ///
+    /// ## Usage:
+    ///
+    /// ```
+    /// use fsutils::{HashAlgorithm, Hasher};
+    ///
+    /// let mut hasher = Hasher::new(HashAlgorithm::Sha256);
+    /// assert_eq!(hasher.update_reader(&b"abc"[..]).unwrap(), 3);
+    /// assert_eq!(hasher.finish(), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+    /// ```
+    pub fn update_reader<R: Read>(&mut self, mut reader: R) -> io::Result<u64> {
+        let mut buf = vec![0; 64 * 1024];
+        let mut total = 0;
+        loop {
+            match reader.read(&mut buf) {
+                Ok(0) => return Ok(total),
+                Ok(n) => {
+                    self.update(&buf[..n]);
+                    total += n as u64;
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Returns the digest of everything consumed, as raw bytes.
+    pub fn finish_bytes(self) -> Vec<u8> {
+        match self.state {
+            State::Sha256(digest) => digest.finish().to_vec(),
+            #[cfg(feature = "sha1")]
+            State::Sha1(digest) => digest.finish().to_vec(),
+            #[cfg(feature = "md5")]
+            State::Md5(digest) => digest.finish().to_vec(),
+            #[cfg(feature = "blake3")]
+            State::Blake3(digest) => digest.finish().to_vec(),
+        }
+    }
+
+    /// Returns the digest of everything consumed as lowercase hex, the form
+    /// `fsutils::sha256_file` and `sha256sum` give.
+    ///
+    /// ## Usage:
+    ///
+    /// ```
+    /// use std::io::Write;
+    /// use fsutils::{HashAlgorithm, Hasher};
+    ///
+    /// let mut copy = Vec::new();
+    /// let mut hasher = Hasher::new(HashAlgorithm::Sha256);
+    /// for chunk in [&b"a"[..], b"bc"] {
+    ///     copy.write_all(chunk).unwrap();
+    ///     hasher.write_all(chunk).unwrap();
+    /// }
+    /// assert_eq!(copy, b"abc");
+    /// assert_eq!(hasher.finish(), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+    /// ```
+    pub fn finish(self) -> String {
+        hex(&self.finish_bytes())
+    }
+}
+
+impl Default for Hasher {
+    fn default() -> Hasher {
+        Hasher::new(HashAlgorithm::default())
+    }
+}
+
+impl Write for Hasher {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Returns the SHA-256 digest of a file as lowercase hex, or `None` if it
+/// cannot be read.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("sha256_file.txt", "abc");
+///
+/// assert_eq!(
+///     fsutils::sha256_file("sha256_file.txt").unwrap(),
+///     "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+/// );
+/// assert_eq!(fsutils::sha256_file("a_very_1234_unlikely_9876_filename"), None);
+///
+/// # // Cleanup
+/// # fsutils::rm("sha256_file.txt");
+/// ```
+pub fn sha256_file<P: AsRef<Path>>(path: P) -> Option<String> {
+    match try_sha256_file(path) {
+        Ok(digest) => Some(digest),
+        Err(e) => {
+            error!("Cannot hash file {}", e);
+            None
+        }
+    }
+}
+
+/// Returns the SHA-256 digest of a file as lowercase hex, like `sha256sum`.
+///
+/// The file is read in chunks, so files of any size take little memory.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::create_file("try_sha256_file.txt");
+///
+/// let digest = fsutils::try_sha256_file("try_sha256_file.txt").unwrap();
+/// assert_eq!(digest, "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+/// assert!(fsutils::try_sha256_file("a_very_1234_unlikely_9876_filename").is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm("try_sha256_file.txt");
+/// ```
+pub fn try_sha256_file<P: AsRef<Path>>(path: P) -> Result<String> {
+    digest_file(path.as_ref(), Sha256::new()).map(|digest| hex(&digest))
+}
+
+/// Returns the SHA-1 digest of a file as lowercase hex, or `None` if it
+/// cannot be read.
+///
+/// Requires the `sha1` feature.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("sha1_file.txt", "abc");
+///
+/// assert_eq!(fsutils::sha1_file("sha1_file.txt").unwrap(), "a9993e364706816aba3e25717850c26c9cd0d89d");
+///
+/// # // Cleanup
+/// # fsutils::rm("sha1_file.txt");
+/// ```
+#[cfg(feature = "sha1")]
+pub fn sha1_file<P: AsRef<Path>>(path: P) -> Option<String> {
+    match try_sha1_file(path) {
+        Ok(digest) => Some(digest),
+        Err(e) => {
+            error!("Cannot hash file {}", e);
+            None
+        }
+    }
+}
+
+/// Returns the SHA-1 digest of a file as lowercase hex, like `sha1sum`,
+/// reading it in chunks.
+///
+/// SHA-1 is broken for security purposes; use it to match checksums that
+/// others publish, and `fsutils::try_sha256_file` otherwise. Requires the
+/// `sha1` feature.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("try_sha1_file.txt", &"a".repeat(1000));
+///
+/// let digest = fsutils::try_sha1_file("try_sha1_file.txt").unwrap();
+/// assert_eq!(digest, "291e9a6c66994949b57ba5e650361e98fc36b1ba");
+/// assert!(fsutils::try_sha1_file("a_very_1234_unlikely_9876_filename").is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm("try_sha1_file.txt");
+/// ```
+#[cfg(feature = "sha1")]
+pub fn try_sha1_file<P: AsRef<Path>>(path: P) -> Result<String> {
+    digest_file(path.as_ref(), Sha1::new()).map(|digest| hex(&digest))
+}
+
+/// Returns the MD5 digest of a file as lowercase hex, or `None` if it
+/// cannot be read.
+///
+/// Requires the `md5` feature.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("md5_file.txt", "abc");
+///
+/// assert_eq!(fsutils::md5_file("md5_file.txt").unwrap(), "900150983cd24fb0d6963f7d28e17f72");
+///
+/// # // Cleanup
+/// # fsutils::rm("md5_file.txt");
+/// ```
+#[cfg(feature = "md5")]
+pub fn md5_file<P: AsRef<Path>>(path: P) -> Option<String> {
+    match try_md5_file(path) {
+        Ok(digest) => Some(digest),
+        Err(e) => {
+            error!("Cannot hash file {}", e);
+            None
+        }
+    }
+}
+
+/// Returns the MD5 digest of a file as lowercase hex, like `md5sum`,
+/// reading it in chunks.
+///
+/// MD5 is broken for security purposes; use it to match checksums that
+/// others publish, and `fsutils::try_sha256_file` otherwise. Requires the
+/// `md5` feature.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::create_file("try_md5_file.txt");
+///
+/// assert_eq!(fsutils::try_md5_file("try_md5_file.txt").unwrap(), "d41d8cd98f00b204e9800998ecf8427e");
+/// assert!(fsutils::try_md5_file("a_very_1234_unlikely_9876_filename").is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm("try_md5_file.txt");
+/// ```
+#[cfg(feature = "md5")]
+pub fn try_md5_file<P: AsRef<Path>>(path: P) -> Result<String> {
+    digest_file(path.as_ref(), Md5::new()).map(|digest| hex(&digest))
+}
+
+/// Returns the BLAKE3 digest of a file as lowercase hex, or `None` if it
+/// cannot be read.
+///
+/// Requires the `blake3` feature.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("blake3_file.txt", "abc");
+///
+/// assert_eq!(
+///     fsutils::blake3_file("blake3_file.txt").unwrap(),
+///     "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85"
+/// );
+///
+/// # // Cleanup
+/// # fsutils::rm("blake3_file.txt");
+/// ```
+#[cfg(feature = "blake3")]
+pub fn blake3_file<P: AsRef<Path>>(path: P) -> Option<String> {
+    match try_blake3_file(path) {
+        Ok(digest) => Some(digest),
+        Err(e) => {
+            error!("Cannot hash file {}", e);
+            None
+        }
+    }
+}
+
+/// Returns the 32-byte BLAKE3 digest of a file as lowercase hex, like
+/// `b3sum`, reading it in chunks.
+///
+/// Requires the `blake3` feature.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::create_file("try_blake3_file.txt");
+///
+/// let digest = fsutils::try_blake3_file("try_blake3_file.txt").unwrap();
+/// assert_eq!(digest, "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262");
+/// assert!(fsutils::try_blake3_file("a_very_1234_unlikely_9876_filename").is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm("try_blake3_file.txt");
+/// ```
+#[cfg(feature = "blake3")]
+pub fn try_blake3_file<P: AsRef<Path>>(path: P) -> Result<String> {
+    digest_file(path.as_ref(), Blake3::new()).map(|digest| hex(&digest))
+}
+
+/// Returns a digest of the tree at `path` as lowercase hex, or `None` if
+/// any of it cannot be read.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::mkdir("hash_dir_a");
+/// fsutils::mkdir("hash_dir_b");
+/// fsutils::write_file("hash_dir_a/a.txt", "a");
+/// fsutils::write_file("hash_dir_b/a.txt", "a");
+///
+/// assert_eq!(fsutils::hash_dir("hash_dir_a"), fsutils::hash_dir("hash_dir_b"));
+///
+/// # // Cleanup
+/// # fsutils::rm_r("hash_dir_a");
+/// # fsutils::rm_r("hash_dir_b");
+/// ```
+pub fn hash_dir<P: AsRef<Path>>(path: P) -> Option<String> {
+    match try_hash_dir(path) {
+        Ok(digest) => Some(digest),
+        Err(e) => {
+            error!("Cannot hash directory {}", e);
+            None
+        }
+    }
+}
+
+/// Returns a SHA-256 digest of the structure and contents of the tree at
+/// `path` as lowercase hex, the same for two trees exactly when they hold
+/// the same names, files and links.
+///
+/// The digest is built like a Merkle tree: a file is hashed by its
+/// contents, a symbolic link by its target without following it, and a
+/// directory by the names and digests of its entries sorted by name, each
+/// tagged with its kind and names prefixed by their length. The name of the
+/// root, times, permissions and owners are left out, so a copy made
+/// anywhere hashes the same, and the digest only changes when a name,
+/// link or byte does. That makes it usable as a cache key for a tree. A
+/// file at `path` is hashed as it would be inside a directory, and a link
+/// there is followed.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::mkdir("try_hash_dir/src");
+/// fsutils::write_file("try_hash_dir/src/lib.rs", "fn f() {}");
+///
+/// let before = fsutils::try_hash_dir("try_hash_dir").unwrap();
+/// assert_eq!(before.len(), 64);
+///
+/// fsutils::write_file("try_hash_dir/src/lib.rs", "fn g() {}");
+/// assert_ne!(fsutils::try_hash_dir("try_hash_dir").unwrap(), before);
+///
+/// fsutils::write_file("try_hash_dir/src/lib.rs", "fn f() {}");
+/// assert_eq!(fsutils::try_hash_dir("try_hash_dir").unwrap(), before);
+/// assert!(fsutils::try_hash_dir("a_very_1234_unlikely_9876_dirname").is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm_r("try_hash_dir");
+/// ```
+pub fn try_hash_dir<P: AsRef<Path>>(path: P) -> Result<String> {
+    let path = path.as_ref();
+    let meta = fs::metadata(path).at(path)?;
+    Ok(hex(&tree_digest(path, &meta)?))
+}
+
+/// The digest of one node of the tree, with `meta` not following links.
+fn tree_digest(path: &Path, meta: &fs::Metadata) -> Result<[u8; 32]> {
+    let mut digest = Sha256::new();
+    let file_type = meta.file_type();
+    if file_type.is_file() {
+        digest.update(b"file\0");
+        digest.update(&digest_file(path, Sha256::new())?);
+    } else if file_type.is_symlink() {
+        digest.update(b"link\0");
+        digest.update(fs::read_link(path).at(path)?.as_os_str().as_encoded_bytes());
+    } else if file_type.is_dir() {
+        digest.update(b"dir\0");
+        let mut entries = fs::read_dir(path).at(path)?.collect::<io::Result<Vec<_>>>().at(path)?;
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            let child = entry.path();
+            let name = entry.file_name();
+            let name = name.as_encoded_bytes();
+            digest.update(&(name.len() as u64).to_le_bytes());
+            digest.update(name);
+            digest.update(&tree_digest(&child, &fs::symlink_metadata(&child).at(&child)?)?);
+        }
+    } else {
+        digest.update(b"other\0");
+    }
+    Ok(digest.finish())
+}
+
+/// Formats a digest as lowercase hex.
+pub(crate) fn hex(digest: &[u8]) -> String {
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
@@ -0,0 +1,212 @@
+// Copyright 2020 Jared Forth.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Rules from `.gitignore` and `.ignore` files, for walks that respect them.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::glob::{to_slash, Pattern};
+
+/// The files read in each directory, the one that takes precedence last.
+const IGNORE_FILES: [&str; 2] = [".gitignore", ".ignore"];
+
+/// One line of an ignore file.
+#[derive(Clone, Debug)]
+struct Rule {
+    pattern: Pattern,
+    /// `!pattern`, which brings back what an earlier rule ignored.
+    negated: bool,
+    /// `pattern/`, which only matches directories.
+    dir_only: bool,
+    /// The pattern has a `/` other than at its end, so it is matched against
+    /// the path relative to the file's directory instead of the name.
+    anchored: bool,
+}
+
+impl Rule {
+    fn parse(line: &str) -> Option<Rule> {
+        let mut line = line.trim_end_matches(['\n', '\r']);
+        while line.ends_with(' ') && !line.ends_with("\\ ") {
+            line = &line[..line.len() - 1];
+        }
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let negated = line.starts_with('!');
+        if negated {
+            line = &line[1..];
+        }
+        let dir_only = line.ends_with('/');
+        let line = line.trim_end_matches('/');
+        let anchored = line.contains('/');
+        let line = line.strip_prefix('/').unwrap_or(line);
+        // Braces have no special meaning in ignore files
+        let source = line.replace('{', "\\{").replace('}', "\\}");
+        match Pattern::new(&source) {
+            Ok(pattern) => Some(Rule { pattern, negated, dir_only, anchored }),
+            Err(e) => {
+                warn!("Skipping ignore rule {}", e);
+                None
+            }
+        }
+    }
+}
+
+/// The rules of one ignore file, and where the paths they match start.
+#[derive(Clone, Debug)]
+struct IgnoreFile {
+    /// The directory the paths are taken relative to, as it appears in the
+    /// paths of the walk.
+    dir: PathBuf,
+    /// What goes in front of those paths to make them relative to the
+    /// directory of the file, for files above the root of the walk.
+    prefix: PathBuf,
+    rules: Vec<Rule>,
+}
+
+impl IgnoreFile {
+    fn read(file: &Path, dir: &Path, prefix: &Path) -> Option<IgnoreFile> {
+        let text = match fs::read_to_string(file) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return None,
+            Err(e) => {
+                warn!("Cannot read ignore file {}: {}", file.display(), e);
+                return None;
+            }
+        };
+        let rules: Vec<_> = text.lines().filter_map(Rule::parse).collect();
+        if rules.is_empty() {
+            return None;
+        }
+        Some(IgnoreFile { dir: dir.to_path_buf(), prefix: prefix.to_path_buf(), rules })
+    }
+
+    /// Whether the last rule matching `path` ignores or brings it back,
+    /// or `None` if no rule matches.
+    fn decide(&self, path: &Path, is_dir: bool) -> Option<bool> {
+        let relative = self.prefix.join(path.strip_prefix(&self.dir).ok()?);
+        let full = to_slash(&relative);
+        let name = relative.file_name().map(|name| name.to_string_lossy());
+        self.rules.iter().rev().find_map(|rule| {
+            if rule.dir_only && !is_dir {
+                return None;
+            }
+            let matches = if rule.anchored {
+                rule.pattern.matches(&full)
+            } else {
+                name.as_ref().is_some_and(|name| rule.pattern.matches(name))
+            };
+            matches.then_some(!rule.negated)
+        })
+    }
+}
+
+/// The ignore rules in effect at some point of a walk.
+///
+/// The files are shared, so a clone for each directory of a parallel walk
+/// is cheap.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Ignores {
+    /// Rules from outside the tree: git's global excludes, the repository's
+    /// `info/exclude` and ignore files in the directories above the root.
+    outer: Arc<[IgnoreFile]>,
+    /// The ignore files of each directory being walked, the root first.
+    dirs: Vec<Arc<[IgnoreFile]>>,
+}
+
+impl Ignores {
+    /// Collects the rules that apply to the tree at `root` from outside it.
+    ///
+    /// Ignore files above the root are read up to the enclosing git
+    /// repository, if there is one.
+    pub(crate) fn new(root: &Path) -> Ignores {
+        let mut outer = Vec::new();
+        let absolute = fs::canonicalize(root).unwrap_or_else(|_| root.to_path_buf());
+        let repo = absolute.ancestors().find(|dir| dir.join(".git").exists());
+        let top = repo.unwrap_or(&absolute);
+        let prefix = absolute.strip_prefix(top).unwrap_or(Path::new(""));
+        if let Some(file) = global_excludes() {
+            outer.extend(IgnoreFile::read(&file, root, prefix));
+        }
+        if let Some(repo) = repo {
+            outer.extend(IgnoreFile::read(&repo.join(".git/info/exclude"), root, prefix));
+            let above: Vec<_> = absolute.ancestors().skip(1).take_while(|dir| dir.starts_with(repo)).collect();
+            for dir in above.into_iter().rev() {
+                let prefix = absolute.strip_prefix(dir).unwrap_or(Path::new(""));
+                for name in IGNORE_FILES {
+                    outer.extend(IgnoreFile::read(&dir.join(name), root, prefix));
+                }
+            }
+        }
+        Ignores { outer: outer.into(), dirs: Vec::new() }
+    }
+
+    /// Reads the ignore files of `dir`, which is being walked into.
+    pub(crate) fn enter(&mut self, dir: &Path) {
+        let files = IGNORE_FILES.iter().filter_map(|name| IgnoreFile::read(&dir.join(name), dir, Path::new("")));
+        self.dirs.push(files.collect::<Vec<_>>().into());
+    }
+
+    /// Drops the ignore files of the directory walked into last.
+    pub(crate) fn leave(&mut self) {
+        self.dirs.pop();
+    }
+
+    /// Whether `path` is ignored. The `.git` directory always is.
+    pub(crate) fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        if path.file_name().is_some_and(|name| name == ".git") {
+            return true;
+        }
+        self.dirs
+            .iter()
+            .rev()
+            .flat_map(|files| files.iter().rev())
+            .chain(self.outer.iter().rev())
+            .find_map(|file| file.decide(path, is_dir))
+            .unwrap_or(false)
+    }
+}
+
+/// The file named by git's `core.excludesFile`, or its default.
+fn global_excludes() -> Option<PathBuf> {
+    let home = env::var_os("HOME").or_else(|| env::var_os("USERPROFILE")).map(PathBuf::from);
+    let config = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| home.as_ref().map(|home| home.join(".config")));
+    let configs = [
+        home.as_ref().map(|home| home.join(".gitconfig")),
+        config.as_ref().map(|config| config.join("git/config")),
+    ];
+    for file in configs.iter().flatten() {
+        if let Some(value) = fs::read_to_string(file).ok().and_then(|text| excludes_file_setting(&text)) {
+            return Some(match (value.strip_prefix("~/"), &home) {
+                (Some(rest), Some(home)) => home.join(rest),
+                _ => PathBuf::from(value),
+            });
+        }
+    }
+    config.map(|config| config.join("git/ignore"))
+}
+
+/// The value of `excludesFile` in the `[core]` section of a git config file.
+fn excludes_file_setting(config: &str) -> Option<String> {
+    let mut in_core = false;
+    for line in config.lines().map(str::trim) {
+        if line.starts_with('[') {
+            in_core = line.trim_matches(['[', ']']).trim().eq_ignore_ascii_case("core");
+        } else if let Some((key, value)) = line.split_once('=') {
+            if in_core && key.trim().eq_ignore_ascii_case("excludesfile") {
+                return Some(value.trim().trim_matches('"').to_string());
+            }
+        }
+    }
+    None
+}
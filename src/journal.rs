@@ -0,0 +1,172 @@
+// Copyright 2020 Jared Forth.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! An opt-in record of every operation that modifies the filesystem.
+//!
+//! Once enabled, each modifying call is stored as an [`Entry`] in memory and,
+//! if a journal file was given, appended to that file as one JSON object per
+//! line. Calls made in dry-run mode are recorded too, flagged as such.
+//!
+//! ## Usage:
+//!
+//! ```
+//! use fsutils::journal;
+//!
+//! journal::enable();
+//! fsutils::create_file("journal_file.txt");
+//! fsutils::mv("journal_file.txt", "journal_moved.txt");
+//! fsutils::rm("journal_moved.txt");
+//!
+//! let ops: Vec<_> = journal::entries().into_iter().map(|e| e.op).collect();
+//! assert_eq!(ops, ["create_file", "mv", "rm"]);
+//! assert!(journal::to_json().starts_with("[{\"op\":\"create_file\""));
+//!
+//! journal::disable();
+//! ```
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::error::{IoResultExt, Result};
+use crate::json;
+
+struct Journal {
+    entries: Vec<Entry>,
+    file: Option<File>,
+}
+
+static JOURNAL: Mutex<Option<Journal>> = Mutex::new(None);
+
+fn lock() -> MutexGuard<'static, Option<Journal>> {
+    // A panic while holding the lock cannot leave the journal half-updated
+    JOURNAL.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// A single recorded operation.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    /// The operation, named after the function that performed it (`"mv"`, `"rm"`, ...).
+    pub op: &'static str,
+    /// The path the operation acted on.
+    pub source: PathBuf,
+    /// The destination, for operations that have one.
+    pub destination: Option<PathBuf>,
+    /// When the operation finished.
+    pub timestamp: SystemTime,
+    /// The error message if the operation failed.
+    pub error: Option<String>,
+    /// Whether the operation was skipped because dry-run mode was on.
+    pub dry_run: bool,
+}
+
+impl Entry {
+    /// Returns whether the operation succeeded.
+    pub fn succeeded(&self) -> bool {
+        self.error.is_none()
+    }
+
+    /// Encodes the entry as a single-line JSON object.
+    pub fn to_json(&self) -> String {
+        let millis = self.timestamp.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis());
+        format!(
+            "{{\"op\":{},\"source\":{},\"destination\":{},\"timestamp_ms\":{},\"result\":{},\"error\":{},\"dry_run\":{}}}",
+            json::string(self.op),
+            json::path(&self.source),
+            self.destination.as_deref().map_or_else(|| "null".to_string(), json::path),
+            millis,
+            if self.succeeded() { "\"ok\"" } else { "\"error\"" },
+            self.error.as_deref().map_or_else(|| "null".to_string(), json::string),
+            self.dry_run,
+        )
+    }
+}
+
+/// Starts recording operations in memory.
+///
+/// Entries recorded before a previous `disable` are discarded.
+pub fn enable() {
+    *lock() = Some(Journal { entries: Vec::new(), file: None });
+}
+
+/// Starts recording operations in memory and appending them to `path` as JSON lines.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::journal;
+///
+/// journal::enable_with_file("journal.jsonl").unwrap();
+/// fsutils::mkdir("journal_dir");
+/// fsutils::rmdir("journal_dir");
+/// journal::disable();
+///
+/// assert_eq!(fsutils::read_file("journal.jsonl").lines().count(), 2);
+///
+/// # // Cleanup
+/// # fsutils::rm("journal.jsonl");
+/// ```
+pub fn enable_with_file<P: AsRef<Path>>(path: P) -> Result<()> {
+    let path = path.as_ref();
+    let file = OpenOptions::new().create(true).append(true).open(path).at(path)?;
+    *lock() = Some(Journal { entries: Vec::new(), file: Some(file) });
+    Ok(())
+}
+
+/// Stops recording and closes the journal file, if any.
+pub fn disable() {
+    *lock() = None;
+}
+
+/// Returns whether the journal is recording.
+pub fn is_enabled() -> bool {
+    lock().is_some()
+}
+
+/// Returns a copy of every entry recorded since the journal was enabled or cleared.
+pub fn entries() -> Vec<Entry> {
+    lock().as_ref().map_or_else(Vec::new, |j| j.entries.clone())
+}
+
+/// Forgets the in-memory entries. Lines already written to a journal file are kept.
+pub fn clear() {
+    if let Some(journal) = lock().as_mut() {
+        journal.entries.clear();
+    }
+}
+
+/// Exports the in-memory entries as a JSON array.
+pub fn to_json() -> String {
+    let entries: Vec<String> = entries().iter().map(Entry::to_json).collect();
+    format!("[{}]", entries.join(","))
+}
+
+/// Records an operation if the journal is enabled.
+pub(crate) fn record(op: &'static str, source: &Path, destination: Option<&Path>, error: Option<String>, dry_run: bool) {
+    let mut guard = lock();
+    let journal = match guard.as_mut() {
+        Some(journal) => journal,
+        None => return,
+    };
+    let entry = Entry {
+        op,
+        source: source.to_path_buf(),
+        destination: destination.map(Path::to_path_buf),
+        timestamp: SystemTime::now(),
+        error,
+        dry_run,
+    };
+    if let Some(file) = journal.file.as_mut() {
+        if let Err(e) = writeln!(file, "{}", entry.to_json()) {
+            error!("Cannot write journal entry: {}", e);
+        }
+    }
+    journal.entries.push(entry);
+}
@@ -11,11 +11,209 @@
 //! **fsutils** provides an API based on Bash commands and includes a number
 //! of utility functions to make interacting with the filesystem simpler and more
 //! ergonomic.
+//!
+//! Every command comes in two flavours. The plain functions (`mkdir`, `rm`, ...)
+//! log failures and return a `bool` or `Option`, while the `try_` variants
+//! (`try_mkdir`, `try_rm`, ...) return a `Result` so callers can branch on
+//! the underlying [`FsError`].
+//!
+//! Path parameters accept anything that implements `AsRef<Path>`, so `&str`,
+//! `String`, `&Path` and `PathBuf` can all be passed without conversion:
+//!
+//! ```
+//! use std::path::PathBuf;
+//!
+//! let dir = PathBuf::from("crate_docs_dir");
+//! assert!(fsutils::mkdir(&dir));
+//! assert!(fsutils::create_file(dir.join("file.txt")));
+//!
+//! # // Cleanup
+//! # fsutils::rm_r(&dir);
+//! ```
+//!
+//! Since nothing is routed through `&str`, paths that are not valid UTF-8 work
+//! as well, and every function that hands a path back returns a `PathBuf`:
+//!
+//! ```
+//! # #[cfg(unix)] {
+//! use std::ffi::OsStr;
+//! use std::os::unix::ffi::OsStrExt;
+//!
+//! let name = OsStr::from_bytes(b"non_utf8_\xff_file");
+//! assert!(fsutils::create_file(name));
+//! assert!(fsutils::ls(".").unwrap().iter().any(|p| p.file_name() == Some(name)));
+//! assert!(fsutils::rm(name));
+//! # }
+//! ```
+//!
+//! Calling `fsutils::dry_run(true)` turns every function that modifies the
+//! filesystem into a no-op that only logs what it would have done, which makes
+//! it easy to offer a `--dry-run` flag in tools built on top of this crate.
+//! Those same operations can be recorded for later inspection by the
+//! [`journal`].
 
 #[macro_use]
 extern crate log;
 
+#[cfg(all(feature = "acl", target_os = "linux"))]
+mod acl;
+mod append;
+#[cfg(any(feature = "tar", feature = "zip"))]
+mod archive;
+mod atomic;
+mod auto;
+mod baseline;
+mod cmp;
+#[cfg(any(feature = "lz4", feature = "zstd"))]
+mod compress;
+mod context;
+mod copy;
+mod deflate;
+mod diff;
+mod edit;
+mod encoding;
+mod error;
+mod find;
+mod glob;
+mod hash;
+mod ignore;
+pub mod journal;
+mod json;
+mod lines;
+mod link;
+mod list;
+#[cfg(any(feature = "lz4", feature = "zstd"))]
+mod lz;
+#[cfg(feature = "lz4")]
+mod lz4;
+mod meta;
+mod mkdir;
+#[cfg(feature = "mmap")]
+mod mmap;
+mod mv;
+mod op;
+mod open;
+mod overwrite;
+mod perm;
+mod rm;
+mod search;
+mod size;
+#[cfg(unix)]
+mod sys;
+#[cfg(feature = "tar")]
+mod tar;
+mod temp;
+mod text;
+mod tree;
+mod walk;
+#[cfg(all(feature = "xattr", any(target_os = "linux", target_os = "macos")))]
+mod xattr;
+#[cfg(any(feature = "lz4", feature = "zstd"))]
+mod xxhash;
+#[cfg(feature = "xz")]
+mod xz;
+#[cfg(feature = "zip")]
+mod zip;
+#[cfg(feature = "zstd")]
+mod zstd;
+
+#[cfg(all(feature = "acl", target_os = "linux"))]
+pub use acl::{get_acl, set_acl, try_get_acl, try_set_acl, AclEntry, AclTag};
+pub use append::{append_capped, try_append_capped, CapPolicy};
+pub use atomic::{try_write_file_atomic, try_write_file_durable, write_file_atomic, write_file_durable};
+pub use auto::{read_file_auto, read_file_auto_bytes, try_read_file_auto, try_read_file_auto_bytes};
+pub use baseline::{
+    check_baseline, create_baseline, try_check_baseline, try_create_baseline, Baseline, BaselineEntry, Change, Drift,
+    Modified,
+};
+pub use cmp::{cmp, files_identical, try_cmp, try_files_identical};
+#[cfg(any(feature = "lz4", feature = "zstd"))]
+pub use compress::{compress_file, decompress_file, try_compress_file, try_decompress_file, Codec};
+pub use context::FsContext;
+pub use diff::{diff, diff_hunks, try_diff, try_diff_hunks, DiffLine, Hunk};
+pub use copy::{
+    cp, cp_r, cp_r_with, cp_reflink, cp_resume, cp_with, mirror_dirs, try_cp, try_cp_r, try_cp_reflink, try_cp_resume,
+    try_mirror_dirs, CopyOptions,
+};
+pub use edit::{
+    insert_line, prepend_file, read_range, replace_in_file, replace_line, truncate, truncate_to_zero, try_insert_line,
+    try_prepend_file, try_read_range, try_replace_in_file, try_replace_line, try_truncate, try_truncate_to_zero,
+    try_write_at, write_at,
+};
+#[cfg(feature = "regex")]
+pub use edit::{replace_regex_in_file, replace_regex_in_file_with, try_replace_regex_in_file};
+pub use encoding::{detect_encoding, read_file_detect_encoding, read_file_with_encoding, Encoding};
+pub use error::{FsError, Result};
+pub use find::{
+    newest_file, newest_file_with, oldest_file, oldest_file_with, try_newest_file, try_oldest_file, Find, FindIter,
+    PickOptions,
+};
+pub use glob::{glob, glob_iter, try_glob, GlobIter};
+pub use hash::{hash_dir, sha256_file, try_hash_dir, try_sha256_file, HashAlgorithm, Hasher};
+#[cfg(feature = "blake3")]
+pub use hash::{blake3_file, try_blake3_file};
+#[cfg(feature = "md5")]
+pub use hash::{md5_file, try_md5_file};
+#[cfg(feature = "sha1")]
+pub use hash::{sha1_file, try_sha1_file};
+pub use lines::{
+    head, read_lines, read_lines_vec, tail, try_head, try_read_lines, try_read_lines_vec, try_tail, try_write_lines,
+    write_lines, write_lines_with, LineEnding, Lines,
+};
+pub use link::{
+    find_hardlinks, ln, ln_s, ln_s_with, ln_with, readlink, realpath, realpath_missing, resolve_link_chain,
+    try_find_hardlinks, try_ln, try_ln_s, try_readlink, try_realpath, try_realpath_missing, try_resolve_link_chain,
+    FollowSymlinks, HardLinks, LinkChain, SymlinkFallback,
+};
+pub use list::{ls_a, ls_entries, ls_with, try_ls_a, try_ls_entries, LsEntry, LsOptions, LsSort};
+pub use meta::{
+    age, created, is_hidden, is_readonly, is_same_file, modified, modified_since, set_hidden, set_readonly, stat, touch,
+    touch_at, try_age, try_created, try_is_same_file, try_modified, try_modified_since, try_set_hidden, try_set_readonly,
+    try_stat, try_touch, try_touch_at, FileKind, Stat,
+};
+pub use mkdir::{mkdir_with, MkdirOptions};
+#[cfg(feature = "mmap")]
+pub use mmap::{read_mmap, try_read_mmap, MmapFile};
+pub use mv::{flatten, flatten_with, mv_merge, swap, try_flatten, try_swap};
+pub use open::{open_buffered_reader, open_buffered_writer, try_open_buffered_reader, try_open_buffered_writer};
+pub use op::{clear_confirm, dry_run, is_dry_run, set_confirm};
+pub use overwrite::{unique_path, unique_path_with, OverwritePolicy};
+pub use perm::{chmod, chmod_r, chmod_r_with, chmod_symbolic, try_chmod, try_chmod_r, try_chmod_symbolic, ChmodOptions};
+#[cfg(unix)]
+pub use perm::{chown, chown_r, try_chown, try_chown_r};
+pub use rm::{
+    prune_empty_dirs, purge_older_than, purge_older_than_with, rm_glob, rm_r_filtered, rm_r_glob, rm_r_matching,
+    rm_r_parallel, rm_r_with, shred, shred_r, try_prune_empty_dirs, try_rm_glob, try_rm_r_glob, try_rm_r_parallel,
+    try_shred, try_shred_r, PurgeOptions,
+};
+#[cfg(feature = "trash")]
+pub use rm::{rm_trash, try_rm_trash};
+pub use search::{grep, grep_r, grep_r_with, try_grep, GrepOptions};
+pub use size::{
+    dir_size, dir_size_with, dir_stats, du, du_with, file_size, format_size, try_dir_size, try_dir_stats, try_du,
+    try_file_size, DirStats, DuEntry, ExtensionStats, SizeKind,
+};
+#[cfg(feature = "tar")]
+pub use tar::{tar_create, tar_create_with, tar_extract, try_tar_create, try_tar_extract, TarOptions};
+pub use temp::{CleanupGuard, TempBuilder, TempDir, TempFile};
+pub use text::{
+    concat, concat_with, convert_line_endings, line_endings, reverse_lines, sort_file, sort_file_with, split, try_concat,
+    try_convert_line_endings, try_line_endings, try_reverse_lines, try_sort_file, try_split, try_uniq_file, try_wc,
+    uniq_file, uniq_file_with, wc, LineEndings, SortOptions, SplitBy, UniqOptions, WordCount,
+};
+pub use tree::{tree, tree_json, tree_json_with, tree_with, try_tree, try_tree_json, TreeNode, TreeOptions};
+pub use walk::{par_walk, par_walk_with, walk, walk_with, ParWalk, Walk, WalkEntry, WalkOptions};
+#[cfg(all(feature = "xattr", any(target_os = "linux", target_os = "macos")))]
+pub use xattr::{
+    try_xattr_get, try_xattr_list, try_xattr_remove, try_xattr_set, xattr_get, xattr_list, xattr_remove, xattr_set,
+};
+#[cfg(feature = "zip")]
+pub use zip::{try_unzip, try_zip, unzip, zip, zip_with, ZipOptions};
+use error::IoResultExt;
+use op::Op;
+
 use std::{fs, process, io};
+use std::ffi::OsStr;
 use std::path::{Path, PathBuf};
 use std::io::{Write, Read};
 use std::fs::{File, OpenOptions};
@@ -31,13 +229,11 @@ use std::fs::{File, OpenOptions};
 /// # // Cleanup
 /// # fsutils::rmdir("testdir");
 /// ```
-pub fn mkdir(path: &str) -> bool {
+pub fn mkdir<P: AsRef<Path>>(path: P) -> bool {
+    let path = path.as_ref();
     if !path_exists(path) {
-        match fs::create_dir_all(path) {
-            Ok(_) => {
-                info!("Created {}", path);
-                true
-            }
+        match try_mkdir(path) {
+            Ok(_) => true,
             Err(e) => {
                 error!("Error creating file: {}", e);
                 false
@@ -48,6 +244,34 @@ pub fn mkdir(path: &str) -> bool {
     }
 }
 
+/// Creates a directory recursively at passed path.
+///
+/// Fails with `FsError::AlreadyExists` if the path already exists. Use
+/// `fsutils::mkdir_with` to treat an existing directory as success.
+///
+/// ## Usage:
+///
+/// ```
+/// use std::io::ErrorKind;
+///
+/// assert!(fsutils::try_mkdir("try_mkdir_dir").is_ok());
+/// assert_eq!(fsutils::try_mkdir("try_mkdir_dir").unwrap_err().kind(), ErrorKind::AlreadyExists);
+///
+/// # // Cleanup
+/// # fsutils::rmdir("try_mkdir_dir");
+/// ```
+pub fn try_mkdir<P: AsRef<Path>>(path: P) -> Result<()> {
+    let path = path.as_ref();
+    if path.exists() {
+        return Err(FsError::already_exists(path));
+    }
+    Op::new("mkdir", path).run((), || {
+        fs::create_dir_all(path).at(path)?;
+        info!("Created {}", path.display());
+        Ok(())
+    })
+}
+
 /// Removes a file at passed path
 /// and returns a boolean based on success or failure.
 ///
@@ -57,17 +281,13 @@ pub fn mkdir(path: &str) -> bool {
 /// fsutils::create_file("testfile.txt");
 /// assert_eq!(fsutils::rm("testfile.txt"), true);
 /// ```
-pub fn rm(path: &str) -> bool {
-    // str to Path
-    let new_path = Path::new(path);
-    if new_path.exists() {
-        match fs::remove_file(path) {
-            Ok(_) => {
-                info!("Removed file {}", path);
-                true
-            },
+pub fn rm<P: AsRef<Path>>(path: P) -> bool {
+    let path = path.as_ref();
+    if path.exists() {
+        match try_rm(path) {
+            Ok(_) => true,
             Err(e) => {
-                error!("Error removing {} {}", path, e);
+                error!("Error removing {} {}", path.display(), e);
                 false
             }
         }
@@ -76,6 +296,26 @@ pub fn rm(path: &str) -> bool {
     }
 }
 
+/// Removes a file at passed path.
+///
+/// ## Usage:
+///
+/// ```
+/// use std::io::ErrorKind;
+///
+/// fsutils::create_file("try_rm_file.txt");
+/// assert!(fsutils::try_rm("try_rm_file.txt").is_ok());
+/// assert_eq!(fsutils::try_rm("try_rm_file.txt").unwrap_err().kind(), ErrorKind::NotFound);
+/// ```
+pub fn try_rm<P: AsRef<Path>>(path: P) -> Result<()> {
+    let path = path.as_ref();
+    Op::new("rm", path).destructive(true).run((), || {
+        fs::remove_file(path).at(path)?;
+        info!("Removed file {}", path.display());
+        Ok(())
+    })
+}
+
 /// Removes an empty directory
 /// and returns a boolean based on success or failure.
 ///
@@ -89,26 +329,45 @@ pub fn rm(path: &str) -> bool {
 /// fsutils::mkdir("testdir");
 /// assert_eq!(rmdir("testdir"), true);
 /// ```
-pub fn rmdir(path: &str) -> bool {
-    // Turn str path into Path
-    let new_path = Path::new(path);
-    if new_path.exists() {
-        match fs::remove_dir(path) {
-            Ok(_) => {
-                info!("Removed directory at {}", path);
-                true
-            },
+pub fn rmdir<P: AsRef<Path>>(path: P) -> bool {
+    let path = path.as_ref();
+    if path.exists() {
+        match try_rmdir(path) {
+            Ok(_) => true,
             Err(e) => {
-                error!("The directory {} is not empty. {}", path, e);
+                error!("The directory {} is not empty. {}", path.display(), e);
                 false
             }
         }
     } else {
-        error!("Directory {} does not exist", path);
+        error!("Directory {} does not exist", path.display());
         true
     }
 }
 
+/// Removes an empty directory.
+///
+/// Unlike `fsutils::rmdir`, a missing directory is reported as an error.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::mkdir("try_rmdir_dir");
+/// fsutils::create_file("try_rmdir_dir/file");
+///
+/// assert!(fsutils::try_rmdir("try_rmdir_dir").is_err());
+/// fsutils::rm("try_rmdir_dir/file");
+/// assert!(fsutils::try_rmdir("try_rmdir_dir").is_ok());
+/// ```
+pub fn try_rmdir<P: AsRef<Path>>(path: P) -> Result<()> {
+    let path = path.as_ref();
+    Op::new("rmdir", path).run((), || {
+        fs::remove_dir(path).at(path)?;
+        info!("Removed directory at {}", path.display());
+        Ok(())
+    })
+}
+
 /// Removes a directory recursively
 /// and returns a boolean based on success or failure.
 ///
@@ -122,17 +381,13 @@ pub fn rmdir(path: &str) -> bool {
 ///
 /// assert_eq!(fsutils::rm_r("testdir"), true);
 /// ```
-pub fn rm_r(path: &str) -> bool {
-    // Turn str path into Path
-    let new_path = Path::new(path);
-    if new_path.exists() {
-        match fs::remove_dir_all(path) {
-            Ok(_) => {
-                info!("Removed directory at {}", path);
-                true
-            },
+pub fn rm_r<P: AsRef<Path>>(path: P) -> bool {
+    let path = path.as_ref();
+    if path.exists() {
+        match try_rm_r(path) {
+            Ok(_) => true,
             Err(e) => {
-                error!("The directory {} is not empty. {}", path, e);
+                error!("The directory {} is not empty. {}", path.display(), e);
                 false
             }
         }
@@ -142,6 +397,26 @@ pub fn rm_r(path: &str) -> bool {
     }
 }
 
+/// Removes a directory recursively.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::mkdir("try_rm_r_dir/nested");
+/// fsutils::create_file("try_rm_r_dir/nested/file");
+///
+/// assert!(fsutils::try_rm_r("try_rm_r_dir").is_ok());
+/// assert!(fsutils::try_rm_r("try_rm_r_dir").is_err());
+/// ```
+pub fn try_rm_r<P: AsRef<Path>>(path: P) -> Result<()> {
+    let path = path.as_ref();
+    Op::new("rm_r", path).destructive(true).run((), || {
+        fs::remove_dir_all(path).at(path)?;
+        info!("Removed directory at {}", path.display());
+        Ok(())
+    })
+}
+
 /// Checks if a path exists
 /// and returns a boolean based on success or failure.
 ///
@@ -151,18 +426,17 @@ pub fn rm_r(path: &str) -> bool {
 /// fsutils::create_file("testfile");
 /// assert_eq!(fsutils::path_exists("testfile"), true);
 /// assert_eq!(fsutils::path_exists("a_very_1234_unlikely_9876_filename"), false);
-/// 
+///
 /// # // Cleanup
 /// # fsutils::rm("testfile");
 /// ```
-pub fn path_exists(path: &str) -> bool {
-    // Turn str path into Path
-    let new_path = Path::new(path);
-    if new_path.exists() {
-        info!("{} exists", path);
+pub fn path_exists<P: AsRef<Path>>(path: P) -> bool {
+    let path = path.as_ref();
+    if path.exists() {
+        info!("{} exists", path.display());
         true
     } else {
-        info!("{} does not exist", path);
+        info!("{} does not exist", path.display());
         false
     }
 }
@@ -185,37 +459,46 @@ pub fn path_exists(path: &str) -> bool {
 /// # fsutils::rmdir("empty_directory");
 /// # fsutils::rm_r("full_directory");
 /// ```
-pub fn directory_is_empty(path: &str) -> bool {
-    // Turn str path into Path
-    let new_path = Path::new(path);
-    if new_path.exists() {
-        if new_path.is_dir() {
-            let mut i = 0;
-            // iterate through entries and count them
-            // `fs::read_dir` returns type `ReadDir`
-            for entry in fs::read_dir(path) {
-                // Iterating over `ReadDir` returns a Result<DirEntry>`
-                // which is what we want to give us the count.
-                for _ in entry {
-                    i += 1;
+pub fn directory_is_empty<P: AsRef<Path>>(path: P) -> bool {
+    let path = path.as_ref();
+    if path.exists() {
+        if path.is_dir() {
+            match try_directory_is_empty(path) {
+                Ok(empty) => empty,
+                Err(e) => {
+                    error!("Cannot read directory {}: {}", path.display(), e);
+                    false
                 }
             }
-            // if the count of directory entries is 1 (it counts itself), it is empty
-            if i == 0 {
-                true
-            } else {
-                false
-            }
         } else {
-            error!("The path {} passed is not a directory", path);
+            error!("The path {} passed is not a directory", path.display());
             false
         }
     } else {
-        error!("The path {} passed does not exist.", path);
+        error!("The path {} passed does not exist.", path.display());
         false
     }
 }
 
+/// Checks if a directory is empty.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::mkdir("try_empty_directory");
+///
+/// assert_eq!(fsutils::try_directory_is_empty("try_empty_directory").unwrap(), true);
+/// assert!(fsutils::try_directory_is_empty("a_very_1234_unlikely_9876_dirname").is_err());
+///
+/// # // Cleanup
+/// # fsutils::rmdir("try_empty_directory");
+/// ```
+pub fn try_directory_is_empty<P: AsRef<Path>>(path: P) -> Result<bool> {
+    let path = path.as_ref();
+    // `fs::read_dir` never yields `.` or `..`, so any entry means the directory is not empty
+    Ok(fs::read_dir(path).at(path)?.next().is_none())
+}
+
 /// Moves a file from `path_one` to `path_two`
 /// and returns a boolean based on success or failure.
 ///
@@ -232,14 +515,12 @@ pub fn directory_is_empty(path: &str) -> bool {
 /// # fsutils::rm_r("directory_one");
 /// # fsutils::rm_r("directory_two");
 /// ```
-pub fn mv(path_one: &str, path_two: &str) -> bool {
-    let p1 = Path::new(path_one);
-    if p1.exists() {
-        match fs::rename(path_one, path_two) {
-            Ok(_) => {
-                info!("Moved from {} to {}.", path_one, path_two);
-                true
-            },
+pub fn mv<P: AsRef<Path>, Q: AsRef<Path>>(path_one: P, path_two: Q) -> bool {
+    let path_one = path_one.as_ref();
+    let path_two = path_two.as_ref();
+    if path_one.exists() {
+        match try_mv(path_one, path_two) {
+            Ok(_) => true,
             Err(e) => {
                 error!("File moving error: {}", e);
                 false
@@ -250,6 +531,127 @@ pub fn mv(path_one: &str, path_two: &str) -> bool {
     }
 }
 
+/// Moves a file from `path_one` to `path_two`.
+///
+/// When the two paths are on different filesystems, where a rename is not
+/// possible, the file or directory tree is copied with its metadata, the copy
+/// is checked against the original, and only then is the original removed.
+/// If the copy fails, what it wrote is removed and the original is left as it
+/// was.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::create_file("try_mv_source");
+///
+/// assert!(fsutils::try_mv("try_mv_source", "try_mv_destination").is_ok());
+/// assert!(fsutils::try_mv("try_mv_source", "try_mv_destination").is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm("try_mv_destination");
+/// ```
+///
+/// A copy across filesystems that fails leaves nothing behind:
+///
+/// ```
+/// # #[cfg(target_os = "linux")]
+/// # {
+/// // /proc is a filesystem of its own, and reading this file fails
+/// assert!(fsutils::try_mv("/proc/self/mem", "try_mv_unreadable").is_err());
+/// assert!(!fsutils::path_exists("try_mv_unreadable"));
+/// # }
+/// ```
+pub fn try_mv<P: AsRef<Path>, Q: AsRef<Path>>(path_one: P, path_two: Q) -> Result<()> {
+    let path_one = path_one.as_ref();
+    let path_two = path_two.as_ref();
+    Op::new("mv", path_one).to(path_two).destructive(path_two.symlink_metadata().is_ok()).run((), || {
+        mv::rename_or_copy(path_one, path_two)?;
+        info!("Moved from {} to {}.", path_one.display(), path_two.display());
+        Ok(())
+    })
+}
+
+/// Moves a file from `path_one` to `path_two`, consulting `policy` if `path_two` exists.
+///
+/// Returns the path the file ended up at, or `None` if the policy skipped the move.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::OverwritePolicy;
+///
+/// fsutils::write_file("mv_with_source", "source");
+/// fsutils::write_file("mv_with_destination", "destination");
+///
+/// assert!(fsutils::mv_with("mv_with_source", "mv_with_destination", &OverwritePolicy::Error).is_err());
+/// assert_eq!(fsutils::mv_with("mv_with_source", "mv_with_destination", &OverwritePolicy::Skip).unwrap(), None);
+/// assert!(fsutils::mv_with("mv_with_source", "mv_with_destination", &OverwritePolicy::Overwrite).unwrap().is_some());
+/// assert_eq!(fsutils::read_file("mv_with_destination"), "source");
+///
+/// # // Cleanup
+/// # fsutils::rm("mv_with_destination");
+/// ```
+///
+/// Numbered backups keep every version that gets replaced:
+///
+/// ```
+/// use fsutils::OverwritePolicy;
+///
+/// fsutils::mkdir("mv_backup");
+/// fsutils::write_file("mv_backup/config", "first");
+/// for version in &["second", "third"] {
+///     fsutils::write_file("mv_backup/incoming", version);
+///     fsutils::mv_with("mv_backup/incoming", "mv_backup/config", &OverwritePolicy::Backup).unwrap();
+/// }
+///
+/// assert_eq!(fsutils::read_file("mv_backup/config"), "third");
+/// assert_eq!(fsutils::read_file("mv_backup/config.~1~"), "first");
+/// assert_eq!(fsutils::read_file("mv_backup/config.~2~"), "second");
+///
+/// # // Cleanup
+/// # fsutils::rm_r("mv_backup");
+/// ```
+///
+/// Names that are not valid UTF-8 are numbered the same way:
+///
+/// ```
+/// # #[cfg(target_os = "linux")]
+/// # {
+/// use std::ffi::OsStr;
+/// use std::os::unix::ffi::OsStrExt;
+/// use fsutils::OverwritePolicy;
+///
+/// let config = std::path::Path::new("mv_backup_bytes").join(OsStr::from_bytes(b"config\xff"));
+/// fsutils::mkdir("mv_backup_bytes");
+/// fsutils::write_file(&config, "first");
+/// for version in &["second", "third"] {
+///     fsutils::write_file("mv_backup_bytes/incoming", version);
+///     fsutils::mv_with("mv_backup_bytes/incoming", &config, &OverwritePolicy::Backup).unwrap();
+/// }
+///
+/// let backup = |n: &[u8]| config.with_file_name(OsStr::from_bytes(&[b"config\xff.~", n, b"~"].concat()));
+/// assert_eq!(fsutils::read_file(backup(b"1")), "first");
+/// assert_eq!(fsutils::read_file(backup(b"2")), "second");
+///
+/// # // Cleanup
+/// # fsutils::rm_r("mv_backup_bytes");
+/// # }
+/// ```
+pub fn mv_with<P: AsRef<Path>, Q: AsRef<Path>>(path_one: P, path_two: Q, policy: &OverwritePolicy) -> Result<Option<PathBuf>> {
+    let path_one = path_one.as_ref();
+    if path_one.symlink_metadata().is_err() {
+        return Err(FsError::from_io(path_one, io::ErrorKind::NotFound.into()));
+    }
+    match policy.resolve(path_two.as_ref())? {
+        Some(dest) => {
+            policy.back_up(&dest)?;
+            try_mv(path_one, &dest)?;
+            Ok(Some(dest))
+        }
+        None => Ok(None),
+    }
+}
+
 /// Creates a file and returns a boolean based on success or failure.
 ///
 /// ## Usage:
@@ -260,12 +662,10 @@ pub fn mv(path_one: &str, path_two: &str) -> bool {
 /// # // Cleanup
 /// # fsutils::rm("the_file");
 /// ```
-pub fn create_file(path: &str) -> bool {
-    match fs::File::create(path) {
-        Ok(_f) => {
-            info!("Successfully wrote file to {}", path);
-            true
-        }
+pub fn create_file<P: AsRef<Path>>(path: P) -> bool {
+    let path = path.as_ref();
+    match try_create_file(path) {
+        Ok(_) => true,
         Err(e) => {
             error!("{}", e);
             false
@@ -273,6 +673,29 @@ pub fn create_file(path: &str) -> bool {
     }
 }
 
+/// Creates a file.
+///
+/// An existing file is truncated to zero bytes. Use `fsutils::touch` to
+/// create a file only if it is missing.
+///
+/// ## Usage:
+///
+/// ```
+/// assert!(fsutils::try_create_file("try_create_file_file").is_ok());
+/// assert!(fsutils::try_create_file("a_very_1234_unlikely_9876_dirname/file").is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm("try_create_file_file");
+/// ```
+pub fn try_create_file<P: AsRef<Path>>(path: P) -> Result<()> {
+    let path = path.as_ref();
+    Op::new("create_file", path).destructive(path.exists()).run((), || {
+        fs::File::create(path).at(path)?;
+        info!("Successfully wrote file to {}", path.display());
+        Ok(())
+    })
+}
+
 /// Creates a file from bytes
 /// and returns a boolean based on success or failure.
 ///
@@ -286,20 +709,10 @@ pub fn create_file(path: &str) -> bool {
 /// # // Cleanup
 /// # fsutils::rm("a_binary_file");
 /// ```
-pub fn create_file_bytes(path: &str, bytes_to_write: &[u8]) -> bool {
-    match fs::File::create(path) {
-        Ok(mut buffer) => {
-            match buffer.write_all(bytes_to_write) {
-                Ok(_) => {
-                    info!("Wrote buffer to {}", path);
-                    true
-                },
-                Err(e) => {
-                    error!("{}", e);
-                    false
-                }
-            }
-        }
+pub fn create_file_bytes<P: AsRef<Path>>(path: P, bytes_to_write: &[u8]) -> bool {
+    let path = path.as_ref();
+    match try_create_file_bytes(path, bytes_to_write) {
+        Ok(_) => true,
         Err(e) => {
             error!("{}", e);
             false
@@ -307,6 +720,26 @@ pub fn create_file_bytes(path: &str, bytes_to_write: &[u8]) -> bool {
     }
 }
 
+/// Creates a file from bytes.
+///
+/// ## Usage:
+///
+/// ```
+/// assert!(fsutils::try_create_file_bytes("try_binary_file", b"\x00\x01\x02").is_ok());
+///
+/// # // Cleanup
+/// # fsutils::rm("try_binary_file");
+/// ```
+pub fn try_create_file_bytes<P: AsRef<Path>>(path: P, bytes_to_write: &[u8]) -> Result<()> {
+    let path = path.as_ref();
+    Op::new("create_file_bytes", path).bytes(bytes_to_write.len() as u64).destructive(path.exists()).run((), || {
+        let mut buffer = fs::File::create(path).at(path)?;
+        buffer.write_all(bytes_to_write).at(path)?;
+        info!("Wrote buffer to {}", path.display());
+        Ok(())
+    })
+}
+
 /// Reads data to a file
 /// and returns a `bool` on success
 ///
@@ -320,19 +753,77 @@ pub fn create_file_bytes(path: &str, bytes_to_write: &[u8]) -> bool {
 /// # // Cleanup
 /// # fsutils::rm("text.txt");
 /// ```
-pub fn write_file(path: &str, contents: &str) -> bool {
-    match File::create(path) {
-        Ok(mut f) => {
-            f.write_all(contents.as_ref()).unwrap();
-            true
-        }
+pub fn write_file<P: AsRef<Path>>(path: P, contents: &str) -> bool {
+    let path = path.as_ref();
+    match try_write_file(path, contents) {
+        Ok(_) => true,
         Err(e) => {
-            error!("Cannot write file to location '{}' {}", path, e);
+            error!("Cannot write file to location '{}' {}", path.display(), e);
             false
         }
     }
 }
 
+/// Writes data to a file, replacing any existing contents.
+///
+/// ## Usage:
+///
+/// ```
+/// assert!(fsutils::try_write_file("try_write.txt", "Hello, world!").is_ok());
+///
+/// assert_eq!(fsutils::try_read_file("try_write.txt").unwrap(), "Hello, world!");
+///
+/// # // Cleanup
+/// # fsutils::rm("try_write.txt");
+/// ```
+pub fn try_write_file<P: AsRef<Path>>(path: P, contents: &str) -> Result<()> {
+    let path = path.as_ref();
+    Op::new("write_file", path).bytes(contents.len() as u64).destructive(path.exists()).run((), || {
+        let mut f = File::create(path).at(path)?;
+        f.write_all(contents.as_ref()).at(path)?;
+        info!("Wrote file to {}", path.display());
+        Ok(())
+    })
+}
+
+/// Writes data to a file, consulting `policy` if the file already exists.
+///
+/// Returns the path that was written, or `None` if the policy skipped the write.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::OverwritePolicy;
+///
+/// assert!(fsutils::write_file_with("write_with.txt", "first", &OverwritePolicy::Error).is_ok());
+/// assert!(fsutils::write_file_with("write_with.txt", "second", &OverwritePolicy::Error).is_err());
+/// assert_eq!(fsutils::write_file_with("write_with.txt", "second", &OverwritePolicy::Skip).unwrap(), None);
+/// assert_eq!(fsutils::read_file("write_with.txt"), "first");
+///
+/// # // Cleanup
+/// # fsutils::rm("write_with.txt");
+/// ```
+pub fn write_file_with<P: AsRef<Path>>(path: P, contents: &str, policy: &OverwritePolicy) -> Result<Option<PathBuf>> {
+    let path = path.as_ref();
+    if let OverwritePolicy::Error = policy {
+        // `create_new` makes the existence check and the creation a single step
+        return Op::new("write_file", path).bytes(contents.len() as u64).run(Some(path.to_path_buf()), || {
+            let mut f = OpenOptions::new().write(true).create_new(true).open(path).at(path)?;
+            f.write_all(contents.as_ref()).at(path)?;
+            info!("Wrote file to {}", path.display());
+            Ok(Some(path.to_path_buf()))
+        });
+    }
+    match policy.resolve(path)? {
+        Some(dest) => {
+            policy.back_up(&dest)?;
+            try_write_file(&dest, contents)?;
+            Ok(Some(dest))
+        }
+        None => Ok(None),
+    }
+}
+
 /// Appends data to a file
 /// and returns a `bool` on success
 ///
@@ -347,16 +838,9 @@ pub fn write_file(path: &str, contents: &str) -> bool {
 /// # // Cleanup
 /// # fsutils::rm("text.txt");
 /// ```
-pub fn write_file_append(path: &str, contents: &str) -> bool {
-    match OpenOptions::new()
-        .write(true)
-        .create(true)
-        .append(true)
-        .open(path) {
-        Ok(mut f) => {
-            f.write_all(contents.as_ref()).unwrap();
-            true
-        }
+pub fn write_file_append<P: AsRef<Path>>(path: P, contents: &str) -> bool {
+    match try_write_file_append(path, contents) {
+        Ok(_) => true,
         Err(e) => {
             error!("Cannot write file {}", e);
             false
@@ -364,9 +848,40 @@ pub fn write_file_append(path: &str, contents: &str) -> bool {
     }
 }
 
+/// Appends data to a file, creating it if it does not exist.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::try_write_file_append("try_append.txt", "Hello, world! ").unwrap();
+/// fsutils::try_write_file_append("try_append.txt", "Hi Again!").unwrap();
+///
+/// assert_eq!(fsutils::try_read_file("try_append.txt").unwrap(), "Hello, world! Hi Again!");
+///
+/// # // Cleanup
+/// # fsutils::rm("try_append.txt");
+/// ```
+pub fn try_write_file_append<P: AsRef<Path>>(path: P, contents: &str) -> Result<()> {
+    let path = path.as_ref();
+    Op::new("write_file_append", path).bytes(contents.len() as u64).run((), || {
+        let mut f = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .at(path)?;
+        f.write_all(contents.as_ref()).at(path)?;
+        info!("Appended to file {}", path.display());
+        Ok(())
+    })
+}
+
 /// Reads data from a file
 /// and returns a `String` with the files's contents
 ///
+/// An empty `String` is returned if the file cannot be read or is not valid
+/// UTF-8. Use `fsutils::try_read_file` to find out why, or
+/// `fsutils::read_file_lossy` to read files with invalid UTF-8.
+///
 /// ## Usage:
 ///
 /// ```
@@ -377,15 +892,83 @@ pub fn write_file_append(path: &str, contents: &str) -> bool {
 /// # // Cleanup
 /// # fsutils::rm("text.txt");
 /// ```
-pub fn read_file(path: &str) -> String {
+pub fn read_file<P: AsRef<Path>>(path: P) -> String {
+    match try_read_file(path) {
+        Ok(contents) => contents,
+        Err(e) => {
+            error!("Cannot read file {}", e);
+            String::new()
+        }
+    }
+}
+
+/// Reads data from a file into a `String`.
+///
+/// Fails with an error of kind `io::ErrorKind::InvalidData` if the file is not
+/// valid UTF-8. Use `fsutils::read_file_with_encoding` for files in other encodings.
+///
+/// ## Usage:
+///
+/// ```
+/// use std::io::ErrorKind;
+///
+/// fsutils::create_file_bytes("try_read_invalid.txt", b"\xff\xfe");
+///
+/// assert_eq!(fsutils::try_read_file("try_read_invalid.txt").unwrap_err().kind(), ErrorKind::InvalidData);
+/// assert_eq!(fsutils::try_read_file("a_very_1234_unlikely_9876_filename").unwrap_err().kind(), ErrorKind::NotFound);
+///
+/// # // Cleanup
+/// # fsutils::rm("try_read_invalid.txt");
+/// ```
+pub fn try_read_file<P: AsRef<Path>>(path: P) -> Result<String> {
+    let path = path.as_ref();
     let mut contents = String::new();
-    match File::open(path) {
-        Ok(mut f) => {
-            f.read_to_string(&mut contents).unwrap();
+    File::open(path).at(path)?.read_to_string(&mut contents).at(path)?;
+    Ok(contents)
+}
+
+/// Reads data from a file
+/// and returns a `String`, replacing invalid UTF-8 sequences with `U+FFFD`.
+///
+/// An empty `String` is returned if the file cannot be read.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::create_file_bytes("lossy.txt", b"Hello\xff world");
+///
+/// assert_eq!(fsutils::read_file_lossy("lossy.txt"), "Hello\u{FFFD} world");
+///
+/// # // Cleanup
+/// # fsutils::rm("lossy.txt");
+/// ```
+pub fn read_file_lossy<P: AsRef<Path>>(path: P) -> String {
+    match try_read_file_lossy(path) {
+        Ok(contents) => contents,
+        Err(e) => {
+            error!("Cannot read file {}", e);
+            String::new()
         }
-        Err(e) => error!("Cannot read file {}", e)
     }
-    contents
+}
+
+/// Reads data from a file into a `String`, replacing invalid UTF-8 sequences with `U+FFFD`.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::create_file_bytes("try_lossy.txt", b"\xfe\xff");
+///
+/// assert_eq!(fsutils::try_read_file_lossy("try_lossy.txt").unwrap(), "\u{FFFD}\u{FFFD}");
+/// assert!(fsutils::try_read_file_lossy("a_very_1234_unlikely_9876_filename").is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm("try_lossy.txt");
+/// ```
+pub fn try_read_file_lossy<P: AsRef<Path>>(path: P) -> Result<String> {
+    let path = path.as_ref();
+    let bytes = fs::read(path).at(path)?;
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
 }
 
 /// Change the current working directory
@@ -399,13 +982,10 @@ pub fn read_file(path: &str) -> String {
 /// assert_eq!(cd(Path::new("target")).is_some(), true);
 /// assert_eq!(cd(Path::new("does_not_exist")).is_none(), true)
 /// ```
-pub fn cd(cd_path: &Path) -> Option<()> {
-    // Change working directory to directory
-    match std::env::set_current_dir(&cd_path) {
-        Ok(_) => {
-            info!("Changed current dir to {}", cd_path.display());
-            Some(())
-        },
+pub fn cd<P: AsRef<Path>>(cd_path: P) -> Option<()> {
+    let cd_path = cd_path.as_ref();
+    match try_cd(cd_path) {
+        Ok(_) => Some(()),
         Err(e) => {
             error!("Could not set current dir to {}: {}", cd_path.display(), e);
             None
@@ -413,6 +993,25 @@ pub fn cd(cd_path: &Path) -> Option<()> {
     }
 }
 
+/// Change the current working directory.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::try_cd;
+/// use std::path::Path;
+///
+/// assert!(try_cd(Path::new("does_not_exist")).is_err());
+/// assert!(try_cd(Path::new("target")).is_ok());
+/// ```
+pub fn try_cd<P: AsRef<Path>>(cd_path: P) -> Result<()> {
+    let cd_path = cd_path.as_ref();
+    // Change working directory to directory
+    std::env::set_current_dir(cd_path).at(cd_path)?;
+    info!("Changed current dir to {}", cd_path.display());
+    Ok(())
+}
+
 /// Execute an arbitrary system command
 ///
 /// ## Usage
@@ -422,11 +1021,14 @@ pub fn cd(cd_path: &Path) -> Option<()> {
 ///
 /// assert!(run_command("ls", ["-l"].to_vec()).is_some());
 /// ```
-pub fn run_command(program: &str, args: Vec<&str>) -> Option<i32> {
-    match process::Command::new(program).args(args).status() {
-        Ok(s) => {
-            s.code()
-        }
+pub fn run_command<S, I, A>(program: S, args: I) -> Option<i32>
+where
+    S: AsRef<OsStr>,
+    I: IntoIterator<Item = A>,
+    A: AsRef<OsStr>,
+{
+    match try_run_command(program, args) {
+        Ok(code) => code,
         Err(e) => {
             error!("There was an error {}", e);
             None
@@ -434,6 +1036,33 @@ pub fn run_command(program: &str, args: Vec<&str>) -> Option<i32> {
     }
 }
 
+/// Execute an arbitrary system command and return its exit code.
+///
+/// The exit code is `None` if the process was terminated by a signal.
+/// The program and its arguments may be any `OsStr`-like value.
+///
+/// ## Usage
+///
+/// ```
+/// use fsutils::try_run_command;
+/// use std::path::Path;
+///
+/// assert!(try_run_command("ls", ["-l"].to_vec()).is_ok());
+/// assert!(try_run_command("ls", &[Path::new("src")]).is_ok());
+/// assert!(try_run_command("a_very_1234_unlikely_9876_program", Vec::<&str>::new()).is_err());
+/// ```
+pub fn try_run_command<S, I, A>(program: S, args: I) -> Result<Option<i32>>
+where
+    S: AsRef<OsStr>,
+    I: IntoIterator<Item = A>,
+    A: AsRef<OsStr>,
+{
+    let program = program.as_ref();
+    Op::new("run_command", Path::new(program)).run(Some(0), || {
+        Ok(process::Command::new(program).args(args).status().at(program)?.code())
+    })
+}
+
 /// List directory contents
 ///
 /// ## Usage
@@ -443,25 +1072,38 @@ pub fn run_command(program: &str, args: Vec<&str>) -> Option<i32> {
 ///
 /// assert!(ls(".").is_some());
 /// ```
-pub fn ls(path: &str) -> Option<Vec<PathBuf>> {
-    match fs::read_dir(path) {
-        Ok(p) => {
-            let results = p.map(|res| res.map(|e| e.path()))
-                .collect::<Result<Vec<_>, io::Error>>();
-            match results {
-                Ok(r) => {
-                    println!("{:?}", r);
-                    Some(r)
-                },
-                Err(e) => {
-                    println!("{}", e);
-                    None
-                }
-            }
+pub fn ls<P: AsRef<Path>>(path: P) -> Option<Vec<PathBuf>> {
+    let path = path.as_ref();
+    match try_ls(path) {
+        Ok(r) => {
+            println!("{:?}", r);
+            Some(r)
         }
         Err(e) => {
             println!("{:?}", e);
             None
         }
     }
-}
\ No newline at end of file
+}
+
+/// List directory contents.
+///
+/// The paths come in whatever order the operating system returns them. See
+/// `fsutils::try_ls_entries` for entries with their metadata, sorted.
+///
+/// ## Usage
+///
+/// ```
+/// use fsutils::try_ls;
+///
+/// assert!(try_ls(".").unwrap().len() > 0);
+/// assert!(try_ls("a_very_1234_unlikely_9876_dirname").is_err());
+/// ```
+pub fn try_ls<P: AsRef<Path>>(path: P) -> Result<Vec<PathBuf>> {
+    let path = path.as_ref();
+    fs::read_dir(path)
+        .at(path)?
+        .map(|res| res.map(|e| e.path()))
+        .collect::<io::Result<Vec<_>>>()
+        .at(path)
+}
@@ -0,0 +1,370 @@
+// Copyright 2020 Jared Forth.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Line-oriented reading and writing.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::error::{FsError, IoResultExt, Result};
+use crate::op::Op;
+
+/// The terminator written after each line.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum LineEnding {
+    /// `\n`, as used on Unix.
+    #[default]
+    Lf,
+    /// `\r\n`, as used on Windows.
+    CrLf,
+}
+
+impl LineEnding {
+    /// Returns the terminator as a string.
+    ///
+    /// ## Usage:
+    ///
+    /// ```
+    /// use fsutils::LineEnding;
+    ///
+    /// assert_eq!(LineEnding::CrLf.as_str(), "\r\n");
+    /// ```
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+        }
+    }
+}
+
+/// An iterator over the lines of a file, returned by `fsutils::try_read_lines`.
+///
+/// The file is read through a buffer, so only one line is held in memory at
+/// a time. Lines are returned without their `\n` or `\r\n` terminator. A line
+/// that is not valid UTF-8 yields an error of kind `io::ErrorKind::InvalidData`.
+#[derive(Debug)]
+pub struct Lines {
+    path: PathBuf,
+    lines: io::Lines<BufReader<File>>,
+}
+
+impl Iterator for Lines {
+    type Item = Result<String>;
+
+    fn next(&mut self) -> Option<Result<String>> {
+        let path = &self.path;
+        self.lines.next().map(|line| line.map_err(|e| FsError::from_io(path, e)))
+    }
+}
+
+/// Opens a file for reading line by line
+/// and returns an iterator over its lines, or `None` if it cannot be opened.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("read_lines.log", "INFO start\nERROR disk full\nINFO stop\n");
+///
+/// let errors = fsutils::read_lines("read_lines.log")
+///     .unwrap()
+///     .filter_map(Result::ok)
+///     .filter(|line| line.starts_with("ERROR"))
+///     .count();
+/// assert_eq!(errors, 1);
+///
+/// # // Cleanup
+/// # fsutils::rm("read_lines.log");
+/// ```
+pub fn read_lines<P: AsRef<Path>>(path: P) -> Option<Lines> {
+    match try_read_lines(path) {
+        Ok(lines) => Some(lines),
+        Err(e) => {
+            error!("Cannot read file {}", e);
+            None
+        }
+    }
+}
+
+/// Opens a file for reading line by line.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("try_read_lines.txt", "one\r\ntwo");
+///
+/// let lines: Vec<String> = fsutils::try_read_lines("try_read_lines.txt").unwrap().map(Result::unwrap).collect();
+/// assert_eq!(lines, ["one", "two"]);
+/// assert!(fsutils::try_read_lines("a_very_1234_unlikely_9876_filename").is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm("try_read_lines.txt");
+/// ```
+pub fn try_read_lines<P: AsRef<Path>>(path: P) -> Result<Lines> {
+    let path = path.as_ref();
+    let file = File::open(path).at(path)?;
+    Ok(Lines { path: path.to_path_buf(), lines: BufReader::new(file).lines() })
+}
+
+/// Reads every line of a file into a `Vec`
+/// and returns an empty `Vec` if the file cannot be read.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("read_lines_vec.txt", "alpha\nbeta\n");
+///
+/// assert_eq!(fsutils::read_lines_vec("read_lines_vec.txt"), ["alpha", "beta"]);
+/// assert!(fsutils::read_lines_vec("a_very_1234_unlikely_9876_filename").is_empty());
+///
+/// # // Cleanup
+/// # fsutils::rm("read_lines_vec.txt");
+/// ```
+pub fn read_lines_vec<P: AsRef<Path>>(path: P) -> Vec<String> {
+    match try_read_lines_vec(path) {
+        Ok(lines) => lines,
+        Err(e) => {
+            error!("Cannot read file {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// Reads every line of a file into a `Vec`.
+///
+/// Fails with an error of kind `io::ErrorKind::InvalidData` if the file is not valid UTF-8.
+///
+/// ## Usage:
+///
+/// ```
+/// use std::io::ErrorKind;
+///
+/// fsutils::create_file_bytes("try_read_lines_vec.txt", b"fine\n\xff\n");
+///
+/// assert_eq!(fsutils::try_read_lines_vec("try_read_lines_vec.txt").unwrap_err().kind(), ErrorKind::InvalidData);
+///
+/// # // Cleanup
+/// # fsutils::rm("try_read_lines_vec.txt");
+/// ```
+pub fn try_read_lines_vec<P: AsRef<Path>>(path: P) -> Result<Vec<String>> {
+    try_read_lines(path)?.collect()
+}
+
+/// Writes each item as a line terminated by `\n`, replacing any existing contents,
+/// and returns a boolean based on success or failure.
+///
+/// ## Usage:
+///
+/// ```
+/// assert!(fsutils::write_lines("write_lines.txt", &["first", "second"]));
+///
+/// assert_eq!(fsutils::read_file("write_lines.txt"), "first\nsecond\n");
+///
+/// # // Cleanup
+/// # fsutils::rm("write_lines.txt");
+/// ```
+pub fn write_lines<P, I>(path: P, lines: I) -> bool
+where
+    P: AsRef<Path>,
+    I: IntoIterator,
+    I::Item: AsRef<str>,
+{
+    let path = path.as_ref();
+    match try_write_lines(path, lines) {
+        Ok(_) => true,
+        Err(e) => {
+            error!("Cannot write file to location '{}' {}", path.display(), e);
+            false
+        }
+    }
+}
+
+/// Writes each item as a line terminated by `\n`, replacing any existing contents.
+///
+/// ## Usage:
+///
+/// ```
+/// let names = vec![String::from("ada"), String::from("grace")];
+/// assert!(fsutils::try_write_lines("try_write_lines.txt", &names).is_ok());
+///
+/// assert_eq!(fsutils::read_lines_vec("try_write_lines.txt"), names);
+///
+/// # // Cleanup
+/// # fsutils::rm("try_write_lines.txt");
+/// ```
+pub fn try_write_lines<P, I>(path: P, lines: I) -> Result<()>
+where
+    P: AsRef<Path>,
+    I: IntoIterator,
+    I::Item: AsRef<str>,
+{
+    write_lines_with(path, lines, LineEnding::Lf)
+}
+
+/// Writes each item as a line terminated by `ending`, replacing any existing contents.
+///
+/// Lines are streamed through a buffer, so the iterator can produce more text
+/// than fits in memory.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::LineEnding;
+///
+/// let rows = (1..=3).map(|i| format!("{},{}", i, i * i));
+/// assert!(fsutils::write_lines_with("write_lines_with.csv", rows, LineEnding::CrLf).is_ok());
+///
+/// assert_eq!(fsutils::read_file("write_lines_with.csv"), "1,1\r\n2,4\r\n3,9\r\n");
+///
+/// # // Cleanup
+/// # fsutils::rm("write_lines_with.csv");
+/// ```
+pub fn write_lines_with<P, I>(path: P, lines: I, ending: LineEnding) -> Result<()>
+where
+    P: AsRef<Path>,
+    I: IntoIterator,
+    I::Item: AsRef<str>,
+{
+    let path = path.as_ref();
+    Op::new("write_lines", path).destructive(path.exists()).run((), || {
+        let mut writer = BufWriter::new(File::create(path).at(path)?);
+        for line in lines {
+            writer.write_all(line.as_ref().as_bytes()).at(path)?;
+            writer.write_all(ending.as_str().as_bytes()).at(path)?;
+        }
+        writer.flush().at(path)?;
+        info!("Wrote lines to {}", path.display());
+        Ok(())
+    })
+}
+
+/// How much `fsutils::try_tail` reads at a time, working back from the end.
+const TAIL_BLOCK: u64 = 64 * 1024;
+
+/// Returns the first `n` lines of a file,
+/// or an empty `Vec` if the file cannot be read.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_lines("head.txt", &["1", "2", "3"]);
+///
+/// assert_eq!(fsutils::head("head.txt", 2), ["1", "2"]);
+///
+/// # // Cleanup
+/// # fsutils::rm("head.txt");
+/// ```
+pub fn head<P: AsRef<Path>>(path: P, n: usize) -> Vec<String> {
+    match try_head(path, n) {
+        Ok(lines) => lines,
+        Err(e) => {
+            error!("Cannot read file {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// Returns the first `n` lines of a file, or all of them if it has fewer.
+///
+/// Only as much of the file as is needed for `n` lines is read.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("try_head.txt", "only\r\nlines");
+///
+/// assert_eq!(fsutils::try_head("try_head.txt", 10).unwrap(), ["only", "lines"]);
+/// assert!(fsutils::try_head("a_very_1234_unlikely_9876_filename", 1).is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm("try_head.txt");
+/// ```
+pub fn try_head<P: AsRef<Path>>(path: P, n: usize) -> Result<Vec<String>> {
+    try_read_lines(path)?.take(n).collect()
+}
+
+/// Returns the last `n` lines of a file,
+/// or an empty `Vec` if the file cannot be read.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_lines("tail.txt", &["1", "2", "3"]);
+///
+/// assert_eq!(fsutils::tail("tail.txt", 2), ["2", "3"]);
+///
+/// # // Cleanup
+/// # fsutils::rm("tail.txt");
+/// ```
+pub fn tail<P: AsRef<Path>>(path: P, n: usize) -> Vec<String> {
+    match try_tail(path, n) {
+        Ok(lines) => lines,
+        Err(e) => {
+            error!("Cannot read file {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// Returns the last `n` lines of a file, or all of them if it has fewer.
+///
+/// The file is read backwards in blocks from the end until `n` lines have
+/// been found, so the cost depends on the length of those lines and not on
+/// the size of the file. A terminator at the very end of the file does not
+/// start another, empty line. Fails with an error of kind
+/// `io::ErrorKind::InvalidData` if the lines returned are not valid UTF-8.
+///
+/// ## Usage:
+///
+/// ```
+/// let lines = (1..=10_000).map(|i| format!("entry {}", i));
+/// fsutils::write_lines("try_tail.log", lines);
+///
+/// assert_eq!(fsutils::try_tail("try_tail.log", 2).unwrap(), ["entry 9999", "entry 10000"]);
+/// assert!(fsutils::try_tail("try_tail.log", 0).unwrap().is_empty());
+/// assert_eq!(fsutils::try_tail("try_tail.log", usize::MAX).unwrap().len(), 10_000);
+///
+/// # // Cleanup
+/// # fsutils::rm("try_tail.log");
+/// ```
+pub fn try_tail<P: AsRef<Path>>(path: P, n: usize) -> Result<Vec<String>> {
+    let path = path.as_ref();
+    let mut file = File::open(path).at(path)?;
+    let len = file.metadata().at(path)?.len();
+    if len == 0 || n == 0 {
+        return Ok(Vec::new());
+    }
+    let mut last = [0];
+    file.seek(SeekFrom::Start(len - 1)).at(path)?;
+    file.read_exact(&mut last).at(path)?;
+    // Leave out the final terminator so it does not count as an empty last line
+    let mut pos = if last[0] == b'\n' { len - 1 } else { len };
+
+    let mut data = Vec::new();
+    let mut newlines = 0;
+    // `n` lines are complete once the newline before the first of them is found
+    while pos > 0 && newlines < n {
+        let start = pos.saturating_sub(TAIL_BLOCK);
+        let mut block = vec![0; (pos - start) as usize];
+        file.seek(SeekFrom::Start(start)).at(path)?;
+        file.read_exact(&mut block).at(path)?;
+        newlines += block.iter().filter(|&&b| b == b'\n').count();
+        block.extend_from_slice(&data);
+        data = block;
+        pos = start;
+    }
+    let mut lines = Vec::with_capacity(n.min(newlines + 1));
+    for line in data.rsplit(|&b| b == b'\n').take(n) {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        let line = String::from_utf8(line.to_vec())
+            .map_err(|e| FsError::from_io(path, io::Error::new(io::ErrorKind::InvalidData, e)))?;
+        lines.push(line);
+    }
+    lines.reverse();
+    Ok(lines)
+}
@@ -0,0 +1,920 @@
+// Copyright 2020 Jared Forth.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Hard and symbolic links.
+
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use crate::error::{FsError, IoResultExt, Result};
+use crate::op::Op;
+use crate::overwrite::{unique_path_with, OverwritePolicy};
+
+/// Creates a hard link at `dst` to the file at `src`
+/// and returns a boolean based on success or failure.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("ln_source.txt", "shared");
+///
+/// assert!(fsutils::ln("ln_source.txt", "ln_link.txt"));
+/// assert_eq!(fsutils::read_file("ln_link.txt"), "shared");
+///
+/// # // Cleanup
+/// # fsutils::rm("ln_source.txt");
+/// # fsutils::rm("ln_link.txt");
+/// ```
+pub fn ln<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dst: Q) -> bool {
+    match try_ln(src, dst) {
+        Ok(_) => true,
+        Err(e) => {
+            error!("Cannot create link {}", e);
+            false
+        }
+    }
+}
+
+/// Creates a hard link at `dst` to the file at `src`, like `ln`.
+///
+/// Both names then refer to the same file, so a change through one is seen
+/// through the other, and the data stays until both are removed. The two
+/// paths must be on the same filesystem, and most systems do not allow hard
+/// links to directories. Fails with an error of kind `io::ErrorKind::NotFound`
+/// if `src` does not exist and `io::ErrorKind::AlreadyExists` if `dst` does.
+///
+/// ## Usage:
+///
+/// ```
+/// use std::io::ErrorKind;
+///
+/// fsutils::write_file("try_ln_source.txt", "shared");
+///
+/// fsutils::try_ln("try_ln_source.txt", "try_ln_link.txt").unwrap();
+/// assert!(fsutils::is_same_file("try_ln_source.txt", "try_ln_link.txt"));
+/// let again = fsutils::try_ln("try_ln_source.txt", "try_ln_link.txt");
+/// assert_eq!(again.unwrap_err().kind(), ErrorKind::AlreadyExists);
+///
+/// # // Cleanup
+/// # fsutils::rm("try_ln_source.txt");
+/// # fsutils::rm("try_ln_link.txt");
+/// ```
+pub fn try_ln<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dst: Q) -> Result<()> {
+    let (src, dst) = (src.as_ref(), dst.as_ref());
+    check_link(src, dst)?;
+    Op::new("ln", src).to(dst).run((), || {
+        fs::hard_link(src, dst).at2(src, dst)?;
+        info!("Linked {} to {}", dst.display(), src.display());
+        Ok(())
+    })
+}
+
+/// Creates a hard link at `dst` to the file at `src`, consulting `policy` if
+/// `dst` exists.
+///
+/// Returns the path of the new link, or `None` if the policy skipped it. An
+/// existing `dst` that is overwritten is replaced in a single rename, so
+/// there is no moment at which it is missing.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::OverwritePolicy;
+///
+/// fsutils::write_file("ln_with_new.txt", "new");
+/// fsutils::write_file("ln_with_current.txt", "old");
+///
+/// assert_eq!(fsutils::ln_with("ln_with_new.txt", "ln_with_current.txt", &OverwritePolicy::Skip).unwrap(), None);
+/// fsutils::ln_with("ln_with_new.txt", "ln_with_current.txt", &OverwritePolicy::Overwrite).unwrap();
+/// assert_eq!(fsutils::read_file("ln_with_current.txt"), "new");
+///
+/// # // Cleanup
+/// # fsutils::rm("ln_with_new.txt");
+/// # fsutils::rm("ln_with_current.txt");
+/// ```
+pub fn ln_with<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dst: Q, policy: &OverwritePolicy) -> Result<Option<PathBuf>> {
+    let src = src.as_ref();
+    if src.symlink_metadata().is_err() {
+        return Err(FsError::from_io(src, io::ErrorKind::NotFound.into()));
+    }
+    let dest = match policy.resolve(dst.as_ref())? {
+        Some(dest) => dest,
+        None => return Ok(None),
+    };
+    policy.back_up(&dest)?;
+    let op = Op::new("ln", src).to(&dest);
+    op.destructive(dest.symlink_metadata().is_ok()).run(Some(dest.clone()), || {
+        replace_link(&dest, |temp| fs::hard_link(src, temp).at2(src, temp))?;
+        info!("Linked {} to {}", dest.display(), src.display());
+        Ok(Some(dest.clone()))
+    })
+}
+
+/// What `fsutils::ln_s_with` does when the process may not create symbolic
+/// links, which on Windows needs Developer Mode or administrator rights.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::SymlinkFallback;
+///
+/// assert_eq!(SymlinkFallback::default(), SymlinkFallback::None);
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum SymlinkFallback {
+    /// Return the error. As the result of `fsutils::ln_s_with`, a symbolic link was created.
+    #[default]
+    None,
+    /// Create a directory junction instead, which any user may on Windows.
+    /// Junctions always point at an absolute path and only to directories,
+    /// so other targets still fail. Not available elsewhere.
+    Junction,
+    /// Copy the target instead, with `fsutils::try_cp` or `fsutils::try_cp_r`,
+    /// so the link works for reading but does not follow later changes.
+    Copy,
+}
+
+/// Creates a symbolic link at `link` pointing to `target`
+/// and returns a boolean based on success or failure.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("ln_s_target.txt", "pointed at");
+///
+/// if fsutils::ln_s("ln_s_target.txt", "ln_s_link.txt") {
+///     assert_eq!(fsutils::read_file("ln_s_link.txt"), "pointed at");
+///     # fsutils::rm("ln_s_link.txt");
+/// }
+///
+/// # // Cleanup
+/// # fsutils::rm("ln_s_target.txt");
+/// ```
+pub fn ln_s<P: AsRef<Path>, Q: AsRef<Path>>(target: P, link: Q) -> bool {
+    match try_ln_s(target, link) {
+        Ok(_) => true,
+        Err(e) => {
+            error!("Cannot create link {}", e);
+            false
+        }
+    }
+}
+
+/// Creates a symbolic link at `link` pointing to `target`, like `ln -s`.
+///
+/// As with `ln -s`, a relative `target` is relative to the directory holding
+/// the link, not to the current directory, and it need not exist. Windows
+/// has separate links to files and to directories; a directory link is
+/// made if `target` is a directory at the time. Fails with an error of kind
+/// `io::ErrorKind::AlreadyExists` if `link` exists. See `fsutils::ln_s_with`
+/// for what to do when symbolic links may not be created.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::mkdir("try_ln_s_dir/releases/v2");
+///
+/// if fsutils::try_ln_s("releases/v2", "try_ln_s_dir/current").is_ok() {
+///     assert!(std::path::Path::new("try_ln_s_dir/current").is_dir());
+///     assert!(fsutils::try_ln_s("releases/v2", "try_ln_s_dir/current").is_err());
+/// }
+///
+/// # // Cleanup
+/// # fsutils::rm_r("try_ln_s_dir");
+/// ```
+pub fn try_ln_s<P: AsRef<Path>, Q: AsRef<Path>>(target: P, link: Q) -> Result<()> {
+    ln_s_with(target, link, SymlinkFallback::None).map(|_| ())
+}
+
+/// Creates a symbolic link at `link` pointing to `target`, falling back to a
+/// junction or a copy if the process may not create symbolic links.
+///
+/// Returns the fallback that was used, which is `SymlinkFallback::None` when
+/// a symbolic link was created. Other errors, such as a missing parent
+/// directory, are returned as they are. Otherwise this is the same as
+/// `fsutils::try_ln_s`; a copy, in particular, needs `target` to exist.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::SymlinkFallback;
+///
+/// fsutils::mkdir("ln_s_with_dir/assets");
+/// fsutils::write_file("ln_s_with_dir/assets/logo.svg", "<svg/>");
+///
+/// let made = fsutils::ln_s_with("assets", "ln_s_with_dir/static", SymlinkFallback::Copy).unwrap();
+/// assert_eq!(fsutils::read_file("ln_s_with_dir/static/logo.svg"), "<svg/>");
+/// # #[cfg(unix)]
+/// assert_eq!(made, SymlinkFallback::None);
+///
+/// # // Cleanup
+/// # fsutils::rm_r("ln_s_with_dir");
+/// ```
+pub fn ln_s_with<P: AsRef<Path>, Q: AsRef<Path>>(
+    target: P,
+    link: Q,
+    fallback: SymlinkFallback,
+) -> Result<SymlinkFallback> {
+    let (target, link) = (target.as_ref(), link.as_ref());
+    if link.symlink_metadata().is_ok() {
+        return Err(FsError::already_exists(link));
+    }
+    // What the link will point at, for deciding its kind and for fallbacks
+    let resolved = match link.parent() {
+        Some(parent) => parent.join(target),
+        None => target.to_path_buf(),
+    };
+    Op::new("ln_s", link).to(target).run(SymlinkFallback::None, || {
+        let e = match symlink(target, link, resolved.is_dir()) {
+            Ok(()) => {
+                info!("Linked {} to {}", link.display(), target.display());
+                return Ok(SymlinkFallback::None);
+            }
+            Err(e) => e,
+        };
+        if fallback == SymlinkFallback::None || !lacks_privilege(&e) {
+            return Err(FsError::from_io(link, e));
+        }
+        match fallback {
+            SymlinkFallback::Copy if resolved.is_dir() => crate::try_cp_r(&resolved, link).map(|_| ())?,
+            SymlinkFallback::Copy => crate::try_cp(&resolved, link).map(|_| ())?,
+            _ => junction(&resolved, link).at2(target, link)?,
+        }
+        info!("Made {} a {:?} of {} as symbolic links are not allowed", link.display(), fallback, target.display());
+        Ok(fallback)
+    })
+}
+
+#[cfg(unix)]
+pub(crate) fn symlink(target: &Path, link: &Path, _is_dir: bool) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(windows)]
+pub(crate) fn symlink(target: &Path, link: &Path, is_dir: bool) -> io::Result<()> {
+    if is_dir {
+        std::os::windows::fs::symlink_dir(target, link)
+    } else {
+        std::os::windows::fs::symlink_file(target, link)
+    }
+}
+
+/// Whether `e` is the refusal of a symbolic link, rather than some other failure.
+fn lacks_privilege(e: &io::Error) -> bool {
+    // ERROR_PRIVILEGE_NOT_HELD on Windows
+    const PRIVILEGE_NOT_HELD: i32 = 1314;
+    e.kind() == io::ErrorKind::PermissionDenied || (cfg!(windows) && e.raw_os_error() == Some(PRIVILEGE_NOT_HELD))
+}
+
+#[cfg(not(windows))]
+fn junction(_target: &Path, _link: &Path) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "junctions only exist on Windows"))
+}
+
+/// Creates `link` as a directory junction to the directory `target`.
+#[cfg(windows)]
+fn junction(target: &Path, link: &Path) -> io::Result<()> {
+    use std::ffi::c_void;
+    use std::os::windows::ffi::OsStrExt;
+    use std::os::windows::fs::OpenOptionsExt;
+    use std::os::windows::io::AsRawHandle;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn DeviceIoControl(
+            device: *mut c_void,
+            code: u32,
+            input: *const c_void,
+            input_len: u32,
+            output: *mut c_void,
+            output_len: u32,
+            returned: *mut u32,
+            overlapped: *mut c_void,
+        ) -> i32;
+    }
+
+    const FSCTL_SET_REPARSE_POINT: u32 = 0x0009_00A4;
+    const IO_REPARSE_TAG_MOUNT_POINT: u32 = 0xA000_0003;
+    const FILE_FLAG_OPEN_REPARSE_POINT: u32 = 0x0020_0000;
+    const FILE_FLAG_BACKUP_SEMANTICS: u32 = 0x0200_0000;
+
+    if !target.is_dir() {
+        return Err(io::Error::new(io::ErrorKind::NotADirectory, "junctions can only point to directories"));
+    }
+    // Junctions hold an NT path, `\??\C:\...`, and a display path, `C:\...`
+    let absolute: Vec<u16> = fs::canonicalize(target)?.as_os_str().encode_wide().collect();
+    let verbatim: Vec<u16> = r"\\?\".encode_utf16().collect();
+    let print = absolute.strip_prefix(&verbatim[..]).unwrap_or(&absolute);
+    let substitute: Vec<u16> = r"\??\".encode_utf16().chain(print.iter().copied()).collect();
+
+    // A REPARSE_DATA_BUFFER holding a MountPointReparseBuffer
+    let mut names = substitute.clone();
+    names.push(0);
+    names.extend_from_slice(print);
+    names.push(0);
+    let data_len = 8 + names.len() * 2;
+    let mut buffer = Vec::with_capacity(8 + data_len);
+    buffer.extend_from_slice(&IO_REPARSE_TAG_MOUNT_POINT.to_le_bytes());
+    buffer.extend_from_slice(&(data_len as u16).to_le_bytes());
+    buffer.extend_from_slice(&0u16.to_le_bytes());
+    buffer.extend_from_slice(&0u16.to_le_bytes());
+    buffer.extend_from_slice(&((substitute.len() * 2) as u16).to_le_bytes());
+    buffer.extend_from_slice(&((substitute.len() * 2 + 2) as u16).to_le_bytes());
+    buffer.extend_from_slice(&((print.len() * 2) as u16).to_le_bytes());
+    for unit in names {
+        buffer.extend_from_slice(&unit.to_le_bytes());
+    }
+
+    fs::create_dir(link)?;
+    let set = fs::OpenOptions::new()
+        .write(true)
+        .custom_flags(FILE_FLAG_OPEN_REPARSE_POINT | FILE_FLAG_BACKUP_SEMANTICS)
+        .open(link)
+        .and_then(|dir| {
+            let mut returned = 0;
+            // SAFETY: the handle stays open for the duration of the call, and
+            // the input pointer and length describe `buffer`
+            let ok = unsafe {
+                DeviceIoControl(
+                    dir.as_raw_handle(),
+                    FSCTL_SET_REPARSE_POINT,
+                    buffer.as_ptr() as *const c_void,
+                    buffer.len() as u32,
+                    std::ptr::null_mut(),
+                    0,
+                    &mut returned,
+                    std::ptr::null_mut(),
+                )
+            };
+            if ok == 0 {
+                Err(io::Error::last_os_error())
+            } else {
+                Ok(())
+            }
+        });
+    if set.is_err() {
+        let _ = fs::remove_dir(link);
+    }
+    set
+}
+
+/// The most symbolic links `fsutils::try_resolve_link_chain` follows, the
+/// same limit Linux applies when opening a path.
+const MAX_HOPS: usize = 40;
+
+/// The symbolic links leading from a path to what it finally refers to, as
+/// returned by `fsutils::try_resolve_link_chain`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LinkChain {
+    /// The links followed in order, starting with the path that was given.
+    /// Empty if that path is not a symbolic link.
+    pub links: Vec<PathBuf>,
+    /// Where the chain ends: the first path that is not a symbolic link.
+    pub target: PathBuf,
+    /// Whether `target` exists. A chain whose target is missing is broken.
+    pub exists: bool,
+}
+
+impl LinkChain {
+    /// Returns `true` if the last link points to something that does not exist.
+    pub fn is_broken(&self) -> bool {
+        !self.exists
+    }
+}
+
+/// Returns what the symbolic link at `path` points to, or `None` if it is
+/// not a link or cannot be read.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("readlink.txt", "text");
+///
+/// if fsutils::ln_s("readlink.txt", "readlink_link.txt") {
+///     assert_eq!(fsutils::readlink("readlink_link.txt").unwrap().to_str(), Some("readlink.txt"));
+///     # fsutils::rm("readlink_link.txt");
+/// }
+/// assert_eq!(fsutils::readlink("readlink.txt"), None);
+///
+/// # // Cleanup
+/// # fsutils::rm("readlink.txt");
+/// ```
+pub fn readlink<P: AsRef<Path>>(path: P) -> Option<PathBuf> {
+    match try_readlink(path) {
+        Ok(target) => Some(target),
+        Err(e) => {
+            error!("Cannot read link {}", e);
+            None
+        }
+    }
+}
+
+/// Returns what the symbolic link at `path` points to, like `readlink`.
+///
+/// The target is returned as it is stored, so a relative target is relative
+/// to the directory holding the link. Only this one link is read; see
+/// `fsutils::try_resolve_link_chain` to follow links to their end. Fails with
+/// an error of kind `io::ErrorKind::InvalidInput` if `path` is not a link.
+///
+/// ## Usage:
+///
+/// ```
+/// use std::io::ErrorKind;
+///
+/// fsutils::mkdir("try_readlink_dir");
+///
+/// if fsutils::try_ln_s("missing.txt", "try_readlink_dir/dangling").is_ok() {
+///     let target = fsutils::try_readlink("try_readlink_dir/dangling").unwrap();
+///     assert_eq!(target.to_str(), Some("missing.txt"));
+/// }
+/// let error = fsutils::try_readlink("try_readlink_dir").unwrap_err();
+/// assert_eq!(error.kind(), ErrorKind::InvalidInput);
+///
+/// # // Cleanup
+/// # fsutils::rm_r("try_readlink_dir");
+/// ```
+pub fn try_readlink<P: AsRef<Path>>(path: P) -> Result<PathBuf> {
+    let path = path.as_ref();
+    if !path.symlink_metadata().at(path)?.file_type().is_symlink() {
+        return Err(FsError::from_io(path, io::Error::new(io::ErrorKind::InvalidInput, "not a symbolic link")));
+    }
+    fs::read_link(path).at(path)
+}
+
+/// Follows the symbolic links from `path` to the end, or returns `None` if
+/// `path` does not exist or the links cannot be followed.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("resolve_link_chain.txt", "text");
+///
+/// let chain = fsutils::resolve_link_chain("resolve_link_chain.txt").unwrap();
+/// assert!(chain.links.is_empty());
+/// assert!(!chain.is_broken());
+///
+/// # // Cleanup
+/// # fsutils::rm("resolve_link_chain.txt");
+/// ```
+pub fn resolve_link_chain<P: AsRef<Path>>(path: P) -> Option<LinkChain> {
+    match try_resolve_link_chain(path) {
+        Ok(chain) => Some(chain),
+        Err(e) => {
+            error!("Cannot resolve links {}", e);
+            None
+        }
+    }
+}
+
+/// Follows the symbolic links from `path` one at a time, recording each.
+///
+/// Each relative link target is taken relative to the directory holding that
+/// link. A chain that ends at a missing path is broken, which is returned as
+/// a `LinkChain` with `exists` set to `false`, whereas a `path` that does not
+/// exist at all is an error of kind `io::ErrorKind::NotFound`. Following more
+/// than 40 links, as a loop would, fails with `FsError::InvalidPath`.
+///
+/// ## Usage:
+///
+/// ```
+/// use std::path::Path;
+///
+/// fsutils::mkdir("try_resolve_link_chain_dir");
+/// fsutils::write_file("try_resolve_link_chain_dir/v2.conf", "text");
+///
+/// if fsutils::try_ln_s("v2.conf", "try_resolve_link_chain_dir/current.conf").is_ok() {
+///     fsutils::try_ln_s("current.conf", "try_resolve_link_chain_dir/app.conf").unwrap();
+///     let chain = fsutils::try_resolve_link_chain("try_resolve_link_chain_dir/app.conf").unwrap();
+///     assert_eq!(chain.links.len(), 2);
+///     assert_eq!(chain.target, Path::new("try_resolve_link_chain_dir/v2.conf"));
+///
+///     fsutils::rm("try_resolve_link_chain_dir/v2.conf");
+///     let chain = fsutils::try_resolve_link_chain("try_resolve_link_chain_dir/app.conf").unwrap();
+///     assert!(chain.is_broken());
+///
+///     fsutils::try_ln_s("loop", "try_resolve_link_chain_dir/loop").unwrap();
+///     assert!(fsutils::try_resolve_link_chain("try_resolve_link_chain_dir/loop").is_err());
+/// }
+/// assert!(fsutils::try_resolve_link_chain("try_resolve_link_chain_dir/missing").is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm_r("try_resolve_link_chain_dir");
+/// ```
+pub fn try_resolve_link_chain<P: AsRef<Path>>(path: P) -> Result<LinkChain> {
+    let path = path.as_ref();
+    let mut links = Vec::new();
+    let mut current = path.to_path_buf();
+    let mut metadata = current.symlink_metadata().at(&current)?;
+    while metadata.file_type().is_symlink() {
+        if links.len() == MAX_HOPS {
+            return Err(too_many_links(path));
+        }
+        let target = fs::read_link(&current).at(&current)?;
+        let next = match current.parent() {
+            Some(parent) => parent.join(target),
+            None => target,
+        };
+        links.push(current);
+        current = next;
+        metadata = match current.symlink_metadata() {
+            Ok(metadata) => metadata,
+            Err(e) if is_missing(&e) => {
+                return Ok(LinkChain { links, target: current, exists: false })
+            }
+            Err(e) => return Err(FsError::from_io(&current, e)),
+        };
+    }
+    Ok(LinkChain { links, target: current, exists: true })
+}
+
+/// Returns the absolute path `path` refers to with all symbolic links, `.`
+/// and `..` resolved, or `None` if it does not exist or cannot be resolved.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::mkdir("realpath_dir");
+///
+/// let path = fsutils::realpath("realpath_dir/../realpath_dir").unwrap();
+/// assert!(path.is_absolute());
+/// assert!(path.ends_with("realpath_dir"));
+/// assert_eq!(fsutils::realpath("realpath_dir/missing"), None);
+///
+/// # // Cleanup
+/// # fsutils::rm_r("realpath_dir");
+/// ```
+pub fn realpath<P: AsRef<Path>>(path: P) -> Option<PathBuf> {
+    match try_realpath(path) {
+        Ok(resolved) => Some(resolved),
+        Err(e) => {
+            error!("Cannot resolve path {}", e);
+            None
+        }
+    }
+}
+
+/// Returns the absolute path `path` refers to with all symbolic links, `.`
+/// and `..` resolved, like `realpath`.
+///
+/// This is `fs::canonicalize`, so `path` must exist; see
+/// `fsutils::try_realpath_missing` for paths that may not. On Windows the
+/// result is in the `\\?\` form.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::mkdir("try_realpath_dir/nested");
+///
+/// let nested = fsutils::try_realpath("try_realpath_dir/nested").unwrap();
+/// assert_eq!(fsutils::try_realpath("try_realpath_dir/nested/./..").unwrap(), nested.parent().unwrap());
+/// assert!(fsutils::try_realpath("try_realpath_dir/missing").is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm_r("try_realpath_dir");
+/// ```
+pub fn try_realpath<P: AsRef<Path>>(path: P) -> Result<PathBuf> {
+    let path = path.as_ref();
+    fs::canonicalize(path).at(path)
+}
+
+/// Resolves `path` like `fsutils::try_realpath`, except that it need not
+/// exist, or returns `None` if it cannot be resolved.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::mkdir("realpath_missing_dir");
+///
+/// let path = fsutils::realpath_missing("realpath_missing_dir/not/yet.txt").unwrap();
+/// assert!(path.is_absolute());
+/// assert!(path.ends_with("realpath_missing_dir/not/yet.txt"));
+///
+/// # // Cleanup
+/// # fsutils::rm_r("realpath_missing_dir");
+/// ```
+pub fn realpath_missing<P: AsRef<Path>>(path: P) -> Option<PathBuf> {
+    match try_realpath_missing(path) {
+        Ok(resolved) => Some(resolved),
+        Err(e) => {
+            error!("Cannot resolve path {}", e);
+            None
+        }
+    }
+}
+
+/// Resolves `path` to an absolute path like `realpath -m`, so that it, or any
+/// of its parents, need not exist.
+///
+/// Symbolic links are resolved as far as the path exists, including one that
+/// points to a missing path, and the rest is resolved lexically: `.` is
+/// dropped and `..` removes the component before it. This gives where a file
+/// would be created, say, to check that it would end up inside a directory.
+/// Following more than 40 links fails with `FsError::InvalidPath`.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::mkdir("try_realpath_missing_dir/logs");
+/// let dir = fsutils::try_realpath("try_realpath_missing_dir").unwrap();
+///
+/// let path = fsutils::try_realpath_missing("try_realpath_missing_dir/logs/../new/./a.txt").unwrap();
+/// assert_eq!(path, dir.join("new").join("a.txt"));
+///
+/// if fsutils::try_ln_s("gone/target.txt", "try_realpath_missing_dir/dangling").is_ok() {
+///     let path = fsutils::try_realpath_missing("try_realpath_missing_dir/dangling").unwrap();
+///     assert_eq!(path, dir.join("gone").join("target.txt"));
+/// }
+///
+/// # // Cleanup
+/// # fsutils::rm_r("try_realpath_missing_dir");
+/// ```
+pub fn try_realpath_missing<P: AsRef<Path>>(path: P) -> Result<PathBuf> {
+    let path = path.as_ref();
+    let mut resolved = if path.has_root() { PathBuf::new() } else { fs::canonicalize(".").at(".")? };
+    // The components still to resolve, the next one last
+    let mut pending: Vec<PathBuf> = path.components().rev().map(|part| PathBuf::from(part.as_os_str())).collect();
+    let mut hops = 0;
+    let mut missing = false;
+    while let Some(part) = pending.pop() {
+        match part.components().next() {
+            Some(Component::Prefix(_)) | Some(Component::RootDir) => resolved.push(&part),
+            Some(Component::ParentDir) => {
+                resolved.pop();
+            }
+            Some(Component::Normal(name)) => {
+                let next = resolved.join(name);
+                if !missing {
+                    match next.symlink_metadata() {
+                        Ok(metadata) if metadata.file_type().is_symlink() => {
+                            hops += 1;
+                            if hops > MAX_HOPS {
+                                return Err(too_many_links(path));
+                            }
+                            // The target is relative to `resolved`, the directory holding the link
+                            let target = fs::read_link(&next).at(&next)?;
+                            pending.extend(target.components().rev().map(|part| PathBuf::from(part.as_os_str())));
+                            continue;
+                        }
+                        Ok(_) => {}
+                        Err(e) if is_missing(&e) => missing = true,
+                        Err(e) => return Err(FsError::from_io(&next, e)),
+                    }
+                }
+                resolved = next;
+            }
+            Some(Component::CurDir) | None => {}
+        }
+    }
+    Ok(resolved)
+}
+
+/// Whether `e` means a path, or one of its parents, does not exist.
+fn is_missing(e: &io::Error) -> bool {
+    e.kind() == io::ErrorKind::NotFound || e.kind() == io::ErrorKind::NotADirectory
+}
+
+fn too_many_links(path: &Path) -> FsError {
+    FsError::invalid_path(path, format!("more than {} levels of symbolic links", MAX_HOPS))
+}
+
+/// The names of a file with several hard links, as found by
+/// `fsutils::try_find_hardlinks`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct HardLinks {
+    /// How many names the file has in all, according to its metadata.
+    pub count: u64,
+    /// The names found below the search root, in name order, including the
+    /// path that was searched for if it is below the root.
+    pub paths: Vec<PathBuf>,
+}
+
+impl HardLinks {
+    /// Returns `true` if every name of the file was found, so none are
+    /// outside the search root.
+    pub fn is_complete(&self) -> bool {
+        self.paths.len() as u64 >= self.count
+    }
+}
+
+/// Finds the other names of a file below `search_root`, or returns `None`
+/// if the file or the tree cannot be read.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::mkdir("find_hardlinks_dir");
+/// fsutils::write_file("find_hardlinks_dir/a.txt", "shared");
+///
+/// if fsutils::ln("find_hardlinks_dir/a.txt", "find_hardlinks_dir/b.txt") {
+///     let links = fsutils::find_hardlinks("find_hardlinks_dir/a.txt", "find_hardlinks_dir").unwrap();
+///     assert_eq!(links.count, 2);
+/// }
+///
+/// # // Cleanup
+/// # fsutils::rm_r("find_hardlinks_dir");
+/// ```
+pub fn find_hardlinks<P: AsRef<Path>, Q: AsRef<Path>>(path: P, search_root: Q) -> Option<HardLinks> {
+    match try_find_hardlinks(path, search_root) {
+        Ok(links) => Some(links),
+        Err(e) => {
+            error!("Cannot find hard links {}", e);
+            None
+        }
+    }
+}
+
+/// Finds every name of the file at `path` below `search_root` by walking the
+/// tree for entries that are the same file.
+///
+/// The number of names the file has comes from its metadata, and the walk
+/// stops once that many are found. Symbolic links are neither followed nor
+/// reported, and other filesystems mounted below the root are skipped, as a
+/// hard link cannot cross one. Fails with an error of kind
+/// `io::ErrorKind::InvalidInput` if `path` is a directory.
+///
+/// ## Usage:
+///
+/// ```
+/// use std::path::Path;
+///
+/// fsutils::mkdir("try_find_hardlinks_dir/backup");
+/// fsutils::write_file("try_find_hardlinks_dir/data.bin", "bytes");
+/// fsutils::write_file("try_find_hardlinks_dir/other.bin", "bytes");
+///
+/// fsutils::try_ln("try_find_hardlinks_dir/data.bin", "try_find_hardlinks_dir/backup/data.bin").unwrap();
+/// let links = fsutils::try_find_hardlinks("try_find_hardlinks_dir/data.bin", "try_find_hardlinks_dir").unwrap();
+/// assert_eq!(links.paths, [
+///     Path::new("try_find_hardlinks_dir/backup/data.bin"),
+///     Path::new("try_find_hardlinks_dir/data.bin"),
+/// ]);
+/// assert!(links.is_complete());
+///
+/// let links = fsutils::try_find_hardlinks("try_find_hardlinks_dir/data.bin", "try_find_hardlinks_dir/backup").unwrap();
+/// assert!(!links.is_complete());
+///
+/// # // Cleanup
+/// # fsutils::rm_r("try_find_hardlinks_dir");
+/// ```
+pub fn try_find_hardlinks<P: AsRef<Path>, Q: AsRef<Path>>(path: P, search_root: Q) -> Result<HardLinks> {
+    let (path, root) = (path.as_ref(), search_root.as_ref());
+    let meta = path.metadata().at(path)?;
+    if meta.is_dir() {
+        let source = io::Error::new(io::ErrorKind::InvalidInput, "directories do not have hard links");
+        return Err(FsError::from_io(path, source));
+    }
+    let id = crate::meta::file_id(path).at(path)?;
+    let mut links = HardLinks { count: crate::meta::link_count(path).at(path)?, paths: Vec::new() };
+    if crate::meta::file_id(root).at(root)?.0 == id.0 {
+        search_links(root, id, meta.len(), &mut links)?;
+    }
+    Ok(links)
+}
+
+/// Adds the entries below `dir` that are the file with `id` and length `len`
+/// to `links`, until all its names are found.
+fn search_links(dir: &Path, id: (u64, u64), len: u64, links: &mut HardLinks) -> Result<()> {
+    let mut entries = fs::read_dir(dir).at(dir)?.collect::<io::Result<Vec<_>>>().at(dir)?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        if links.is_complete() {
+            break;
+        }
+        let path = entry.path();
+        let file_type = entry.file_type().at(&path)?;
+        if file_type.is_dir() {
+            // Only the same filesystem can hold another name for the file
+            if crate::meta::file_id(&path).at(&path)?.0 == id.0 {
+                search_links(&path, id, len, links)?;
+            }
+        } else if file_type.is_file()
+            && entry.metadata().at(&path)?.len() == len
+            && crate::meta::file_id(&path).at(&path)? == id
+        {
+            links.paths.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Whether a walk over a tree dereferences the symbolic links it finds, for
+/// `fsutils::CopyOptions`, `fsutils::WalkOptions`, `fsutils::rm_r_with`,
+/// `fsutils::dir_size_with` and `fsutils::du_with`.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::FollowSymlinks;
+///
+/// assert_eq!(FollowSymlinks::default(), FollowSymlinks::CommandLineOnly);
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum FollowSymlinks {
+    /// Follow every link, like `cp -L` or `du -L`. A link to a directory the
+    /// walk is already inside is left as a link, so loops end.
+    Always,
+    /// Follow no links, like `cp -P` or `du -P`, so links are copied, removed
+    /// or measured as links.
+    Never,
+    /// Follow the path the walk starts from if it is a link, but no links
+    /// inside it, like `cp -H`.
+    #[default]
+    CommandLineOnly,
+}
+
+impl FollowSymlinks {
+    /// Whether a link is followed, `top` meaning it is where the walk starts.
+    pub(crate) fn follows(self, top: bool) -> bool {
+        match self {
+            FollowSymlinks::Always => true,
+            FollowSymlinks::Never => false,
+            FollowSymlinks::CommandLineOnly => top,
+        }
+    }
+
+    /// The metadata of `path`, or of what it points to if it is a link that
+    /// is followed and not dangling.
+    pub(crate) fn metadata(self, path: &Path, top: bool) -> io::Result<fs::Metadata> {
+        let meta = path.symlink_metadata()?;
+        if meta.file_type().is_symlink() && self.follows(top) {
+            match path.metadata() {
+                Ok(target) => return Ok(target),
+                Err(e) if is_missing(&e) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(meta)
+    }
+}
+
+/// The directories a walk is inside, so that one following symbolic links
+/// does not go round a loop. Only kept for `FollowSymlinks::Always`, as
+/// otherwise no link inside the walk is followed.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Ancestors {
+    track: bool,
+    ids: Vec<(u64, u64)>,
+}
+
+impl Ancestors {
+    pub(crate) fn new(follow: FollowSymlinks) -> Ancestors {
+        Ancestors { track: follow == FollowSymlinks::Always, ids: Vec::new() }
+    }
+
+    /// Records that the walk is entering the directory `dir`, or returns
+    /// `false` if it is inside it already.
+    pub(crate) fn enter(&mut self, dir: &Path) -> Result<bool> {
+        if !self.track {
+            return Ok(true);
+        }
+        let id = crate::meta::file_id(dir).at(dir)?;
+        if self.ids.contains(&id) {
+            info!("Not following {} back into a directory already being walked", dir.display());
+            return Ok(false);
+        }
+        self.ids.push(id);
+        Ok(true)
+    }
+
+    /// Records that the walk has left the directory it entered last.
+    pub(crate) fn leave(&mut self) {
+        self.ids.pop();
+    }
+}
+
+/// Fails unless `src` exists and `dst` does not.
+fn check_link(src: &Path, dst: &Path) -> Result<()> {
+    if src.symlink_metadata().is_err() {
+        return Err(FsError::from_io(src, io::ErrorKind::NotFound.into()));
+    }
+    if dst.symlink_metadata().is_ok() {
+        return Err(FsError::already_exists(dst));
+    }
+    Ok(())
+}
+
+/// Creates a link at `dest` with `create`, which is given a free temporary
+/// name next to `dest` if something is already there, renamed over it after.
+fn replace_link<F>(dest: &Path, create: F) -> Result<()>
+where
+    F: FnOnce(&Path) -> Result<()>,
+{
+    if dest.symlink_metadata().is_err() {
+        return create(dest);
+    }
+    let name = dest.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    let temp = unique_path_with(dest.with_file_name(format!(".{}.tmp", name)), "{stem}.{n}{ext}")?;
+    create(&temp)?;
+    if let Err(e) = fs::rename(&temp, dest) {
+        let _ = fs::remove_file(&temp);
+        return Err(FsError::from_io2(&temp, dest, e));
+    }
+    Ok(())
+}
@@ -0,0 +1,288 @@
+// Copyright 2020 Jared Forth.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Listing directory contents with their metadata.
+
+use std::cmp::Ordering;
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, Metadata};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::error::{IoResultExt, Result};
+use crate::meta::FileKind;
+
+/// One entry of a directory listing from `fsutils::ls_with`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct LsEntry {
+    /// The file name of the entry.
+    pub name: OsString,
+    /// The path of the entry, the listed directory joined with `name`.
+    pub path: PathBuf,
+    /// What the entry is. Symbolic links are not followed.
+    pub kind: FileKind,
+    /// The length in bytes, of the link itself for a symbolic link.
+    pub size: u64,
+    /// When the entry was last modified, where the platform reports it.
+    pub modified: Option<SystemTime>,
+}
+
+impl LsEntry {
+    fn new(path: PathBuf, meta: &Metadata) -> LsEntry {
+        LsEntry {
+            name: path.file_name().map(OsString::from).unwrap_or_default(),
+            kind: FileKind::of(meta),
+            size: meta.len(),
+            modified: meta.modified().ok(),
+            path,
+        }
+    }
+}
+
+/// The order of the entries returned by `fsutils::ls_with`.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::LsSort;
+///
+/// assert_eq!(LsSort::default(), LsSort::Name);
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum LsSort {
+    /// By file name, comparing bytes, so capitals come before small letters.
+    #[default]
+    Name,
+    /// Largest first, like `ls -S`, and by name among equal sizes.
+    Size,
+    /// Most recently modified first, like `ls -t`, and by name among equal times.
+    Modified,
+    /// In whatever order the operating system returns them, like `ls -U`.
+    Unsorted,
+}
+
+/// Options for `fsutils::ls_with`.
+///
+/// By default hidden entries are left out and the rest are sorted by name.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::{LsOptions, LsSort};
+///
+/// let mut opts = LsOptions::new();
+/// opts.sort(LsSort::Modified).dirs_first(true).all(true);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct LsOptions {
+    sort: LsSort,
+    reverse: bool,
+    dirs_first: bool,
+    all: bool,
+}
+
+impl LsOptions {
+    /// Creates the default options.
+    pub fn new() -> LsOptions {
+        LsOptions::default()
+    }
+
+    /// How to order the entries.
+    pub fn sort(&mut self, sort: LsSort) -> &mut LsOptions {
+        self.sort = sort;
+        self
+    }
+
+    /// Reverse the order, like `ls -r`. Directories still come first with
+    /// `dirs_first`.
+    pub fn reverse(&mut self, reverse: bool) -> &mut LsOptions {
+        self.reverse = reverse;
+        self
+    }
+
+    /// List directories before everything else, like
+    /// `ls --group-directories-first`. Links to directories are not counted
+    /// as directories.
+    pub fn dirs_first(&mut self, dirs_first: bool) -> &mut LsOptions {
+        self.dirs_first = dirs_first;
+        self
+    }
+
+    /// Include hidden entries, like `ls -A`. These are those whose names
+    /// start with a dot, or on Windows those with the hidden attribute.
+    pub fn all(&mut self, all: bool) -> &mut LsOptions {
+        self.all = all;
+        self
+    }
+
+    fn compare(&self, a: &LsEntry, b: &LsEntry) -> Ordering {
+        let by_name = || a.name.cmp(&b.name);
+        let order = match self.sort {
+            LsSort::Name => by_name(),
+            LsSort::Size => b.size.cmp(&a.size).then_with(by_name),
+            LsSort::Modified => b.modified.cmp(&a.modified).then_with(by_name),
+            LsSort::Unsorted => Ordering::Equal,
+        };
+        let order = if self.reverse { order.reverse() } else { order };
+        if self.dirs_first {
+            (b.kind == FileKind::Dir).cmp(&(a.kind == FileKind::Dir)).then(order)
+        } else {
+            order
+        }
+    }
+}
+
+/// Lists the entries of a directory, sorted by name and without hidden ones,
+/// or returns an empty `Vec` if it cannot be read.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::mkdir("ls_entries_dir");
+/// fsutils::write_file("ls_entries_dir/notes.txt", "text");
+///
+/// for entry in fsutils::ls_entries("ls_entries_dir") {
+///     println!("{:?} {} {}", entry.kind, entry.size, entry.name.to_string_lossy());
+/// }
+///
+/// # // Cleanup
+/// # fsutils::rm_r("ls_entries_dir");
+/// ```
+pub fn ls_entries<P: AsRef<Path>>(path: P) -> Vec<LsEntry> {
+    match try_ls_entries(path) {
+        Ok(entries) => entries,
+        Err(e) => {
+            error!("Cannot list directory {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// Lists the entries of a directory with their kind, size and modification
+/// time, sorted by name and without hidden ones, like `ls -l`.
+///
+/// See `fsutils::ls_with` for other orders and hidden entries.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::FileKind;
+///
+/// fsutils::mkdir("try_ls_entries_dir/sub");
+/// fsutils::write_file("try_ls_entries_dir/b.txt", "12345");
+/// fsutils::write_file("try_ls_entries_dir/.hidden", "");
+///
+/// let entries = fsutils::try_ls_entries("try_ls_entries_dir").unwrap();
+/// assert_eq!(entries.len(), 2);
+/// assert_eq!((entries[0].name.to_str(), entries[0].kind, entries[0].size), (Some("b.txt"), FileKind::File, 5));
+/// assert_eq!((entries[1].name.to_str(), entries[1].kind), (Some("sub"), FileKind::Dir));
+/// assert!(fsutils::try_ls_entries("a_very_1234_unlikely_9876_dirname").is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm_r("try_ls_entries_dir");
+/// ```
+pub fn try_ls_entries<P: AsRef<Path>>(path: P) -> Result<Vec<LsEntry>> {
+    ls_with(path, &LsOptions::new())
+}
+
+/// Lists every entry of a directory, hidden ones too, sorted by name, or
+/// returns an empty `Vec` if it cannot be read.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::mkdir("ls_a_dir");
+/// fsutils::write_file("ls_a_dir/.env", "KEY=value");
+///
+/// assert_eq!(fsutils::ls_a("ls_a_dir").len(), 1);
+///
+/// # // Cleanup
+/// # fsutils::rm_r("ls_a_dir");
+/// ```
+pub fn ls_a<P: AsRef<Path>>(path: P) -> Vec<LsEntry> {
+    match try_ls_a(path) {
+        Ok(entries) => entries,
+        Err(e) => {
+            error!("Cannot list directory {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// Lists every entry of a directory, hidden ones too, sorted by name, like
+/// `ls -lA`. The `.` and `..` entries are never listed.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::mkdir("try_ls_a_dir");
+/// fsutils::write_file("try_ls_a_dir/.gitignore", "target");
+/// fsutils::write_file("try_ls_a_dir/Cargo.toml", "");
+///
+/// let names: Vec<_> = fsutils::try_ls_a("try_ls_a_dir").unwrap().into_iter().map(|e| e.name).collect();
+/// # #[cfg(unix)]
+/// assert_eq!(names, [".gitignore", "Cargo.toml"]);
+///
+/// # // Cleanup
+/// # fsutils::rm_r("try_ls_a_dir");
+/// ```
+pub fn try_ls_a<P: AsRef<Path>>(path: P) -> Result<Vec<LsEntry>> {
+    ls_with(path, LsOptions::new().all(true))
+}
+
+/// Lists the entries of a directory according to `opts`.
+///
+/// Symbolic links are listed as links, and the directory itself is followed
+/// if it is one.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::{LsOptions, LsSort};
+///
+/// fsutils::mkdir("ls_with_dir/zeta");
+/// fsutils::write_file("ls_with_dir/small.txt", "1");
+/// fsutils::write_file("ls_with_dir/large.txt", "123456789");
+///
+/// let mut opts = LsOptions::new();
+/// opts.sort(LsSort::Size).dirs_first(true);
+/// let names: Vec<_> = fsutils::ls_with("ls_with_dir", &opts).unwrap().into_iter().map(|e| e.name).collect();
+/// assert_eq!(names, ["zeta", "large.txt", "small.txt"]);
+///
+/// opts.sort(LsSort::Name).reverse(true);
+/// let names: Vec<_> = fsutils::ls_with("ls_with_dir", &opts).unwrap().into_iter().map(|e| e.name).collect();
+/// assert_eq!(names, ["zeta", "small.txt", "large.txt"]);
+///
+/// # // Cleanup
+/// # fsutils::rm_r("ls_with_dir");
+/// ```
+pub fn ls_with<P: AsRef<Path>>(path: P, opts: &LsOptions) -> Result<Vec<LsEntry>> {
+    let path = path.as_ref();
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(path).at(path)? {
+        let entry = entry.at(path)?;
+        let meta = entry.metadata().at(entry.path())?;
+        if opts.all || !is_hidden(&entry.file_name(), &meta) {
+            entries.push(LsEntry::new(entry.path(), &meta));
+        }
+    }
+    entries.sort_by(|a, b| opts.compare(a, b));
+    Ok(entries)
+}
+
+#[cfg(windows)]
+fn is_hidden(_name: &OsStr, meta: &Metadata) -> bool {
+    use std::os::windows::fs::MetadataExt;
+    meta.file_attributes() & crate::meta::FILE_ATTRIBUTE_HIDDEN != 0
+}
+
+#[cfg(not(windows))]
+fn is_hidden(name: &OsStr, _meta: &Metadata) -> bool {
+    name.to_string_lossy().starts_with('.')
+}
@@ -0,0 +1,234 @@
+// Copyright 2020 Jared Forth.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Finding repeated strings for the LZ4 and Zstandard compressors.
+
+/// The shortest match looked for, which is also how many bytes are hashed.
+pub(crate) const MIN_MATCH: usize = 4;
+const NONE: u32 = u32::MAX;
+
+/// How hard to look for matches.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Params {
+    /// The furthest back a match may start, as a power of two.
+    pub(crate) window_log: u32,
+    pub(crate) hash_log: u32,
+    /// How many earlier positions with the same four bytes are tried.
+    pub(crate) chain: usize,
+    /// Stop looking once a match is at least this long.
+    pub(crate) nice: usize,
+    /// Try the next position before taking a match, and keep the longer.
+    pub(crate) lazy: bool,
+    /// Skip ahead faster and faster through data that does not match, and
+    /// only remember the positions that were tried.
+    pub(crate) fast: bool,
+}
+
+/// A run of literals followed by a match, which is `len` bytes copied from
+/// `offset` bytes back.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Sequence {
+    pub(crate) literals: usize,
+    pub(crate) offset: usize,
+    pub(crate) len: usize,
+}
+
+/// Hash chains over `data`, which persist while it is parsed a range at a
+/// time, so matches can reach back into earlier ranges.
+pub(crate) struct Matcher<'a> {
+    data: &'a [u8],
+    params: Params,
+    head: Vec<u32>,
+    /// The previous position with the same hash, indexed by position modulo
+    /// the window.
+    prev: Vec<u32>,
+    /// The next position to remember.
+    next: usize,
+}
+
+impl<'a> Matcher<'a> {
+    pub(crate) fn new(data: &'a [u8], params: Params) -> Matcher<'a> {
+        let window = 1usize << params.window_log;
+        let prev = if params.chain > 1 { vec![NONE; window.min(data.len().next_power_of_two())] } else { Vec::new() };
+        Matcher { data, params, head: vec![NONE; 1 << params.hash_log], prev, next: 0 }
+    }
+
+    fn hash(&self, pos: usize) -> usize {
+        let bytes = u32::from_le_bytes([self.data[pos], self.data[pos + 1], self.data[pos + 2], self.data[pos + 3]]);
+        (bytes.wrapping_mul(2_654_435_761) >> (32 - self.params.hash_log)) as usize
+    }
+
+    fn insert(&mut self, pos: usize) {
+        if pos + MIN_MATCH > self.data.len() {
+            return;
+        }
+        let hash = self.hash(pos);
+        if !self.prev.is_empty() {
+            let slot = pos & (self.prev.len() - 1);
+            self.prev[slot] = self.head[hash];
+        }
+        self.head[hash] = pos as u32;
+        self.next = pos + 1;
+    }
+
+    /// Remembers the positions up to `pos`, or just jumps past them when
+    /// only tried positions are remembered.
+    fn insert_to(&mut self, pos: usize) {
+        if self.params.fast {
+            self.next = self.next.max(pos);
+        }
+        while self.next < pos {
+            let next = self.next;
+            self.insert(next);
+            self.next = next + 1;
+        }
+    }
+
+    /// The best earlier match at `pos` that ends by `end`, as its length and
+    /// offset, remembering `pos` as well.
+    ///
+    /// A match at the offset `repeat` of the one before is tried first and
+    /// counted as nearly free, as formats that repeat offsets encode it so.
+    fn find(&mut self, pos: usize, end: usize, repeat: usize) -> (usize, usize) {
+        self.insert_to(pos);
+        let data = self.data;
+        let (mut best_len, mut best_offset, mut best_gain) = (MIN_MATCH - 1, 0, 0);
+        let max_len = end - pos;
+        let window = 1usize << self.params.window_log;
+        if repeat > 0 && repeat <= pos && data[pos - repeat..pos - repeat + MIN_MATCH] == data[pos..pos + MIN_MATCH] {
+            best_len = common_len(&data[pos - repeat..], &data[pos..pos + max_len]);
+            best_offset = repeat;
+            best_gain = gain(best_len, 1);
+        }
+        let mut candidate = self.head[self.hash(pos)];
+        let mut tries = self.params.chain;
+        while candidate != NONE && tries > 0 && best_len < max_len && best_len < self.params.nice {
+            let from = candidate as usize;
+            if from >= pos || pos - from >= window {
+                break;
+            }
+            let quick = data[from + best_len] == data[pos + best_len];
+            if quick && data[from..from + MIN_MATCH] == data[pos..pos + MIN_MATCH] {
+                let len = common_len(&data[from..], &data[pos..pos + max_len]);
+                if len > best_len && (best_offset == 0 || gain(len, pos - from) > best_gain) {
+                    best_len = len;
+                    best_offset = pos - from;
+                    best_gain = gain(len, best_offset);
+                }
+            }
+            if self.prev.is_empty() {
+                break;
+            }
+            let older = self.prev[from & (self.prev.len() - 1)];
+            if older >= candidate {
+                break;
+            }
+            candidate = older;
+            tries -= 1;
+        }
+        if self.next <= pos {
+            self.insert(pos);
+        }
+        if best_offset == 0 {
+            (0, 0)
+        } else {
+            (best_len, best_offset)
+        }
+    }
+
+    /// Parses `data[from..to]` into sequences, and returns them with the
+    /// number of literals left at the end.
+    ///
+    /// Matches start before `start_limit` and end by `end_limit`, which are
+    /// at most `to`, so formats can keep their last bytes as literals.
+    pub(crate) fn parse(
+        &mut self,
+        from: usize,
+        to: usize,
+        start_limit: usize,
+        end_limit: usize,
+    ) -> (Vec<Sequence>, usize) {
+        let mut sequences = Vec::new();
+        let (mut pos, mut anchor) = (from, from);
+        let (mut misses, mut repeat) = (0, 0);
+        let start_limit = start_limit.min(end_limit.saturating_sub(MIN_MATCH - 1));
+        while pos < start_limit {
+            let (mut len, mut offset) = self.find(pos, end_limit, repeat);
+            if len == 0 {
+                misses += 1;
+                pos += if self.params.fast { 1 + (misses >> 5) } else { 1 };
+                continue;
+            }
+            misses = 0;
+            while self.params.lazy && pos + 1 < start_limit && len < self.params.nice {
+                let (next_len, next_offset) = self.find(pos + 1, end_limit, repeat);
+                // Waiting costs a literal, so the next match has to be worth more
+                let cost = |len, offset| if offset == repeat { gain(len, 1) } else { gain(len, offset) };
+                if next_len == 0 || cost(next_len, next_offset) <= cost(len, offset) + 4 {
+                    break;
+                }
+                pos += 1;
+                len = next_len;
+                offset = next_offset;
+            }
+            while pos > anchor && pos > offset && self.data[pos - 1] == self.data[pos - 1 - offset] {
+                pos -= 1;
+                len += 1;
+            }
+            sequences.push(Sequence { literals: pos - anchor, offset, len });
+            repeat = offset;
+            pos += len;
+            anchor = pos;
+            self.insert_to(pos.min(start_limit));
+        }
+        (sequences, to - anchor)
+    }
+}
+
+/// Roughly what a match saves, counting a byte as four and the bits of its
+/// offset as one each, so a longer match further back can lose to one close by.
+fn gain(len: usize, offset: usize) -> isize {
+    4 * len as isize - (usize::BITS - offset.leading_zeros()) as isize
+}
+
+/// How many bytes `a` and `b` have in common at their start, up to the
+/// length of `b`.
+fn common_len(a: &[u8], b: &[u8]) -> usize {
+    let word = |bytes: &[u8]| {
+        let mut word = [0; 8];
+        word.copy_from_slice(&bytes[..8]);
+        u64::from_le_bytes(word)
+    };
+    let mut len = 0;
+    while len + 8 <= b.len() {
+        let (x, y) = (word(&a[len..]), word(&b[len..]));
+        if x != y {
+            return len + ((x ^ y).trailing_zeros() / 8) as usize;
+        }
+        len += 8;
+    }
+    while len < b.len() && a[len] == b[len] {
+        len += 1;
+    }
+    len
+}
+
+/// Appends `len` bytes copied from `offset` bytes before the end of `out`,
+/// which may overlap what they append.
+pub(crate) fn copy_match(out: &mut Vec<u8>, offset: usize, len: usize) {
+    let start = out.len() - offset;
+    if offset >= len {
+        out.extend_from_within(start..start + len);
+    } else {
+        out.reserve(len);
+        for i in start..start + len {
+            let byte = out[i];
+            out.push(byte);
+        }
+    }
+}
@@ -0,0 +1,300 @@
+// Copyright 2020 Jared Forth.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! The LZ4 frame format.
+//!
+//! Compression writes independent 4 MiB blocks with a checksum of the
+//! content. Decompression also reads linked blocks, block checksums and the
+//! legacy format of `lz4 -l`.
+
+use std::io::{self, Read, Write};
+
+use crate::cmp::fill;
+use crate::compress::{read_u32, read_u32_or_end, read_vec};
+use crate::lz::{copy_match, Matcher, Params, MIN_MATCH};
+use crate::xxhash::{xxh32, Xxh32};
+
+pub(crate) const MAGIC: u32 = 0x184d_2204;
+pub(crate) const LEGACY_MAGIC: u32 = 0x184c_2102;
+pub(crate) const DEFAULT_LEVEL: u32 = 1;
+pub(crate) const MAX_LEVEL: u32 = 12;
+
+const BLOCK_SIZE: usize = 4 << 20;
+const LEGACY_BLOCK_SIZE: usize = 8 << 20;
+/// How far back linked blocks may reach.
+const HISTORY: usize = 64 << 10;
+/// The last match starts at least this far from the end of a block.
+const MATCH_LIMIT: usize = 12;
+/// The last bytes of a block are always literals.
+const LAST_LITERALS: usize = 5;
+
+fn corrupt(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("corrupt lz4 frame: {}", reason))
+}
+
+fn is_skippable(magic: u32) -> bool {
+    magic & 0xffff_fff0 == 0x184d_2a50
+}
+
+/// How hard each level looks for matches.
+fn params(level: u32) -> Params {
+    let level = level.clamp(1, MAX_LEVEL) as usize;
+    const CHAIN: [usize; 12] = [1, 1, 2, 4, 8, 16, 32, 64, 128, 256, 512, 1024];
+    Params {
+        window_log: 16,
+        hash_log: if level == 1 { 16 } else { 17 },
+        chain: CHAIN[level - 1],
+        nice: if level < 10 { 64 << (level / 3) } else { 4096 },
+        lazy: level >= 4,
+        fast: level == 1,
+    }
+}
+
+fn write_len(out: &mut Vec<u8>, mut len: usize) {
+    while len >= 255 {
+        out.push(255);
+        len -= 255;
+    }
+    out.push(len as u8);
+}
+
+fn write_sequence(out: &mut Vec<u8>, literals: &[u8], found: Option<(usize, usize)>) {
+    let match_len = found.map_or(0, |(_, len)| len - MIN_MATCH);
+    out.push((literals.len().min(15) << 4 | match_len.min(15)) as u8);
+    if literals.len() >= 15 {
+        write_len(out, literals.len() - 15);
+    }
+    out.extend_from_slice(literals);
+    if let Some((offset, _)) = found {
+        out.extend_from_slice(&(offset as u16).to_le_bytes());
+        if match_len >= 15 {
+            write_len(out, match_len - 15);
+        }
+    }
+}
+
+fn compress_block(data: &[u8], params: Params) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() / 2);
+    let mut at = 0;
+    if data.len() > MATCH_LIMIT {
+        let end = data.len();
+        let (sequences, _) = Matcher::new(data, params).parse(0, end, end - MATCH_LIMIT, end - LAST_LITERALS);
+        for sequence in sequences {
+            let literals = &data[at..at + sequence.literals];
+            write_sequence(&mut out, literals, Some((sequence.offset, sequence.len)));
+            at += sequence.literals + sequence.len;
+        }
+    }
+    write_sequence(&mut out, &data[at..], None);
+    out
+}
+
+/// Compresses everything from `input` into `output` as an LZ4 frame at
+/// `level`, from `1` to `12`.
+pub(crate) fn compress<R: Read, W: Write>(mut input: R, output: &mut W, level: u32) -> io::Result<()> {
+    let params = params(level);
+    let descriptor = [0x64, 0x70];
+    output.write_all(&MAGIC.to_le_bytes())?;
+    output.write_all(&descriptor)?;
+    output.write_all(&[(xxh32(&descriptor) >> 8) as u8])?;
+
+    let mut checksum = Xxh32::new();
+    let mut block = vec![0; BLOCK_SIZE];
+    loop {
+        let n = fill(&mut input, &mut block)?;
+        if n == 0 {
+            break;
+        }
+        checksum.update(&block[..n]);
+        let compressed = compress_block(&block[..n], params);
+        if compressed.len() < n {
+            output.write_all(&(compressed.len() as u32).to_le_bytes())?;
+            output.write_all(&compressed)?;
+        } else {
+            output.write_all(&(n as u32 | 1 << 31).to_le_bytes())?;
+            output.write_all(&block[..n])?;
+        }
+        if n < BLOCK_SIZE {
+            break;
+        }
+    }
+    output.write_all(&0u32.to_le_bytes())?;
+    output.write_all(&checksum.finish().to_le_bytes())
+}
+
+/// Decodes a block onto the end of `out`, whose earlier contents matches
+/// may reach back into, producing at most `limit` bytes.
+fn decode_block(block: &[u8], out: &mut Vec<u8>, limit: usize) -> io::Result<()> {
+    let start = out.len();
+    let truncated = || corrupt("truncated block");
+    let mut at = 0;
+    let read_len = |at: &mut usize, mut len: usize| -> io::Result<usize> {
+        loop {
+            let byte = *block.get(*at).ok_or_else(truncated)?;
+            *at += 1;
+            len += usize::from(byte);
+            if byte != 255 {
+                return Ok(len);
+            }
+        }
+    };
+    loop {
+        let token = *block.get(at).ok_or_else(truncated)?;
+        at += 1;
+        let mut literals = usize::from(token >> 4);
+        if literals == 15 {
+            literals = read_len(&mut at, literals)?;
+        }
+        let run = block.get(at..at + literals).ok_or_else(truncated)?;
+        if out.len() - start + literals > limit {
+            return Err(corrupt("block too large"));
+        }
+        out.extend_from_slice(run);
+        at += literals;
+        if at == block.len() {
+            return Ok(());
+        }
+        let offset = block.get(at..at + 2).ok_or_else(truncated)?;
+        let offset = usize::from(u16::from_le_bytes([offset[0], offset[1]]));
+        at += 2;
+        let mut len = usize::from(token & 15);
+        if len == 15 {
+            len = read_len(&mut at, len)?;
+        }
+        len += MIN_MATCH;
+        if offset == 0 || offset > out.len() {
+            return Err(corrupt("match starts before the data"));
+        }
+        if out.len() - start + len > limit {
+            return Err(corrupt("block too large"));
+        }
+        copy_match(out, offset, len);
+    }
+}
+
+fn read_bytes<R: Read>(input: &mut R, count: usize) -> io::Result<Vec<u8>> {
+    read_vec(input, count, "lz4")
+}
+
+/// Decompresses one frame after its magic number, and returns its size.
+fn decompress_frame<R: Read, W: Write>(input: &mut R, output: &mut W) -> io::Result<u64> {
+    let mut descriptor = read_bytes(input, 2)?;
+    let (flags, block_max) = (descriptor[0], descriptor[1]);
+    if flags >> 6 != 1 || flags & 2 != 0 || block_max & 0x8f != 0 || block_max >> 4 < 4 {
+        return Err(corrupt("bad frame descriptor"));
+    }
+    let linked = flags & 0x20 == 0;
+    let block_checksums = flags & 0x10 != 0;
+    let content_checksum = flags & 4 != 0;
+    let limit = 1 << (8 + 2 * (block_max >> 4));
+    let mut content_size = None;
+    if flags & 8 != 0 {
+        let bytes = read_bytes(input, 8)?;
+        descriptor.extend_from_slice(&bytes);
+        content_size = Some(bytes.iter().rev().fold(0u64, |n, &b| n << 8 | u64::from(b)));
+    }
+    if flags & 1 != 0 {
+        descriptor.extend_from_slice(&read_bytes(input, 4)?);
+    }
+    if read_bytes(input, 1)?[0] != (xxh32(&descriptor) >> 8) as u8 {
+        return Err(corrupt("frame descriptor checksum mismatch"));
+    }
+    if flags & 1 != 0 {
+        return Err(io::Error::new(io::ErrorKind::Unsupported, "lz4 dictionaries are not supported"));
+    }
+
+    let mut checksum = Xxh32::new();
+    let mut total = 0u64;
+    let mut out = Vec::new();
+    loop {
+        let size = read_u32(input, "lz4")?;
+        if size == 0 {
+            break;
+        }
+        let len = (size & !(1 << 31)) as usize;
+        if len > limit {
+            return Err(corrupt("block too large"));
+        }
+        let block = read_bytes(input, len)?;
+        if block_checksums && read_u32(input, "lz4")? != xxh32(&block) {
+            return Err(corrupt("block checksum mismatch"));
+        }
+        if !linked {
+            out.clear();
+        } else if out.len() > HISTORY {
+            out.drain(..out.len() - HISTORY);
+        }
+        let start = out.len();
+        if size & 1 << 31 != 0 {
+            out.extend_from_slice(&block);
+        } else {
+            decode_block(&block, &mut out, limit)?;
+        }
+        output.write_all(&out[start..])?;
+        checksum.update(&out[start..]);
+        total += (out.len() - start) as u64;
+    }
+    if content_checksum && read_u32(input, "lz4")? != checksum.finish() {
+        return Err(corrupt("checksum mismatch"));
+    }
+    if content_size.is_some_and(|size| size != total) {
+        return Err(corrupt("content size does not match"));
+    }
+    Ok(total)
+}
+
+/// Decompresses legacy blocks until the input ends, and returns their size
+/// with the magic number of the frame after them, if any.
+fn decompress_legacy<R: Read, W: Write>(input: &mut R, output: &mut W) -> io::Result<(u64, Option<u32>)> {
+    let mut total = 0;
+    let mut out = Vec::new();
+    loop {
+        let size = match read_u32_or_end(input)? {
+            None => return Ok((total, None)),
+            Some(size) if size == MAGIC || size == LEGACY_MAGIC || is_skippable(size) => {
+                return Ok((total, Some(size)))
+            }
+            Some(size) => size as usize,
+        };
+        if size > LEGACY_BLOCK_SIZE + LEGACY_BLOCK_SIZE / 255 + 16 {
+            return Err(corrupt("block too large"));
+        }
+        let block = read_bytes(input, size)?;
+        out.clear();
+        decode_block(&block, &mut out, LEGACY_BLOCK_SIZE)?;
+        output.write_all(&out)?;
+        total += out.len() as u64;
+    }
+}
+
+/// Decompresses every frame from `input` into `output`, given the magic
+/// number of the first, and returns how many bytes they held.
+pub(crate) fn decompress<R: Read, W: Write>(magic: u32, mut input: R, output: &mut W) -> io::Result<u64> {
+    let mut next = Some(magic);
+    let mut total = 0;
+    while let Some(magic) = next {
+        if magic == LEGACY_MAGIC {
+            let (size, after) = decompress_legacy(&mut input, output)?;
+            total += size;
+            next = after;
+            continue;
+        }
+        if is_skippable(magic) {
+            let size = u64::from(read_u32(&mut input, "lz4")?);
+            if io::copy(&mut input.by_ref().take(size), &mut io::sink())? != size {
+                return Err(corrupt("truncated skippable frame"));
+            }
+        } else if magic == MAGIC {
+            total += decompress_frame(&mut input, output)?;
+        } else {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not an lz4 frame"));
+        }
+        next = read_u32_or_end(&mut input)?;
+    }
+    Ok(total)
+}
@@ -0,0 +1,820 @@
+// Copyright 2020 Jared Forth.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! File metadata and attributes.
+
+use std::fs::{self, File, FileTimes, FileType, Metadata, OpenOptions, Permissions};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+#[cfg(not(windows))]
+use crate::error::FsError;
+use crate::error::{IoResultExt, Result};
+use crate::op::Op;
+
+/// The kind of an entry in the filesystem, as reported by `fsutils::stat`
+/// and `fsutils::ls_with`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum FileKind {
+    /// A regular file.
+    File,
+    /// A directory.
+    Dir,
+    /// A symbolic link that is not followed, or whose target does not exist.
+    Symlink,
+    /// Anything else, such as a device, socket or named pipe.
+    Other,
+}
+
+impl FileKind {
+    pub(crate) fn of(meta: &Metadata) -> FileKind {
+        FileKind::of_type(meta.file_type())
+    }
+
+    pub(crate) fn of_type(file_type: FileType) -> FileKind {
+        if file_type.is_file() {
+            FileKind::File
+        } else if file_type.is_dir() {
+            FileKind::Dir
+        } else if file_type.is_symlink() {
+            FileKind::Symlink
+        } else {
+            FileKind::Other
+        }
+    }
+}
+
+/// Everything `fsutils::stat` knows about a path.
+///
+/// Symbolic links are followed, so this describes the file a link points to,
+/// and `is_symlink` tells whether the path itself is a link. Fields that the
+/// platform or filesystem does not provide are `None`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Stat {
+    /// Size in bytes.
+    pub size: u64,
+    /// What kind of entry this is.
+    pub kind: FileKind,
+    /// The permissions, as std reports them.
+    pub permissions: Permissions,
+    /// The permission bits and file type as `st_mode`, on Unix.
+    pub mode: Option<u32>,
+    /// The owning user's id, on Unix.
+    pub uid: Option<u32>,
+    /// The owning group's id, on Unix.
+    pub gid: Option<u32>,
+    /// When the file was created, where the filesystem records it.
+    pub created: Option<SystemTime>,
+    /// When the contents were last changed.
+    pub modified: Option<SystemTime>,
+    /// When the file was last read.
+    pub accessed: Option<SystemTime>,
+    /// Whether the path is a symbolic link.
+    pub is_symlink: bool,
+}
+
+impl Stat {
+    fn new(meta: &Metadata, is_symlink: bool) -> Stat {
+        #[cfg(unix)]
+        let (mode, uid, gid) = {
+            use std::os::unix::fs::MetadataExt;
+            (Some(meta.mode()), Some(meta.uid()), Some(meta.gid()))
+        };
+        #[cfg(not(unix))]
+        let (mode, uid, gid) = (None, None, None);
+        Stat {
+            size: meta.len(),
+            kind: FileKind::of(meta),
+            permissions: meta.permissions(),
+            mode,
+            uid,
+            gid,
+            created: meta.created().ok(),
+            modified: meta.modified().ok(),
+            accessed: meta.accessed().ok(),
+            is_symlink,
+        }
+    }
+}
+
+/// Returns the metadata of a path
+/// or `None` if it does not exist or cannot be read.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::FileKind;
+///
+/// fsutils::write_file("stat.txt", "12345");
+///
+/// let stat = fsutils::stat("stat.txt").unwrap();
+/// assert_eq!(stat.size, 5);
+/// assert_eq!(stat.kind, FileKind::File);
+///
+/// # // Cleanup
+/// # fsutils::rm("stat.txt");
+/// ```
+pub fn stat<P: AsRef<Path>>(path: P) -> Option<Stat> {
+    match try_stat(path) {
+        Ok(stat) => Some(stat),
+        Err(e) => {
+            error!("Cannot read metadata {}", e);
+            None
+        }
+    }
+}
+
+/// Returns the size, kind, permissions, owner, times and symlink status of a
+/// path in one struct.
+///
+/// A symbolic link is described by its target, unless the target does not
+/// exist, in which case the link itself is described with the kind
+/// `FileKind::Symlink`.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::FileKind;
+///
+/// fsutils::mkdir("try_stat_dir");
+///
+/// let stat = fsutils::try_stat("try_stat_dir").unwrap();
+/// assert_eq!(stat.kind, FileKind::Dir);
+/// assert!(!stat.is_symlink);
+/// assert!(stat.modified.is_some());
+/// # #[cfg(unix)]
+/// assert_eq!(stat.mode.unwrap() & 0o170000, 0o040000);
+/// assert!(fsutils::try_stat("a_very_1234_unlikely_9876_filename").is_err());
+///
+/// # // Cleanup
+/// # fsutils::rmdir("try_stat_dir");
+/// ```
+pub fn try_stat<P: AsRef<Path>>(path: P) -> Result<Stat> {
+    let path = path.as_ref();
+    let link = fs::symlink_metadata(path).at(path)?;
+    if !link.file_type().is_symlink() {
+        return Ok(Stat::new(&link, false));
+    }
+    match fs::metadata(path) {
+        Ok(meta) => Ok(Stat::new(&meta, true)),
+        Err(_) => Ok(Stat::new(&link, true)),
+    }
+}
+
+/// Returns when a file was created, or `None` if that is not known.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("created.txt", "new");
+///
+/// if let Some(time) = fsutils::created("created.txt") {
+///     assert!(time <= std::time::SystemTime::now());
+/// }
+///
+/// # // Cleanup
+/// # fsutils::rm("created.txt");
+/// ```
+pub fn created<P: AsRef<Path>>(path: P) -> Option<SystemTime> {
+    match try_created(path) {
+        Ok(time) => Some(time),
+        Err(e) => {
+            error!("Cannot read creation time {}", e);
+            None
+        }
+    }
+}
+
+/// Returns when a file was created.
+///
+/// Not every platform and filesystem records this; where it is missing the
+/// error is of kind `io::ErrorKind::Unsupported`. Symbolic links are followed.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("try_created.txt", "new");
+///
+/// if let Ok(time) = fsutils::try_created("try_created.txt") {
+///     assert!(time.elapsed().is_ok());
+/// }
+/// assert!(fsutils::try_created("a_very_1234_unlikely_9876_filename").is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm("try_created.txt");
+/// ```
+pub fn try_created<P: AsRef<Path>>(path: P) -> Result<SystemTime> {
+    let path = path.as_ref();
+    fs::metadata(path).at(path)?.created().at(path)
+}
+
+/// Returns when the contents of a file were last changed, or `None` if it
+/// cannot be read.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("modified.txt", "text");
+///
+/// assert!(fsutils::modified("modified.txt").is_some());
+///
+/// # // Cleanup
+/// # fsutils::rm("modified.txt");
+/// ```
+pub fn modified<P: AsRef<Path>>(path: P) -> Option<SystemTime> {
+    match try_modified(path) {
+        Ok(time) => Some(time),
+        Err(e) => {
+            error!("Cannot read modification time {}", e);
+            None
+        }
+    }
+}
+
+/// Returns when the contents of a file were last changed. Symbolic links are
+/// followed.
+///
+/// ## Usage:
+///
+/// ```
+/// use std::time::{Duration, UNIX_EPOCH};
+///
+/// let then = UNIX_EPOCH + Duration::from_secs(1_234_567_890);
+/// fsutils::touch_at("try_modified.txt", then, then);
+///
+/// assert_eq!(fsutils::try_modified("try_modified.txt").unwrap(), then);
+/// assert!(fsutils::try_modified("a_very_1234_unlikely_9876_filename").is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm("try_modified.txt");
+/// ```
+pub fn try_modified<P: AsRef<Path>>(path: P) -> Result<SystemTime> {
+    let path = path.as_ref();
+    fs::metadata(path).at(path)?.modified().at(path)
+}
+
+/// Returns how long ago a file was last modified, or `None` if it cannot be
+/// read.
+///
+/// ## Usage:
+///
+/// ```
+/// use std::time::Duration;
+///
+/// fsutils::write_file("age.txt", "fresh");
+///
+/// assert!(fsutils::age("age.txt").unwrap() < Duration::from_secs(60));
+///
+/// # // Cleanup
+/// # fsutils::rm("age.txt");
+/// ```
+pub fn age<P: AsRef<Path>>(path: P) -> Option<Duration> {
+    match try_age(path) {
+        Ok(age) => Some(age),
+        Err(e) => {
+            error!("Cannot read modification time {}", e);
+            None
+        }
+    }
+}
+
+/// Returns how long ago a file was last modified.
+///
+/// A modification time in the future, as left by a clock that was wrong or
+/// a file from another machine, gives an age of zero rather than an error.
+///
+/// ## Usage:
+///
+/// ```
+/// use std::time::{Duration, SystemTime};
+///
+/// let hour_ago = SystemTime::now() - Duration::from_secs(60 * 60);
+/// fsutils::touch_at("try_age.txt", hour_ago, hour_ago);
+///
+/// assert!(fsutils::try_age("try_age.txt").unwrap() >= Duration::from_secs(60 * 60));
+/// assert!(fsutils::try_age("a_very_1234_unlikely_9876_filename").is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm("try_age.txt");
+/// ```
+pub fn try_age<P: AsRef<Path>>(path: P) -> Result<Duration> {
+    let modified = try_modified(path)?;
+    Ok(SystemTime::now().duration_since(modified).unwrap_or_default())
+}
+
+/// Returns whether a file was modified after `time`, or `false` if it cannot
+/// be read.
+///
+/// ## Usage:
+///
+/// ```
+/// use std::time::{Duration, SystemTime};
+///
+/// let started = SystemTime::now() - Duration::from_secs(60);
+/// fsutils::write_file("modified_since.txt", "output");
+///
+/// assert!(fsutils::modified_since("modified_since.txt", started));
+///
+/// # // Cleanup
+/// # fsutils::rm("modified_since.txt");
+/// ```
+pub fn modified_since<P: AsRef<Path>>(path: P, time: SystemTime) -> bool {
+    match try_modified_since(path, time) {
+        Ok(since) => since,
+        Err(e) => {
+            error!("Cannot read modification time {}", e);
+            false
+        }
+    }
+}
+
+/// Returns whether a file was modified after `time`, as a build tool checks
+/// whether an output is out of date.
+///
+/// ## Usage:
+///
+/// ```
+/// use std::time::{Duration, UNIX_EPOCH};
+///
+/// let old = UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+/// fsutils::touch_at("try_modified_since.txt", old, old);
+///
+/// assert!(!fsutils::try_modified_since("try_modified_since.txt", old).unwrap());
+/// assert!(fsutils::try_modified_since("try_modified_since.txt", old - Duration::from_secs(1)).unwrap());
+/// assert!(fsutils::try_modified_since("a_very_1234_unlikely_9876_filename", old).is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm("try_modified_since.txt");
+/// ```
+pub fn try_modified_since<P: AsRef<Path>>(path: P, time: SystemTime) -> Result<bool> {
+    Ok(try_modified(path)? > time)
+}
+
+/// Returns whether two paths refer to the same file, or `false` if either
+/// cannot be read.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::mkdir("is_same_file_dir");
+/// fsutils::write_file("is_same_file_dir/a.txt", "a");
+///
+/// assert!(fsutils::is_same_file("is_same_file_dir/a.txt", "is_same_file_dir/../is_same_file_dir/a.txt"));
+///
+/// # // Cleanup
+/// # fsutils::rm_r("is_same_file_dir");
+/// ```
+pub fn is_same_file<P: AsRef<Path>, Q: AsRef<Path>>(a: P, b: Q) -> bool {
+    match try_is_same_file(a, b) {
+        Ok(same) => same,
+        Err(e) => {
+            error!("Cannot compare files {}", e);
+            false
+        }
+    }
+}
+
+/// Returns whether two paths refer to the same file, such as through a hard
+/// link, a symbolic link or `..`, rather than two files with the same
+/// contents.
+///
+/// Files are identified by device and inode number on Unix, and by volume
+/// serial number and file index on Windows. Checking this before copying or
+/// moving one path onto another avoids truncating the source.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("try_is_same_file_a.txt", "same");
+/// fsutils::write_file("try_is_same_file_b.txt", "same");
+/// std::fs::hard_link("try_is_same_file_a.txt", "try_is_same_file_c.txt").unwrap();
+///
+/// assert!(!fsutils::try_is_same_file("try_is_same_file_a.txt", "try_is_same_file_b.txt").unwrap());
+/// assert!(fsutils::try_is_same_file("try_is_same_file_a.txt", "try_is_same_file_c.txt").unwrap());
+/// assert!(fsutils::try_is_same_file("try_is_same_file_a.txt", "a_very_1234_unlikely_9876_filename").is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm("try_is_same_file_a.txt");
+/// # fsutils::rm("try_is_same_file_b.txt");
+/// # fsutils::rm("try_is_same_file_c.txt");
+/// ```
+pub fn try_is_same_file<P: AsRef<Path>, Q: AsRef<Path>>(a: P, b: Q) -> Result<bool> {
+    let (a, b) = (a.as_ref(), b.as_ref());
+    Ok(file_id(a).at(a)? == file_id(b).at(b)?)
+}
+
+/// The device and file number of the file at `path`, following symbolic links.
+#[cfg(unix)]
+pub(crate) fn file_id(path: &Path) -> io::Result<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    let meta = fs::metadata(path)?;
+    Ok((meta.dev(), meta.ino()))
+}
+
+/// The volume serial number and file index of the file at `path`, following
+/// symbolic links.
+#[cfg(windows)]
+pub(crate) fn file_id(path: &Path) -> io::Result<(u64, u64)> {
+    let info = file_information(path)?;
+    Ok((u64::from(info.volume_serial_number), u64::from(info.index_high) << 32 | u64::from(info.index_low)))
+}
+
+/// How many hard links the file at `path` has, following symbolic links.
+#[cfg(unix)]
+pub(crate) fn link_count(path: &Path) -> io::Result<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Ok(fs::metadata(path)?.nlink())
+}
+
+/// How many hard links the file at `path` has, following symbolic links.
+#[cfg(windows)]
+pub(crate) fn link_count(path: &Path) -> io::Result<u64> {
+    Ok(u64::from(file_information(path)?.links))
+}
+
+#[cfg(windows)]
+#[repr(C)]
+struct ByHandleFileInformation {
+    attributes: u32,
+    times: [u32; 6],
+    volume_serial_number: u32,
+    size: [u32; 2],
+    links: u32,
+    index_high: u32,
+    index_low: u32,
+}
+
+#[cfg(windows)]
+fn file_information(path: &Path) -> io::Result<ByHandleFileInformation> {
+    use std::os::windows::fs::OpenOptionsExt;
+    use std::os::windows::io::AsRawHandle;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetFileInformationByHandle(file: *mut std::ffi::c_void, info: *mut ByHandleFileInformation) -> i32;
+    }
+
+    const FILE_FLAG_BACKUP_SEMANTICS: u32 = 0x0200_0000;
+    let file = OpenOptions::new().access_mode(0).custom_flags(FILE_FLAG_BACKUP_SEMANTICS).open(path)?;
+    let mut info = std::mem::MaybeUninit::<ByHandleFileInformation>::uninit();
+    // SAFETY: the handle stays open for the duration of the call, and `info`
+    // is large enough for the structure it fills in
+    if unsafe { GetFileInformationByHandle(file.as_raw_handle(), info.as_mut_ptr()) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: the call succeeded, so it filled in every field
+    Ok(unsafe { info.assume_init() })
+}
+
+/// Creates a file if it does not exist, or else sets its modification and
+/// access times to now, and returns a boolean based on success or failure.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("touch.txt", "kept");
+///
+/// assert!(fsutils::touch("touch.txt"));
+/// assert_eq!(fsutils::read_file("touch.txt"), "kept");
+///
+/// # // Cleanup
+/// # fsutils::rm("touch.txt");
+/// ```
+pub fn touch<P: AsRef<Path>>(path: P) -> bool {
+    match try_touch(path) {
+        Ok(_) => true,
+        Err(e) => {
+            error!("Cannot touch file {}", e);
+            false
+        }
+    }
+}
+
+/// Creates a file if it does not exist, or else sets its modification and
+/// access times to now, like `touch`.
+///
+/// Unlike `fsutils::create_file`, the contents of an existing file are left
+/// alone. Directories can be touched as well, and symbolic links are followed.
+///
+/// ## Usage:
+///
+/// ```
+/// assert!(fsutils::try_touch("try_touch.txt").is_ok());
+/// assert!(fsutils::path_exists("try_touch.txt"));
+/// assert!(fsutils::try_touch("a_very_1234_unlikely_9876_dirname/file").is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm("try_touch.txt");
+/// ```
+pub fn try_touch<P: AsRef<Path>>(path: P) -> Result<()> {
+    let now = SystemTime::now();
+    set_times(path.as_ref(), now, now, "touch")
+}
+
+/// Sets the modification and access times of a file, creating it if it does
+/// not exist, and returns a boolean based on success or failure.
+///
+/// ## Usage:
+///
+/// ```
+/// use std::time::{Duration, UNIX_EPOCH};
+///
+/// let noon = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+/// assert!(fsutils::touch_at("touch_at.txt", noon, noon));
+///
+/// # // Cleanup
+/// # fsutils::rm("touch_at.txt");
+/// ```
+pub fn touch_at<P: AsRef<Path>>(path: P, mtime: SystemTime, atime: SystemTime) -> bool {
+    match try_touch_at(path, mtime, atime) {
+        Ok(_) => true,
+        Err(e) => {
+            error!("Cannot touch file {}", e);
+            false
+        }
+    }
+}
+
+/// Sets the modification and access times of a file, creating it if it does
+/// not exist, like `touch -d`.
+///
+/// Useful for giving build inputs or test fixtures a known age. How precisely
+/// the times are stored depends on the filesystem.
+///
+/// ## Usage:
+///
+/// ```
+/// use std::time::{Duration, UNIX_EPOCH};
+///
+/// let mtime = UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+/// let atime = UNIX_EPOCH + Duration::from_secs(1_500_000_000);
+/// fsutils::try_touch_at("try_touch_at.txt", mtime, atime).unwrap();
+///
+/// let stat = fsutils::stat("try_touch_at.txt").unwrap();
+/// assert_eq!(stat.modified, Some(mtime));
+/// assert_eq!(stat.accessed, Some(atime));
+///
+/// # // Cleanup
+/// # fsutils::rm("try_touch_at.txt");
+/// ```
+pub fn try_touch_at<P: AsRef<Path>>(path: P, mtime: SystemTime, atime: SystemTime) -> Result<()> {
+    set_times(path.as_ref(), mtime, atime, "touch_at")
+}
+
+fn set_times(path: &Path, mtime: SystemTime, atime: SystemTime, name: &'static str) -> Result<()> {
+    Op::new(name, path).run((), || {
+        let file = open_for_times(path).at(path)?;
+        file.set_times(FileTimes::new().set_modified(mtime).set_accessed(atime)).at(path)?;
+        info!("Set times of {}", path.display());
+        Ok(())
+    })
+}
+
+/// Opens a file or directory with enough access to change its times,
+/// creating a file if nothing exists at `path`.
+pub(crate) fn open_for_times(path: &Path) -> io::Result<File> {
+    if !path.is_dir() {
+        return OpenOptions::new().write(true).create(true).truncate(false).open(path);
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::OpenOptionsExt;
+        const FILE_WRITE_ATTRIBUTES: u32 = 0x100;
+        const FILE_FLAG_BACKUP_SEMANTICS: u32 = 0x0200_0000;
+        OpenOptions::new().access_mode(FILE_WRITE_ATTRIBUTES).custom_flags(FILE_FLAG_BACKUP_SEMANTICS).open(path)
+    }
+    #[cfg(not(windows))]
+    File::open(path)
+}
+
+/// Returns whether a file or directory is read-only, or `false` if it does
+/// not exist.
+///
+/// On Unix that means nobody has write permission, on Windows that the
+/// read-only attribute is set.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("is_readonly.txt", "text");
+///
+/// assert!(!fsutils::is_readonly("is_readonly.txt"));
+///
+/// # // Cleanup
+/// # fsutils::rm("is_readonly.txt");
+/// ```
+pub fn is_readonly<P: AsRef<Path>>(path: P) -> bool {
+    match fs::metadata(path) {
+        Ok(meta) => meta.permissions().readonly(),
+        Err(_) => false,
+    }
+}
+
+/// Makes a file or directory read-only or writable again
+/// and returns a boolean based on success or failure.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("set_readonly.txt", "text");
+///
+/// assert!(fsutils::set_readonly("set_readonly.txt", true));
+/// assert!(fsutils::is_readonly("set_readonly.txt"));
+///
+/// # // Cleanup
+/// # fsutils::set_readonly("set_readonly.txt", false);
+/// # fsutils::rm("set_readonly.txt");
+/// ```
+pub fn set_readonly<P: AsRef<Path>>(path: P, readonly: bool) -> bool {
+    match try_set_readonly(path, readonly) {
+        Ok(_) => true,
+        Err(e) => {
+            error!("Cannot change permissions {}", e);
+            false
+        }
+    }
+}
+
+/// Makes a file or directory read-only or writable again.
+///
+/// On Unix, making it read-only removes every write permission bit, and
+/// making it writable adds write permission for the owner only, rather than
+/// for everyone as `std::fs::Permissions::set_readonly(false)` does. On
+/// Windows this sets or clears the read-only attribute.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("try_set_readonly.txt", "text");
+///
+/// fsutils::try_set_readonly("try_set_readonly.txt", true).unwrap();
+/// fsutils::try_set_readonly("try_set_readonly.txt", false).unwrap();
+/// assert!(!fsutils::is_readonly("try_set_readonly.txt"));
+/// # #[cfg(unix)]
+/// assert_eq!(fsutils::stat("try_set_readonly.txt").unwrap().mode.unwrap() & 0o022, 0);
+///
+/// # // Cleanup
+/// # fsutils::rm("try_set_readonly.txt");
+/// ```
+pub fn try_set_readonly<P: AsRef<Path>>(path: P, readonly: bool) -> Result<()> {
+    let path = path.as_ref();
+    Op::new("set_readonly", path).run((), || {
+        let mut permissions = fs::metadata(path).at(path)?.permissions();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = permissions.mode();
+            permissions.set_mode(if readonly { mode & !0o222 } else { mode | 0o200 });
+        }
+        #[cfg(not(unix))]
+        permissions.set_readonly(readonly);
+        fs::set_permissions(path, permissions).at(path)?;
+        info!("Made {} {}", path.display(), if readonly { "read-only" } else { "writable" });
+        Ok(())
+    })
+}
+
+/// Returns whether a file or directory is hidden from normal listings.
+///
+/// On Unix that means its name starts with a dot. On Windows it means the
+/// hidden attribute is set, and a path that does not exist is not hidden.
+///
+/// ## Usage:
+///
+/// ```
+/// # #[cfg(unix)] {
+/// assert!(fsutils::is_hidden(".gitignore"));
+/// assert!(!fsutils::is_hidden("Cargo.toml"));
+/// # }
+/// ```
+pub fn is_hidden<P: AsRef<Path>>(path: P) -> bool {
+    let path = path.as_ref();
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::MetadataExt;
+        if let Ok(meta) = fs::metadata(path) {
+            return meta.file_attributes() & FILE_ATTRIBUTE_HIDDEN != 0;
+        }
+    }
+    #[cfg(not(windows))]
+    {
+        if let Some(name) = path.file_name() {
+            return name.to_string_lossy().starts_with('.');
+        }
+    }
+    false
+}
+
+/// Hides a file or directory or makes it visible again, and returns the
+/// path it has afterwards, or `None` on failure.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("set_hidden.txt", "text");
+///
+/// let hidden = fsutils::set_hidden("set_hidden.txt", true).unwrap();
+/// assert!(fsutils::is_hidden(&hidden));
+///
+/// # // Cleanup
+/// # fsutils::rm(hidden);
+/// ```
+pub fn set_hidden<P: AsRef<Path>>(path: P, hidden: bool) -> Option<PathBuf> {
+    match try_set_hidden(path, hidden) {
+        Ok(path) => Some(path),
+        Err(e) => {
+            error!("Cannot change attributes {}", e);
+            None
+        }
+    }
+}
+
+/// Hides a file or directory or makes it visible again, and returns the
+/// path it has afterwards.
+///
+/// Unix has no hidden attribute, only the convention that names starting with
+/// a dot are left out of listings, so there the file is renamed: `notes`
+/// becomes `.notes` and back. Renaming fails with an error of kind
+/// `io::ErrorKind::AlreadyExists` if the new name is taken. On Windows the
+/// hidden attribute is set or cleared and the path stays the same.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("try_set_hidden.txt", "text");
+///
+/// let hidden = fsutils::try_set_hidden("try_set_hidden.txt", true).unwrap();
+/// let visible = fsutils::try_set_hidden(&hidden, false).unwrap();
+/// assert_eq!(visible, std::path::Path::new("try_set_hidden.txt"));
+/// assert!(!fsutils::is_hidden(&visible));
+///
+/// # // Cleanup
+/// # fsutils::rm("try_set_hidden.txt");
+/// ```
+pub fn try_set_hidden<P: AsRef<Path>>(path: P, hidden: bool) -> Result<PathBuf> {
+    let path = path.as_ref();
+    #[cfg(windows)]
+    {
+        Op::new("set_hidden", path).run(path.to_path_buf(), || {
+            set_attribute(path, FILE_ATTRIBUTE_HIDDEN, hidden).at(path)?;
+            info!("Made {} {}", path.display(), if hidden { "hidden" } else { "visible" });
+            Ok(path.to_path_buf())
+        })
+    }
+    #[cfg(not(windows))]
+    {
+        let name = match path.file_name() {
+            Some(name) => name.to_string_lossy(),
+            None => return Err(FsError::invalid_path(path, "has no file name")),
+        };
+        let renamed = match (hidden, name.strip_prefix('.')) {
+            (true, None) => format!(".{}", name),
+            (false, Some(visible)) if !visible.is_empty() => visible.to_string(),
+            _ => {
+                fs::symlink_metadata(path).at(path)?;
+                return Ok(path.to_path_buf());
+            }
+        };
+        let to = path.with_file_name(renamed);
+        if to.symlink_metadata().is_ok() {
+            return Err(FsError::already_exists(&to));
+        }
+        Op::new("set_hidden", path).to(&to).run(to.clone(), || {
+            fs::rename(path, &to).at2(path, &to)?;
+            info!("Renamed {} to {}", path.display(), to.display());
+            Ok(to.clone())
+        })
+    }
+}
+
+#[cfg(windows)]
+pub(crate) const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+
+/// Sets or clears one of the attributes of a file.
+#[cfg(windows)]
+fn set_attribute(path: &Path, attribute: u32, on: bool) -> io::Result<()> {
+    use std::os::windows::ffi::OsStrExt;
+    use std::os::windows::fs::MetadataExt;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn SetFileAttributesW(name: *const u16, attributes: u32) -> i32;
+    }
+
+    let current = fs::metadata(path)?.file_attributes();
+    let attributes = if on { current | attribute } else { current & !attribute };
+    let name: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+    // SAFETY: `name` is a NUL-terminated wide string that outlives the call
+    if unsafe { SetFileAttributesW(name.as_ptr(), attributes) } == 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
@@ -0,0 +1,151 @@
+// Copyright 2020 Jared Forth.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Directory creation with explicit options.
+
+use std::fs;
+use std::path::Path;
+
+use crate::error::{FsError, IoResultExt, Result};
+use crate::op::Op;
+
+/// Options for `fsutils::mkdir_with`.
+///
+/// The defaults match `fsutils::mkdir`: parents are created, and an existing
+/// path is an error.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::MkdirOptions;
+///
+/// let mut opts = MkdirOptions::new();
+/// opts.exist_ok(true).mode(0o750);
+///
+/// assert_eq!(fsutils::mkdir_with("mkdir_with_dir/nested", &opts).unwrap(), true);
+/// assert_eq!(fsutils::mkdir_with("mkdir_with_dir/nested", &opts).unwrap(), false);
+///
+/// assert!(fsutils::mkdir_with("mkdir_with_missing/nested", MkdirOptions::new().parents(false)).is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm_r("mkdir_with_dir");
+/// ```
+#[derive(Clone, Debug)]
+pub struct MkdirOptions {
+    parents: bool,
+    exist_ok: bool,
+    mode: Option<u32>,
+    owner: Option<(Option<u32>, Option<u32>)>,
+}
+
+impl MkdirOptions {
+    /// Creates the default options.
+    pub fn new() -> MkdirOptions {
+        MkdirOptions {
+            parents: true,
+            exist_ok: false,
+            mode: None,
+            owner: None,
+        }
+    }
+
+    /// Whether missing parent directories are created, like `mkdir -p`.
+    pub fn parents(&mut self, parents: bool) -> &mut MkdirOptions {
+        self.parents = parents;
+        self
+    }
+
+    /// Whether an existing directory counts as success instead of an error.
+    pub fn exist_ok(&mut self, exist_ok: bool) -> &mut MkdirOptions {
+        self.exist_ok = exist_ok;
+        self
+    }
+
+    /// The permission bits for every created directory, subject to the umask.
+    ///
+    /// Ignored on platforms without unix permissions.
+    pub fn mode(&mut self, mode: u32) -> &mut MkdirOptions {
+        self.mode = Some(mode);
+        self
+    }
+
+    /// The user and group ids to give the final directory, `None` leaving one unchanged.
+    ///
+    /// Ignored on platforms without unix ownership.
+    pub fn owner(&mut self, uid: Option<u32>, gid: Option<u32>) -> &mut MkdirOptions {
+        self.owner = Some((uid, gid));
+        self
+    }
+}
+
+impl Default for MkdirOptions {
+    fn default() -> MkdirOptions {
+        MkdirOptions::new()
+    }
+}
+
+/// Creates a directory according to `opts`.
+///
+/// Returns `true` if the directory was created and `false` if it already
+/// existed and `exist_ok` is set. A path that exists but is not a directory is
+/// always an error.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::MkdirOptions;
+///
+/// assert!(fsutils::mkdir_with("mkdir_with_strict", &MkdirOptions::new()).unwrap());
+/// assert!(fsutils::mkdir_with("mkdir_with_strict", &MkdirOptions::new()).is_err());
+///
+/// # // Cleanup
+/// # fsutils::rmdir("mkdir_with_strict");
+/// ```
+pub fn mkdir_with<P: AsRef<Path>>(path: P, opts: &MkdirOptions) -> Result<bool> {
+    let path = path.as_ref();
+    if let Ok(meta) = path.metadata() {
+        if meta.is_dir() && opts.exist_ok {
+            return Ok(false);
+        }
+        return Err(FsError::already_exists(path));
+    }
+    Op::new("mkdir", path).run(true, || {
+        let mut builder = fs::DirBuilder::new();
+        builder.recursive(opts.parents);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::DirBuilderExt;
+            if let Some(mode) = opts.mode {
+                builder.mode(mode);
+            }
+        }
+        builder.create(path).at(path)?;
+        if let Some((uid, gid)) = opts.owner {
+            chown(path, uid, gid)?;
+        }
+        #[cfg(not(unix))]
+        {
+            if opts.mode.is_some() {
+                info!("Ignoring mode for {} on this platform", path.display());
+            }
+        }
+        info!("Created {}", path.display());
+        Ok(true)
+    })
+}
+
+#[cfg(unix)]
+fn chown(path: &Path, uid: Option<u32>, gid: Option<u32>) -> Result<()> {
+    std::os::unix::fs::chown(path, uid, gid).at(path)
+}
+
+#[cfg(not(unix))]
+fn chown(path: &Path, _uid: Option<u32>, _gid: Option<u32>) -> Result<()> {
+    info!("Ignoring owner for {} on this platform", path.display());
+    Ok(())
+}
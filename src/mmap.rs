@@ -0,0 +1,111 @@
+// Copyright 2020 Jared Forth.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Memory-mapped reads, behind the `mmap` feature.
+
+use std::fs::File;
+use std::ops::Deref;
+use std::path::Path;
+
+use crate::error::{IoResultExt, Result};
+
+/// The contents of a file mapped into memory, returned by `fsutils::try_read_mmap`.
+///
+/// Dereferences to `[u8]`. Pages are loaded from disk by the operating system
+/// as they are first touched, so nothing is read up front and nothing is
+/// copied. The mapping is removed when this is dropped.
+#[derive(Debug)]
+pub struct MmapFile {
+    map: memmap2::Mmap,
+}
+
+impl Deref for MmapFile {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.map
+    }
+}
+
+impl AsRef<[u8]> for MmapFile {
+    fn as_ref(&self) -> &[u8] {
+        &self.map
+    }
+}
+
+/// Maps a file into memory
+/// and returns `None` if it cannot be mapped.
+///
+/// Requires the `mmap` feature.
+///
+/// # Safety
+///
+/// The same as for `fsutils::try_read_mmap`.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("read_mmap.dat", "mapped");
+///
+/// let map = unsafe { fsutils::read_mmap("read_mmap.dat") }.unwrap();
+/// assert_eq!(&map[..], b"mapped");
+///
+/// # // Cleanup
+/// # drop(map);
+/// # fsutils::rm("read_mmap.dat");
+/// ```
+pub unsafe fn read_mmap<P: AsRef<Path>>(path: P) -> Option<MmapFile> {
+    match try_read_mmap(path) {
+        Ok(map) => Some(map),
+        Err(e) => {
+            error!("Cannot map file {}", e);
+            None
+        }
+    }
+}
+
+/// Maps a file into memory for reading without copying it.
+///
+/// This is the fastest way to parse large files that do not change, such as
+/// game assets or model weights, because the bytes are read straight from
+/// the page cache. For files that are read once from start to end, or that
+/// are small, `std::fs::read` is usually just as fast.
+///
+/// Requires the `mmap` feature.
+///
+/// # Safety
+///
+/// The returned bytes are the file itself, not a copy. If the file is
+/// changed while it is mapped, by this process or any other, the bytes change
+/// underneath any `&[u8]` borrowed from the map, which is undefined behaviour.
+/// If the file is truncated, touching the missing pages crashes the process
+/// with `SIGBUS` on Unix. The caller must make sure that nothing modifies or
+/// truncates the file until the `MmapFile` is dropped, for example because the
+/// file is read-only or only ever replaced by renaming a new file over it.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::create_file_bytes("try_read_mmap.bin", b"\x7fELF\x02\x01");
+///
+/// // SAFETY: the file is not modified while it is mapped
+/// let map = unsafe { fsutils::try_read_mmap("try_read_mmap.bin") }.unwrap();
+/// assert!(map.starts_with(b"\x7fELF"));
+/// assert!(unsafe { fsutils::try_read_mmap("a_very_1234_unlikely_9876_filename") }.is_err());
+///
+/// # // Cleanup
+/// # drop(map);
+/// # fsutils::rm("try_read_mmap.bin");
+/// ```
+pub unsafe fn try_read_mmap<P: AsRef<Path>>(path: P) -> Result<MmapFile> {
+    let path = path.as_ref();
+    let file = File::open(path).at(path)?;
+    // SAFETY: the caller upholds that the file is not modified while mapped
+    let map = unsafe { memmap2::Mmap::map(&file) }.at(path)?;
+    Ok(MmapFile { map })
+}
@@ -0,0 +1,399 @@
+// Copyright 2020 Jared Forth.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Moves that do more than a single rename.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::error::{FsError, IoResultExt, Result};
+use crate::meta::FileKind;
+use crate::op::Op;
+use crate::overwrite::OverwritePolicy;
+
+/// Moves the contents of the directory `src` into the directory `dst`, merging
+/// subdirectories that exist in both, and returns the number of entries moved.
+///
+/// Where a file exists in both, `policy` decides what happens to it. Skipped
+/// files stay behind in `src`; directories in `src` are removed once they
+/// are empty. `dst` is created if it does not exist.
+///
+/// A file is never put in place of a directory, or a directory in place of a
+/// file, as `mv` refuses to: unless `policy` renames or backs up what is in
+/// the way, such a clash fails with `FsError::AlreadyExists` or
+/// `FsError::NotADirectory`, and what was moved before it stays moved.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::OverwritePolicy;
+///
+/// fsutils::mkdir("mv_merge_src/images");
+/// fsutils::mkdir("mv_merge_dst/images");
+/// fsutils::write_file("mv_merge_src/images/new.png", "new");
+/// fsutils::write_file("mv_merge_src/index.html", "updated");
+/// fsutils::write_file("mv_merge_dst/images/old.png", "old");
+/// fsutils::write_file("mv_merge_dst/index.html", "original");
+///
+/// assert_eq!(fsutils::mv_merge("mv_merge_src", "mv_merge_dst", &OverwritePolicy::Skip).unwrap(), 1);
+/// assert!(fsutils::path_exists("mv_merge_dst/images/new.png"));
+/// assert!(fsutils::path_exists("mv_merge_dst/images/old.png"));
+/// assert_eq!(fsutils::read_file("mv_merge_dst/index.html"), "original");
+/// assert!(fsutils::path_exists("mv_merge_src/index.html"));
+///
+/// assert_eq!(fsutils::mv_merge("mv_merge_src", "mv_merge_dst", &OverwritePolicy::Overwrite).unwrap(), 1);
+/// assert_eq!(fsutils::read_file("mv_merge_dst/index.html"), "updated");
+/// assert!(!fsutils::path_exists("mv_merge_src"));
+///
+/// # // Cleanup
+/// # fsutils::rm_r("mv_merge_dst");
+/// ```
+///
+/// Overwriting does not let a file replace a whole directory:
+///
+/// ```
+/// use fsutils::{FsError, OverwritePolicy};
+///
+/// fsutils::mkdir("mv_merge_clash_src");
+/// fsutils::mkdir("mv_merge_clash_dst/logs");
+/// fsutils::write_file("mv_merge_clash_src/logs", "a file");
+/// fsutils::write_file("mv_merge_clash_dst/logs/today.log", "kept");
+///
+/// let result = fsutils::mv_merge("mv_merge_clash_src", "mv_merge_clash_dst", &OverwritePolicy::Overwrite);
+/// assert!(matches!(result, Err(FsError::AlreadyExists { .. })));
+/// assert_eq!(fsutils::read_file("mv_merge_clash_dst/logs/today.log"), "kept");
+///
+/// # // Cleanup
+/// # fsutils::rm_r("mv_merge_clash_src");
+/// # fsutils::rm_r("mv_merge_clash_dst");
+/// ```
+pub fn mv_merge<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dst: Q, policy: &OverwritePolicy) -> Result<usize> {
+    let src = src.as_ref();
+    let dst = dst.as_ref();
+    if !src.metadata().at(src)?.is_dir() {
+        return Err(FsError::from_io(src, io::ErrorKind::NotADirectory.into()));
+    }
+    if let Ok(meta) = dst.metadata() {
+        if !meta.is_dir() {
+            return Err(FsError::from_io(dst, io::ErrorKind::NotADirectory.into()));
+        }
+    }
+    Op::new("mv_merge", src).to(dst).run(0, || {
+        fs::create_dir_all(dst).at(dst)?;
+        let moved = merge(src, dst, policy)?;
+        info!("Merged {} into {}", src.display(), dst.display());
+        Ok(moved)
+    })
+}
+
+/// Moves everything in `src` into the existing directory `dst`, then removes `src` if it is empty.
+fn merge(src: &Path, dst: &Path, policy: &OverwritePolicy) -> Result<usize> {
+    let mut moved = 0;
+    for entry in fs::read_dir(src).at(src)? {
+        let entry = entry.at(src)?;
+        let from = entry.path();
+        let to = dst.join(entry.file_name());
+        let is_dir = entry.file_type().at(&from)?.is_dir();
+        let existing = to.symlink_metadata().ok();
+
+        if is_dir && existing.as_ref().is_some_and(|meta| meta.is_dir()) {
+            moved += merge(&from, &to, policy)?;
+            continue;
+        }
+        let to = match policy.resolve(&to)? {
+            Some(to) => to,
+            None => continue,
+        };
+        policy.back_up(&to)?;
+        if let Ok(meta) = to.symlink_metadata() {
+            // Only a file can replace a file
+            let clash = if meta.is_dir() {
+                Some((io::ErrorKind::AlreadyExists, "cannot overwrite directory with non-directory"))
+            } else if is_dir {
+                Some((io::ErrorKind::NotADirectory, "cannot overwrite non-directory with directory"))
+            } else {
+                None
+            };
+            if let Some((kind, reason)) = clash {
+                return Err(FsError::from_io(&to, io::Error::new(kind, reason)));
+            }
+        }
+        rename_or_copy(&from, &to)?;
+        moved += 1;
+    }
+    if fs::read_dir(src).at(src)?.next().is_none() {
+        fs::remove_dir(src).at(src)?;
+    }
+    Ok(moved)
+}
+
+/// Moves every file below the subdirectories of `dir` up into `dir` itself,
+/// renaming on collisions, removes the emptied subdirectories and returns
+/// the number of files moved, or 0 if it fails.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::mkdir("flatten_dir/2020/01");
+/// fsutils::write_file("flatten_dir/2020/01/photo.jpg", "");
+///
+/// assert_eq!(fsutils::flatten("flatten_dir"), 1);
+/// assert!(fsutils::path_exists("flatten_dir/photo.jpg"));
+///
+/// # // Cleanup
+/// # fsutils::rm_r("flatten_dir");
+/// ```
+pub fn flatten<P: AsRef<Path>>(dir: P) -> usize {
+    match try_flatten(dir) {
+        Ok(moved) => moved,
+        Err(e) => {
+            error!("Cannot flatten directory {}", e);
+            0
+        }
+    }
+}
+
+/// Moves every file below the subdirectories of `dir` up into `dir` itself,
+/// removes the emptied subdirectories and returns the number of files moved.
+///
+/// A file whose name is taken gets the first free name of the form
+/// `name (1).ext`, `name (2).ext`, ..., as with `OverwritePolicy::Rename`.
+/// See `fsutils::flatten_with` for other policies.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::mkdir("try_flatten_dir/a");
+/// fsutils::mkdir("try_flatten_dir/b/c");
+/// fsutils::write_file("try_flatten_dir/notes.txt", "root");
+/// fsutils::write_file("try_flatten_dir/a/notes.txt", "a");
+/// fsutils::write_file("try_flatten_dir/b/c/notes.txt", "c");
+///
+/// assert_eq!(fsutils::try_flatten("try_flatten_dir").unwrap(), 2);
+/// assert_eq!(fsutils::read_file("try_flatten_dir/notes.txt"), "root");
+/// assert_eq!(fsutils::read_file("try_flatten_dir/notes (1).txt"), "a");
+/// assert_eq!(fsutils::read_file("try_flatten_dir/notes (2).txt"), "c");
+/// assert!(!fsutils::path_exists("try_flatten_dir/b"));
+/// assert!(fsutils::try_flatten("a_very_1234_unlikely_9876_dirname").is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm_r("try_flatten_dir");
+/// ```
+pub fn try_flatten<P: AsRef<Path>>(dir: P) -> Result<usize> {
+    flatten_with(dir, &OverwritePolicy::Rename)
+}
+
+/// Moves every file below the subdirectories of `dir` up into `dir` itself,
+/// with `policy` deciding what happens when a name is already taken, removes
+/// the subdirectories left empty and returns the number of files moved.
+///
+/// Files are moved in the order of `fsutils::walk`, so with
+/// `OverwritePolicy::Rename` the shallower file keeps the plain name.
+/// Symbolic links are moved as links and not followed. A file never replaces
+/// a directory; where a directory has its name, it gets a numbered name
+/// instead, whatever the policy. Files that are skipped stay where they are,
+/// along with the directories holding them; every other empty subdirectory
+/// is removed.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::OverwritePolicy;
+///
+/// fsutils::mkdir("flatten_with_dir/docs");
+/// fsutils::write_file("flatten_with_dir/README.md", "new");
+/// fsutils::write_file("flatten_with_dir/docs/README.md", "old");
+/// fsutils::write_file("flatten_with_dir/docs/guide.md", "");
+///
+/// assert_eq!(fsutils::flatten_with("flatten_with_dir", &OverwritePolicy::Skip).unwrap(), 1);
+/// assert!(fsutils::path_exists("flatten_with_dir/guide.md"));
+/// assert!(fsutils::path_exists("flatten_with_dir/docs/README.md"));
+/// assert!(fsutils::flatten_with("flatten_with_dir", &OverwritePolicy::Error).is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm_r("flatten_with_dir");
+/// ```
+pub fn flatten_with<P: AsRef<Path>>(dir: P, policy: &OverwritePolicy) -> Result<usize> {
+    let dir = dir.as_ref();
+    if !dir.metadata().at(dir)?.is_dir() {
+        return Err(FsError::from_io(dir, io::ErrorKind::NotADirectory.into()));
+    }
+    let mut files = Vec::new();
+    let mut subdirs = Vec::new();
+    for entry in crate::walk::walk(dir) {
+        let entry = entry?;
+        if entry.depth == 0 {
+            continue;
+        }
+        if entry.kind == FileKind::Dir {
+            subdirs.push(entry.path);
+        } else if entry.depth > 1 {
+            files.push(entry.path);
+        }
+    }
+    Op::new("flatten", dir).run(0, || {
+        let mut moved = 0;
+        for from in &files {
+            let to = dir.join(from.file_name().unwrap_or_default());
+            let to = if to.is_dir() && !to.is_symlink() {
+                crate::overwrite::unique_path(&to)
+            } else {
+                match policy.resolve(&to)? {
+                    Some(to) => to,
+                    None => continue,
+                }
+            };
+            policy.back_up(&to)?;
+            rename_or_copy(from, &to)?;
+            moved += 1;
+        }
+        // Deeper directories come later in walk order, so are removed first
+        for subdir in subdirs.iter().rev() {
+            if fs::read_dir(subdir).at(subdir)?.next().is_none() {
+                fs::remove_dir(subdir).at(subdir)?;
+            }
+        }
+        info!("Flattened {}, moving {} files", dir.display(), moved);
+        Ok(moved)
+    })
+}
+
+/// Renames `from` to `to`, or copies and then removes it if they are on
+/// different filesystems.
+///
+/// If the copy fails, what it wrote at `to` is removed again, so `from` is
+/// left as the only copy. A directory that was already at `to` is kept, as
+/// only some of what is in it came from `from`.
+pub(crate) fn rename_or_copy(from: &Path, to: &Path) -> Result<()> {
+    match fs::rename(from, to) {
+        Err(ref e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            info!("Copying {} to {} across filesystems.", from.display(), to.display());
+            let merging = to.symlink_metadata().map(|meta| meta.is_dir()).unwrap_or(false);
+            if let Err(e) = crate::copy::copy_for_move(from, to) {
+                if !merging {
+                    remove_partial_copy(to);
+                }
+                return Err(e);
+            }
+            if from.symlink_metadata().at(from)?.is_dir() {
+                fs::remove_dir_all(from).at(from)
+            } else {
+                fs::remove_file(from).at(from)
+            }
+        }
+        result => result.at2(from, to),
+    }
+}
+
+/// Removes the file or tree a failed copy left at `path`, if any.
+fn remove_partial_copy(path: &Path) {
+    let result = match path.symlink_metadata() {
+        Ok(meta) if meta.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => fs::remove_file(path),
+        Err(_) => return,
+    };
+    if let Err(e) = result {
+        warn!("Cannot remove partial copy {}: {}", path.display(), e);
+    }
+}
+
+/// Exchanges the files or directories at `path_a` and `path_b`
+/// and returns a boolean based on success or failure.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("swap_blue.conf", "blue");
+/// fsutils::write_file("swap_green.conf", "green");
+///
+/// assert_eq!(fsutils::swap("swap_blue.conf", "swap_green.conf"), true);
+/// assert_eq!(fsutils::read_file("swap_blue.conf"), "green");
+/// assert_eq!(fsutils::read_file("swap_green.conf"), "blue");
+///
+/// # // Cleanup
+/// # fsutils::rm("swap_blue.conf");
+/// # fsutils::rm("swap_green.conf");
+/// ```
+pub fn swap<P: AsRef<Path>, Q: AsRef<Path>>(path_a: P, path_b: Q) -> bool {
+    match try_swap(path_a, path_b) {
+        Ok(_) => true,
+        Err(e) => {
+            error!("Swapping error: {}", e);
+            false
+        }
+    }
+}
+
+/// Exchanges the files or directories at `path_a` and `path_b`.
+///
+/// On Linux (`renameat2` with `RENAME_EXCHANGE`) and macOS (`renamex_np` with
+/// `RENAME_SWAP`) the exchange is atomic, so anything opening either path sees
+/// one version or the other and never a missing file. Elsewhere, or where the
+/// filesystem does not support it, `path_a` is renamed to a temporary name
+/// first, which leaves a brief moment where `path_a` does not exist.
+///
+/// Both paths must exist and be on the same filesystem.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::mkdir("try_swap_current");
+/// fsutils::mkdir("try_swap_next");
+/// fsutils::create_file("try_swap_next/release.txt");
+///
+/// assert!(fsutils::try_swap("try_swap_current", "try_swap_next").is_ok());
+/// assert!(fsutils::path_exists("try_swap_current/release.txt"));
+/// assert!(fsutils::try_swap("try_swap_current", "try_swap_missing").is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm_r("try_swap_current");
+/// # fsutils::rm_r("try_swap_next");
+/// ```
+pub fn try_swap<P: AsRef<Path>, Q: AsRef<Path>>(path_a: P, path_b: Q) -> Result<()> {
+    let path_a = path_a.as_ref();
+    let path_b = path_b.as_ref();
+    for path in &[path_a, path_b] {
+        path.symlink_metadata().at(path)?;
+    }
+    Op::new("swap", path_a).to(path_b).run((), || {
+        match exchange(path_a, path_b) {
+            Err(ref e) if matches!(e.kind(), io::ErrorKind::Unsupported | io::ErrorKind::InvalidInput) => {
+                swap_via_temp(path_a, path_b)?
+            }
+            result => result.at2(path_a, path_b)?,
+        }
+        info!("Swapped {} and {}", path_a.display(), path_b.display());
+        Ok(())
+    })
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos", target_os = "ios"))]
+fn exchange(path_a: &Path, path_b: &Path) -> io::Result<()> {
+    crate::sys::exchange(path_a, path_b)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "ios")))]
+fn exchange(_path_a: &Path, _path_b: &Path) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "no atomic exchange on this platform"))
+}
+
+/// Swaps with three renames through a temporary name next to `path_a`.
+fn swap_via_temp(path_a: &Path, path_b: &Path) -> Result<()> {
+    let mut name = path_a.file_name().map(|n| n.to_os_string()).unwrap_or_default();
+    name.push(format!(".swap-{}", crate::temp::random_name()));
+    let temp = path_a.with_file_name(name);
+
+    fs::rename(path_a, &temp).at2(path_a, &temp)?;
+    if let Err(e) = fs::rename(path_b, path_a) {
+        // Put things back as they were
+        let _ = fs::rename(&temp, path_a);
+        return Err(FsError::from_io2(path_b, path_a, e));
+    }
+    fs::rename(&temp, path_b).at2(&temp, path_b)
+}
@@ -0,0 +1,245 @@
+// Copyright 2020 Jared Forth.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Bookkeeping shared by every operation that modifies the filesystem.
+
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+
+use crate::error::{FsError, Result};
+use crate::journal;
+
+static DRY_RUN: AtomicBool = AtomicBool::new(false);
+
+type ConfirmFn = dyn Fn(&str, &Path) -> bool + Send + Sync;
+
+static CONFIRM: RwLock<Option<Arc<ConfirmFn>>> = RwLock::new(None);
+
+/// Turns dry-run mode on or off for the whole process.
+///
+/// While dry-run mode is on, every function that would modify the filesystem
+/// logs what it would have done and reports success without touching the disk.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::dry_run(true);
+/// assert!(fsutils::mkdir("dry_run_dir"));
+/// assert!(!fsutils::path_exists("dry_run_dir"));
+///
+/// fsutils::dry_run(false);
+/// assert!(fsutils::mkdir("dry_run_dir"));
+/// assert!(fsutils::path_exists("dry_run_dir"));
+///
+/// # // Cleanup
+/// # fsutils::rmdir("dry_run_dir");
+/// ```
+pub fn dry_run(enabled: bool) {
+    DRY_RUN.store(enabled, Ordering::SeqCst);
+}
+
+/// Returns whether dry-run mode is on.
+///
+/// ## Usage:
+///
+/// ```
+/// assert_eq!(fsutils::is_dry_run(), false);
+/// ```
+pub fn is_dry_run() -> bool {
+    DRY_RUN.load(Ordering::SeqCst)
+}
+
+/// Installs a hook that is asked before anything is removed or overwritten.
+///
+/// The hook receives the name of the operation (`"rm"`, `"rm_r"`,
+/// `"write_file"`, `"cp"`, ...) and the path that would be lost, and returns
+/// whether to go ahead. A declined operation fails with `FsError::Declined`,
+/// so the plain functions return `false`. Operations on a whole tree, such as
+/// `rm_r` and `shred_r`, ask once for the tree, while those that choose what
+/// to remove or overwrite within it, such as `cp_r`, `tar_extract`, `unzip`,
+/// `rm_r_filtered`, `prune_empty_dirs` and `purge_older_than`, ask for each
+/// path and leave the declined ones in place. `split` asks for each part it
+/// would overwrite and stops at the first one declined. This is enough to
+/// build `rm -i` or `cp -i` style prompts on top of fsutils. The hook applies
+/// to the whole process and is not consulted in dry-run mode.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::FsError;
+///
+/// fsutils::write_file("set_confirm_keep.txt", "keep me");
+/// fsutils::write_file("set_confirm_drop.txt", "drop me");
+///
+/// fsutils::set_confirm(|op, path| op == "rm" && path.ends_with("set_confirm_drop.txt"));
+/// assert!(fsutils::rm("set_confirm_drop.txt"));
+/// assert!(!fsutils::rm("set_confirm_keep.txt"));
+/// assert!(matches!(fsutils::try_write_file("set_confirm_keep.txt", "new"), Err(FsError::Declined { .. })));
+/// assert_eq!(fsutils::read_file("set_confirm_keep.txt"), "keep me");
+///
+/// // Creating a file that does not exist yet needs no confirmation
+/// assert!(fsutils::write_file("set_confirm_new.txt", "new"));
+///
+/// // But moving over one that does, or shredding one, does
+/// assert!(!fsutils::mv("set_confirm_new.txt", "set_confirm_keep.txt"));
+/// assert!(!fsutils::shred("set_confirm_keep.txt", 1));
+/// assert_eq!(fsutils::read_file("set_confirm_keep.txt"), "keep me");
+///
+/// fsutils::clear_confirm();
+/// assert!(fsutils::rm("set_confirm_keep.txt"));
+///
+/// # // Cleanup
+/// # fsutils::rm("set_confirm_new.txt");
+/// ```
+pub fn set_confirm<F>(hook: F)
+where
+    F: Fn(&str, &Path) -> bool + Send + Sync + 'static,
+{
+    *CONFIRM.write().unwrap_or_else(|e| e.into_inner()) = Some(Arc::new(hook));
+}
+
+/// Removes the hook installed with `fsutils::set_confirm`.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::set_confirm(|_, _| false);
+/// fsutils::clear_confirm();
+///
+/// fsutils::create_file("clear_confirm.txt");
+/// assert!(fsutils::rm("clear_confirm.txt"));
+/// ```
+pub fn clear_confirm() {
+    *CONFIRM.write().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+/// Asks the confirm hook, if there is one, whether `name` may go ahead on `path`.
+fn confirmed(name: &str, path: &Path) -> bool {
+    // Clone the hook out so it can call back into fsutils without deadlocking
+    let hook = CONFIRM.read().unwrap_or_else(|e| e.into_inner()).clone();
+    hook.is_none_or(|hook| hook(name, path))
+}
+
+/// Asks the confirm hook whether `name` may remove or overwrite `path`, for
+/// operations on trees that pick what to remove as they go. A declined path
+/// is left as it is and the operation carries on with the rest.
+pub(crate) fn confirm_each(name: &str, path: &Path) -> bool {
+    if confirmed(name, path) {
+        return true;
+    }
+    info!("Declined {} {}", name, path.display());
+    false
+}
+
+/// A single modifying operation, e.g. `rm foo` or `mv foo bar`.
+///
+/// Every operation is logged in dry-run mode, recorded in the journal and,
+/// with the `tracing` feature, wrapped in a span.
+pub(crate) struct Op<'a> {
+    name: &'static str,
+    path: &'a Path,
+    dest: Option<&'a Path>,
+    bytes: Option<u64>,
+    destructive: bool,
+}
+
+impl<'a> Op<'a> {
+    pub(crate) fn new(name: &'static str, path: &'a Path) -> Op<'a> {
+        Op { name, path, dest: None, bytes: None, destructive: false }
+    }
+
+    pub(crate) fn to(mut self, dest: &'a Path) -> Op<'a> {
+        self.dest = Some(dest);
+        self
+    }
+
+    /// Sets the number of bytes the operation is expected to transfer.
+    pub(crate) fn bytes(mut self, bytes: u64) -> Op<'a> {
+        self.bytes = Some(bytes);
+        self
+    }
+
+    /// Marks the operation as removing or overwriting something, which makes
+    /// it ask the confirm hook first. The path asked about is the destination
+    /// if there is one.
+    pub(crate) fn destructive(mut self, destructive: bool) -> Op<'a> {
+        self.destructive = destructive;
+        self
+    }
+
+    /// Runs `f`, or returns `dry` without running it in dry-run mode.
+    pub(crate) fn run<T, F>(self, dry: T, f: F) -> Result<T>
+    where
+        F: FnOnce() -> Result<T>,
+    {
+        #[cfg(feature = "tracing")]
+        let span = self.span();
+        #[cfg(feature = "tracing")]
+        let _entered = span.enter();
+
+        if is_dry_run() {
+            match self.dest {
+                Some(dest) => info!("[dry-run] {} {} {}", self.name, self.path.display(), dest.display()),
+                None => info!("[dry-run] {} {}", self.name, self.path.display()),
+            }
+            journal::record(self.name, self.path, self.dest, None, true);
+            return Ok(dry);
+        }
+
+        if self.destructive {
+            let target = self.dest.unwrap_or(self.path);
+            if !confirmed(self.name, target) {
+                info!("Declined {} {}", self.name, target.display());
+                let error = FsError::Declined { path: target.to_path_buf() };
+                journal::record(self.name, self.path, self.dest, Some(error.to_string()), false);
+                return Err(error);
+            }
+        }
+
+        #[cfg(feature = "tracing")]
+        let start = std::time::Instant::now();
+        let result = f();
+        let error = result.as_ref().err().map(ToString::to_string);
+
+        #[cfg(feature = "tracing")]
+        {
+            span.record("duration_us", start.elapsed().as_micros() as u64);
+            if let Some(error) = &error {
+                span.record("error", tracing::field::display(error));
+                tracing::error!(error = %error, "{} failed", self.name);
+            }
+        }
+
+        journal::record(self.name, self.path, self.dest, error, false);
+        result
+    }
+
+    #[cfg(feature = "tracing")]
+    fn span(&self) -> tracing::Span {
+        use tracing::field::{display, Empty};
+
+        let span = tracing::info_span!(
+            "fsutils",
+            op = self.name,
+            path = %self.path.display(),
+            dest = Empty,
+            bytes = Empty,
+            duration_us = Empty,
+            dry_run = is_dry_run(),
+            error = Empty,
+        );
+        if let Some(dest) = self.dest {
+            span.record("dest", display(dest.display()));
+        }
+        if let Some(bytes) = self.bytes {
+            span.record("bytes", bytes);
+        }
+        span
+    }
+}
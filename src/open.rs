@@ -0,0 +1,147 @@
+// Copyright 2020 Jared Forth.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Buffered file handles for streaming.
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+
+use crate::error::{IoResultExt, Result};
+use crate::op::Op;
+
+/// The buffer size of the handles returned here. std's 8 KiB default means a
+/// system call for every few lines of a large file.
+const BUFFER_CAPACITY: usize = 64 * 1024;
+
+#[cfg(not(windows))]
+const NULL_DEVICE: &str = "/dev/null";
+#[cfg(windows)]
+const NULL_DEVICE: &str = "NUL";
+
+/// Opens a file for buffered reading
+/// and returns `None` if it cannot be opened.
+///
+/// ## Usage:
+///
+/// ```
+/// use std::io::Read;
+///
+/// fsutils::write_file("open_reader.txt", "streamed");
+///
+/// let mut contents = String::new();
+/// fsutils::open_buffered_reader("open_reader.txt").unwrap().read_to_string(&mut contents).unwrap();
+/// assert_eq!(contents, "streamed");
+///
+/// # // Cleanup
+/// # fsutils::rm("open_reader.txt");
+/// ```
+pub fn open_buffered_reader<P: AsRef<Path>>(path: P) -> Option<BufReader<File>> {
+    match try_open_buffered_reader(path) {
+        Ok(reader) => Some(reader),
+        Err(e) => {
+            error!("Cannot open file {}", e);
+            None
+        }
+    }
+}
+
+/// Opens a file for reading through a 64 KiB buffer.
+///
+/// ## Usage:
+///
+/// ```
+/// use std::io::BufRead;
+///
+/// fsutils::write_file("try_open_reader.txt", "first\nsecond\n");
+///
+/// let reader = fsutils::try_open_buffered_reader("try_open_reader.txt").unwrap();
+/// assert_eq!(reader.lines().count(), 2);
+/// assert!(fsutils::try_open_buffered_reader("a_very_1234_unlikely_9876_filename").is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm("try_open_reader.txt");
+/// ```
+pub fn try_open_buffered_reader<P: AsRef<Path>>(path: P) -> Result<BufReader<File>> {
+    let path = path.as_ref();
+    let file = File::open(path).at(path)?;
+    Ok(BufReader::with_capacity(BUFFER_CAPACITY, file))
+}
+
+/// Opens a file for buffered writing, appending to it or replacing its contents,
+/// and returns `None` if it cannot be opened.
+///
+/// ## Usage:
+///
+/// ```
+/// use std::io::Write;
+///
+/// let mut writer = fsutils::open_buffered_writer("open_writer.txt", false).unwrap();
+/// writer.write_all(b"buffered").unwrap();
+/// drop(writer);
+///
+/// assert_eq!(fsutils::read_file("open_writer.txt"), "buffered");
+///
+/// # // Cleanup
+/// # fsutils::rm("open_writer.txt");
+/// ```
+pub fn open_buffered_writer<P: AsRef<Path>>(path: P, append: bool) -> Option<BufWriter<File>> {
+    match try_open_buffered_writer(path, append) {
+        Ok(writer) => Some(writer),
+        Err(e) => {
+            error!("Cannot open file {}", e);
+            None
+        }
+    }
+}
+
+/// Opens a file for writing through a 64 KiB buffer, creating it if it does
+/// not exist.
+///
+/// With `append` the file's contents are kept and writes go to the end,
+/// otherwise the file is truncated. Data still in the buffer is written when
+/// the writer is dropped, but errors are lost then, so call `flush` before
+/// dropping it if they matter. In dry-run mode the file is left alone and the
+/// writer discards everything written to it.
+///
+/// ## Usage:
+///
+/// ```
+/// use std::io::Write;
+///
+/// fsutils::write_file("try_open_writer.log", "one\n");
+///
+/// let mut writer = fsutils::try_open_buffered_writer("try_open_writer.log", true).unwrap();
+/// writeln!(writer, "two").unwrap();
+/// writer.flush().unwrap();
+///
+/// assert_eq!(fsutils::read_file("try_open_writer.log"), "one\ntwo\n");
+///
+/// # // Cleanup
+/// # drop(writer);
+/// # fsutils::rm("try_open_writer.log");
+/// ```
+pub fn try_open_buffered_writer<P: AsRef<Path>>(path: P, append: bool) -> Result<BufWriter<File>> {
+    let path = path.as_ref();
+    let name = if append { "open_append" } else { "open_truncate" };
+    let file = Op::new(name, path).destructive(!append && path.exists()).run(None, || {
+        let mut options = OpenOptions::new();
+        options.create(true);
+        if append {
+            options.append(true);
+        } else {
+            options.write(true).truncate(true);
+        }
+        Ok(Some(options.open(path).at(path)?))
+    })?;
+    let file = match file {
+        Some(file) => file,
+        None => OpenOptions::new().write(true).open(NULL_DEVICE).at(NULL_DEVICE)?,
+    };
+    Ok(BufWriter::with_capacity(BUFFER_CAPACITY, file))
+}
@@ -0,0 +1,260 @@
+// Copyright 2020 Jared Forth.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! What to do when the destination of an operation already exists.
+
+use std::ffi::OsString;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::error::{FsError, IoResultExt, Result};
+use crate::op::Op;
+
+/// Decides what happens when an operation would replace an existing path.
+///
+/// The default is `OverwritePolicy::Error`, so nothing is clobbered unless
+/// the caller opts in.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::OverwritePolicy;
+///
+/// fsutils::write_file("policy.txt", "original");
+///
+/// let written = fsutils::write_file_with("policy.txt", "new", &OverwritePolicy::Rename).unwrap();
+/// assert_eq!(written.unwrap().to_str(), Some("policy (1).txt"));
+/// assert_eq!(fsutils::read_file("policy.txt"), "original");
+///
+/// let prompt = OverwritePolicy::prompt(|path| path.ends_with("policy.txt"));
+/// assert!(fsutils::write_file_with("policy.txt", "new", &prompt).unwrap().is_some());
+/// assert_eq!(fsutils::read_file("policy.txt"), "new");
+///
+/// # // Cleanup
+/// # fsutils::rm("policy.txt");
+/// # fsutils::rm("policy (1).txt");
+/// ```
+#[derive(Clone, Default)]
+pub enum OverwritePolicy {
+    /// Fail with `FsError::AlreadyExists`.
+    #[default]
+    Error,
+    /// Leave the destination alone and report that nothing was done.
+    Skip,
+    /// Replace the destination.
+    Overwrite,
+    /// Pick the first free name of the form `name (1).ext`, `name (2).ext`, ...
+    Rename,
+    /// Ask the callback, which returns `true` to overwrite and `false` to skip.
+    Prompt(Arc<dyn Fn(&Path) -> bool + Send + Sync>),
+    /// Rename the destination to a numbered backup, `name.~1~`, `name.~2~`, ...,
+    /// and then replace it, like `mv --backup=numbered`.
+    Backup,
+    /// Rename the destination to its name plus this suffix, replacing any older
+    /// backup, and then replace it, like `mv --backup=simple --suffix=...`.
+    BackupSuffix(String),
+}
+
+impl OverwritePolicy {
+    /// Builds an `OverwritePolicy::Prompt` from a closure.
+    pub fn prompt<F>(f: F) -> OverwritePolicy
+    where
+        F: Fn(&Path) -> bool + Send + Sync + 'static,
+    {
+        OverwritePolicy::Prompt(Arc::new(f))
+    }
+
+    /// Works out where to write given the requested destination.
+    ///
+    /// Returns `None` if the operation should be skipped.
+    pub(crate) fn resolve(&self, dest: &Path) -> Result<Option<PathBuf>> {
+        // `symlink_metadata` so that a dangling symlink still counts as existing
+        if dest.symlink_metadata().is_err() {
+            return Ok(Some(dest.to_path_buf()));
+        }
+        match self {
+            OverwritePolicy::Error => Err(FsError::already_exists(dest)),
+            OverwritePolicy::Skip => {
+                info!("Skipping existing {}", dest.display());
+                Ok(None)
+            }
+            OverwritePolicy::Overwrite | OverwritePolicy::Backup | OverwritePolicy::BackupSuffix(_) => {
+                Ok(Some(dest.to_path_buf()))
+            }
+            OverwritePolicy::Rename => Ok(Some(next_free_path(dest, DEFAULT_PATTERN))),
+            OverwritePolicy::Prompt(confirm) => {
+                if confirm(dest) {
+                    Ok(Some(dest.to_path_buf()))
+                } else {
+                    info!("Skipping existing {}", dest.display());
+                    Ok(None)
+                }
+            }
+        }
+    }
+
+    /// Moves an existing `dest` out of the way if the policy keeps backups.
+    ///
+    /// Call this with a path from `resolve` just before writing to it.
+    pub(crate) fn back_up(&self, dest: &Path) -> Result<()> {
+        if dest.symlink_metadata().is_err() {
+            return Ok(());
+        }
+        let backup = match self {
+            OverwritePolicy::Backup => next_backup_path(dest)?,
+            OverwritePolicy::BackupSuffix(suffix) => {
+                let mut name = dest.file_name().map(OsString::from).unwrap_or_default();
+                name.push(suffix);
+                dest.with_file_name(name)
+            }
+            _ => return Ok(()),
+        };
+        Op::new("backup", dest).to(&backup).run((), || {
+            fs::rename(dest, &backup).at2(dest, &backup)?;
+            info!("Backed up {} to {}", dest.display(), backup.display());
+            Ok(())
+        })
+    }
+}
+
+impl fmt::Debug for OverwritePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OverwritePolicy::Error => f.write_str("Error"),
+            OverwritePolicy::Skip => f.write_str("Skip"),
+            OverwritePolicy::Overwrite => f.write_str("Overwrite"),
+            OverwritePolicy::Rename => f.write_str("Rename"),
+            OverwritePolicy::Prompt(_) => f.write_str("Prompt(..)"),
+            OverwritePolicy::Backup => f.write_str("Backup"),
+            OverwritePolicy::BackupSuffix(suffix) => f.debug_tuple("BackupSuffix").field(suffix).finish(),
+        }
+    }
+}
+
+/// The pattern `unique_path` uses, giving names like `report (1).pdf`.
+const DEFAULT_PATTERN: &str = "{stem} ({n}){ext}";
+
+/// Returns `path` if nothing exists there, or else the first free name of the
+/// form `name (1).ext`, `name (2).ext`, ... next to it.
+///
+/// The check and any later creation are separate steps, so another process can
+/// still take the name in between; pair this with an exclusive create where
+/// that matters.
+///
+/// ## Usage:
+///
+/// ```
+/// use std::path::Path;
+///
+/// assert_eq!(fsutils::unique_path("unique_report.pdf"), Path::new("unique_report.pdf"));
+///
+/// fsutils::create_file("unique_report.pdf");
+/// fsutils::create_file("unique_report (1).pdf");
+/// assert_eq!(fsutils::unique_path("unique_report.pdf"), Path::new("unique_report (2).pdf"));
+///
+/// # // Cleanup
+/// # fsutils::rm("unique_report.pdf");
+/// # fsutils::rm("unique_report (1).pdf");
+/// ```
+pub fn unique_path<P: AsRef<Path>>(path: P) -> PathBuf {
+    unique_path_with(path, DEFAULT_PATTERN).expect("the default pattern is valid")
+}
+
+/// Like `fsutils::unique_path`, but with the numbered names built from `pattern`.
+///
+/// In the pattern, `{stem}` is the file name without its extension, `{ext}`
+/// is the extension including its dot, or nothing if there is none, and `{n}`
+/// is the number, which must appear.
+///
+/// ## Usage:
+///
+/// ```
+/// use std::path::Path;
+///
+/// fsutils::create_file("unique_export.csv");
+/// let path = fsutils::unique_path_with("unique_export.csv", "{stem}-{n}{ext}").unwrap();
+/// assert_eq!(path, Path::new("unique_export-1.csv"));
+///
+/// assert!(fsutils::unique_path_with("unique_export.csv", "{stem}{ext}").is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm("unique_export.csv");
+/// ```
+pub fn unique_path_with<P: AsRef<Path>>(path: P, pattern: &str) -> Result<PathBuf> {
+    let path = path.as_ref();
+    if !pattern.contains("{n}") {
+        return Err(FsError::invalid_path(path, format!("pattern {:?} has no {{n}}", pattern)));
+    }
+    if path.symlink_metadata().is_err() {
+        return Ok(path.to_path_buf());
+    }
+    Ok(next_free_path(path, pattern))
+}
+
+/// Returns the first sibling of `path` named after `pattern` that does not exist.
+fn next_free_path(path: &Path, pattern: &str) -> PathBuf {
+    let stem = path.file_stem().map(OsString::from).unwrap_or_default();
+    let ext = path.extension();
+    (1u64..)
+        .map(|n| {
+            let mut name = OsString::new();
+            let mut rest = pattern;
+            while let Some(start) = rest.find('{') {
+                name.push(&rest[..start]);
+                rest = &rest[start..];
+                if let Some(after) = rest.strip_prefix("{stem}") {
+                    name.push(&stem);
+                    rest = after;
+                } else if let Some(after) = rest.strip_prefix("{ext}") {
+                    if let Some(ext) = ext {
+                        name.push(".");
+                        name.push(ext);
+                    }
+                    rest = after;
+                } else if let Some(after) = rest.strip_prefix("{n}") {
+                    name.push(n.to_string());
+                    rest = after;
+                } else {
+                    name.push("{");
+                    rest = &rest[1..];
+                }
+            }
+            name.push(rest);
+            path.with_file_name(name)
+        })
+        .find(|candidate| candidate.symlink_metadata().is_err())
+        .expect("ran out of candidate file names")
+}
+
+/// Returns `path` with `.~n~` appended, for one more than the highest `n` in use.
+fn next_backup_path(path: &Path) -> Result<PathBuf> {
+    let mut prefix = path.file_name().map(OsString::from).unwrap_or_default();
+    prefix.push(".~");
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let mut highest = 0;
+    for entry in fs::read_dir(parent).at(parent)? {
+        let entry = entry.at(parent)?;
+        let entry_name = entry.file_name();
+        let n = entry_name
+            .as_encoded_bytes()
+            .strip_prefix(prefix.as_encoded_bytes())
+            .and_then(|n| n.strip_suffix(b"~"))
+            .and_then(|n| std::str::from_utf8(n).ok())
+            .and_then(|n| n.parse::<u64>().ok());
+        if let Some(n) = n {
+            highest = highest.max(n);
+        }
+    }
+    prefix.push(format!("{}~", highest + 1));
+    Ok(path.with_file_name(prefix))
+}
@@ -0,0 +1,577 @@
+// Copyright 2020 Jared Forth.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Changing permissions and ownership.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::error::{FsError, IoResultExt, Result};
+use crate::glob::Pattern;
+use crate::op::Op;
+
+/// Options for `fsutils::chmod_r_with`.
+///
+/// By default every file and directory in the tree is changed. Include and
+/// exclude patterns work as in `fsutils::CopyOptions`: a pattern without a `/`
+/// is matched against the file name, anything else against the path relative
+/// to the top directory. Excludes apply to files and directories, skipping
+/// everything below an excluded directory, while includes only select files.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::ChmodOptions;
+///
+/// let mut opts = ChmodOptions::new();
+/// opts.include("*.html").unwrap().exclude(".git").unwrap();
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct ChmodOptions {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+}
+
+impl ChmodOptions {
+    /// Creates the default options.
+    pub fn new() -> ChmodOptions {
+        ChmodOptions::default()
+    }
+
+    /// Only change files matching `pattern`. May be given several times.
+    pub fn include(&mut self, pattern: &str) -> Result<&mut ChmodOptions> {
+        self.include.push(Pattern::new(pattern)?);
+        Ok(self)
+    }
+
+    /// Leave files and directories matching `pattern` alone. May be given several times.
+    pub fn exclude(&mut self, pattern: &str) -> Result<&mut ChmodOptions> {
+        self.exclude.push(Pattern::new(pattern)?);
+        Ok(self)
+    }
+
+    fn excluded(&self, relative: &Path) -> bool {
+        self.exclude.iter().any(|p| p.matches_path(relative))
+    }
+
+    fn included(&self, relative: &Path) -> bool {
+        self.include.is_empty() || self.include.iter().any(|p| p.matches_path(relative))
+    }
+}
+
+/// Sets the permission bits of a file or directory, such as `0o644`,
+/// and returns a boolean based on success or failure.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("chmod.sh", "#!/bin/sh\n");
+///
+/// assert!(fsutils::chmod("chmod.sh", 0o755));
+///
+/// # // Cleanup
+/// # fsutils::rm("chmod.sh");
+/// ```
+pub fn chmod<P: AsRef<Path>>(path: P, mode: u32) -> bool {
+    match try_chmod(path, mode) {
+        Ok(_) => true,
+        Err(e) => {
+            error!("Cannot change permissions {}", e);
+            false
+        }
+    }
+}
+
+/// Sets the permission bits of a file or directory, like `chmod 644`.
+///
+/// `mode` holds the usual octal bits, including setuid, setgid and sticky;
+/// anything above `0o7777` is ignored. Symbolic links are followed. Unix
+/// modes have no equivalent on Windows, so there this only logs that the
+/// file was left as it is.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("try_chmod.txt", "secret");
+///
+/// fsutils::try_chmod("try_chmod.txt", 0o600).unwrap();
+/// # #[cfg(unix)]
+/// assert_eq!(fsutils::stat("try_chmod.txt").unwrap().mode.unwrap() & 0o777, 0o600);
+/// assert!(fsutils::try_chmod("a_very_1234_unlikely_9876_filename", 0o600).is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm("try_chmod.txt");
+/// ```
+pub fn try_chmod<P: AsRef<Path>>(path: P, mode: u32) -> Result<()> {
+    let path = path.as_ref();
+    Op::new("chmod", path).run((), || set_mode(path, mode & 0o7777))
+}
+
+/// Changes the permission bits of a file or directory with a symbolic mode
+/// such as `u+x,go-w`, and returns a boolean based on success or failure.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("chmod_symbolic.sh", "#!/bin/sh\n");
+///
+/// assert!(fsutils::chmod_symbolic("chmod_symbolic.sh", "u+x"));
+/// assert!(!fsutils::chmod_symbolic("chmod_symbolic.sh", "u+q"));
+///
+/// # // Cleanup
+/// # fsutils::rm("chmod_symbolic.sh");
+/// ```
+pub fn chmod_symbolic<P: AsRef<Path>>(path: P, mode: &str) -> bool {
+    match try_chmod_symbolic(path, mode) {
+        Ok(_) => true,
+        Err(e) => {
+            error!("Cannot change permissions {}", e);
+            false
+        }
+    }
+}
+
+/// Changes the permission bits of a file or directory with a symbolic mode,
+/// like `chmod u+x,go-w`.
+///
+/// `mode` is a comma-separated list of clauses. Each names who it applies to
+/// with any of `u`, `g`, `o` and `a`, then one or more operators `+`, `-` or
+/// `=` each followed by any of `r`, `w`, `x`, `X`, `s` and `t`. `X` adds
+/// execute permission only to directories and files that are already
+/// executable by someone. A clause without a `u`, `g`, `o` or `a` applies to
+/// everyone; unlike `chmod`, the umask is not consulted. A malformed mode
+/// fails with an error of kind `io::ErrorKind::InvalidInput`, on every
+/// platform, before anything is changed. On Windows a valid mode is only
+/// logged, as for `fsutils::try_chmod`.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("try_chmod_symbolic.txt", "shared");
+///
+/// fsutils::try_chmod("try_chmod_symbolic.txt", 0o600).unwrap();
+/// fsutils::try_chmod_symbolic("try_chmod_symbolic.txt", "g+r,o=r").unwrap();
+/// # #[cfg(unix)]
+/// assert_eq!(fsutils::stat("try_chmod_symbolic.txt").unwrap().mode.unwrap() & 0o777, 0o644);
+///
+/// let invalid = fsutils::try_chmod_symbolic("try_chmod_symbolic.txt", "u~x");
+/// assert_eq!(invalid.unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+///
+/// # // Cleanup
+/// # fsutils::rm("try_chmod_symbolic.txt");
+/// ```
+pub fn try_chmod_symbolic<P: AsRef<Path>>(path: P, mode: &str) -> Result<()> {
+    let path = path.as_ref();
+    let symbolic = SymbolicMode::parse(mode)
+        .map_err(|reason| FsError::from_io(path, io::Error::new(io::ErrorKind::InvalidInput, reason)))?;
+    Op::new("chmod", path).run((), || set_symbolic(path, &symbolic))
+}
+
+/// Sets the modes of every file and directory below `dir`, and of `dir`
+/// itself, and returns a boolean based on success or failure.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::mkdir("chmod_r_dir/assets");
+/// fsutils::write_file("chmod_r_dir/assets/logo.svg", "<svg/>");
+///
+/// assert!(fsutils::chmod_r("chmod_r_dir", 0o644, 0o755));
+///
+/// # // Cleanup
+/// # fsutils::rm_r("chmod_r_dir");
+/// ```
+pub fn chmod_r<P: AsRef<Path>>(dir: P, file_mode: u32, dir_mode: u32) -> bool {
+    match try_chmod_r(dir, file_mode, dir_mode) {
+        Ok(_) => true,
+        Err(e) => {
+            error!("Cannot change permissions {}", e);
+            false
+        }
+    }
+}
+
+/// Sets `file_mode` on every file and `dir_mode` on every directory in a
+/// tree, and returns how many entries were changed.
+///
+/// This is the usual normalisation of a checkout or an unpacked archive to
+/// `644` and `755`. See `fsutils::chmod_r_with` for the details.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::mkdir("try_chmod_r_dir/sub");
+/// fsutils::write_file("try_chmod_r_dir/sub/file.txt", "text");
+///
+/// assert_eq!(fsutils::try_chmod_r("try_chmod_r_dir", 0o600, 0o700).unwrap(), 3);
+/// # #[cfg(unix)]
+/// assert_eq!(fsutils::stat("try_chmod_r_dir/sub/file.txt").unwrap().mode.unwrap() & 0o777, 0o600);
+/// assert!(fsutils::try_chmod_r("a_very_1234_unlikely_9876_dirname", 0o644, 0o755).is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm_r("try_chmod_r_dir");
+/// ```
+pub fn try_chmod_r<P: AsRef<Path>>(dir: P, file_mode: u32, dir_mode: u32) -> Result<usize> {
+    chmod_r_with(dir, file_mode, dir_mode, &ChmodOptions::new())
+}
+
+/// Sets `file_mode` on the files and `dir_mode` on the directories in a tree
+/// selected by `opts`, and returns how many entries were changed.
+///
+/// `dir` itself is changed as well, unless an exclude pattern matches its
+/// name. Each directory is changed before its contents are, so a tree that
+/// could not be read before can be once `dir_mode` allows it. Symbolic links
+/// are neither changed nor followed. On Windows the modes are only logged,
+/// as for `fsutils::try_chmod`.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::ChmodOptions;
+///
+/// fsutils::mkdir("chmod_r_with_dir/bin");
+/// fsutils::write_file("chmod_r_with_dir/bin/run.sh", "#!/bin/sh\n");
+/// fsutils::write_file("chmod_r_with_dir/notes.txt", "notes");
+///
+/// let mut opts = ChmodOptions::new();
+/// opts.include("*.sh").unwrap();
+/// assert_eq!(fsutils::chmod_r_with("chmod_r_with_dir", 0o755, 0o755, &opts).unwrap(), 3);
+/// # #[cfg(unix)]
+/// assert_eq!(fsutils::stat("chmod_r_with_dir/bin/run.sh").unwrap().mode.unwrap() & 0o777, 0o755);
+///
+/// # // Cleanup
+/// # fsutils::rm_r("chmod_r_with_dir");
+/// ```
+pub fn chmod_r_with<P: AsRef<Path>>(dir: P, file_mode: u32, dir_mode: u32, opts: &ChmodOptions) -> Result<usize> {
+    let dir = dir.as_ref();
+    Op::new("chmod_r", dir).run(0, || {
+        let name = dir.file_name().map(Path::new);
+        if opts.excluded(name.unwrap_or(dir)) {
+            return Ok(0);
+        }
+        let modes = (file_mode & 0o7777, dir_mode & 0o7777);
+        let count = chmod_dir(dir, dir, modes, opts)?;
+        info!("Changed permissions of {} entries in {}", count, dir.display());
+        Ok(count)
+    })
+}
+
+fn chmod_dir(root: &Path, dir: &Path, modes: (u32, u32), opts: &ChmodOptions) -> Result<usize> {
+    set_mode(dir, modes.1)?;
+    let mut count = 1;
+    let mut entries = fs::read_dir(dir).at(dir)?.collect::<io::Result<Vec<_>>>().at(dir)?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let path = entry.path();
+        let relative = path.strip_prefix(root).unwrap_or(&path);
+        if opts.excluded(relative) {
+            continue;
+        }
+        let file_type = entry.file_type().at(&path)?;
+        if file_type.is_dir() {
+            count += chmod_dir(root, &path, modes, opts)?;
+        } else if file_type.is_file() && opts.included(relative) {
+            set_mode(&path, modes.0)?;
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> Result<()> {
+    use std::fs::Permissions;
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::set_permissions(path, Permissions::from_mode(mode)).at(path)?;
+    info!("Set mode of {} to {:o}", path.display(), mode);
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn set_mode(path: &Path, _mode: u32) -> Result<()> {
+    unsupported(path)
+}
+
+#[cfg(unix)]
+fn set_symbolic(path: &Path, symbolic: &SymbolicMode) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let meta = path.metadata().at(path)?;
+    let mode = symbolic.apply(meta.permissions().mode() & 0o7777, meta.is_dir());
+    set_mode(path, mode)
+}
+
+#[cfg(not(unix))]
+fn set_symbolic(path: &Path, _symbolic: &SymbolicMode) -> Result<()> {
+    unsupported(path)
+}
+
+#[cfg(not(unix))]
+fn unsupported(path: &Path) -> Result<()> {
+    path.metadata().at(path)?;
+    info!("Leaving permissions of {} as they are, as Unix modes have no meaning on this platform", path.display());
+    Ok(())
+}
+
+/// A parsed symbolic mode such as `u+x,go-w`. Elsewhere than Unix it is only
+/// parsed, to check it.
+#[derive(Debug)]
+#[cfg_attr(not(unix), allow(dead_code))]
+pub(crate) struct SymbolicMode {
+    actions: Vec<Action>,
+}
+
+/// One operator of a clause, with the permission bits of the classes it
+/// applies to.
+#[derive(Debug)]
+#[cfg_attr(not(unix), allow(dead_code))]
+struct Action {
+    who: u32,
+    op: u8,
+    perms: Vec<u8>,
+}
+
+impl SymbolicMode {
+    pub(crate) fn parse(text: &str) -> std::result::Result<SymbolicMode, String> {
+        let mut actions = Vec::new();
+        for clause in text.split(',') {
+            let bytes = clause.as_bytes();
+            let mut i = 0;
+            let mut who = 0;
+            while let Some(&c) = bytes.get(i) {
+                who |= match c {
+                    b'u' => 0o700,
+                    b'g' => 0o070,
+                    b'o' => 0o007,
+                    b'a' => 0o777,
+                    _ => break,
+                };
+                i += 1;
+            }
+            if who == 0 {
+                who = 0o777;
+            }
+            if i == bytes.len() {
+                return Err(format!("clause `{}` of mode `{}` has no operator", clause, text));
+            }
+            while let Some(&op) = bytes.get(i) {
+                if !matches!(op, b'+' | b'-' | b'=') {
+                    return Err(format!("unexpected `{}` in clause `{}` of mode `{}`", op as char, clause, text));
+                }
+                i += 1;
+                let start = i;
+                while bytes.get(i).is_some_and(|c| b"rwxXst".contains(c)) {
+                    i += 1;
+                }
+                actions.push(Action { who, op, perms: bytes[start..i].to_vec() });
+            }
+        }
+        Ok(SymbolicMode { actions })
+    }
+
+    /// Returns `mode` changed by each clause in turn.
+    #[cfg(unix)]
+    pub(crate) fn apply(&self, mut mode: u32, is_dir: bool) -> u32 {
+        for action in &self.actions {
+            let who = action.who;
+            let mut bits = 0;
+            for perm in &action.perms {
+                bits |= match perm {
+                    b'r' => 0o444 & who,
+                    b'w' => 0o222 & who,
+                    b'x' => 0o111 & who,
+                    b'X' if is_dir || mode & 0o111 != 0 => 0o111 & who,
+                    b's' => special(who, 0o700, 0o4000) | special(who, 0o070, 0o2000),
+                    b't' => special(who, 0o007, 0o1000),
+                    _ => 0,
+                };
+            }
+            match action.op {
+                b'+' => mode |= bits,
+                b'-' => mode &= !bits,
+                _ => {
+                    let cleared = who | special(who, 0o700, 0o4000) | special(who, 0o070, 0o2000) | special(who, 0o007, 0o1000);
+                    mode = (mode & !cleared) | bits;
+                }
+            }
+        }
+        mode
+    }
+}
+
+/// `bit` if `who` includes the class `class`, otherwise nothing.
+#[cfg(unix)]
+fn special(who: u32, class: u32, bit: u32) -> u32 {
+    if who & class != 0 {
+        bit
+    } else {
+        0
+    }
+}
+
+/// Changes the owner and group of a file or directory, by name or numeric id,
+/// and returns a boolean based on success or failure.
+///
+/// Only available on Unix.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("chown.txt", "mine");
+/// let uid = fsutils::stat("chown.txt").unwrap().uid.unwrap().to_string();
+///
+/// assert!(fsutils::chown("chown.txt", Some(&uid), None));
+/// assert!(!fsutils::chown("chown.txt", Some("a_very_1234_unlikely_9876_user"), None));
+///
+/// # // Cleanup
+/// # fsutils::rm("chown.txt");
+/// ```
+#[cfg(unix)]
+pub fn chown<P: AsRef<Path>>(path: P, user: Option<&str>, group: Option<&str>) -> bool {
+    match try_chown(path, user, group) {
+        Ok(_) => true,
+        Err(e) => {
+            error!("Cannot change owner {}", e);
+            false
+        }
+    }
+}
+
+/// Changes the owner and group of a file or directory, like
+/// `chown user:group`.
+///
+/// `user` and `group` are names, looked up in the users and groups
+/// databases, or numeric ids. `None` leaves that one as it is. A name that
+/// does not exist fails with an error of kind `io::ErrorKind::InvalidInput`
+/// before anything is changed. Symbolic links are followed. Giving a file to
+/// another user normally requires root.
+///
+/// Only available on Unix.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("try_chown.txt", "mine");
+/// let gid = fsutils::stat("try_chown.txt").unwrap().gid.unwrap();
+///
+/// fsutils::try_chown("try_chown.txt", None, Some(&gid.to_string())).unwrap();
+/// assert_eq!(fsutils::stat("try_chown.txt").unwrap().gid, Some(gid));
+/// assert!(fsutils::try_chown("a_very_1234_unlikely_9876_filename", None, Some(&gid.to_string())).is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm("try_chown.txt");
+/// ```
+#[cfg(unix)]
+pub fn try_chown<P: AsRef<Path>>(path: P, user: Option<&str>, group: Option<&str>) -> Result<()> {
+    let path = path.as_ref();
+    let (uid, gid) = resolve_owner(path, user, group)?;
+    Op::new("chown", path).run((), || {
+        std::os::unix::fs::chown(path, uid, gid).at(path)?;
+        info!("Changed owner of {}", path.display());
+        Ok(())
+    })
+}
+
+/// Changes the owner and group of everything below `dir`, and of `dir`
+/// itself, and returns a boolean based on success or failure.
+///
+/// Only available on Unix.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::mkdir("chown_r_dir/sub");
+/// let uid = fsutils::stat("chown_r_dir").unwrap().uid.unwrap().to_string();
+///
+/// assert!(fsutils::chown_r("chown_r_dir", Some(&uid), None));
+///
+/// # // Cleanup
+/// # fsutils::rm_r("chown_r_dir");
+/// ```
+#[cfg(unix)]
+pub fn chown_r<P: AsRef<Path>>(dir: P, user: Option<&str>, group: Option<&str>) -> bool {
+    match try_chown_r(dir, user, group) {
+        Ok(_) => true,
+        Err(e) => {
+            error!("Cannot change owner {}", e);
+            false
+        }
+    }
+}
+
+/// Changes the owner and group of everything below `dir`, and of `dir`
+/// itself, like `chown -R`, and returns how many entries were changed.
+///
+/// Names are resolved as for `fsutils::try_chown`, once, before anything is
+/// changed. Symbolic links in the tree are changed themselves and not
+/// followed.
+///
+/// Only available on Unix.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::mkdir("try_chown_r_dir/sub");
+/// fsutils::write_file("try_chown_r_dir/sub/file.txt", "text");
+/// let uid = fsutils::stat("try_chown_r_dir").unwrap().uid.unwrap().to_string();
+///
+/// assert_eq!(fsutils::try_chown_r("try_chown_r_dir", Some(&uid), None).unwrap(), 3);
+///
+/// # // Cleanup
+/// # fsutils::rm_r("try_chown_r_dir");
+/// ```
+#[cfg(unix)]
+pub fn try_chown_r<P: AsRef<Path>>(dir: P, user: Option<&str>, group: Option<&str>) -> Result<usize> {
+    let dir = dir.as_ref();
+    let (uid, gid) = resolve_owner(dir, user, group)?;
+    Op::new("chown_r", dir).run(0, || {
+        std::os::unix::fs::chown(dir, uid, gid).at(dir)?;
+        let count = 1 + chown_dir(dir, uid, gid)?;
+        info!("Changed owner of {} entries in {}", count, dir.display());
+        Ok(count)
+    })
+}
+
+#[cfg(unix)]
+fn chown_dir(dir: &Path, uid: Option<u32>, gid: Option<u32>) -> Result<usize> {
+    let mut count = 0;
+    for entry in fs::read_dir(dir).at(dir)? {
+        let entry = entry.at(dir)?;
+        let path = entry.path();
+        std::os::unix::fs::lchown(&path, uid, gid).at(&path)?;
+        count += 1;
+        if entry.file_type().at(&path)?.is_dir() {
+            count += chown_dir(&path, uid, gid)?;
+        }
+    }
+    Ok(count)
+}
+
+/// Turns user and group names or ids into ids.
+#[cfg(unix)]
+fn resolve_owner(path: &Path, user: Option<&str>, group: Option<&str>) -> Result<(Option<u32>, Option<u32>)> {
+    let resolve = |name: Option<&str>, kind: &str, lookup: fn(&str) -> io::Result<Option<u32>>| -> Result<Option<u32>> {
+        let name = match name {
+            Some(name) => name,
+            None => return Ok(None),
+        };
+        if let Ok(id) = name.parse() {
+            return Ok(Some(id));
+        }
+        match lookup(name).at(path)? {
+            Some(id) => Ok(Some(id)),
+            None => {
+                let reason = format!("no {} named `{}`", kind, name);
+                Err(FsError::from_io(path, io::Error::new(io::ErrorKind::InvalidInput, reason)))
+            }
+        }
+    };
+    Ok((resolve(user, "user", crate::sys::user_id)?, resolve(group, "group", crate::sys::group_id)?))
+}
@@ -0,0 +1,866 @@
+// Copyright 2020 Jared Forth.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Removal beyond a plain unlink.
+
+use std::collections::hash_map::RandomState;
+use std::fs::{self, OpenOptions};
+use std::hash::{BuildHasher, Hasher};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use crate::error::{FsError, IoResultExt, Result};
+use crate::glob::Pattern;
+use crate::link::{Ancestors, FollowSymlinks};
+use crate::op::{confirm_each, Op};
+
+/// Moves a file or directory to the trash
+/// and returns a boolean based on success or failure.
+///
+/// Requires the `trash` feature.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("rm_trash_file.txt", "recoverable");
+///
+/// assert_eq!(fsutils::rm_trash("rm_trash_file.txt"), true);
+/// assert!(!fsutils::path_exists("rm_trash_file.txt"));
+/// ```
+#[cfg(feature = "trash")]
+pub fn rm_trash<P: AsRef<Path>>(path: P) -> bool {
+    match try_rm_trash(path) {
+        Ok(_) => true,
+        Err(e) => {
+            error!("Trashing error: {}", e);
+            false
+        }
+    }
+}
+
+/// Moves a file or directory to the trash, where it can still be restored,
+/// instead of deleting it.
+///
+/// This uses the freedesktop.org trash on Linux and other unix desktops, the
+/// Trash on macOS and the Recycle Bin on Windows. Requires the `trash` feature.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::mkdir("try_rm_trash_dir");
+///
+/// assert!(fsutils::try_rm_trash("try_rm_trash_dir").is_ok());
+/// assert!(fsutils::try_rm_trash("try_rm_trash_dir").is_err());
+/// ```
+#[cfg(feature = "trash")]
+pub fn try_rm_trash<P: AsRef<Path>>(path: P) -> Result<()> {
+    let path = path.as_ref();
+    path.symlink_metadata().at(path)?;
+    Op::new("rm_trash", path).destructive(true).run((), || {
+        trash::delete(path).map_err(|e| FsError::from_io(path, std::io::Error::other(e.to_string())))?;
+        info!("Moved {} to the trash", path.display());
+        Ok(())
+    })
+}
+
+/// Overwrites a file with random data `passes` times before removing it
+/// and returns a boolean based on success or failure.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("shred_secret.txt", "hunter2");
+///
+/// assert_eq!(fsutils::shred("shred_secret.txt", 3), true);
+/// assert!(!fsutils::path_exists("shred_secret.txt"));
+/// ```
+pub fn shred<P: AsRef<Path>>(path: P, passes: u32) -> bool {
+    match try_shred(path, passes) {
+        Ok(_) => true,
+        Err(e) => {
+            error!("Shredding error: {}", e);
+            false
+        }
+    }
+}
+
+/// Overwrites a file with random data `passes` times, flushing each pass to
+/// disk, then truncates and removes it.
+///
+/// This only helps where writes land on the same blocks as the original data.
+/// Copy-on-write and log-structured filesystems, snapshots, and the wear
+/// levelling in SSDs and flash media can all keep old copies around.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("try_shred_secret.txt", "hunter2");
+///
+/// assert!(fsutils::try_shred("try_shred_secret.txt", 1).is_ok());
+/// assert!(fsutils::try_shred("try_shred_secret.txt", 1).is_err());
+/// assert!(fsutils::try_shred("src", 1).is_err());
+/// ```
+pub fn try_shred<P: AsRef<Path>>(path: P, passes: u32) -> Result<()> {
+    let path = path.as_ref();
+    let meta = path.symlink_metadata().at(path)?;
+    if !meta.is_file() {
+        let source = std::io::Error::new(std::io::ErrorKind::InvalidInput, "only regular files can be shredded");
+        return Err(FsError::from_io(path, source));
+    }
+    Op::new("shred", path).bytes(meta.len()).destructive(true).run((), || {
+        overwrite(path, meta.len(), passes)?;
+        fs::remove_file(path).at(path)?;
+        info!("Shredded {}", path.display());
+        Ok(())
+    })
+}
+
+/// Shreds every file under `path` and then removes the directories
+/// and returns a boolean based on success or failure.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::mkdir("shred_r_dir/keys");
+/// fsutils::write_file("shred_r_dir/keys/id_rsa", "private");
+///
+/// assert_eq!(fsutils::shred_r("shred_r_dir", 1), true);
+/// assert!(!fsutils::path_exists("shred_r_dir"));
+/// ```
+pub fn shred_r<P: AsRef<Path>>(path: P, passes: u32) -> bool {
+    match try_shred_r(path, passes) {
+        Ok(_) => true,
+        Err(e) => {
+            error!("Shredding error: {}", e);
+            false
+        }
+    }
+}
+
+/// Shreds every file under `path` and then removes the directories.
+///
+/// Symlinks are removed without touching what they point to. See
+/// `fsutils::try_shred` for the limits of overwriting.
+pub fn try_shred_r<P: AsRef<Path>>(path: P, passes: u32) -> Result<()> {
+    let path = path.as_ref();
+    path.symlink_metadata().at(path)?;
+    Op::new("shred_r", path).destructive(true).run((), || {
+        shred_tree(path, passes)?;
+        info!("Shredded {}", path.display());
+        Ok(())
+    })
+}
+
+fn shred_tree(path: &Path, passes: u32) -> Result<()> {
+    let meta = path.symlink_metadata().at(path)?;
+    if meta.is_dir() {
+        for entry in fs::read_dir(path).at(path)? {
+            shred_tree(&entry.at(path)?.path(), passes)?;
+        }
+        fs::remove_dir(path).at(path)
+    } else {
+        if meta.is_file() {
+            overwrite(path, meta.len(), passes)?;
+        }
+        fs::remove_file(path).at(path)
+    }
+}
+
+/// Writes `passes` rounds of random bytes over the first `len` bytes of `path`,
+/// then truncates it.
+fn overwrite(path: &Path, len: u64, passes: u32) -> Result<()> {
+    let mut file = OpenOptions::new().write(true).open(path).at(path)?;
+    let mut rng = XorShift::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    for _ in 0..passes {
+        file.seek(SeekFrom::Start(0)).at(path)?;
+        let mut left = len;
+        while left > 0 {
+            let n = left.min(buf.len() as u64) as usize;
+            rng.fill(&mut buf[..n]);
+            file.write_all(&buf[..n]).at(path)?;
+            left -= n as u64;
+        }
+        file.sync_all().at(path)?;
+    }
+    file.set_len(0).and_then(|_| file.sync_all()).at(path)
+}
+
+/// A fast non-cryptographic generator, which is enough to bury old contents.
+struct XorShift(u64);
+
+impl XorShift {
+    fn new() -> XorShift {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u32(std::process::id());
+        XorShift(hasher.finish() | 1)
+    }
+
+    fn fill(&mut self, buf: &mut [u8]) {
+        for chunk in buf.chunks_mut(8) {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            chunk.copy_from_slice(&self.0.to_le_bytes()[..chunk.len()]);
+        }
+    }
+}
+
+/// Removes the files matching a wildcard pattern
+/// and returns the paths removed.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::mkdir("rm_glob_logs");
+/// fsutils::create_file("rm_glob_logs/a.tmp");
+/// fsutils::create_file("rm_glob_logs/b.tmp");
+/// fsutils::create_file("rm_glob_logs/keep.log");
+///
+/// assert_eq!(fsutils::rm_glob("rm_glob_logs/*.tmp").map(|removed| removed.len()), Some(2));
+/// assert!(fsutils::path_exists("rm_glob_logs/keep.log"));
+///
+/// # // Cleanup
+/// # fsutils::rm_r("rm_glob_logs");
+/// ```
+pub fn rm_glob(pattern: &str) -> Option<Vec<PathBuf>> {
+    match try_rm_glob(pattern) {
+        Ok(removed) => Some(removed),
+        Err(e) => {
+            error!("File removal error: {}", e);
+            None
+        }
+    }
+}
+
+/// Removes the files and symlinks matching a wildcard pattern and returns the
+/// paths removed. Matching directories are left alone; see `fsutils::try_rm_r_glob`.
+///
+/// See `fsutils::try_glob` for the pattern syntax. Unlike in a shell,
+/// wildcards also match names that start with a dot.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::mkdir("try_rm_glob_build/nested");
+/// fsutils::create_file("try_rm_glob_build/main.o");
+/// fsutils::create_file("try_rm_glob_build/nested/lib.o");
+///
+/// let removed = fsutils::try_rm_glob("try_rm_glob_build/**/*.o").unwrap();
+/// assert_eq!(removed.len(), 2);
+/// assert!(fsutils::path_exists("try_rm_glob_build/nested"));
+///
+/// assert!(fsutils::try_rm_glob("try_rm_glob_build/[").is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm_r("try_rm_glob_build");
+/// ```
+pub fn try_rm_glob(pattern: &str) -> Result<Vec<PathBuf>> {
+    let mut removed = Vec::new();
+    for path in crate::glob::try_glob(pattern)? {
+        if !path.symlink_metadata().at(&path)?.is_dir() {
+            crate::try_rm(&path)?;
+            removed.push(path);
+        }
+    }
+    Ok(removed)
+}
+
+/// Removes the files and directory trees matching a wildcard pattern
+/// and returns the paths removed.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::mkdir("rm_r_glob_cache/a.d/nested");
+/// fsutils::mkdir("rm_r_glob_cache/b.d");
+/// fsutils::create_file("rm_r_glob_cache/index");
+///
+/// assert_eq!(fsutils::rm_r_glob("rm_r_glob_cache/*.d").map(|removed| removed.len()), Some(2));
+/// assert!(fsutils::path_exists("rm_r_glob_cache/index"));
+///
+/// # // Cleanup
+/// # fsutils::rm_r("rm_r_glob_cache");
+/// ```
+pub fn rm_r_glob(pattern: &str) -> Option<Vec<PathBuf>> {
+    match try_rm_r_glob(pattern) {
+        Ok(removed) => Some(removed),
+        Err(e) => {
+            error!("Directory removal error: {}", e);
+            None
+        }
+    }
+}
+
+/// Removes the files and directory trees matching a wildcard pattern and
+/// returns the paths removed. A match inside an already removed directory is
+/// not listed separately.
+///
+/// See `fsutils::try_rm_glob` for the pattern syntax.
+pub fn try_rm_r_glob(pattern: &str) -> Result<Vec<PathBuf>> {
+    let mut removed: Vec<PathBuf> = Vec::new();
+    // Sorted, so a directory always comes before anything inside it
+    for path in crate::glob::try_glob(pattern)? {
+        if removed.iter().any(|done| path.starts_with(done)) {
+            continue;
+        }
+        if path.symlink_metadata().at(&path)?.is_dir() {
+            crate::try_rm_r(&path)?;
+        } else {
+            crate::try_rm(&path)?;
+        }
+        removed.push(path);
+    }
+    Ok(removed)
+}
+
+/// Removes a directory tree like `fsutils::try_rm_r`, with `follow` deciding
+/// whether symbolic links in it are removed alone or with what they point to.
+///
+/// With `FollowSymlinks::Never` only the links are removed, which is what
+/// `try_rm_r` does. With `CommandLineOnly`, if `path` is itself a link, the
+/// file or tree it points to is removed along with it. With `Always`, every
+/// link is removed along with what it points to, even outside the tree; a
+/// link to a directory being removed already is removed alone. Use this
+/// carefully.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::FollowSymlinks;
+///
+/// fsutils::mkdir("rm_r_with_dir/cache/data");
+/// fsutils::write_file("rm_r_with_dir/cache/data/blob", "bytes");
+///
+/// if fsutils::try_ln_s("cache", "rm_r_with_dir/current").is_ok() {
+///     fsutils::rm_r_with("rm_r_with_dir/current", FollowSymlinks::Never).unwrap();
+///     assert!(fsutils::path_exists("rm_r_with_dir/cache/data/blob"));
+///
+///     fsutils::try_ln_s("cache", "rm_r_with_dir/current").unwrap();
+///     fsutils::rm_r_with("rm_r_with_dir/current", FollowSymlinks::CommandLineOnly).unwrap();
+///     assert!(!fsutils::path_exists("rm_r_with_dir/cache"));
+///     assert!(!fsutils::path_exists("rm_r_with_dir/current"));
+/// }
+///
+/// # // Cleanup
+/// # fsutils::rm_r("rm_r_with_dir");
+/// ```
+pub fn rm_r_with<P: AsRef<Path>>(path: P, follow: FollowSymlinks) -> Result<()> {
+    let path = path.as_ref();
+    path.symlink_metadata().at(path)?;
+    Op::new("rm_r", path).destructive(true).run((), || {
+        remove_followed(path, follow, true, &mut Ancestors::new(follow))?;
+        info!("Removed directory at {}", path.display());
+        Ok(())
+    })
+}
+
+/// Removes `path`, and what it points to if it is a link that `follow` says
+/// to follow, `top` meaning it is the path given to `rm_r_with`.
+fn remove_followed(path: &Path, follow: FollowSymlinks, top: bool, ancestors: &mut Ancestors) -> Result<()> {
+    let meta = path.symlink_metadata().at(path)?;
+    if !meta.file_type().is_symlink() {
+        return if meta.is_dir() {
+            remove_dir_followed(path, follow, ancestors)
+        } else {
+            fs::remove_file(path).at(path)
+        };
+    }
+    if follow.follows(top) {
+        // A dangling link has nothing to remove behind it
+        if let Ok(target) = fs::canonicalize(path) {
+            if target.is_dir() {
+                remove_dir_followed(&target, follow, ancestors)?;
+            } else {
+                fs::remove_file(&target).at(&target)?;
+            }
+        }
+    }
+    fs::remove_file(path).at(path)
+}
+
+/// Removes the directory `dir`, following the links in it if `follow` is
+/// `Always`. A directory the removal is inside already is left alone.
+fn remove_dir_followed(dir: &Path, follow: FollowSymlinks, ancestors: &mut Ancestors) -> Result<()> {
+    if follow != FollowSymlinks::Always {
+        return fs::remove_dir_all(dir).at(dir);
+    }
+    if !ancestors.enter(dir)? {
+        return Ok(());
+    }
+    for entry in fs::read_dir(dir).at(dir)? {
+        let path = entry.at(dir)?.path();
+        match remove_followed(&path, follow, false, ancestors) {
+            // Removed already through a link to it
+            Err(FsError::NotFound { path: missing, .. }) if missing == path => {}
+            result => result?,
+        }
+    }
+    ancestors.leave();
+    fs::remove_dir(dir).at(dir)
+}
+
+/// Removes a directory tree using several threads
+/// and returns a boolean based on success or failure.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::mkdir("rm_r_parallel_dir/a/b");
+/// fsutils::create_file("rm_r_parallel_dir/a/b/file");
+///
+/// assert_eq!(fsutils::rm_r_parallel("rm_r_parallel_dir", 4), true);
+/// assert!(!fsutils::path_exists("rm_r_parallel_dir"));
+/// ```
+pub fn rm_r_parallel<P: AsRef<Path>>(path: P, threads: usize) -> bool {
+    match try_rm_r_parallel(path, threads) {
+        Ok(_) => true,
+        Err(e) => {
+            error!("Directory removal error: {}", e);
+            false
+        }
+    }
+}
+
+/// Removes a directory tree using `threads` threads, or one per CPU for `0`.
+///
+/// Each thread takes a directory off a shared queue, unlinks its files and
+/// queues its subdirectories. The emptied directories are removed last,
+/// deepest first. Symlinks are removed, not followed.
+///
+/// Whether this beats `fsutils::try_rm_r` depends on the filesystem and the
+/// number of CPUs, and on a single CPU it does not. The `rm_r_parallel`
+/// example times both on a given directory:
+/// `cargo run --release --example rm_r_parallel -- <dir>`.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::mkdir("try_rm_r_parallel_dir");
+/// for i in 0..50 {
+///     fsutils::mkdir(format!("try_rm_r_parallel_dir/{}", i));
+///     fsutils::create_file(format!("try_rm_r_parallel_dir/{}/file", i));
+/// }
+///
+/// assert!(fsutils::try_rm_r_parallel("try_rm_r_parallel_dir", 0).is_ok());
+/// assert!(fsutils::try_rm_r_parallel("try_rm_r_parallel_dir", 0).is_err());
+/// ```
+pub fn try_rm_r_parallel<P: AsRef<Path>>(path: P, threads: usize) -> Result<()> {
+    let path = path.as_ref();
+    if !path.symlink_metadata().at(path)?.is_dir() {
+        let source = std::io::Error::new(std::io::ErrorKind::NotADirectory, "use rm to remove files");
+        return Err(FsError::from_io(path, source));
+    }
+    let threads = match threads {
+        0 => thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n,
+    };
+    Op::new("rm_r", path).destructive(true).run((), || {
+        let queue = RemovalQueue::new(path);
+        thread::scope(|scope| {
+            for _ in 0..threads {
+                scope.spawn(|| queue.work());
+            }
+        });
+        let mut dirs = queue.finish()?;
+        dirs.sort_by_key(|dir| std::cmp::Reverse(dir.components().count()));
+        for dir in &dirs {
+            fs::remove_dir(dir).at(dir)?;
+        }
+        info!("Removed directory at {}", path.display());
+        Ok(())
+    })
+}
+
+/// Directories waiting to be emptied, shared by the threads of `try_rm_r_parallel`.
+struct RemovalQueue {
+    state: Mutex<QueueState>,
+    changed: Condvar,
+}
+
+struct QueueState {
+    pending: Vec<PathBuf>,
+    /// Directories seen so far, to remove once they are empty.
+    seen: Vec<PathBuf>,
+    /// How many threads are emptying a directory, and so may queue more.
+    busy: usize,
+    failure: Option<FsError>,
+}
+
+impl RemovalQueue {
+    fn new(root: &Path) -> RemovalQueue {
+        let state = QueueState {
+            pending: vec![root.to_path_buf()],
+            seen: vec![root.to_path_buf()],
+            busy: 0,
+            failure: None,
+        };
+        RemovalQueue { state: Mutex::new(state), changed: Condvar::new() }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, QueueState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Empties queued directories until none are left or one fails.
+    fn work(&self) {
+        loop {
+            let dir = {
+                let mut state = self.lock();
+                loop {
+                    if state.failure.is_some() {
+                        return;
+                    }
+                    if let Some(dir) = state.pending.pop() {
+                        state.busy += 1;
+                        break dir;
+                    }
+                    if state.busy == 0 {
+                        return;
+                    }
+                    state = self.changed.wait(state).unwrap_or_else(|e| e.into_inner());
+                }
+            };
+            let result = empty_dir(&dir);
+            let mut state = self.lock();
+            state.busy -= 1;
+            match result {
+                Ok(subdirs) => {
+                    state.seen.extend(subdirs.iter().cloned());
+                    state.pending.extend(subdirs);
+                }
+                Err(e) => {
+                    state.failure.get_or_insert(e);
+                }
+            }
+            self.changed.notify_all();
+        }
+    }
+
+    /// Returns every directory seen, or the first failure.
+    fn finish(self) -> Result<Vec<PathBuf>> {
+        let state = self.state.into_inner().unwrap_or_else(|e| e.into_inner());
+        match state.failure {
+            Some(e) => Err(e),
+            None => Ok(state.seen),
+        }
+    }
+}
+
+/// Removes everything in `dir` except subdirectories, which are returned.
+fn empty_dir(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut subdirs = Vec::new();
+    for entry in fs::read_dir(dir).at(dir)? {
+        let entry = entry.at(dir)?;
+        let path = entry.path();
+        if entry.file_type().at(&path)?.is_dir() {
+            subdirs.push(path);
+        } else {
+            fs::remove_file(&path).at(&path)?;
+        }
+    }
+    Ok(subdirs)
+}
+
+/// Removes the files under `path` for which `predicate` returns `true`, then
+/// any directories left empty by that, and returns the paths removed.
+///
+/// The predicate is given each file's path and `symlink_metadata`, so
+/// symlinks are judged, and removed, themselves. Directories that were already
+/// empty, and `path` itself, are kept.
+///
+/// ## Usage:
+///
+/// ```
+/// use std::time::{Duration, SystemTime};
+///
+/// fsutils::mkdir("rm_r_filtered_dir/old");
+/// fsutils::write_file("rm_r_filtered_dir/old/big.bin", &"x".repeat(1000));
+/// fsutils::write_file("rm_r_filtered_dir/small.txt", "x");
+///
+/// let removed = fsutils::rm_r_filtered("rm_r_filtered_dir", |_, meta| meta.len() > 100).unwrap();
+/// assert_eq!(removed.len(), 2);
+/// assert!(!fsutils::path_exists("rm_r_filtered_dir/old"));
+/// assert!(fsutils::path_exists("rm_r_filtered_dir/small.txt"));
+///
+/// // Or by age, like everything not touched in a week
+/// let week_ago = SystemTime::now() - Duration::from_secs(7 * 24 * 60 * 60);
+/// let removed = fsutils::rm_r_filtered("rm_r_filtered_dir", |_, meta| {
+///     meta.modified().map_or(false, |time| time < week_ago)
+/// });
+/// assert!(removed.unwrap().is_empty());
+///
+/// # // Cleanup
+/// # fsutils::rm_r("rm_r_filtered_dir");
+/// ```
+pub fn rm_r_filtered<P, F>(path: P, mut predicate: F) -> Result<Vec<PathBuf>>
+where
+    P: AsRef<Path>,
+    F: FnMut(&Path, &fs::Metadata) -> bool,
+{
+    let path = path.as_ref();
+    if !path.metadata().at(path)?.is_dir() {
+        return Err(FsError::from_io(path, std::io::ErrorKind::NotADirectory.into()));
+    }
+    let mut removed = Vec::new();
+    Op::new("rm_r_filtered", path).run((), || remove_matching(path, &mut predicate, &mut removed).map(|_| ()))?;
+    Ok(removed)
+}
+
+/// Removes the files under `path` matching any of `patterns`, then any
+/// directories left empty by that, and returns the paths removed.
+///
+/// A pattern without a `/` is matched against file names, so `*.o` finds
+/// object files at any depth; anything else against the path relative to
+/// `path`, as in `fsutils::CopyOptions::exclude`.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::mkdir("rm_r_matching_dir/src");
+/// fsutils::create_file("rm_r_matching_dir/src/main.o");
+/// fsutils::create_file("rm_r_matching_dir/src/main.c");
+/// fsutils::create_file("rm_r_matching_dir/core");
+///
+/// let removed = fsutils::rm_r_matching("rm_r_matching_dir", &["*.o", "core"]).unwrap();
+/// assert_eq!(removed.len(), 2);
+/// assert!(fsutils::path_exists("rm_r_matching_dir/src/main.c"));
+///
+/// # // Cleanup
+/// # fsutils::rm_r("rm_r_matching_dir");
+/// ```
+pub fn rm_r_matching<P: AsRef<Path>>(path: P, patterns: &[&str]) -> Result<Vec<PathBuf>> {
+    let path = path.as_ref();
+    let patterns = patterns.iter().map(|p| Pattern::new(p)).collect::<Result<Vec<_>>>()?;
+    rm_r_filtered(path, |file, _| {
+        let relative = file.strip_prefix(path).unwrap_or(file);
+        patterns.iter().any(|p| p.matches_path(relative))
+    })
+}
+
+/// Removes matching files below `dir`, and subdirectories emptied by that.
+/// Returns whether anything in `dir` was removed.
+fn remove_matching<F>(dir: &Path, predicate: &mut F, removed: &mut Vec<PathBuf>) -> Result<bool>
+where
+    F: FnMut(&Path, &fs::Metadata) -> bool,
+{
+    let mut any = false;
+    for entry in fs::read_dir(dir).at(dir)? {
+        let path = entry.at(dir)?.path();
+        let meta = path.symlink_metadata().at(&path)?;
+        if meta.is_dir() {
+            if remove_matching(&path, predicate, removed)? {
+                any = true;
+                if fs::read_dir(&path).at(&path)?.next().is_none() && confirm_each("rm_r_filtered", &path) {
+                    fs::remove_dir(&path).at(&path)?;
+                    removed.push(path);
+                }
+            }
+        } else if predicate(&path, &meta) && confirm_each("rm_r_filtered", &path) {
+            fs::remove_file(&path).at(&path)?;
+            removed.push(path);
+            any = true;
+        }
+    }
+    Ok(any)
+}
+
+/// Removes every empty directory below `root`
+/// and returns how many were removed.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::mkdir("prune_dir/a/b/c");
+/// fsutils::mkdir("prune_dir/kept");
+/// fsutils::create_file("prune_dir/kept/file");
+///
+/// assert_eq!(fsutils::prune_empty_dirs("prune_dir"), Some(3));
+/// assert!(fsutils::path_exists("prune_dir/kept/file"));
+/// assert!(!fsutils::path_exists("prune_dir/a"));
+///
+/// # // Cleanup
+/// # fsutils::rm_r("prune_dir");
+/// ```
+pub fn prune_empty_dirs<P: AsRef<Path>>(root: P) -> Option<usize> {
+    match try_prune_empty_dirs(root) {
+        Ok(count) => Some(count),
+        Err(e) => {
+            error!("Directory removal error: {}", e);
+            None
+        }
+    }
+}
+
+/// Removes every empty directory below `root`, deepest first, so directories
+/// holding only empty directories go too, and returns how many were removed.
+///
+/// `root` itself is kept, and symlinks to directories are not followed.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::mkdir("try_prune_dir/empty");
+///
+/// assert_eq!(fsutils::try_prune_empty_dirs("try_prune_dir").unwrap(), 1);
+/// assert_eq!(fsutils::try_prune_empty_dirs("try_prune_dir").unwrap(), 0);
+/// assert!(fsutils::path_exists("try_prune_dir"));
+///
+/// # // Cleanup
+/// # fsutils::rmdir("try_prune_dir");
+/// ```
+pub fn try_prune_empty_dirs<P: AsRef<Path>>(root: P) -> Result<usize> {
+    let root = root.as_ref();
+    if !root.metadata().at(root)?.is_dir() {
+        return Err(FsError::from_io(root, std::io::ErrorKind::NotADirectory.into()));
+    }
+    Op::new("prune_empty_dirs", root).run(0, || {
+        let mut count = 0;
+        prune(root, &mut count)?;
+        info!("Removed {} empty directories under {}", count, root.display());
+        Ok(count)
+    })
+}
+
+/// Prunes below `dir` and returns whether `dir` is now empty.
+fn prune(dir: &Path, count: &mut usize) -> Result<bool> {
+    let mut empty = true;
+    for entry in fs::read_dir(dir).at(dir)? {
+        let entry = entry.at(dir)?;
+        let path = entry.path();
+        if entry.file_type().at(&path)?.is_dir() && prune(&path, count)? && confirm_each("prune_empty_dirs", &path) {
+            fs::remove_dir(&path).at(&path)?;
+            *count += 1;
+        } else {
+            empty = false;
+        }
+    }
+    Ok(empty)
+}
+
+/// Options for `fsutils::purge_older_than_with`.
+///
+/// By default only files are removed, judged by their modification time.
+#[derive(Clone, Debug, Default)]
+pub struct PurgeOptions {
+    access_time: bool,
+    directories: bool,
+}
+
+impl PurgeOptions {
+    /// Creates the default options.
+    pub fn new() -> PurgeOptions {
+        PurgeOptions::default()
+    }
+
+    /// Judge age by the last access instead of the last modification, like
+    /// `tmpwatch --atime`. Many systems only update access times lazily.
+    pub fn access_time(&mut self, access_time: bool) -> &mut PurgeOptions {
+        self.access_time = access_time;
+        self
+    }
+
+    /// Also remove directories that are old and empty once their old files are gone.
+    pub fn directories(&mut self, directories: bool) -> &mut PurgeOptions {
+        self.directories = directories;
+        self
+    }
+
+    fn is_old(&self, meta: &fs::Metadata, cutoff: SystemTime) -> bool {
+        let time = if self.access_time { meta.accessed() } else { meta.modified() };
+        time.is_ok_and(|time| time < cutoff)
+    }
+}
+
+/// Removes the files under `dir` last modified more than `age` ago and
+/// returns the paths removed.
+///
+/// See `fsutils::purge_older_than_with` for more control.
+///
+/// ## Usage:
+///
+/// ```
+/// use std::fs::{File, FileTimes};
+/// use std::time::{Duration, SystemTime};
+///
+/// fsutils::mkdir("purge_cache");
+/// fsutils::create_file("purge_cache/fresh");
+/// fsutils::create_file("purge_cache/stale");
+/// let last_month = SystemTime::now() - Duration::from_secs(30 * 24 * 60 * 60);
+/// File::options().write(true).open("purge_cache/stale").unwrap()
+///     .set_times(FileTimes::new().set_modified(last_month)).unwrap();
+///
+/// let removed = fsutils::purge_older_than("purge_cache", Duration::from_secs(7 * 24 * 60 * 60)).unwrap();
+/// assert_eq!(removed.len(), 1);
+/// assert!(fsutils::path_exists("purge_cache/fresh"));
+///
+/// # // Cleanup
+/// # fsutils::rm_r("purge_cache");
+/// ```
+pub fn purge_older_than<P: AsRef<Path>>(dir: P, age: Duration) -> Result<Vec<PathBuf>> {
+    purge_older_than_with(dir, age, &PurgeOptions::new())
+}
+
+/// Removes the files, and optionally directories, under `dir` older than `age`
+/// according to `opts`, and returns the paths removed.
+///
+/// Directory ages are taken before their contents are purged, since removing
+/// files touches the directory. `dir` itself is kept.
+///
+/// ## Usage:
+///
+/// ```
+/// use std::time::Duration;
+/// use fsutils::PurgeOptions;
+///
+/// fsutils::mkdir("purge_with_tmp/session");
+///
+/// let mut opts = PurgeOptions::new();
+/// opts.directories(true);
+/// let removed = fsutils::purge_older_than_with("purge_with_tmp", Duration::from_secs(0), &opts).unwrap();
+/// assert_eq!(removed.len(), 1);
+///
+/// # // Cleanup
+/// # fsutils::rmdir("purge_with_tmp");
+/// ```
+pub fn purge_older_than_with<P: AsRef<Path>>(dir: P, age: Duration, opts: &PurgeOptions) -> Result<Vec<PathBuf>> {
+    let dir = dir.as_ref();
+    if !dir.metadata().at(dir)?.is_dir() {
+        return Err(FsError::from_io(dir, std::io::ErrorKind::NotADirectory.into()));
+    }
+    let cutoff = SystemTime::now().checked_sub(age).unwrap_or(SystemTime::UNIX_EPOCH);
+    let mut removed = Vec::new();
+    Op::new("purge_older_than", dir).run((), || purge(dir, cutoff, opts, &mut removed))?;
+    info!("Purged {} entries under {}", removed.len(), dir.display());
+    Ok(removed)
+}
+
+fn purge(dir: &Path, cutoff: SystemTime, opts: &PurgeOptions, removed: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(dir).at(dir)? {
+        let path = entry.at(dir)?.path();
+        let meta = path.symlink_metadata().at(&path)?;
+        if meta.is_dir() {
+            purge(&path, cutoff, opts, removed)?;
+            if opts.directories
+                && opts.is_old(&meta, cutoff)
+                && fs::read_dir(&path).at(&path)?.next().is_none()
+                && confirm_each("purge_older_than", &path)
+            {
+                fs::remove_dir(&path).at(&path)?;
+                removed.push(path);
+            }
+        } else if opts.is_old(&meta, cutoff) && confirm_each("purge_older_than", &path) {
+            fs::remove_file(&path).at(&path)?;
+            removed.push(path);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,256 @@
+// Copyright 2020 Jared Forth.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Searching file contents.
+
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+use crate::error::{IoResultExt, Result};
+use crate::glob::Pattern;
+
+/// How much of a file `fsutils::grep_r` looks at to decide whether it is binary.
+const BINARY_SNIFF: u64 = 8 * 1024;
+
+/// Options for `fsutils::grep_r_with`.
+///
+/// By default every file below the directory is searched, case-sensitively.
+/// Include and exclude patterns work as in `fsutils::CopyOptions`: a pattern
+/// without a `/` is matched against the file name, anything else against the
+/// path relative to the directory searched. Excludes apply to files and
+/// directories, includes only to files.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::GrepOptions;
+///
+/// let mut opts = GrepOptions::new();
+/// opts.include("*.rs").unwrap().exclude("target").unwrap().ignore_case(true);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct GrepOptions {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+    ignore_case: bool,
+}
+
+impl GrepOptions {
+    /// Creates the default options.
+    pub fn new() -> GrepOptions {
+        GrepOptions::default()
+    }
+
+    /// Only search files matching `pattern`. May be given several times.
+    pub fn include(&mut self, pattern: &str) -> Result<&mut GrepOptions> {
+        self.include.push(Pattern::new(pattern)?);
+        Ok(self)
+    }
+
+    /// Skip files and directories matching `pattern`. May be given several times.
+    pub fn exclude(&mut self, pattern: &str) -> Result<&mut GrepOptions> {
+        self.exclude.push(Pattern::new(pattern)?);
+        Ok(self)
+    }
+
+    /// Match regardless of case, like `grep -i`.
+    pub fn ignore_case(&mut self, ignore_case: bool) -> &mut GrepOptions {
+        self.ignore_case = ignore_case;
+        self
+    }
+
+    fn excluded(&self, relative: &Path) -> bool {
+        self.exclude.iter().any(|p| p.matches_path(relative))
+    }
+
+    fn included(&self, relative: &Path) -> bool {
+        self.include.is_empty() || self.include.iter().any(|p| p.matches_path(relative))
+    }
+}
+
+/// Returns the lines of a file containing `pattern`, with their line numbers
+/// counting from 1, or an empty `Vec` if the file cannot be read.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("grep.log", "INFO start\nERROR disk full\nINFO stop\n");
+///
+/// assert_eq!(fsutils::grep("grep.log", "ERROR"), [(2, String::from("ERROR disk full"))]);
+///
+/// # // Cleanup
+/// # fsutils::rm("grep.log");
+/// ```
+pub fn grep<P: AsRef<Path>>(path: P, pattern: &str) -> Vec<(usize, String)> {
+    match try_grep(path, pattern) {
+        Ok(matches) => matches,
+        Err(e) => {
+            error!("Cannot search file {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// Returns the lines of a file containing `pattern`, with their line numbers
+/// counting from 1.
+///
+/// `pattern` is a plain string, not a regular expression. The file is read
+/// line by line, so it can be larger than memory, and invalid UTF-8 is
+/// replaced with `U+FFFD` rather than failing the search.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("try_grep.txt", "alpha\r\nbeta\r\nalphabet\r\n");
+///
+/// let matches = fsutils::try_grep("try_grep.txt", "alpha").unwrap();
+/// assert_eq!(matches, [(1, String::from("alpha")), (3, String::from("alphabet"))]);
+/// assert!(fsutils::try_grep("a_very_1234_unlikely_9876_filename", "x").is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm("try_grep.txt");
+/// ```
+pub fn try_grep<P: AsRef<Path>>(path: P, pattern: &str) -> Result<Vec<(usize, String)>> {
+    let path = path.as_ref();
+    search_file(path, &Matcher::new(pattern, false))
+}
+
+/// Searches every file below `dir` for lines containing `pattern` and returns
+/// the matches as `(path, line number, line)`, or an empty `Vec` on failure.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::mkdir("grep_r_dir/src");
+/// fsutils::write_file("grep_r_dir/src/main.rs", "// TODO: tests\nfn main() {}\n");
+/// fsutils::write_file("grep_r_dir/notes.md", "nothing to do\n");
+///
+/// let matches = fsutils::grep_r("grep_r_dir", "TODO");
+/// assert_eq!(matches.len(), 1);
+/// assert!(matches[0].0.ends_with("src/main.rs"));
+///
+/// # // Cleanup
+/// # fsutils::rm_r("grep_r_dir");
+/// ```
+pub fn grep_r<P: AsRef<Path>>(dir: P, pattern: &str) -> Vec<(PathBuf, usize, String)> {
+    match grep_r_with(dir, pattern, &GrepOptions::new()) {
+        Ok(matches) => matches,
+        Err(e) => {
+            error!("Cannot search directory {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// Searches the files below `dir` selected by `opts` for lines containing
+/// `pattern` and returns the matches as `(path, line number, line)`.
+///
+/// Directories are searched in name order and symlinks are not followed.
+/// Like `grep -r`, files with a NUL byte near the start are taken to be
+/// binary and skipped.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::GrepOptions;
+///
+/// fsutils::mkdir("grep_r_with_dir/target");
+/// fsutils::write_file("grep_r_with_dir/lib.rs", "use Log;\n");
+/// fsutils::write_file("grep_r_with_dir/target/gen.rs", "use log;\n");
+/// fsutils::write_file("grep_r_with_dir/README", "log output\n");
+/// fsutils::create_file_bytes("grep_r_with_dir/blob.rs", b"\0log");
+///
+/// let mut opts = GrepOptions::new();
+/// opts.include("*.rs").unwrap().exclude("target").unwrap().ignore_case(true);
+/// let matches = fsutils::grep_r_with("grep_r_with_dir", "log", &opts).unwrap();
+/// assert_eq!(matches.len(), 1);
+/// assert_eq!(matches[0].2, "use Log;");
+///
+/// # // Cleanup
+/// # fsutils::rm_r("grep_r_with_dir");
+/// ```
+pub fn grep_r_with<P: AsRef<Path>>(dir: P, pattern: &str, opts: &GrepOptions) -> Result<Vec<(PathBuf, usize, String)>> {
+    let dir = dir.as_ref();
+    let matcher = Matcher::new(pattern, opts.ignore_case);
+    let mut matches = Vec::new();
+    search_dir(dir, dir, &matcher, opts, &mut matches)?;
+    Ok(matches)
+}
+
+fn search_dir(
+    root: &Path,
+    dir: &Path,
+    matcher: &Matcher,
+    opts: &GrepOptions,
+    matches: &mut Vec<(PathBuf, usize, String)>,
+) -> Result<()> {
+    let mut entries = fs::read_dir(dir).at(dir)?.collect::<std::io::Result<Vec<_>>>().at(dir)?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let path = entry.path();
+        let relative = path.strip_prefix(root).unwrap_or(&path);
+        if opts.excluded(relative) {
+            continue;
+        }
+        let file_type = entry.file_type().at(&path)?;
+        if file_type.is_dir() {
+            search_dir(root, &path, matcher, opts, matches)?;
+        } else if file_type.is_file() && opts.included(relative) && !is_binary(&path)? {
+            let found = search_file(&path, matcher)?;
+            matches.extend(found.into_iter().map(|(n, line)| (path.clone(), n, line)));
+        }
+    }
+    Ok(())
+}
+
+fn search_file(path: &Path, matcher: &Matcher) -> Result<Vec<(usize, String)>> {
+    let mut reader = BufReader::new(File::open(path).at(path)?);
+    let mut matches = Vec::new();
+    let mut line = Vec::new();
+    let mut number = 0;
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line).at(path)? == 0 {
+            return Ok(matches);
+        }
+        number += 1;
+        let text = String::from_utf8_lossy(&line);
+        let text = text.trim_end_matches('\n').trim_end_matches('\r');
+        if matcher.is_match(text) {
+            matches.push((number, text.to_string()));
+        }
+    }
+}
+
+/// Whether the start of the file contains a NUL byte, which text never does.
+fn is_binary(path: &Path) -> Result<bool> {
+    let mut start = Vec::new();
+    File::open(path).at(path)?.take(BINARY_SNIFF).read_to_end(&mut start).at(path)?;
+    Ok(start.contains(&0))
+}
+
+struct Matcher {
+    pattern: String,
+    ignore_case: bool,
+}
+
+impl Matcher {
+    fn new(pattern: &str, ignore_case: bool) -> Matcher {
+        let pattern = if ignore_case { pattern.to_lowercase() } else { pattern.to_string() };
+        Matcher { pattern, ignore_case }
+    }
+
+    fn is_match(&self, line: &str) -> bool {
+        if self.ignore_case {
+            line.to_lowercase().contains(&self.pattern)
+        } else {
+            line.contains(&self.pattern)
+        }
+    }
+}
@@ -0,0 +1,507 @@
+// Copyright 2020 Jared Forth.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Measuring the space used by files and trees.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, Metadata};
+use std::path::{Path, PathBuf};
+
+use crate::error::{FsError, IoResultExt, Result};
+use crate::link::{Ancestors, FollowSymlinks};
+use crate::meta::FileKind;
+
+/// Which size `fsutils::dir_size_with` adds up.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::SizeKind;
+///
+/// assert_eq!(SizeKind::default(), SizeKind::Apparent);
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum SizeKind {
+    /// The length of each file in bytes, like `du -b`.
+    #[default]
+    Apparent,
+    /// The space allocated for each file on disk, like `du -B1`, which is
+    /// usually rounded up to whole blocks and smaller for sparse files. Only
+    /// known on Unix; elsewhere this is the same as `Apparent`.
+    OnDisk,
+}
+
+impl SizeKind {
+    fn of(self, meta: &Metadata) -> u64 {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            if self == SizeKind::OnDisk {
+                return meta.blocks() * 512;
+            }
+        }
+        meta.len()
+    }
+}
+
+/// Returns the size of a file in bytes, or 0 if it cannot be read.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("file_size.txt", "12345");
+///
+/// assert_eq!(fsutils::file_size("file_size.txt"), 5);
+/// assert_eq!(fsutils::file_size("a_very_1234_unlikely_9876_filename"), 0);
+///
+/// # // Cleanup
+/// # fsutils::rm("file_size.txt");
+/// ```
+pub fn file_size<P: AsRef<Path>>(path: P) -> u64 {
+    match try_file_size(path) {
+        Ok(size) => size,
+        Err(e) => {
+            error!("Cannot read metadata {}", e);
+            0
+        }
+    }
+}
+
+/// Returns the size of a file in bytes. Symbolic links are followed.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::create_file_bytes("try_file_size.bin", &[0; 1000]);
+///
+/// assert_eq!(fsutils::try_file_size("try_file_size.bin").unwrap(), 1000);
+/// assert!(fsutils::try_file_size("a_very_1234_unlikely_9876_filename").is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm("try_file_size.bin");
+/// ```
+pub fn try_file_size<P: AsRef<Path>>(path: P) -> Result<u64> {
+    let path = path.as_ref();
+    Ok(fs::metadata(path).at(path)?.len())
+}
+
+/// Returns the total size in bytes of the files below a directory, or 0 if
+/// it cannot be read.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::mkdir("dir_size_dir/sub");
+/// fsutils::write_file("dir_size_dir/a.txt", "123");
+/// fsutils::write_file("dir_size_dir/sub/b.txt", "4567");
+///
+/// assert_eq!(fsutils::dir_size("dir_size_dir"), 7);
+///
+/// # // Cleanup
+/// # fsutils::rm_r("dir_size_dir");
+/// ```
+pub fn dir_size<P: AsRef<Path>>(path: P) -> u64 {
+    match try_dir_size(path) {
+        Ok(size) => size,
+        Err(e) => {
+            error!("Cannot measure directory {}", e);
+            0
+        }
+    }
+}
+
+/// Returns the total length in bytes of the files below a directory, like
+/// `du -sb`.
+///
+/// See `fsutils::dir_size_with` for the details.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::mkdir("try_dir_size_dir");
+/// fsutils::create_file_bytes("try_dir_size_dir/data.bin", &[1; 4096]);
+///
+/// assert_eq!(fsutils::try_dir_size("try_dir_size_dir").unwrap(), 4096);
+/// assert!(fsutils::try_dir_size("a_very_1234_unlikely_9876_dirname").is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm_r("try_dir_size_dir");
+/// ```
+pub fn try_dir_size<P: AsRef<Path>>(path: P) -> Result<u64> {
+    dir_size_with(path, SizeKind::Apparent, FollowSymlinks::default())
+}
+
+/// Returns the total size of the files below a directory, counting either
+/// their lengths or the space they take up on disk.
+///
+/// Only regular files are counted, not directories themselves, and `follow`
+/// decides which symbolic links are counted as what they point to; links
+/// that are not followed count as nothing. A file with several hard links in
+/// the tree, or reached through several symbolic links, is counted once on
+/// Unix, as `du` does. Passing a file gives its own size.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::{FollowSymlinks, SizeKind};
+///
+/// fsutils::mkdir("dir_size_with_dir/tree");
+/// fsutils::write_file("dir_size_with_dir/tree/small.txt", "x");
+/// fsutils::write_file("dir_size_with_dir/outside.txt", "12345");
+///
+/// let size = fsutils::dir_size_with("dir_size_with_dir/tree", SizeKind::Apparent, FollowSymlinks::default());
+/// assert_eq!(size.unwrap(), 1);
+/// # #[cfg(unix)]
+/// assert!(fsutils::dir_size_with("dir_size_with_dir/tree", SizeKind::OnDisk, FollowSymlinks::Never).unwrap() >= 1);
+///
+/// if fsutils::try_ln_s("../outside.txt", "dir_size_with_dir/tree/link.txt").is_ok() {
+///     let size = fsutils::dir_size_with("dir_size_with_dir/tree", SizeKind::Apparent, FollowSymlinks::Always);
+///     assert_eq!(size.unwrap(), 6);
+/// }
+///
+/// # // Cleanup
+/// # fsutils::rm_r("dir_size_with_dir");
+/// ```
+pub fn dir_size_with<P: AsRef<Path>>(path: P, kind: SizeKind, follow: FollowSymlinks) -> Result<u64> {
+    let path = path.as_ref();
+    Tally::new(kind, follow).size(path, true)
+}
+
+/// A directory and the total size of the files below it, reported by
+/// `fsutils::du`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct DuEntry {
+    /// The directory, starting with the path that was passed to `du`.
+    pub path: PathBuf,
+    /// The total size of the files anywhere below the directory.
+    pub size: u64,
+    /// How far below the top directory this one is, where the top is 0.
+    pub depth: usize,
+}
+
+/// Returns the size of a directory and each of its subdirectories down to
+/// `max_depth`, or an empty `Vec` if it cannot be read.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::mkdir("du_dir/logs");
+/// fsutils::write_file("du_dir/logs/app.log", "0123456789");
+///
+/// for entry in fsutils::du("du_dir", 1) {
+///     println!("{:>10}  {}", fsutils::format_size(entry.size), entry.path.display());
+/// }
+///
+/// # // Cleanup
+/// # fsutils::rm_r("du_dir");
+/// ```
+pub fn du<P: AsRef<Path>>(path: P, max_depth: usize) -> Vec<DuEntry> {
+    match try_du(path, max_depth) {
+        Ok(entries) => entries,
+        Err(e) => {
+            error!("Cannot measure directory {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// Returns the length of the files below a directory and each of its
+/// subdirectories down to `max_depth`, like `du -b --max-depth`.
+///
+/// See `fsutils::du_with` for the details.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::mkdir("try_du_dir/a/deep");
+/// fsutils::mkdir("try_du_dir/b");
+/// fsutils::write_file("try_du_dir/a/deep/file.txt", "12345");
+/// fsutils::write_file("try_du_dir/b/file.txt", "12");
+///
+/// let entries = fsutils::try_du("try_du_dir", 1).unwrap();
+/// let sizes: Vec<_> = entries.iter().map(|e| (e.path.to_str().unwrap(), e.size)).collect();
+/// # #[cfg(unix)]
+/// assert_eq!(sizes, [("try_du_dir/a", 5), ("try_du_dir/b", 2), ("try_du_dir", 7)]);
+/// assert!(fsutils::try_du("a_very_1234_unlikely_9876_dirname", 1).is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm_r("try_du_dir");
+/// ```
+pub fn try_du<P: AsRef<Path>>(path: P, max_depth: usize) -> Result<Vec<DuEntry>> {
+    du_with(path, max_depth, SizeKind::Apparent, FollowSymlinks::default())
+}
+
+/// Returns the size of the files below a directory and each of its
+/// subdirectories down to `max_depth`, counting either their lengths or the
+/// space they take up on disk.
+///
+/// Sizes are added up as by `fsutils::dir_size_with`, so every entry
+/// includes all the files below it, however deep. As in the output of `du`,
+/// each directory comes after the ones inside it, siblings are in name
+/// order, and the top directory is last. With a `max_depth` of 0 only the
+/// top directory is reported.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::{FollowSymlinks, SizeKind};
+///
+/// fsutils::mkdir("du_with_dir/sub");
+/// fsutils::create_file_bytes("du_with_dir/sub/data.bin", &[0; 2048]);
+///
+/// let entries = fsutils::du_with("du_with_dir", 0, SizeKind::OnDisk, FollowSymlinks::Never).unwrap();
+/// assert_eq!(entries.len(), 1);
+/// assert_eq!(entries[0].depth, 0);
+///
+/// # // Cleanup
+/// # fsutils::rm_r("du_with_dir");
+/// ```
+pub fn du_with<P: AsRef<Path>>(
+    path: P,
+    max_depth: usize,
+    kind: SizeKind,
+    follow: FollowSymlinks,
+) -> Result<Vec<DuEntry>> {
+    let path = path.as_ref();
+    if !follow.metadata(path, true).at(path)?.is_dir() {
+        let source = std::io::Error::new(std::io::ErrorKind::NotADirectory, "use file_size to measure files");
+        return Err(FsError::from_io(path, source));
+    }
+    let mut entries = Vec::new();
+    Tally::new(kind, follow).report(path, 0, max_depth, &mut entries)?;
+    Ok(entries)
+}
+
+/// Counts and sizes for a directory tree, returned by `fsutils::dir_stats`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DirStats {
+    /// The number of regular files.
+    pub files: u64,
+    /// The number of directories below the root, not counting the root.
+    pub dirs: u64,
+    /// The number of symbolic links. They are not followed.
+    pub symlinks: u64,
+    /// The number of entries that are none of the above, such as sockets.
+    pub others: u64,
+    /// The total length in bytes of the files, counting a file with several
+    /// hard links in the tree once on Unix, like `fsutils::dir_size`.
+    pub total_size: u64,
+    /// The number and total length of the files with each extension, without
+    /// the dot and as it is written, so `"jpg"` and `"JPG"` are apart. Files
+    /// without an extension are under `""`. Extensions that are not valid
+    /// UTF-8 are converted lossily.
+    pub by_extension: BTreeMap<String, ExtensionStats>,
+    /// The largest file and its length in bytes, the first in walk order
+    /// among equal lengths, or `None` if there are no files.
+    pub largest: Option<(PathBuf, u64)>,
+}
+
+/// The files with one extension, in `DirStats::by_extension`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ExtensionStats {
+    /// The number of files.
+    pub count: u64,
+    /// Their total length in bytes, every hard link counted.
+    pub size: u64,
+}
+
+/// Returns counts and sizes for the tree at `path`, or `None` if any of it
+/// cannot be read.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::mkdir("dir_stats_dir/sub");
+/// fsutils::write_file("dir_stats_dir/sub/a.txt", "123");
+///
+/// if let Some(stats) = fsutils::dir_stats("dir_stats_dir") {
+///     println!("{} files in {} directories", stats.files, stats.dirs);
+/// }
+///
+/// # // Cleanup
+/// # fsutils::rm_r("dir_stats_dir");
+/// ```
+pub fn dir_stats<P: AsRef<Path>>(path: P) -> Option<DirStats> {
+    match try_dir_stats(path) {
+        Ok(stats) => Some(stats),
+        Err(e) => {
+            error!("Cannot get directory statistics {}", e);
+            None
+        }
+    }
+}
+
+/// Returns counts of the files, directories and links in the tree at `path`,
+/// the total size of the files, their sizes by extension and the largest one,
+/// all in a single walk.
+///
+/// Symbolic links are counted as links and not followed, unless the root is
+/// one. Fails on the first entry that cannot be read. Passing a file gives
+/// the statistics for that file alone.
+///
+/// ## Usage:
+///
+/// ```
+/// use std::path::Path;
+///
+/// fsutils::mkdir("try_dir_stats_dir/logs");
+/// fsutils::write_file("try_dir_stats_dir/notes.txt", "12");
+/// fsutils::write_file("try_dir_stats_dir/logs/a.log", "1234");
+/// fsutils::write_file("try_dir_stats_dir/logs/b.log", "123456");
+/// fsutils::write_file("try_dir_stats_dir/Makefile", "");
+///
+/// let stats = fsutils::try_dir_stats("try_dir_stats_dir").unwrap();
+/// assert_eq!((stats.files, stats.dirs, stats.total_size), (4, 1, 12));
+/// assert_eq!(stats.by_extension["log"].count, 2);
+/// assert_eq!(stats.by_extension["log"].size, 10);
+/// assert_eq!(stats.by_extension[""].count, 1);
+/// assert_eq!(stats.largest, Some((Path::new("try_dir_stats_dir/logs/b.log").to_path_buf(), 6)));
+/// assert!(fsutils::try_dir_stats("a_very_1234_unlikely_9876_dirname").is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm_r("try_dir_stats_dir");
+/// ```
+pub fn try_dir_stats<P: AsRef<Path>>(path: P) -> Result<DirStats> {
+    let mut stats = DirStats::default();
+    let mut tally = Tally::new(SizeKind::Apparent, FollowSymlinks::default());
+    for entry in crate::walk::walk(path) {
+        let entry = entry?;
+        match entry.kind {
+            FileKind::File => {
+                let meta = entry.path.metadata().at(&entry.path)?;
+                let len = meta.len();
+                stats.files += 1;
+                stats.total_size += tally.file(&meta);
+                let extension = entry.path.extension().map(|ext| ext.to_string_lossy().into_owned()).unwrap_or_default();
+                let by_extension = stats.by_extension.entry(extension).or_default();
+                by_extension.count += 1;
+                by_extension.size += len;
+                if stats.largest.as_ref().is_none_or(|(_, largest)| len > *largest) {
+                    stats.largest = Some((entry.path, len));
+                }
+            }
+            FileKind::Dir if entry.depth > 0 => stats.dirs += 1,
+            FileKind::Dir => {}
+            FileKind::Symlink => stats.symlinks += 1,
+            FileKind::Other => stats.others += 1,
+        }
+    }
+    Ok(stats)
+}
+
+/// Formats a number of bytes for people to read, in powers of 1024, such as
+/// `"512 B"`, `"1.5 KiB"` or `"20.0 GiB"`.
+///
+/// ## Usage:
+///
+/// ```
+/// assert_eq!(fsutils::format_size(1000), "1000 B");
+/// assert_eq!(fsutils::format_size(1536), "1.5 KiB");
+/// assert_eq!(fsutils::format_size(5 * 1024 * 1024 * 1024), "5.0 GiB");
+/// ```
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 6] = ["KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    // Move up before rounding would show 1024.0 of the smaller unit
+    while size >= 1023.95 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// Adds up sizes across a walk, remembering hard-linked files already counted.
+struct Tally {
+    kind: SizeKind,
+    follow: FollowSymlinks,
+    ancestors: Ancestors,
+    #[cfg_attr(not(unix), allow(dead_code))]
+    seen: HashSet<(u64, u64)>,
+}
+
+impl Tally {
+    fn new(kind: SizeKind, follow: FollowSymlinks) -> Tally {
+        Tally { kind, follow, ancestors: Ancestors::new(follow), seen: HashSet::new() }
+    }
+
+    /// The size of the file or tree at `path`, `top` meaning it is where the walk starts.
+    fn size(&mut self, path: &Path, top: bool) -> Result<u64> {
+        let meta = self.follow.metadata(path, top).at(path)?;
+        if !meta.is_dir() {
+            return Ok(self.file(&meta));
+        }
+        if !self.ancestors.enter(path)? {
+            return Ok(0);
+        }
+        let mut total = 0;
+        for entry in fs::read_dir(path).at(path)? {
+            let entry = entry.at(path)?;
+            let file_type = entry.file_type().at(entry.path())?;
+            if file_type.is_dir() || (file_type.is_symlink() && self.follow.follows(false)) {
+                total += self.size(&entry.path(), false)?;
+            } else if file_type.is_file() {
+                total += self.file(&entry.metadata().at(entry.path())?);
+            }
+        }
+        self.ancestors.leave();
+        Ok(total)
+    }
+
+    /// The size of the tree at `dir`, adding an entry for it and for each
+    /// directory inside it down to `max_depth` to `entries`.
+    fn report(&mut self, dir: &Path, depth: usize, max_depth: usize, entries: &mut Vec<DuEntry>) -> Result<u64> {
+        if !self.ancestors.enter(dir)? {
+            return Ok(0);
+        }
+        let mut children = fs::read_dir(dir).at(dir)?.collect::<std::io::Result<Vec<_>>>().at(dir)?;
+        children.sort_by_key(|entry| entry.file_name());
+        let mut total = 0;
+        for child in children {
+            let path = child.path();
+            let mut file_type = child.file_type().at(&path)?;
+            if file_type.is_symlink() && self.follow.follows(false) {
+                file_type = self.follow.metadata(&path, false).at(&path)?.file_type();
+            }
+            if file_type.is_dir() {
+                total += if depth < max_depth {
+                    self.report(&path, depth + 1, max_depth, entries)?
+                } else {
+                    self.size(&path, false)?
+                };
+            } else if file_type.is_file() {
+                total += self.file(&path.metadata().at(&path)?);
+            }
+        }
+        self.ancestors.leave();
+        entries.push(DuEntry { path: dir.to_path_buf(), size: total, depth });
+        Ok(total)
+    }
+
+    /// The size of a single entry, or 0 if it is not a regular file or is a
+    /// file already counted.
+    fn file(&mut self, meta: &Metadata) -> u64 {
+        if !meta.is_file() {
+            return 0;
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            // Any file may be reached twice when links are followed
+            let shared = meta.nlink() > 1 || self.follow == FollowSymlinks::Always;
+            if shared && !self.seen.insert((meta.dev(), meta.ino())) {
+                return 0;
+            }
+        }
+        self.kind.of(meta)
+    }
+}
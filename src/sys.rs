@@ -0,0 +1,276 @@
+// Copyright 2020 Jared Forth.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Thin wrappers around platform calls that std does not expose.
+
+use std::io;
+
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "macos", target_os = "ios"))]
+fn check(ret: libc::c_int) -> io::Result<()> {
+    if ret == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos", target_os = "ios"))]
+fn cstring(path: &std::path::Path) -> io::Result<std::ffi::CString> {
+    use std::os::unix::ffi::OsStrExt;
+    std::ffi::CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "path contains a NUL byte"))
+}
+
+/// Makes `dst` share the blocks of `src` (btrfs, XFS).
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) fn ficlone(src: &std::fs::File, dst: &std::fs::File) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+    // SAFETY: both descriptors stay open for the duration of the call
+    check(unsafe { libc::ioctl(dst.as_raw_fd(), libc::FICLONE, src.as_raw_fd()) })
+}
+
+/// Copies up to `len` bytes between the current positions of two files inside
+/// the kernel, and returns how many were copied.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) fn copy_file_range(from: &std::fs::File, to: &std::fs::File, len: usize) -> io::Result<usize> {
+    use std::os::unix::io::AsRawFd;
+    let null = std::ptr::null_mut();
+    // SAFETY: both descriptors stay open for the duration of the call, and null
+    // offsets mean the file positions are used and updated
+    let ret = unsafe { libc::copy_file_range(from.as_raw_fd(), null, to.as_raw_fd(), null, len, 0) };
+    if ret == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(ret as usize)
+    }
+}
+
+/// Whether `e` means a call is unavailable for these files, rather than that it failed.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) fn is_unsupported(e: &io::Error) -> bool {
+    let unsupported = [libc::ENOSYS, libc::EXDEV, libc::EINVAL, libc::EOPNOTSUPP, libc::EPERM, libc::EBADF];
+    e.raw_os_error().is_some_and(|code| unsupported.contains(&code))
+}
+
+/// Creates `dst` as a copy-on-write clone of `src` (APFS). `dst` must not exist.
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+pub(crate) fn clonefile(src: &std::path::Path, dst: &std::path::Path) -> io::Result<()> {
+    let (src, dst) = (cstring(src)?, cstring(dst)?);
+    // SAFETY: both pointers are valid NUL-terminated strings
+    check(unsafe { libc::clonefile(src.as_ptr(), dst.as_ptr(), 0) })
+}
+
+/// Returns the start of the first data region at or after `offset`, or `None`
+/// if there is only a hole from there to the end of the file.
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd"
+))]
+pub(crate) fn seek_data(file: &std::fs::File, offset: u64) -> io::Result<Option<u64>> {
+    match lseek(file, offset, libc::SEEK_DATA) {
+        Err(ref e) if e.raw_os_error() == Some(libc::ENXIO) => Ok(None),
+        result => result.map(Some),
+    }
+}
+
+/// Returns the start of the first hole at or after `offset`, where the end of
+/// the file counts as a hole.
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd"
+))]
+pub(crate) fn seek_hole(file: &std::fs::File, offset: u64) -> io::Result<u64> {
+    lseek(file, offset, libc::SEEK_HOLE)
+}
+
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd"
+))]
+fn lseek(file: &std::fs::File, offset: u64, whence: libc::c_int) -> io::Result<u64> {
+    use std::os::unix::io::AsRawFd;
+    // SAFETY: the descriptor stays open for the duration of the call
+    let ret = unsafe { libc::lseek(file.as_raw_fd(), offset as libc::off_t, whence) };
+    if ret == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(ret as u64)
+    }
+}
+
+/// Atomically exchanges the entries at `a` and `b`.
+#[cfg(target_os = "linux")]
+pub(crate) fn exchange(a: &std::path::Path, b: &std::path::Path) -> io::Result<()> {
+    let (a, b) = (cstring(a)?, cstring(b)?);
+    // SAFETY: both pointers are valid NUL-terminated strings
+    check(unsafe { libc::renameat2(libc::AT_FDCWD, a.as_ptr(), libc::AT_FDCWD, b.as_ptr(), libc::RENAME_EXCHANGE) })
+}
+
+/// Atomically exchanges the entries at `a` and `b`.
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+pub(crate) fn exchange(a: &std::path::Path, b: &std::path::Path) -> io::Result<()> {
+    let (a, b) = (cstring(a)?, cstring(b)?);
+    // SAFETY: both pointers are valid NUL-terminated strings
+    check(unsafe { libc::renamex_np(a.as_ptr(), b.as_ptr(), libc::RENAME_SWAP) })
+}
+
+/// Looks up the id of the user called `name` in the users database.
+pub(crate) fn user_id(name: &str) -> io::Result<Option<u32>> {
+    let name = c_name(name)?;
+    lookup_id(|buf| {
+        // SAFETY: `passwd` is plain data, which `getpwnam_r` fills in
+        let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+        let mut found = std::ptr::null_mut();
+        // SAFETY: every pointer is valid for the duration of the call, and the
+        // length passed is that of `buf`
+        let ret = unsafe { libc::getpwnam_r(name.as_ptr(), &mut pwd, buf.as_mut_ptr(), buf.len(), &mut found) };
+        (ret, if found.is_null() { None } else { Some(pwd.pw_uid) })
+    })
+}
+
+/// Looks up the id of the group called `name` in the groups database.
+pub(crate) fn group_id(name: &str) -> io::Result<Option<u32>> {
+    let name = c_name(name)?;
+    lookup_id(|buf| {
+        // SAFETY: `group` is plain data, which `getgrnam_r` fills in
+        let mut grp: libc::group = unsafe { std::mem::zeroed() };
+        let mut found = std::ptr::null_mut();
+        // SAFETY: every pointer is valid for the duration of the call, and the
+        // length passed is that of `buf`
+        let ret = unsafe { libc::getgrnam_r(name.as_ptr(), &mut grp, buf.as_mut_ptr(), buf.len(), &mut found) };
+        (ret, if found.is_null() { None } else { Some(grp.gr_gid) })
+    })
+}
+
+fn c_name(name: &str) -> io::Result<std::ffi::CString> {
+    std::ffi::CString::new(name).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "name contains a NUL byte"))
+}
+
+/// Calls a `get*nam_r` function with a buffer for the strings of the entry,
+/// growing it until they fit.
+fn lookup_id<F>(mut lookup: F) -> io::Result<Option<u32>>
+where
+    F: FnMut(&mut [libc::c_char]) -> (libc::c_int, Option<u32>),
+{
+    let mut buf = vec![0; 1024];
+    loop {
+        match lookup(&mut buf) {
+            (0, id) => return Ok(id),
+            (libc::ERANGE, _) => {
+                let len = buf.len() * 2;
+                buf.resize(len, 0);
+            }
+            // Some systems report a missing entry as an error
+            (libc::ENOENT, _) | (libc::ESRCH, _) => return Ok(None),
+            (code, _) => return Err(io::Error::from_raw_os_error(code)),
+        }
+    }
+}
+
+/// Returns the value of the extended attribute `name` of `path`, or `None` if
+/// it has no such attribute.
+#[cfg(all(feature = "xattr", any(target_os = "linux", target_os = "macos")))]
+pub(crate) fn getxattr(path: &std::path::Path, name: &std::ffi::OsStr) -> io::Result<Option<Vec<u8>>> {
+    let (path, name) = (cstring(path)?, cstring(name.as_ref())?);
+    let result = read_sized(|buf, len| {
+        let value = buf as *mut libc::c_void;
+        // SAFETY: both strings are NUL-terminated, and `value` is null or valid
+        // for `len` bytes
+        #[cfg(target_os = "linux")]
+        let ret = unsafe { libc::getxattr(path.as_ptr(), name.as_ptr(), value, len) };
+        #[cfg(target_os = "macos")]
+        let ret = unsafe { libc::getxattr(path.as_ptr(), name.as_ptr(), value, len, 0, 0) };
+        ret
+    });
+    match result {
+        Err(ref e) if e.raw_os_error() == Some(NO_ATTRIBUTE) => Ok(None),
+        result => result.map(Some),
+    }
+}
+
+/// Sets the extended attribute `name` of `path` to `value`.
+#[cfg(all(feature = "xattr", any(target_os = "linux", target_os = "macos")))]
+pub(crate) fn setxattr(path: &std::path::Path, name: &std::ffi::OsStr, value: &[u8]) -> io::Result<()> {
+    let (path, name) = (cstring(path)?, cstring(name.as_ref())?);
+    let data = value.as_ptr() as *const libc::c_void;
+    // SAFETY: both strings are NUL-terminated and `data` is valid for `value.len()` bytes
+    #[cfg(target_os = "linux")]
+    let ret = unsafe { libc::setxattr(path.as_ptr(), name.as_ptr(), data, value.len(), 0) };
+    #[cfg(target_os = "macos")]
+    let ret = unsafe { libc::setxattr(path.as_ptr(), name.as_ptr(), data, value.len(), 0, 0) };
+    check(ret)
+}
+
+/// Returns the names of the extended attributes of `path`.
+#[cfg(all(feature = "xattr", any(target_os = "linux", target_os = "macos")))]
+pub(crate) fn listxattr(path: &std::path::Path) -> io::Result<Vec<std::ffi::OsString>> {
+    use std::os::unix::ffi::OsStrExt;
+    let path = cstring(path)?;
+    let list = read_sized(|buf, len| {
+        // SAFETY: the path is NUL-terminated, and `buf` is null or valid for `len` bytes
+        #[cfg(target_os = "linux")]
+        let ret = unsafe { libc::listxattr(path.as_ptr(), buf as *mut libc::c_char, len) };
+        #[cfg(target_os = "macos")]
+        let ret = unsafe { libc::listxattr(path.as_ptr(), buf as *mut libc::c_char, len, 0) };
+        ret
+    })?;
+    let names = list.split(|&b| b == 0).filter(|name| !name.is_empty());
+    Ok(names.map(|name| std::ffi::OsStr::from_bytes(name).to_os_string()).collect())
+}
+
+/// Removes the extended attribute `name` of `path`.
+#[cfg(all(feature = "xattr", any(target_os = "linux", target_os = "macos")))]
+pub(crate) fn removexattr(path: &std::path::Path, name: &std::ffi::OsStr) -> io::Result<()> {
+    let (path, name) = (cstring(path)?, cstring(name.as_ref())?);
+    // SAFETY: both strings are NUL-terminated
+    #[cfg(target_os = "linux")]
+    let ret = unsafe { libc::removexattr(path.as_ptr(), name.as_ptr()) };
+    #[cfg(target_os = "macos")]
+    let ret = unsafe { libc::removexattr(path.as_ptr(), name.as_ptr(), 0) };
+    check(ret)
+}
+
+#[cfg(all(feature = "xattr", target_os = "linux"))]
+const NO_ATTRIBUTE: libc::c_int = libc::ENODATA;
+#[cfg(all(feature = "xattr", target_os = "macos"))]
+const NO_ATTRIBUTE: libc::c_int = libc::ENOATTR;
+
+/// Calls an xattr function first to learn the size of the data and then to
+/// read it, trying again if it grew in between.
+#[cfg(all(feature = "xattr", any(target_os = "linux", target_os = "macos")))]
+fn read_sized<F>(mut call: F) -> io::Result<Vec<u8>>
+where
+    F: FnMut(*mut u8, usize) -> libc::ssize_t,
+{
+    loop {
+        let len = call(std::ptr::null_mut(), 0);
+        if len == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut buf = vec![0; len as usize];
+        let read = call(buf.as_mut_ptr(), buf.len());
+        if read == -1 {
+            let e = io::Error::last_os_error();
+            if e.raw_os_error() == Some(libc::ERANGE) {
+                continue;
+            }
+            return Err(e);
+        }
+        buf.truncate(read as usize);
+        return Ok(buf);
+    }
+}
@@ -0,0 +1,680 @@
+// Copyright 2020 Jared Forth.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Creating and extracting tar archives, optionally compressed with gzip.
+
+use std::convert::TryFrom;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+use crate::archive::{
+    check_links, collect, create_file, create_symlink, declined, entry_name, finish_dirs, leaves_destination,
+    mode_of, read_name, safe_relative, set_mode, Source, MODE_TYPE,
+};
+use crate::atomic::replace_file;
+use crate::deflate;
+use crate::error::{FsError, IoResultExt, Result};
+use crate::glob::Pattern;
+use crate::meta::{file_id, FileKind};
+use crate::op::Op;
+
+const BLOCK: usize = 512;
+
+const REGULAR: u8 = b'0';
+const HARD_LINK: u8 = b'1';
+const SYMLINK: u8 = b'2';
+const DIRECTORY: u8 = b'5';
+/// A pax extended header, whose records apply to the entry after it.
+const PAX_HEADER: u8 = b'x';
+const PAX_GLOBAL_HEADER: u8 = b'g';
+/// GNU long names and link targets, which apply to the entry after them.
+const GNU_LONG_NAME: u8 = b'L';
+const GNU_LONG_LINK: u8 = b'K';
+
+/// Options for `fsutils::tar_create_with`.
+///
+/// By default nothing is excluded and the archive is compressed with gzip
+/// when its name ends in `.gz` or `.tgz`. Patterns use shell wildcards (`*`,
+/// `?`, `**`, `[...]`) and are matched against paths relative to each
+/// source, or just the file name for patterns without a `/`, as for
+/// `fsutils::CopyOptions`.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::TarOptions;
+///
+/// let mut opts = TarOptions::new();
+/// opts.exclude(".git").unwrap().gzip(true);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct TarOptions {
+    exclude: Vec<Pattern>,
+    gzip: Option<bool>,
+}
+
+impl TarOptions {
+    /// Creates the default options.
+    pub fn new() -> TarOptions {
+        TarOptions::default()
+    }
+
+    /// Leave out files and directories matching `pattern`. May be given
+    /// several times.
+    pub fn exclude(&mut self, pattern: &str) -> Result<&mut TarOptions> {
+        self.exclude.push(Pattern::new(pattern)?);
+        Ok(self)
+    }
+
+    /// Compress the archive with gzip, or not, whatever its name.
+    pub fn gzip(&mut self, gzip: bool) -> &mut TarOptions {
+        self.gzip = Some(gzip);
+        self
+    }
+}
+
+/// Packs files and directory trees into a tar archive, and returns whether
+/// that succeeded.
+///
+/// Requires the `tar` feature.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::mkdir("tar_create_dir");
+/// fsutils::write_file("tar_create_dir/a.txt", "a");
+///
+/// assert!(fsutils::tar_create(&["tar_create_dir"], "tar_create.tar"));
+/// assert!(!fsutils::tar_create(&["a_very_1234_unlikely_9876_dirname"], "tar_create.tar"));
+///
+/// # // Cleanup
+/// # fsutils::rm_r("tar_create_dir");
+/// # fsutils::rm("tar_create.tar");
+/// ```
+pub fn tar_create<P: AsRef<Path>, Q: AsRef<Path>>(sources: &[P], archive: Q) -> bool {
+    match try_tar_create(sources, archive) {
+        Ok(_) => true,
+        Err(e) => {
+            error!("Cannot create tar archive {}", e);
+            false
+        }
+    }
+}
+
+/// Packs files and directory trees into a tar archive, like `tar -cf`, and
+/// returns the number of entries in it.
+///
+/// A file is stored under its name and a directory under its name with
+/// everything below it, so `tar_create(&["site"], ...)` gives entries such
+/// as `site/index.html`. A source without a name of its own, such as `.`,
+/// contributes only what is below it. Symbolic links inside directories are
+/// stored as links, and permissions, owner ids and modification times are
+/// kept. The archive is compressed with gzip when its name ends in `.gz` or
+/// `.tgz`, in which case it is built in memory first, and replaces `archive`
+/// once it is complete. Requires the `tar` feature.
+///
+/// Entries are written in the POSIX ustar format, with pax extended headers
+/// for long names and large files, which every current `tar` reads. Names
+/// and link targets are stored as UTF-8, as pax records them, so a file
+/// whose name is not valid UTF-8 fails with `FsError::InvalidPath` naming it
+/// before the archive is written, rather than being stored under a different
+/// name.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::mkdir("try_tar_create_dir/src");
+/// fsutils::write_file("try_tar_create_dir/src/lib.rs", "fn f() {}");
+///
+/// assert_eq!(fsutils::try_tar_create(&["try_tar_create_dir"], "try_tar_create.tar.gz").unwrap(), 3);
+/// fsutils::try_tar_extract("try_tar_create.tar.gz", "try_tar_create_out").unwrap();
+/// assert_eq!(fsutils::read_file("try_tar_create_out/try_tar_create_dir/src/lib.rs"), "fn f() {}");
+///
+/// # #[cfg(target_os = "linux")]
+/// # {
+/// use std::ffi::OsStr;
+/// use std::os::unix::ffi::OsStrExt;
+/// use fsutils::FsError;
+///
+/// fsutils::create_file(std::path::Path::new("try_tar_create_dir").join(OsStr::from_bytes(b"bad\xffname")));
+/// let result = fsutils::try_tar_create(&["try_tar_create_dir"], "try_tar_create_bad.tar");
+/// assert!(matches!(result, Err(FsError::InvalidPath { .. })));
+/// assert!(!fsutils::path_exists("try_tar_create_bad.tar"));
+/// # }
+///
+/// # // Cleanup
+/// # fsutils::rm_r("try_tar_create_dir");
+/// # fsutils::rm_r("try_tar_create_out");
+/// # fsutils::rm("try_tar_create.tar.gz");
+/// ```
+pub fn try_tar_create<P: AsRef<Path>, Q: AsRef<Path>>(sources: &[P], archive: Q) -> Result<usize> {
+    tar_create_with(sources, archive, &TarOptions::new())
+}
+
+/// Packs files and directory trees into a tar archive like
+/// `fsutils::try_tar_create`, according to `opts`.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::TarOptions;
+///
+/// fsutils::mkdir("tar_create_with_dir/.git");
+/// fsutils::write_file("tar_create_with_dir/main.rs", "fn main() {}");
+/// fsutils::write_file("tar_create_with_dir/.git/HEAD", "");
+///
+/// let opts = TarOptions::new().exclude(".git").unwrap().gzip(true).clone();
+/// assert_eq!(fsutils::tar_create_with(&["tar_create_with_dir"], "tar_create_with.tar", &opts).unwrap(), 2);
+///
+/// # // Cleanup
+/// # fsutils::rm_r("tar_create_with_dir");
+/// # fsutils::rm("tar_create_with.tar");
+/// ```
+pub fn tar_create_with<P: AsRef<Path>, Q: AsRef<Path>>(sources: &[P], archive: Q, opts: &TarOptions) -> Result<usize> {
+    let archive = archive.as_ref();
+    Op::new("tar_create", archive).destructive(archive.exists()).run(0, || {
+        let archive_id = file_id(archive).ok();
+        let mut found = Vec::new();
+        for source in sources {
+            collect(source.as_ref(), &opts.exclude, archive_id, &mut found)?;
+        }
+        let gzip = opts.gzip.unwrap_or_else(|| has_gzip_extension(archive));
+        replace_file(archive, |file| {
+            if gzip {
+                let mut tar = Vec::new();
+                write_tar(&mut tar, &found, archive)?;
+                file.write_all(&deflate::gzip(&tar)).at(archive)
+            } else {
+                let mut out = BufWriter::new(file);
+                write_tar(&mut out, &found, archive)?;
+                out.flush().at(archive)
+            }
+        })?;
+        info!("Archived {} entries into {}", found.len(), archive.display());
+        Ok(found.len())
+    })
+}
+
+fn has_gzip_extension(archive: &Path) -> bool {
+    let name = archive.file_name().map(|name| name.to_string_lossy().to_lowercase()).unwrap_or_default();
+    name.ends_with(".gz") || name.ends_with(".tgz")
+}
+
+/// Writes the entries for everything found and the end of the archive.
+fn write_tar<W: Write>(out: &mut W, found: &[Source], archive: &Path) -> Result<()> {
+    for source in found {
+        let path = &source.path;
+        let meta = if source.kind == FileKind::Symlink { fs::symlink_metadata(path) } else { fs::metadata(path) };
+        let meta = meta.at(path)?;
+        let (kind, size, link) = match source.kind {
+            FileKind::File => (REGULAR, meta.len(), String::new()),
+            FileKind::Dir => (DIRECTORY, 0, String::new()),
+            _ => (SYMLINK, 0, entry_name(path, &fs::read_link(path).at(path)?)?),
+        };
+        let (uid, gid) = owner(&meta);
+        let mtime = meta.modified().ok().and_then(|t| t.duration_since(UNIX_EPOCH).ok()).map_or(0, |d| d.as_secs());
+        let mode = mode_of(&meta, source.kind) & !MODE_TYPE;
+        let header = Header { name: &source.name, mode, uid, gid, size, mtime, kind, link: &link };
+        header.write(out).at(archive)?;
+        if kind == REGULAR {
+            let copied = io::copy(&mut File::open(path).at(path)?.take(size), out).at(path)?;
+            if copied != size {
+                let e = io::Error::new(io::ErrorKind::UnexpectedEof, "file shrank while it was archived");
+                return Err(FsError::from_io(path, e));
+            }
+            out.write_all(&[0; BLOCK][..padding(size as usize)]).at(archive)?;
+        }
+    }
+    out.write_all(&[0; 2 * BLOCK]).at(archive)
+}
+
+#[cfg(unix)]
+fn owner(meta: &fs::Metadata) -> (u64, u64) {
+    use std::os::unix::fs::MetadataExt;
+    (u64::from(meta.uid()), u64::from(meta.gid()))
+}
+
+#[cfg(not(unix))]
+fn owner(_meta: &fs::Metadata) -> (u64, u64) {
+    (0, 0)
+}
+
+/// How many bytes of padding follow `size` bytes of data, up to the next
+/// block.
+fn padding(size: usize) -> usize {
+    (BLOCK - size % BLOCK) % BLOCK
+}
+
+/// What goes in the header of an entry.
+struct Header<'a> {
+    name: &'a str,
+    mode: u32,
+    uid: u64,
+    gid: u64,
+    size: u64,
+    mtime: u64,
+    kind: u8,
+    link: &'a str,
+}
+
+impl Header<'_> {
+    /// Writes the header, preceded by a pax extended header for whatever
+    /// does not fit in its fields.
+    fn write<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let mut records = Vec::new();
+        let (prefix, name) = split_name(self.name).unwrap_or_else(|| {
+            pax_record(&mut records, "path", self.name);
+            ("", self.name)
+        });
+        if self.link.len() > 100 {
+            pax_record(&mut records, "linkpath", self.link);
+        }
+        for (key, value, digits) in [("size", self.size, 11), ("uid", self.uid, 7), ("gid", self.gid, 7)] {
+            if value >= 1 << (3 * digits) {
+                pax_record(&mut records, key, &value.to_string());
+            }
+        }
+        if !records.is_empty() {
+            let name = "././@PaxHeader";
+            let pax = Header { name, mode: 0o644, size: records.len() as u64, kind: PAX_HEADER, link: "", ..*self };
+            out.write_all(&pax.block("", name))?;
+            out.write_all(&records)?;
+            out.write_all(&[0; BLOCK][..padding(records.len())])?;
+        }
+        out.write_all(&self.block(prefix, name))
+    }
+
+    /// The header block itself, with the name split into `prefix` and `name`.
+    fn block(&self, prefix: &str, name: &str) -> [u8; BLOCK] {
+        fn put(field: &mut [u8], value: &[u8]) {
+            let len = value.len().min(field.len());
+            field[..len].copy_from_slice(&value[..len]);
+        }
+        let mut block = [0; BLOCK];
+        put(&mut block[0..100], name.as_bytes());
+        octal(&mut block[100..108], u64::from(self.mode));
+        octal(&mut block[108..116], self.uid);
+        octal(&mut block[116..124], self.gid);
+        octal(&mut block[124..136], self.size);
+        octal(&mut block[136..148], self.mtime);
+        block[156] = self.kind;
+        put(&mut block[157..257], self.link.as_bytes());
+        put(&mut block[257..265], b"ustar\x0000");
+        put(&mut block[345..500], prefix.as_bytes());
+        block[148..156].copy_from_slice(b"        ");
+        let sum: u32 = block.iter().map(|&b| u32::from(b)).sum();
+        put(&mut block[148..156], format!("{:06o}\0 ", sum).as_bytes());
+        block
+    }
+}
+
+/// Writes `value` as zero-padded octal ending in a NUL, with the largest
+/// value that fits if it does not, as a pax record then holds it.
+fn octal(field: &mut [u8], value: u64) {
+    let digits = field.len() - 1;
+    let value = value.min((1 << (3 * digits)) - 1);
+    field[..digits].copy_from_slice(format!("{:0width$o}", value, width = digits).as_bytes());
+    field[digits] = 0;
+}
+
+/// Splits a name into the ustar prefix and name fields, or returns `None`
+/// if it does not fit them.
+fn split_name(name: &str) -> Option<(&str, &str)> {
+    if name.len() <= 100 {
+        return Some(("", name));
+    }
+    name.match_indices('/')
+        .map(|(at, _)| (&name[..at], &name[at + 1..]))
+        .find(|(prefix, rest)| prefix.len() <= 155 && !rest.is_empty() && rest.len() <= 100)
+}
+
+/// Appends a pax record, whose length counts its own digits.
+fn pax_record(records: &mut Vec<u8>, key: &str, value: &str) {
+    let rest = format!(" {}={}\n", key, value);
+    let mut len = rest.len() + 1;
+    while len.to_string().len() + rest.len() != len {
+        len = len.to_string().len() + rest.len();
+    }
+    records.extend_from_slice(format!("{}{}", len, rest).as_bytes());
+}
+
+/// Extracts a tar archive into `dst`, and returns whether that succeeded.
+///
+/// Requires the `tar` feature.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("tar_extract.txt", "a");
+/// fsutils::tar_create(&["tar_extract.txt"], "tar_extract.tar");
+///
+/// assert!(fsutils::tar_extract("tar_extract.tar", "tar_extract_out"));
+/// assert!(!fsutils::tar_extract("a_very_1234_unlikely_9876_filename", "tar_extract_out"));
+///
+/// # // Cleanup
+/// # fsutils::rm("tar_extract.txt");
+/// # fsutils::rm("tar_extract.tar");
+/// # fsutils::rm_r("tar_extract_out");
+/// ```
+pub fn tar_extract<P: AsRef<Path>, Q: AsRef<Path>>(archive: P, dst: Q) -> bool {
+    match try_tar_extract(archive, dst) {
+        Ok(_) => true,
+        Err(e) => {
+            error!("Cannot extract tar archive {}", e);
+            false
+        }
+    }
+}
+
+/// Extracts a tar archive into `dst`, like `tar -xf`, creating it if
+/// needed, and returns the number of entries extracted.
+///
+/// Archives compressed with gzip are recognised by their contents and
+/// decompressed in memory. Entries whose names would land outside `dst`,
+/// such as `../evil` or `/etc/passwd`, fail the extraction before anything
+/// is written, and so do symbolic and hard links pointing outside it,
+/// including symbolic links that would lead out through another link in the
+/// archive. Links are created after everything else, so no entry is ever
+/// written through one. Existing files and links are replaced, but the
+/// confirm hook set with `fsutils::set_confirm` is first asked about each of
+/// them, and those it declines are left as they are and not counted.
+/// Permission bits, without setuid, setgid and sticky bits, and modification
+/// times are restored, while ownership is left to the current user. Devices
+/// and FIFOs are skipped. An entry whose name or link target is not valid
+/// UTF-8 fails the extraction with an error of kind
+/// `io::ErrorKind::InvalidData`. Requires the `tar` feature.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::mkdir("try_tar_extract_dir/docs");
+/// fsutils::write_file("try_tar_extract_dir/docs/guide.md", "# Guide");
+/// fsutils::tar_create(&["try_tar_extract_dir"], "try_tar_extract.tgz");
+///
+/// assert_eq!(fsutils::try_tar_extract("try_tar_extract.tgz", "try_tar_extract_out").unwrap(), 3);
+/// assert_eq!(fsutils::read_file("try_tar_extract_out/try_tar_extract_dir/docs/guide.md"), "# Guide");
+/// assert!(fsutils::try_tar_extract("a_very_1234_unlikely_9876_filename", "try_tar_extract_out").is_err());
+///
+/// // Files the confirm hook declines to replace are kept
+/// fsutils::write_file("try_tar_extract_out/try_tar_extract_dir/docs/guide.md", "# Mine");
+/// fsutils::set_confirm(|_, _| false);
+/// assert_eq!(fsutils::try_tar_extract("try_tar_extract.tgz", "try_tar_extract_out").unwrap(), 2);
+/// fsutils::clear_confirm();
+/// assert_eq!(fsutils::read_file("try_tar_extract_out/try_tar_extract_dir/docs/guide.md"), "# Mine");
+///
+/// # #[cfg(unix)]
+/// # {
+/// // `c` only leads out of the destination through the link `a/b`
+/// fsutils::mkdir("try_tar_extract_evil/a");
+/// fsutils::ln_s("..", "try_tar_extract_evil/a/b");
+/// fsutils::ln_s("a/b/..", "try_tar_extract_evil/c");
+/// fsutils::cd("try_tar_extract_evil");
+/// fsutils::tar_create(&["."], "../try_tar_extract_evil.tar");
+/// fsutils::cd("..");
+///
+/// assert!(fsutils::try_tar_extract("try_tar_extract_evil.tar", "try_tar_extract_evil_out").is_err());
+/// assert!(!fsutils::path_exists("try_tar_extract_evil_out/c"));
+/// # }
+///
+/// # // Cleanup
+/// # fsutils::rm_r("try_tar_extract_dir");
+/// # fsutils::rm_r("try_tar_extract_out");
+/// # fsutils::rm("try_tar_extract.tgz");
+/// # fsutils::rm_r("try_tar_extract_evil");
+/// # fsutils::rm_r("try_tar_extract_evil_out");
+/// # fsutils::rm("try_tar_extract_evil.tar");
+/// ```
+pub fn try_tar_extract<P: AsRef<Path>, Q: AsRef<Path>>(archive: P, dst: Q) -> Result<usize> {
+    let (archive, dst) = (archive.as_ref(), dst.as_ref());
+    Op::new("tar_extract", archive).to(dst).run(0, || {
+        let mut data = fs::read(archive).at(archive)?;
+        if deflate::is_gzip(&data) {
+            data = deflate::gunzip(&data).at(archive)?;
+        }
+        let mut entries = Vec::new();
+        for entry in read_entries(&data).at(archive)? {
+            let relative = safe_relative(&entry.name).ok_or_else(|| leaves_destination(dst, &entry.name))?;
+            if relative.as_os_str().is_empty() {
+                continue;
+            }
+            entries.push((relative, entry));
+        }
+        let mut hard_links = Vec::new();
+        let mut links = Vec::new();
+        for (relative, entry) in &entries {
+            match entry.kind {
+                Type::Symlink => links.push((relative.clone(), PathBuf::from(&entry.link))),
+                Type::HardLink => {
+                    let target = safe_relative(&entry.link)
+                        .filter(|target| !target.as_os_str().is_empty())
+                        .ok_or_else(|| leaves_destination(dst, &entry.link))?;
+                    hard_links.push((dst.join(relative), dst.join(target)));
+                }
+                _ => {}
+            }
+        }
+        check_links(dst, &links)?;
+        let replaced: Vec<PathBuf> = entries
+            .iter()
+            .filter(|(_, entry)| entry.kind != Type::Dir)
+            .map(|(relative, _)| dst.join(relative))
+            .collect();
+        let declined = declined("tar_extract", &replaced);
+
+        fs::create_dir_all(dst).at(dst)?;
+        let mut dirs = Vec::new();
+        for (relative, entry) in &entries {
+            let path = dst.join(relative);
+            let modified = UNIX_EPOCH + Duration::from_secs(entry.mtime);
+            match entry.kind {
+                Type::Dir => {
+                    fs::create_dir_all(&path).at(&path)?;
+                    dirs.push((path, modified, Some(entry.mode)));
+                }
+                Type::File if !declined.contains(&path) => {
+                    create_file(&path, &data[entry.data.clone()], modified)?;
+                    set_mode(&path, entry.mode)?;
+                }
+                _ => {}
+            }
+        }
+        for (path, target) in &hard_links {
+            if path == target || declined.contains(path) {
+                continue;
+            }
+            if path.symlink_metadata().is_ok() {
+                fs::remove_file(path).at(path)?;
+            }
+            fs::hard_link(target, path).at2(target, path)?;
+        }
+        for (relative, target) in &links {
+            let path = dst.join(relative);
+            if !declined.contains(&path) {
+                create_symlink(&path, target)?;
+            }
+        }
+        finish_dirs(dirs)?;
+        let extracted = entries.len() - declined.len();
+        info!("Extracted {} entries from {} into {}", extracted, archive.display(), dst.display());
+        Ok(extracted)
+    })
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Type {
+    File,
+    Dir,
+    Symlink,
+    HardLink,
+}
+
+/// An entry read from an archive, with long names and pax records applied.
+struct Entry {
+    name: String,
+    kind: Type,
+    mode: u32,
+    mtime: u64,
+    link: String,
+    /// Where its contents are in the archive.
+    data: Range<usize>,
+}
+
+fn corrupt(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("invalid tar archive: {}", reason))
+}
+
+/// A NUL-terminated field, without the NUL.
+fn field(field: &[u8]) -> &[u8] {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    &field[..end]
+}
+
+/// The value of a numeric field, in octal or in the base-256 form GNU `tar`
+/// uses for values too large for octal.
+fn number(field: &[u8]) -> io::Result<u64> {
+    if field[0] & 0x80 != 0 {
+        return Ok(field[1..].iter().fold(u64::from(field[0] & 0x7f), |n, &b| n << 8 | u64::from(b)));
+    }
+    let digits = String::from_utf8_lossy(self::field(field));
+    let digits = digits.trim_matches(|c: char| c == ' ' || c == '\0');
+    if digits.is_empty() {
+        return Ok(0);
+    }
+    u64::from_str_radix(digits, 8).map_err(|_| corrupt("bad number in header"))
+}
+
+/// Whether the checksum of a header matches, summing its bytes as unsigned
+/// or, as some old archivers did, signed.
+fn checksum_matches(header: &[u8]) -> io::Result<bool> {
+    let expected = number(&header[148..156])?;
+    let field = 148..156;
+    let (mut unsigned, mut signed) = (0u64, 0i64);
+    for (i, &b) in header.iter().enumerate() {
+        let b = if field.contains(&i) { b' ' } else { b };
+        unsigned += u64::from(b);
+        signed += i64::from(b as i8);
+    }
+    Ok(expected == unsigned || expected as i64 == signed)
+}
+
+/// The records of a pax extended header.
+fn pax_records(mut body: &[u8]) -> io::Result<Vec<(String, String)>> {
+    let mut records = Vec::new();
+    while !body.is_empty() && body[0] != 0 {
+        let space = body.iter().position(|&b| b == b' ').ok_or_else(|| corrupt("bad pax record"))?;
+        let len: usize = std::str::from_utf8(&body[..space])
+            .ok()
+            .and_then(|len| len.parse().ok())
+            .filter(|&len| len > space && len <= body.len())
+            .ok_or_else(|| corrupt("bad pax record"))?;
+        let record = &body[space + 1..len];
+        let record = record.strip_suffix(b"\n").unwrap_or(record);
+        if let Some(equals) = record.iter().position(|&b| b == b'=') {
+            let key = String::from_utf8_lossy(&record[..equals]).into_owned();
+            let value = &record[equals + 1..];
+            let value = match key.as_str() {
+                "path" | "linkpath" => read_name(value)?,
+                _ => String::from_utf8_lossy(value).into_owned(),
+            };
+            records.push((key, value));
+        }
+        body = &body[len..];
+    }
+    Ok(records)
+}
+
+/// The last value of `key` in pax records.
+fn pax_value(records: &[(String, String)], key: &str) -> Option<String> {
+    records.iter().rev().find(|(k, _)| k == key).map(|(_, v)| v.clone())
+}
+
+/// Reads the entries of an uncompressed archive, up to its end marker.
+fn read_entries(data: &[u8]) -> io::Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    let mut at = 0;
+    let (mut long_name, mut long_link) = (None, None);
+    let mut pax = Vec::new();
+    while at + BLOCK <= data.len() {
+        let header = &data[at..at + BLOCK];
+        if header.iter().all(|&b| b == 0) {
+            break;
+        }
+        if !checksum_matches(header)? {
+            return Err(corrupt("bad header checksum"));
+        }
+        let kind = header[156];
+        let mut size = number(&header[124..136])?;
+        if kind != PAX_HEADER && kind != PAX_GLOBAL_HEADER {
+            if let Some(value) = pax_value(&pax, "size") {
+                size = value.parse().map_err(|_| corrupt("bad pax size"))?;
+            }
+        }
+        let start = at + BLOCK;
+        let end = usize::try_from(size).ok().and_then(|size| start.checked_add(size)).filter(|&end| end <= data.len());
+        let end = end.ok_or_else(|| corrupt("truncated"))?;
+        at = end + padding(end - start);
+        let body = &data[start..end];
+        match kind {
+            PAX_HEADER => {
+                pax = pax_records(body)?;
+                continue;
+            }
+            PAX_GLOBAL_HEADER => continue,
+            GNU_LONG_NAME => {
+                long_name = Some(read_name(field(body))?);
+                continue;
+            }
+            GNU_LONG_LINK => {
+                long_link = Some(read_name(field(body))?);
+                continue;
+            }
+            _ => {}
+        }
+
+        // The header's own fields only count when nothing longer replaces them
+        let name = match pax_value(&pax, "path").or_else(|| long_name.take()) {
+            Some(name) => name,
+            None => {
+                let name = read_name(field(&header[0..100]))?;
+                let prefix = read_name(field(&header[345..500]))?;
+                if &header[257..262] == b"ustar" && !prefix.is_empty() {
+                    format!("{}/{}", prefix, name)
+                } else {
+                    name
+                }
+            }
+        };
+        let link = match pax_value(&pax, "linkpath").or_else(|| long_link.take()) {
+            Some(link) => link,
+            None => read_name(field(&header[157..257]))?,
+        };
+        let mtime = match pax_value(&pax, "mtime") {
+            // Fractions of a second are dropped
+            Some(value) => value.split('.').next().and_then(|secs| secs.parse().ok()).unwrap_or(0),
+            None => number(&header[136..148])?,
+        };
+        let mode = number(&header[100..108])? as u32;
+        pax.clear();
+        let kind = match kind {
+            REGULAR | 0 | b'7' if name.ends_with('/') => Type::Dir,
+            REGULAR | 0 | b'7' => Type::File,
+            HARD_LINK => Type::HardLink,
+            SYMLINK => Type::Symlink,
+            DIRECTORY => Type::Dir,
+            _ => {
+                warn!("Skipping {}, which is not a file, directory or link", name);
+                continue;
+            }
+        };
+        entries.push(Entry { name, kind, mode, mtime, link, data: start..end });
+    }
+    Ok(entries)
+}
@@ -0,0 +1,321 @@
+// Copyright 2020 Jared Forth.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Temporary files, directories and other paths that clean up after themselves.
+//!
+//! Temporary paths are scratch space, so they are created and removed even in
+//! dry-run mode and are not recorded in the journal.
+
+use std::collections::hash_map::RandomState;
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, File, OpenOptions};
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::SystemTime;
+
+use crate::error::{FsError, IoResultExt, Result};
+
+/// How many names to try before giving up on finding a free one.
+const MAX_ATTEMPTS: u32 = 1 << 16;
+
+/// Options for creating a `TempDir` or `TempFile`.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::TempBuilder;
+///
+/// let dir = TempBuilder::new().prefix("build-").suffix(".d").tempdir().unwrap();
+/// let name = dir.path().file_name().unwrap().to_str().unwrap();
+/// assert!(name.starts_with("build-") && name.ends_with(".d"));
+///
+/// let file = TempBuilder::new().parent(dir.path()).suffix(".txt").tempfile().unwrap();
+/// assert!(file.path().starts_with(dir.path()));
+/// ```
+#[derive(Clone, Debug)]
+pub struct TempBuilder {
+    prefix: OsString,
+    suffix: OsString,
+    parent: Option<PathBuf>,
+}
+
+impl TempBuilder {
+    /// Creates a builder for names like `.tmpXXXXXXXXXX` in the system temp directory.
+    pub fn new() -> TempBuilder {
+        TempBuilder {
+            prefix: OsString::from(".tmp"),
+            suffix: OsString::new(),
+            parent: None,
+        }
+    }
+
+    /// Sets the start of the generated name.
+    pub fn prefix<S: AsRef<OsStr>>(&mut self, prefix: S) -> &mut TempBuilder {
+        self.prefix = prefix.as_ref().to_os_string();
+        self
+    }
+
+    /// Sets the end of the generated name, e.g. a file extension.
+    pub fn suffix<S: AsRef<OsStr>>(&mut self, suffix: S) -> &mut TempBuilder {
+        self.suffix = suffix.as_ref().to_os_string();
+        self
+    }
+
+    /// Creates the temporary path inside `parent` instead of `std::env::temp_dir()`.
+    pub fn parent<P: AsRef<Path>>(&mut self, parent: P) -> &mut TempBuilder {
+        self.parent = Some(parent.as_ref().to_path_buf());
+        self
+    }
+
+    /// Creates a temporary directory.
+    pub fn tempdir(&self) -> Result<TempDir> {
+        let path = self.create(|path| fs::create_dir(path))?;
+        info!("Created temporary directory {}", path.display());
+        Ok(TempDir { guard: CleanupGuard::scratch(path) })
+    }
+
+    /// Creates a temporary file, opened for reading and writing.
+    pub fn tempfile(&self) -> Result<TempFile> {
+        let mut file = None;
+        let path = self.create(|path| {
+            file = Some(OpenOptions::new().read(true).write(true).create_new(true).open(path)?);
+            Ok(())
+        })?;
+        info!("Created temporary file {}", path.display());
+        let file = file.expect("file is set whenever create succeeds");
+        Ok(TempFile { file, guard: CleanupGuard::scratch(path) })
+    }
+
+    /// Calls `create` with fresh names until one does not already exist.
+    fn create<F>(&self, mut create: F) -> Result<PathBuf>
+    where
+        F: FnMut(&Path) -> io::Result<()>,
+    {
+        let parent = self.parent.clone().unwrap_or_else(std::env::temp_dir);
+        for _ in 0..MAX_ATTEMPTS {
+            let mut name = self.prefix.clone();
+            name.push(random_name());
+            name.push(&self.suffix);
+            let path = parent.join(name);
+            match create(&path) {
+                Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                result => return result.map(|_| path.clone()).at(&path),
+            }
+        }
+        let source = io::Error::new(io::ErrorKind::AlreadyExists, "too many temporary files exist");
+        Err(FsError::from_io(parent, source))
+    }
+}
+
+impl Default for TempBuilder {
+    fn default() -> TempBuilder {
+        TempBuilder::new()
+    }
+}
+
+/// Removes a file or directory tree when dropped, unless disarmed with `keep`.
+///
+/// Unlike temporary paths, the removal goes through the same bookkeeping as
+/// `fsutils::rm_r`, so it is skipped in dry-run mode and shows up in the journal.
+/// A path that no longer exists when the guard is dropped is silently ignored.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::CleanupGuard;
+///
+/// {
+///     fsutils::mkdir("guarded_dir");
+///     let _guard = CleanupGuard::new("guarded_dir");
+///     fsutils::create_file("guarded_dir/file.txt");
+/// }
+/// assert!(!fsutils::path_exists("guarded_dir"));
+///
+/// fsutils::create_file("kept_file.txt");
+/// let path = CleanupGuard::new("kept_file.txt").keep();
+/// assert!(fsutils::path_exists(&path));
+///
+/// # // Cleanup
+/// # fsutils::rm(&path);
+/// ```
+#[derive(Debug)]
+pub struct CleanupGuard {
+    path: Option<PathBuf>,
+    scratch: bool,
+}
+
+impl CleanupGuard {
+    /// Arms a guard for `path`.
+    pub fn new<P: AsRef<Path>>(path: P) -> CleanupGuard {
+        CleanupGuard { path: Some(path.as_ref().to_path_buf()), scratch: false }
+    }
+
+    /// Arms a guard for a temporary path, which bypasses dry-run mode and the journal.
+    fn scratch(path: PathBuf) -> CleanupGuard {
+        CleanupGuard { path: Some(path), scratch: true }
+    }
+
+    /// Returns the guarded path.
+    pub fn path(&self) -> &Path {
+        self.path.as_deref().expect("path is only taken on keep or drop")
+    }
+
+    /// Disarms the guard and returns the path, leaving it on disk.
+    pub fn keep(mut self) -> PathBuf {
+        self.path.take().expect("path is only taken on keep or drop")
+    }
+}
+
+impl Drop for CleanupGuard {
+    fn drop(&mut self) {
+        let path = match self.path.take() {
+            Some(path) => path,
+            None => return,
+        };
+        let is_dir = match path.symlink_metadata() {
+            Ok(meta) => meta.is_dir(),
+            Err(_) => return,
+        };
+        let result = match (self.scratch, is_dir) {
+            (true, true) => fs::remove_dir_all(&path).at(&path),
+            (true, false) => fs::remove_file(&path).at(&path),
+            (false, true) => crate::try_rm_r(&path),
+            (false, false) => crate::try_rm(&path),
+        };
+        match result {
+            Ok(_) => info!("Cleaned up {}", path.display()),
+            Err(e) => error!("Cannot clean up {}", e),
+        }
+    }
+}
+
+/// A directory that is removed, along with its contents, when dropped.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::TempDir;
+///
+/// let path = {
+///     let dir = TempDir::new().unwrap();
+///     fsutils::create_file(dir.path().join("scratch.txt"));
+///     dir.path().to_path_buf()
+/// };
+/// assert!(!path.exists());
+/// ```
+#[derive(Debug)]
+pub struct TempDir {
+    guard: CleanupGuard,
+}
+
+impl TempDir {
+    /// Creates a temporary directory in `std::env::temp_dir()`.
+    pub fn new() -> Result<TempDir> {
+        TempBuilder::new().tempdir()
+    }
+
+    /// Creates a temporary directory inside `parent`.
+    pub fn new_in<P: AsRef<Path>>(parent: P) -> Result<TempDir> {
+        TempBuilder::new().parent(parent).tempdir()
+    }
+
+    /// Returns the path of the directory.
+    pub fn path(&self) -> &Path {
+        self.guard.path()
+    }
+
+    /// Disarms the guard and returns the path, leaving the directory on disk.
+    ///
+    /// ## Usage:
+    ///
+    /// ```
+    /// let path = fsutils::TempDir::new().unwrap().keep();
+    /// assert!(path.exists());
+    ///
+    /// # // Cleanup
+    /// # fsutils::rm_r(&path);
+    /// ```
+    pub fn keep(self) -> PathBuf {
+        self.guard.keep()
+    }
+}
+
+/// A file that is removed when dropped.
+///
+/// ## Usage:
+///
+/// ```
+/// use std::io::{Read, Seek, SeekFrom, Write};
+/// use fsutils::TempFile;
+///
+/// let mut tmp = TempFile::new().unwrap();
+/// tmp.as_file().write_all(b"scratch").unwrap();
+///
+/// let mut contents = String::new();
+/// tmp.as_file().seek(SeekFrom::Start(0)).unwrap();
+/// tmp.as_file().read_to_string(&mut contents).unwrap();
+/// assert_eq!(contents, "scratch");
+/// ```
+#[derive(Debug)]
+pub struct TempFile {
+    // Fields drop in order, so the handle is closed before the file is
+    // removed; Windows cannot delete open files
+    file: File,
+    guard: CleanupGuard,
+}
+
+impl TempFile {
+    /// Creates a temporary file in `std::env::temp_dir()`.
+    pub fn new() -> Result<TempFile> {
+        TempBuilder::new().tempfile()
+    }
+
+    /// Creates a temporary file inside `parent`.
+    pub fn new_in<P: AsRef<Path>>(parent: P) -> Result<TempFile> {
+        TempBuilder::new().parent(parent).tempfile()
+    }
+
+    /// Returns the path of the file.
+    pub fn path(&self) -> &Path {
+        self.guard.path()
+    }
+
+    /// Returns the open handle to the file.
+    pub fn as_file(&mut self) -> &mut File {
+        &mut self.file
+    }
+
+    /// Disarms the guard and returns the path, leaving the file on disk.
+    pub fn keep(self) -> PathBuf {
+        self.guard.keep()
+    }
+}
+
+/// Returns ten random alphanumeric characters.
+pub(crate) fn random_name() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    const CHARS: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+
+    // `RandomState` is seeded randomly per process, which is all the randomness we need
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+    hasher.write_u32(std::process::id());
+    if let Ok(elapsed) = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+        hasher.write_u128(elapsed.as_nanos());
+    }
+    let mut bits = hasher.finish();
+    (0..10)
+        .map(|_| {
+            let c = CHARS[(bits % CHARS.len() as u64) as usize];
+            bits /= CHARS.len() as u64;
+            c as char
+        })
+        .collect()
+}
@@ -0,0 +1,968 @@
+// Copyright 2020 Jared Forth.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Whole-file text processing in the style of the coreutils.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::atomic::{replace_file, replace_file_if};
+use crate::error::{FsError, IoResultExt, Result};
+use crate::lines::LineEnding;
+use crate::op::{confirm_each, Op};
+
+/// How much of a file the streaming functions here read at a time.
+const CHUNK: usize = 64 * 1024;
+
+/// The counts reported by `fsutils::wc`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct WordCount {
+    /// Newline characters, as `wc -l` counts them, so a last line without
+    /// a terminator is not counted.
+    pub lines: u64,
+    /// Runs of characters separated by whitespace.
+    pub words: u64,
+    /// Unicode characters, assuming the file is UTF-8.
+    pub chars: u64,
+    /// Bytes.
+    pub bytes: u64,
+}
+
+/// Counts the lines, words, characters and bytes in a file
+/// and returns `None` if it cannot be read.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("wc.txt", "Hello, world!\nsecond line\n");
+///
+/// let counts = fsutils::wc("wc.txt").unwrap();
+/// assert_eq!((counts.lines, counts.words, counts.bytes), (2, 4, 26));
+///
+/// # // Cleanup
+/// # fsutils::rm("wc.txt");
+/// ```
+pub fn wc<P: AsRef<Path>>(path: P) -> Option<WordCount> {
+    match try_wc(path) {
+        Ok(counts) => Some(counts),
+        Err(e) => {
+            error!("Cannot read file {}", e);
+            None
+        }
+    }
+}
+
+/// Counts the lines, words, characters and bytes in a file in a single pass,
+/// like `wc -lwmc`.
+///
+/// The file is streamed, so it can be larger than memory. Characters are
+/// counted as UTF-8 without validating it, and words are split on ASCII
+/// whitespace.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::WordCount;
+///
+/// fsutils::write_file("try_wc.txt", "naïve  café\tau lait");
+///
+/// let counts = fsutils::try_wc("try_wc.txt").unwrap();
+/// assert_eq!(counts, WordCount { lines: 0, words: 4, chars: 19, bytes: 21 });
+/// assert!(fsutils::try_wc("a_very_1234_unlikely_9876_filename").is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm("try_wc.txt");
+/// ```
+pub fn try_wc<P: AsRef<Path>>(path: P) -> Result<WordCount> {
+    let path = path.as_ref();
+    let mut file = File::open(path).at(path)?;
+    let mut counts = WordCount::default();
+    let mut in_word = false;
+    let mut buf = vec![0; CHUNK];
+    loop {
+        let n = match file.read(&mut buf) {
+            Ok(0) => return Ok(counts),
+            Ok(n) => n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(FsError::from_io(path, e)),
+        };
+        counts.bytes += n as u64;
+        for &b in &buf[..n] {
+            if b == b'\n' {
+                counts.lines += 1;
+            }
+            // Every byte but a UTF-8 continuation byte starts a character
+            if b & 0xC0 != 0x80 {
+                counts.chars += 1;
+            }
+            if b.is_ascii_whitespace() {
+                in_word = false;
+            } else if !in_word {
+                in_word = true;
+                counts.words += 1;
+            }
+        }
+    }
+}
+
+/// Options for `fsutils::sort_file_with`.
+///
+/// By default lines are sorted by comparing their bytes, like `LC_ALL=C sort`.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::SortOptions;
+///
+/// let mut opts = SortOptions::new();
+/// opts.numeric(true).reverse(true).unique(true);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct SortOptions {
+    reverse: bool,
+    numeric: bool,
+    unique: bool,
+    ignore_case: bool,
+}
+
+impl SortOptions {
+    /// Creates the default options.
+    pub fn new() -> SortOptions {
+        SortOptions::default()
+    }
+
+    /// Sort in descending order, like `sort -r`.
+    pub fn reverse(&mut self, reverse: bool) -> &mut SortOptions {
+        self.reverse = reverse;
+        self
+    }
+
+    /// Compare the number at the start of each line, like `sort -n`. Lines
+    /// that do not start with a number sort as zero.
+    pub fn numeric(&mut self, numeric: bool) -> &mut SortOptions {
+        self.numeric = numeric;
+        self
+    }
+
+    /// Keep only the first of each run of lines that compare equal, like `sort -u`.
+    pub fn unique(&mut self, unique: bool) -> &mut SortOptions {
+        self.unique = unique;
+        self
+    }
+
+    /// Compare lines regardless of case, like `sort -f`.
+    pub fn ignore_case(&mut self, ignore_case: bool) -> &mut SortOptions {
+        self.ignore_case = ignore_case;
+        self
+    }
+
+    /// Orders two lines by the key these options select, without the final
+    /// tie-break on the whole line.
+    fn compare_keys(&self, a: &str, b: &str) -> Ordering {
+        if self.numeric {
+            leading_number(a).partial_cmp(&leading_number(b)).unwrap_or(Ordering::Equal)
+        } else if self.ignore_case {
+            a.chars().flat_map(char::to_lowercase).cmp(b.chars().flat_map(char::to_lowercase))
+        } else {
+            a.cmp(b)
+        }
+    }
+}
+
+/// Parses the number at the start of `line`, after any leading blanks.
+fn leading_number(line: &str) -> f64 {
+    let line = line.trim_start();
+    let mut end = 0;
+    let mut seen_point = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '-' | '+' if i == 0 => {}
+            '0'..='9' => {}
+            '.' if !seen_point => seen_point = true,
+            _ => break,
+        }
+        end = i + c.len_utf8();
+    }
+    line[..end].parse().unwrap_or(0.0)
+}
+
+/// Sorts the lines of a file in place
+/// and returns a boolean based on success or failure.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("sort_file.txt", "pear\napple\nfig\n");
+///
+/// assert!(fsutils::sort_file("sort_file.txt"));
+/// assert_eq!(fsutils::read_file("sort_file.txt"), "apple\nfig\npear\n");
+///
+/// # // Cleanup
+/// # fsutils::rm("sort_file.txt");
+/// ```
+pub fn sort_file<P: AsRef<Path>>(path: P) -> bool {
+    match try_sort_file(path) {
+        Ok(_) => true,
+        Err(e) => {
+            error!("Cannot sort file {}", e);
+            false
+        }
+    }
+}
+
+/// Sorts the lines of a file in place with the default `SortOptions`.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("try_sort_file.txt", "b\r\na\r\nB");
+///
+/// assert!(fsutils::try_sort_file("try_sort_file.txt").is_ok());
+/// assert_eq!(fsutils::read_file("try_sort_file.txt"), "B\r\na\r\nb\r\n");
+///
+/// # // Cleanup
+/// # fsutils::rm("try_sort_file.txt");
+/// ```
+pub fn try_sort_file<P: AsRef<Path>>(path: P) -> Result<()> {
+    sort_file_with(path, &SortOptions::new())
+}
+
+/// Sorts the lines of a file in place according to `opts`, the equivalent
+/// of `sort -o file file`.
+///
+/// Lines that compare equal keep their byte order, and with `unique` only
+/// the first of them is kept. Every line is written with a terminator, `\r\n`
+/// if the file used it and `\n` otherwise. The file is read into memory and
+/// written back through a temporary file that replaces it, so a crash leaves
+/// the original untouched. Fails with an error of kind
+/// `io::ErrorKind::InvalidData` if the file is not valid UTF-8.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::SortOptions;
+///
+/// fsutils::write_file("sort_file_with.txt", "10 ten\n9 nine\n100 hundred\n9 nine\n");
+///
+/// let mut opts = SortOptions::new();
+/// opts.numeric(true).reverse(true).unique(true);
+/// assert!(fsutils::sort_file_with("sort_file_with.txt", &opts).is_ok());
+/// assert_eq!(fsutils::read_file("sort_file_with.txt"), "100 hundred\n10 ten\n9 nine\n");
+///
+/// # // Cleanup
+/// # fsutils::rm("sort_file_with.txt");
+/// ```
+pub fn sort_file_with<P: AsRef<Path>>(path: P, opts: &SortOptions) -> Result<()> {
+    let path = path.as_ref();
+    Op::new("sort_file", path).destructive(true).run((), || {
+        let contents = crate::try_read_file(path)?;
+        let ending = if contents.contains("\r\n") { LineEnding::CrLf } else { LineEnding::Lf };
+        let mut lines: Vec<&str> = contents.lines().collect();
+        lines.sort_by(|a, b| {
+            let order = opts.compare_keys(a, b).then_with(|| a.cmp(b));
+            if opts.reverse { order.reverse() } else { order }
+        });
+        if opts.unique {
+            lines.dedup_by(|a, b| opts.compare_keys(a, b) == Ordering::Equal);
+        }
+        write_lines_to(path, &lines, ending)?;
+        info!("Sorted {}", path.display());
+        Ok(())
+    })
+}
+
+/// Replaces the file at `path` with `lines`, each followed by `ending`.
+fn write_lines_to(path: &Path, lines: &[&str], ending: LineEnding) -> Result<()> {
+    replace_file(path, |temp| {
+        let mut writer = BufWriter::new(temp);
+        for line in lines {
+            writer.write_all(line.as_bytes()).at(path)?;
+            writer.write_all(ending.as_str().as_bytes()).at(path)?;
+        }
+        writer.flush().at(path)
+    })
+}
+
+/// Options for `fsutils::uniq_file_with`.
+///
+/// By default only adjacent duplicates are removed, like `uniq`.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::UniqOptions;
+///
+/// let mut opts = UniqOptions::new();
+/// opts.global(true).count(true);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct UniqOptions {
+    global: bool,
+    count: bool,
+}
+
+impl UniqOptions {
+    /// Creates the default options.
+    pub fn new() -> UniqOptions {
+        UniqOptions::default()
+    }
+
+    /// Remove every repeat of a line, not only those right after it. The
+    /// first occurrence of each line stays where it was.
+    pub fn global(&mut self, global: bool) -> &mut UniqOptions {
+        self.global = global;
+        self
+    }
+
+    /// Start each line with the number of times it occurred, like `uniq -c`.
+    pub fn count(&mut self, count: bool) -> &mut UniqOptions {
+        self.count = count;
+        self
+    }
+}
+
+/// Removes repeated adjacent lines from a file in place and returns the
+/// number of lines removed, or `None` on failure.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("uniq_file.txt", "a\na\nb\na\n");
+///
+/// assert_eq!(fsutils::uniq_file("uniq_file.txt"), Some(1));
+/// assert_eq!(fsutils::read_file("uniq_file.txt"), "a\nb\na\n");
+///
+/// # // Cleanup
+/// # fsutils::rm("uniq_file.txt");
+/// ```
+pub fn uniq_file<P: AsRef<Path>>(path: P) -> Option<usize> {
+    match try_uniq_file(path) {
+        Ok(removed) => Some(removed),
+        Err(e) => {
+            error!("Cannot deduplicate file {}", e);
+            None
+        }
+    }
+}
+
+/// Removes repeated adjacent lines from a file in place with the default
+/// `UniqOptions`, and returns the number of lines removed.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("try_uniq_file.txt", "x\r\nx\r\ny");
+///
+/// assert_eq!(fsutils::try_uniq_file("try_uniq_file.txt").unwrap(), 1);
+/// assert_eq!(fsutils::read_file("try_uniq_file.txt"), "x\r\ny\r\n");
+///
+/// # // Cleanup
+/// # fsutils::rm("try_uniq_file.txt");
+/// ```
+pub fn try_uniq_file<P: AsRef<Path>>(path: P) -> Result<usize> {
+    uniq_file_with(path, &UniqOptions::new())
+}
+
+/// Removes repeated lines from a file in place according to `opts`, and
+/// returns the number of lines removed.
+///
+/// Lines are compared byte for byte and written with the terminator the file
+/// uses. Adjacent duplicates are removed while streaming, so the file can be
+/// larger than memory; global removal keeps each distinct line in memory. The
+/// result goes through a temporary file that replaces the original, so a
+/// crash leaves the original untouched.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::UniqOptions;
+///
+/// fsutils::write_file("uniq_file_with.log", "GET /\nGET /about\nGET /\n");
+///
+/// let mut opts = UniqOptions::new();
+/// opts.global(true).count(true);
+/// assert_eq!(fsutils::uniq_file_with("uniq_file_with.log", &opts).unwrap(), 1);
+/// assert_eq!(fsutils::read_file("uniq_file_with.log"), "      2 GET /\n      1 GET /about\n");
+///
+/// # // Cleanup
+/// # fsutils::rm("uniq_file_with.log");
+/// ```
+pub fn uniq_file_with<P: AsRef<Path>>(path: P, opts: &UniqOptions) -> Result<usize> {
+    let path = path.as_ref();
+    Op::new("uniq_file", path).destructive(true).run(0, || {
+        let mut removed = 0;
+        replace_file(path, |temp| {
+            let mut reader = BufReader::new(File::open(path).at(path)?);
+            let mut out = UniqWriter { writer: BufWriter::new(temp), ending: None, count: opts.count };
+            // Adjacent mode only ever needs the current run; global mode keeps
+            // every distinct line with its count, in order of first appearance
+            let mut run: Option<(Vec<u8>, usize)> = None;
+            let mut seen: HashMap<Vec<u8>, usize> = HashMap::new();
+            let mut distinct: Vec<(Vec<u8>, usize)> = Vec::new();
+            let mut line = Vec::new();
+            loop {
+                line.clear();
+                if reader.read_until(b'\n', &mut line).at(path)? == 0 {
+                    break;
+                }
+                let content = out.strip(&line).to_vec();
+                if opts.global {
+                    match seen.get(&content) {
+                        Some(&i) => {
+                            distinct[i].1 += 1;
+                            removed += 1;
+                        }
+                        None => {
+                            seen.insert(content.clone(), distinct.len());
+                            distinct.push((content, 1));
+                        }
+                    }
+                } else {
+                    match &mut run {
+                        Some((previous, n)) if *previous == content => {
+                            *n += 1;
+                            removed += 1;
+                        }
+                        _ => {
+                            if let Some((previous, n)) = run.replace((content, 1)) {
+                                out.write(&previous, n).at(path)?;
+                            }
+                        }
+                    }
+                }
+            }
+            for (content, n) in run.into_iter().chain(distinct) {
+                out.write(&content, n).at(path)?;
+            }
+            out.writer.flush().at(path)
+        })?;
+        info!("Removed {} repeated lines from {}", removed, path.display());
+        Ok(removed)
+    })
+}
+
+/// Writes the lines kept by `fsutils::uniq_file_with`.
+struct UniqWriter<W: Write> {
+    writer: W,
+    /// The terminator of the first line that had one.
+    ending: Option<&'static [u8]>,
+    count: bool,
+}
+
+impl<W: Write> UniqWriter<W> {
+    /// Returns `line` without its terminator, noting the terminator if it is the first seen.
+    fn strip<'a>(&mut self, line: &'a [u8]) -> &'a [u8] {
+        let (content, ending): (&[u8], &'static [u8]) = if let Some(content) = line.strip_suffix(b"\r\n") {
+            (content, b"\r\n")
+        } else if let Some(content) = line.strip_suffix(b"\n") {
+            (content, b"\n")
+        } else {
+            return line;
+        };
+        self.ending.get_or_insert(ending);
+        content
+    }
+
+    fn write(&mut self, content: &[u8], n: usize) -> io::Result<()> {
+        if self.count {
+            write!(self.writer, "{:>7} ", n)?;
+        }
+        self.writer.write_all(content)?;
+        self.writer.write_all(self.ending.unwrap_or(b"\n"))
+    }
+}
+
+/// Joins several files into `dst`
+/// and returns a boolean based on success or failure.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("concat_a.txt", "one\n");
+/// fsutils::write_file("concat_b.txt", "two\n");
+///
+/// assert!(fsutils::concat(&["concat_a.txt", "concat_b.txt"], "concat_all.txt"));
+/// assert_eq!(fsutils::read_file("concat_all.txt"), "one\ntwo\n");
+///
+/// # // Cleanup
+/// # fsutils::rm("concat_a.txt");
+/// # fsutils::rm("concat_b.txt");
+/// # fsutils::rm("concat_all.txt");
+/// ```
+pub fn concat<P: AsRef<Path>, Q: AsRef<Path>>(paths: &[P], dst: Q) -> bool {
+    match try_concat(paths, dst) {
+        Ok(_) => true,
+        Err(e) => {
+            error!("Cannot concatenate files {}", e);
+            false
+        }
+    }
+}
+
+/// Joins several files into `dst`, like `cat a b > dst`, and returns the
+/// number of bytes written.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("try_concat.txt", "head ");
+///
+/// assert_eq!(fsutils::try_concat(&["try_concat.txt", "try_concat.txt"], "try_concat.txt").unwrap(), 10);
+/// assert_eq!(fsutils::read_file("try_concat.txt"), "head head ");
+/// assert!(fsutils::try_concat(&["a_very_1234_unlikely_9876_filename"], "try_concat.txt").is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm("try_concat.txt");
+/// ```
+pub fn try_concat<P: AsRef<Path>, Q: AsRef<Path>>(paths: &[P], dst: Q) -> Result<u64> {
+    concat_with(paths, dst, "")
+}
+
+/// Joins several files into `dst` with `separator` between each of them, and
+/// returns the number of bytes written.
+///
+/// The inputs are streamed one after the other, so together they can be
+/// larger than memory. The output goes through a temporary file that then
+/// replaces `dst`, which means `dst` may also be one of the inputs, and is
+/// left untouched if any of them cannot be read.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("concat_with_1.sql", "SELECT 1;");
+/// fsutils::write_file("concat_with_2.sql", "SELECT 2;");
+///
+/// let written = fsutils::concat_with(&["concat_with_1.sql", "concat_with_2.sql"], "concat_with.sql", "\n-- next\n");
+/// assert_eq!(written.unwrap(), 27);
+/// assert_eq!(fsutils::read_file("concat_with.sql"), "SELECT 1;\n-- next\nSELECT 2;");
+///
+/// # // Cleanup
+/// # fsutils::rm("concat_with_1.sql");
+/// # fsutils::rm("concat_with_2.sql");
+/// # fsutils::rm("concat_with.sql");
+/// ```
+pub fn concat_with<P: AsRef<Path>, Q: AsRef<Path>>(paths: &[P], dst: Q, separator: &str) -> Result<u64> {
+    let dst = dst.as_ref();
+    Op::new("concat", dst).destructive(dst.exists()).run(0, || {
+        let mut written = 0;
+        replace_file(dst, |temp| {
+            let mut writer = BufWriter::new(temp);
+            for (i, path) in paths.iter().enumerate() {
+                let path = path.as_ref();
+                if i > 0 {
+                    writer.write_all(separator.as_bytes()).at(dst)?;
+                    written += separator.len() as u64;
+                }
+                let mut file = File::open(path).at(path)?;
+                written += io::copy(&mut file, &mut writer).at(path)?;
+            }
+            writer.flush().at(dst)
+        })?;
+        info!("Concatenated {} files into {}", paths.len(), dst.display());
+        Ok(written)
+    })
+}
+
+/// Where `fsutils::split` ends one part and starts the next.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::SplitBy;
+///
+/// let megabyte = SplitBy::Bytes(1024 * 1024);
+/// let thousand = SplitBy::Lines(1000);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum SplitBy {
+    /// Parts of this many bytes, like `split -b`. Lines may be cut in two.
+    Bytes(u64),
+    /// Parts of this many lines, like `split -l`.
+    Lines(usize),
+}
+
+/// Splits a file into parts named `prefix.000`, `prefix.001` and so on
+/// and returns their paths, or an empty `Vec` on failure.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::SplitBy;
+///
+/// fsutils::write_file("split.csv", "a\nb\nc\n");
+///
+/// let parts = fsutils::split("split.csv", SplitBy::Lines(2), "split.csv.part");
+/// assert_eq!(parts.len(), 2);
+/// assert_eq!(fsutils::read_file("split.csv.part.001"), "c\n");
+///
+/// # // Cleanup
+/// # fsutils::rm("split.csv");
+/// # for part in parts { fsutils::rm(part); }
+/// ```
+pub fn split<P: AsRef<Path>, Q: AsRef<Path>>(path: P, by: SplitBy, prefix: Q) -> Vec<PathBuf> {
+    match try_split(path, by, prefix) {
+        Ok(parts) => parts,
+        Err(e) => {
+            error!("Cannot split file {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// Splits a file into parts named `prefix.000`, `prefix.001` and so on
+/// and returns their paths in order.
+///
+/// The file is streamed, so it can be larger than memory, and is left as it
+/// is. Every part is full except possibly the last, and an empty file gives
+/// no parts at all. Joining the parts with `fsutils::concat` gives back the
+/// original. Existing files with the names of the parts are overwritten,
+/// but the confirm hook set with `fsutils::set_confirm` is asked about each
+/// first, and the split stops with `FsError::Declined` at the first one it
+/// declines. Fails with an error of kind `io::ErrorKind::InvalidInput` if the
+/// size of a part is 0.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::SplitBy;
+///
+/// fsutils::create_file_bytes("try_split.bin", &[7; 2500]);
+///
+/// let parts = fsutils::try_split("try_split.bin", SplitBy::Bytes(1000), "try_split").unwrap();
+/// let names: Vec<_> = parts.iter().map(|p| p.to_str().unwrap()).collect();
+/// assert_eq!(names, ["try_split.000", "try_split.001", "try_split.002"]);
+/// assert_eq!(std::fs::metadata("try_split.002").unwrap().len(), 500);
+/// assert!(fsutils::try_split("try_split.bin", SplitBy::Lines(0), "try_split").is_err());
+///
+/// fsutils::set_confirm(|_, _| false);
+/// let result = fsutils::try_split("try_split.bin", SplitBy::Bytes(2000), "try_split");
+/// assert!(matches!(result, Err(fsutils::FsError::Declined { .. })));
+/// fsutils::clear_confirm();
+/// assert_eq!(std::fs::metadata("try_split.000").unwrap().len(), 1000);
+///
+/// # // Cleanup
+/// # fsutils::rm("try_split.bin");
+/// # for part in parts { fsutils::rm(part); }
+/// ```
+pub fn try_split<P: AsRef<Path>, Q: AsRef<Path>>(path: P, by: SplitBy, prefix: Q) -> Result<Vec<PathBuf>> {
+    let path = path.as_ref();
+    let prefix = prefix.as_ref();
+    if by == SplitBy::Bytes(0) || by == SplitBy::Lines(0) {
+        return Err(FsError::from_io(path, io::Error::new(io::ErrorKind::InvalidInput, "parts cannot be empty")));
+    }
+    Op::new("split", path).to(prefix).run(Vec::new(), || {
+        let mut reader = BufReader::with_capacity(CHUNK, File::open(path).at(path)?);
+        let mut parts = Vec::new();
+        let mut line = Vec::new();
+        while !reader.fill_buf().at(path)?.is_empty() {
+            let mut name = OsString::from(prefix.as_os_str());
+            name.push(format!(".{:03}", parts.len()));
+            let part = PathBuf::from(name);
+            if part.symlink_metadata().is_ok() && !confirm_each("split", &part) {
+                return Err(FsError::Declined { path: part });
+            }
+            let mut writer = BufWriter::new(File::create(&part).at(&part)?);
+            match by {
+                SplitBy::Bytes(n) => {
+                    io::copy(&mut reader.by_ref().take(n), &mut writer).at(path)?;
+                }
+                SplitBy::Lines(n) => {
+                    for _ in 0..n {
+                        line.clear();
+                        if reader.read_until(b'\n', &mut line).at(path)? == 0 {
+                            break;
+                        }
+                        writer.write_all(&line).at(&part)?;
+                    }
+                }
+            }
+            writer.flush().at(&part)?;
+            parts.push(part);
+        }
+        info!("Split file {} into {} parts", path.display(), parts.len());
+        Ok(parts)
+    })
+}
+
+/// Writes the lines of a file to `dst` in reverse order
+/// and returns a boolean based on success or failure.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("reverse_lines.log", "first\nsecond\nthird\n");
+///
+/// assert!(fsutils::reverse_lines("reverse_lines.log", "reverse_lines.rev"));
+/// assert_eq!(fsutils::read_file("reverse_lines.rev"), "third\nsecond\nfirst\n");
+///
+/// # // Cleanup
+/// # fsutils::rm("reverse_lines.log");
+/// # fsutils::rm("reverse_lines.rev");
+/// ```
+pub fn reverse_lines<P: AsRef<Path>, Q: AsRef<Path>>(path: P, dst: Q) -> bool {
+    match try_reverse_lines(path, dst) {
+        Ok(_) => true,
+        Err(e) => {
+            error!("Cannot reverse file {}", e);
+            false
+        }
+    }
+}
+
+/// Writes the lines of a file to `dst` in reverse order, like `tac`.
+///
+/// The file is read backwards a block at a time, so only about one block and
+/// one line are held in memory however large it is. Every line is written
+/// with a `\n` after it, including a last line that had none, and a `\r`
+/// before a line's `\n` is kept with it. `dst` is replaced through a
+/// temporary file, so it may be the same file as `path`.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("try_reverse_lines.txt", "a\r\nb\r\nc");
+///
+/// fsutils::try_reverse_lines("try_reverse_lines.txt", "try_reverse_lines.txt").unwrap();
+/// assert_eq!(fsutils::read_file("try_reverse_lines.txt"), "c\nb\r\na\r\n");
+/// assert!(fsutils::try_reverse_lines("a_very_1234_unlikely_9876_filename", "try_reverse_lines.txt").is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm("try_reverse_lines.txt");
+/// ```
+pub fn try_reverse_lines<P: AsRef<Path>, Q: AsRef<Path>>(path: P, dst: Q) -> Result<()> {
+    let path = path.as_ref();
+    let dst = dst.as_ref();
+    let mut file = File::open(path).at(path)?;
+    let len = file.metadata().at(path)?.len();
+    Op::new("reverse_lines", path).to(dst).bytes(len).destructive(dst.exists()).run((), || {
+        replace_file(dst, |temp| {
+            let mut writer = BufWriter::new(temp);
+            let mut pos = len;
+            if len > 0 {
+                let mut last = [0];
+                file.seek(SeekFrom::Start(len - 1)).at(path)?;
+                file.read_exact(&mut last).at(path)?;
+                // The final terminator is written again after the last line
+                if last[0] == b'\n' {
+                    pos -= 1;
+                }
+            }
+            // The start of the earliest line seen so far, whose beginning may
+            // still be in the part of the file not yet read
+            let mut partial = Vec::new();
+            while pos > 0 {
+                let start = pos.saturating_sub(CHUNK as u64);
+                let mut block = vec![0; (pos - start) as usize];
+                file.seek(SeekFrom::Start(start)).at(path)?;
+                file.read_exact(&mut block).at(path)?;
+                block.extend_from_slice(&partial);
+                partial = match block.iter().position(|&b| b == b'\n') {
+                    Some(first) => {
+                        for line in block[first + 1..].rsplit(|&b| b == b'\n') {
+                            writer.write_all(line).at(dst)?;
+                            writer.write_all(b"\n").at(dst)?;
+                        }
+                        block.truncate(first);
+                        block
+                    }
+                    None => block,
+                };
+                pos = start;
+            }
+            if len > 0 {
+                writer.write_all(&partial).at(dst)?;
+                writer.write_all(b"\n").at(dst)?;
+            }
+            writer.flush().at(dst)
+        })?;
+        info!("Reversed lines of {} into {}", path.display(), dst.display());
+        Ok(())
+    })
+}
+
+/// The line endings found in a file by `fsutils::line_endings`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct LineEndings {
+    /// Lines ending in a bare `\n`.
+    pub lf: u64,
+    /// Lines ending in `\r\n`.
+    pub crlf: u64,
+}
+
+impl LineEndings {
+    /// Returns the ending used by every line, or `None` if the file mixes
+    /// both or has no terminated lines at all.
+    ///
+    /// ## Usage:
+    ///
+    /// ```
+    /// use fsutils::{LineEnding, LineEndings};
+    ///
+    /// assert_eq!(LineEndings { lf: 0, crlf: 4 }.uniform(), Some(LineEnding::CrLf));
+    /// assert_eq!(LineEndings { lf: 1, crlf: 4 }.uniform(), None);
+    /// ```
+    pub fn uniform(&self) -> Option<LineEnding> {
+        match (self.lf, self.crlf) {
+            (0, 0) => None,
+            (_, 0) => Some(LineEnding::Lf),
+            (0, _) => Some(LineEnding::CrLf),
+            _ => None,
+        }
+    }
+
+    /// Whether the file uses both endings.
+    pub fn is_mixed(&self) -> bool {
+        self.lf > 0 && self.crlf > 0
+    }
+}
+
+/// Counts the line endings of each kind in a file
+/// and returns `None` if it cannot be read.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("line_endings.txt", "one\r\ntwo\nthree\r\n");
+///
+/// let endings = fsutils::line_endings("line_endings.txt").unwrap();
+/// assert_eq!((endings.lf, endings.crlf), (1, 2));
+///
+/// # // Cleanup
+/// # fsutils::rm("line_endings.txt");
+/// ```
+pub fn line_endings<P: AsRef<Path>>(path: P) -> Option<LineEndings> {
+    match try_line_endings(path) {
+        Ok(endings) => Some(endings),
+        Err(e) => {
+            error!("Cannot read file {}", e);
+            None
+        }
+    }
+}
+
+/// Counts the line endings of each kind in a file, reading it in one pass.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::LineEnding;
+///
+/// fsutils::write_file("try_line_endings.txt", "unix\nonly\n");
+///
+/// let endings = fsutils::try_line_endings("try_line_endings.txt").unwrap();
+/// assert_eq!(endings.uniform(), Some(LineEnding::Lf));
+/// assert!(fsutils::try_line_endings("a_very_1234_unlikely_9876_filename").is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm("try_line_endings.txt");
+/// ```
+pub fn try_line_endings<P: AsRef<Path>>(path: P) -> Result<LineEndings> {
+    let path = path.as_ref();
+    let mut reader = BufReader::with_capacity(CHUNK, File::open(path).at(path)?);
+    let mut endings = LineEndings::default();
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line).at(path)? == 0 {
+            return Ok(endings);
+        }
+        if line.ends_with(b"\r\n") {
+            endings.crlf += 1;
+        } else if line.ends_with(b"\n") {
+            endings.lf += 1;
+        }
+    }
+}
+
+/// Rewrites the line endings of a file as `ending`, like `dos2unix` or
+/// `unix2dos`, and returns a boolean based on success or failure.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::LineEnding;
+///
+/// fsutils::write_file("convert_line_endings.bat", "@echo off\r\nexit\r\n");
+///
+/// assert!(fsutils::convert_line_endings("convert_line_endings.bat", LineEnding::Lf));
+/// assert_eq!(fsutils::read_file("convert_line_endings.bat"), "@echo off\nexit\n");
+///
+/// # // Cleanup
+/// # fsutils::rm("convert_line_endings.bat");
+/// ```
+pub fn convert_line_endings<P: AsRef<Path>>(path: P, ending: LineEnding) -> bool {
+    match try_convert_line_endings(path, ending) {
+        Ok(_) => true,
+        Err(e) => {
+            error!("Cannot convert file {}", e);
+            false
+        }
+    }
+}
+
+/// Rewrites the line endings of a file as `ending` and returns the number of
+/// lines changed.
+///
+/// The file is streamed through a temporary file that then replaces it, and
+/// is not rewritten if every line already uses `ending`. A last line without
+/// a terminator is left without one, and a `\r` not followed by `\n` is not
+/// treated as a line ending.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::LineEnding;
+///
+/// fsutils::write_file("try_convert_line_endings.txt", "a\nb\r\nc");
+///
+/// assert_eq!(fsutils::try_convert_line_endings("try_convert_line_endings.txt", LineEnding::CrLf).unwrap(), 1);
+/// assert_eq!(fsutils::read_file("try_convert_line_endings.txt"), "a\r\nb\r\nc");
+/// assert_eq!(fsutils::try_convert_line_endings("try_convert_line_endings.txt", LineEnding::CrLf).unwrap(), 0);
+///
+/// # // Cleanup
+/// # fsutils::rm("try_convert_line_endings.txt");
+/// ```
+pub fn try_convert_line_endings<P: AsRef<Path>>(path: P, ending: LineEnding) -> Result<usize> {
+    let path = path.as_ref();
+    Op::new("convert_line_endings", path).destructive(true).run(0, || {
+        let mut changed = 0;
+        replace_file_if(path, |temp| {
+            let mut reader = BufReader::with_capacity(CHUNK, File::open(path).at(path)?);
+            let mut writer = BufWriter::new(temp);
+            let mut line = Vec::new();
+            loop {
+                line.clear();
+                if reader.read_until(b'\n', &mut line).at(path)? == 0 {
+                    break;
+                }
+                let (text, old) = if line.ends_with(b"\r\n") {
+                    (&line[..line.len() - 2], LineEnding::CrLf)
+                } else if line.ends_with(b"\n") {
+                    (&line[..line.len() - 1], LineEnding::Lf)
+                } else {
+                    writer.write_all(&line).at(path)?;
+                    break;
+                };
+                if old != ending {
+                    changed += 1;
+                }
+                writer.write_all(text).at(path)?;
+                writer.write_all(ending.as_str().as_bytes()).at(path)?;
+            }
+            writer.flush().at(path)?;
+            Ok(changed > 0)
+        })?;
+        info!("Converted {} line endings in {}", changed, path.display());
+        Ok(changed)
+    })
+}
@@ -0,0 +1,313 @@
+// Copyright 2020 Jared Forth.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Rendering directory trees as text or JSON.
+
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::error::{IoResultExt, Result};
+use crate::json;
+use crate::list::{ls_with, LsOptions};
+use crate::meta::FileKind;
+
+/// Options for `fsutils::tree_with` and `fsutils::tree_json_with`.
+///
+/// By default the whole tree is shown, without hidden entries.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::TreeOptions;
+///
+/// let mut opts = TreeOptions::new();
+/// opts.max_depth(2).all(true);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct TreeOptions {
+    max_depth: Option<usize>,
+    all: bool,
+}
+
+impl TreeOptions {
+    /// Creates the default options.
+    pub fn new() -> TreeOptions {
+        TreeOptions::default()
+    }
+
+    /// Only go `depth` levels down, like `tree -L`; `1` shows the direct
+    /// children of the root.
+    pub fn max_depth(&mut self, depth: usize) -> &mut TreeOptions {
+        self.max_depth = Some(depth);
+        self
+    }
+
+    /// Include hidden entries, like `tree -a`. These are the ones
+    /// `fsutils::ls_a` lists and `fsutils::ls_entries` does not.
+    pub fn all(&mut self, all: bool) -> &mut TreeOptions {
+        self.all = all;
+        self
+    }
+}
+
+/// A file, directory or link in a tree from `fsutils::tree_json`, with
+/// everything below it.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TreeNode {
+    /// The file name, or the path as given for the root.
+    pub name: OsString,
+    /// What the entry is. Symbolic links are not followed.
+    pub kind: FileKind,
+    /// The length in bytes, of the link itself for a symbolic link.
+    pub size: u64,
+    /// Where a symbolic link points.
+    pub target: Option<PathBuf>,
+    /// The entries of a directory, sorted by name. Empty for everything
+    /// else, and for directories below the `max_depth`.
+    pub children: Vec<TreeNode>,
+}
+
+impl TreeNode {
+    fn build(path: &Path, name: OsString, opts: &TreeOptions, depth: usize) -> Result<TreeNode> {
+        let meta = if depth == 0 { fs::metadata(path) } else { fs::symlink_metadata(path) }.at(path)?;
+        let kind = FileKind::of(&meta);
+        let target = if kind == FileKind::Symlink { Some(fs::read_link(path).at(path)?) } else { None };
+        let mut children = Vec::new();
+        if kind == FileKind::Dir && opts.max_depth.is_none_or(|max| depth < max) {
+            for entry in ls_with(path, LsOptions::new().all(opts.all))? {
+                children.push(TreeNode::build(&entry.path, entry.name, opts, depth + 1)?);
+            }
+        }
+        Ok(TreeNode { name, kind, size: meta.len(), target, children })
+    }
+
+    /// Encodes the tree as a JSON object, with nested objects for the
+    /// entries of directories.
+    ///
+    /// Every object has a `name`, a `kind` of `"file"`, `"dir"`, `"symlink"`
+    /// or `"other"`, and a `size`. Directories also have `children` and
+    /// links a `target`. Names that are not valid UTF-8 are encoded lossily.
+    ///
+    /// ## Usage:
+    ///
+    /// ```
+    /// fsutils::mkdir("tree_node_to_json_dir");
+    /// fsutils::write_file("tree_node_to_json_dir/a.txt", "abc");
+    ///
+    /// let json = fsutils::try_tree_json("tree_node_to_json_dir").unwrap().to_json();
+    /// assert!(json.ends_with(r#""children":[{"name":"a.txt","kind":"file","size":3}]}"#));
+    ///
+    /// # // Cleanup
+    /// # fsutils::rm_r("tree_node_to_json_dir");
+    /// ```
+    pub fn to_json(&self) -> String {
+        let kind = match self.kind {
+            FileKind::File => "file",
+            FileKind::Dir => "dir",
+            FileKind::Symlink => "symlink",
+            FileKind::Other => "other",
+        };
+        let mut out = format!(
+            "{{\"name\":{},\"kind\":\"{}\",\"size\":{}",
+            json::string(&self.name.to_string_lossy()),
+            kind,
+            self.size
+        );
+        if let Some(target) = &self.target {
+            out.push_str(&format!(",\"target\":{}", json::path(target)));
+        }
+        if self.kind == FileKind::Dir {
+            let children: Vec<String> = self.children.iter().map(TreeNode::to_json).collect();
+            out.push_str(&format!(",\"children\":[{}]", children.join(",")));
+        }
+        out.push('}');
+        out
+    }
+
+    /// Draws the children of this node, each line starting with `indent`.
+    fn render(&self, indent: &str, out: &mut String, counts: &mut (usize, usize)) {
+        for (i, child) in self.children.iter().enumerate() {
+            let last = i + 1 == self.children.len();
+            out.push_str(indent);
+            out.push_str(if last { "└── " } else { "├── " });
+            out.push_str(&child.name.to_string_lossy());
+            if let Some(target) = &child.target {
+                out.push_str(" -> ");
+                out.push_str(&target.to_string_lossy());
+            }
+            out.push('\n');
+            if child.kind == FileKind::Dir {
+                counts.0 += 1;
+            } else {
+                counts.1 += 1;
+            }
+            child.render(&format!("{}{}", indent, if last { "    " } else { "│   " }), out, counts);
+        }
+    }
+}
+
+/// Draws the tree at `path` like the `tree` command, or returns an empty
+/// `String` if it cannot be read.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::mkdir("tree_dir/src");
+/// fsutils::write_file("tree_dir/src/main.rs", "");
+///
+/// print!("{}", fsutils::tree("tree_dir"));
+///
+/// # // Cleanup
+/// # fsutils::rm_r("tree_dir");
+/// ```
+pub fn tree<P: AsRef<Path>>(path: P) -> String {
+    match try_tree(path) {
+        Ok(text) => text,
+        Err(e) => {
+            error!("Cannot draw tree {}", e);
+            String::new()
+        }
+    }
+}
+
+/// Draws the tree at `path` with box-drawing lines, like the `tree` command,
+/// without hidden entries.
+///
+/// The path comes first, then one line per entry below it, sorted by name
+/// within each directory, and last a count of the directories and files
+/// shown. Symbolic links are shown with their targets and not followed,
+/// unless the root is one.
+/// See `fsutils::tree_with` to limit the depth or show hidden entries.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::mkdir("try_tree_dir/src/bin");
+/// fsutils::write_file("try_tree_dir/Cargo.toml", "");
+/// fsutils::write_file("try_tree_dir/src/lib.rs", "");
+/// fsutils::write_file("try_tree_dir/src/bin/main.rs", "");
+///
+/// assert_eq!(fsutils::try_tree("try_tree_dir").unwrap(), "\
+/// try_tree_dir
+/// ├── Cargo.toml
+/// └── src
+///     ├── bin
+///     │   └── main.rs
+///     └── lib.rs
+///
+/// 2 directories, 3 files
+/// ");
+/// assert!(fsutils::try_tree("a_very_1234_unlikely_9876_dirname").is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm_r("try_tree_dir");
+/// ```
+pub fn try_tree<P: AsRef<Path>>(path: P) -> Result<String> {
+    tree_with(path, &TreeOptions::new())
+}
+
+/// Draws the tree at `path` like `fsutils::try_tree`, according to `opts`.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::TreeOptions;
+///
+/// fsutils::mkdir("tree_with_dir/a/b");
+/// fsutils::write_file("tree_with_dir/.env", "");
+///
+/// let text = fsutils::tree_with("tree_with_dir", TreeOptions::new().max_depth(1).all(true)).unwrap();
+/// assert_eq!(text, "tree_with_dir\n├── .env\n└── a\n\n1 directory, 1 file\n");
+///
+/// # // Cleanup
+/// # fsutils::rm_r("tree_with_dir");
+/// ```
+pub fn tree_with<P: AsRef<Path>>(path: P, opts: &TreeOptions) -> Result<String> {
+    let root = tree_json_with(path, opts)?;
+    let mut out = format!("{}\n", root.name.to_string_lossy());
+    let mut counts = (0, 0);
+    root.render("", &mut out, &mut counts);
+    let plural = |n: usize, one: &str, many: &str| format!("{} {}", n, if n == 1 { one } else { many });
+    let (dirs, files) = (plural(counts.0, "directory", "directories"), plural(counts.1, "file", "files"));
+    out.push_str(&format!("\n{}, {}\n", dirs, files));
+    Ok(out)
+}
+
+/// Reads the tree at `path` into nested `TreeNode`s, or returns `None` if it
+/// cannot be read.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::mkdir("tree_json_dir");
+/// fsutils::write_file("tree_json_dir/a.txt", "");
+///
+/// if let Some(root) = fsutils::tree_json("tree_json_dir") {
+///     println!("{}", root.to_json());
+/// }
+///
+/// # // Cleanup
+/// # fsutils::rm_r("tree_json_dir");
+/// ```
+pub fn tree_json<P: AsRef<Path>>(path: P) -> Option<TreeNode> {
+    match try_tree_json(path) {
+        Ok(root) => Some(root),
+        Err(e) => {
+            error!("Cannot read tree {}", e);
+            None
+        }
+    }
+}
+
+/// Reads the tree at `path` into nested `TreeNode`s without hidden entries,
+/// for `TreeNode::to_json` or for walking it in code.
+///
+/// Symbolic links below the root are not followed. See
+/// `fsutils::tree_json_with` to limit the depth or include hidden entries.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::FileKind;
+///
+/// fsutils::mkdir("try_tree_json_dir/src");
+/// fsutils::write_file("try_tree_json_dir/src/lib.rs", "fn f() {}");
+///
+/// let root = fsutils::try_tree_json("try_tree_json_dir").unwrap();
+/// assert_eq!(root.name, "try_tree_json_dir");
+/// assert_eq!(root.children[0].kind, FileKind::Dir);
+/// assert_eq!(root.children[0].children[0].size, 9);
+/// assert!(fsutils::try_tree_json("a_very_1234_unlikely_9876_dirname").is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm_r("try_tree_json_dir");
+/// ```
+pub fn try_tree_json<P: AsRef<Path>>(path: P) -> Result<TreeNode> {
+    tree_json_with(path, &TreeOptions::new())
+}
+
+/// Reads the tree at `path` into nested `TreeNode`s according to `opts`.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::TreeOptions;
+///
+/// fsutils::mkdir("tree_json_with_dir/a/b");
+///
+/// let root = fsutils::tree_json_with("tree_json_with_dir", TreeOptions::new().max_depth(1)).unwrap();
+/// assert!(root.children[0].children.is_empty());
+///
+/// # // Cleanup
+/// # fsutils::rm_r("tree_json_with_dir");
+/// ```
+pub fn tree_json_with<P: AsRef<Path>>(path: P, opts: &TreeOptions) -> Result<TreeNode> {
+    let path = path.as_ref();
+    TreeNode::build(path, path.as_os_str().to_os_string(), opts, 0)
+}
@@ -0,0 +1,593 @@
+// Copyright 2020 Jared Forth.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Walking directory trees lazily, on one thread or several.
+
+use std::fs::{self, DirEntry, FileType};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+use std::thread;
+
+use crate::error::{FsError, IoResultExt, Result};
+use crate::ignore::Ignores;
+use crate::link::{Ancestors, FollowSymlinks};
+use crate::meta::FileKind;
+
+/// A file, directory or link found by `fsutils::walk`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct WalkEntry {
+    /// The path of the entry, starting with the root that was walked.
+    pub path: PathBuf,
+    /// What the entry is, or what it points to if it is a symbolic link
+    /// that is followed and not dangling.
+    pub kind: FileKind,
+    /// Whether the entry is a symbolic link, followed or not.
+    pub is_symlink: bool,
+    /// How far below the root the entry is, where the root is 0.
+    pub depth: usize,
+}
+
+/// Options for `fsutils::walk_with`.
+///
+/// By default the whole tree is walked, ignore files are not looked at and
+/// only a root that is a symbolic link is followed.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::{FollowSymlinks, WalkOptions};
+///
+/// let mut opts = WalkOptions::new();
+/// opts.max_depth(2).follow(FollowSymlinks::Always).gitignore(true);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct WalkOptions {
+    follow: FollowSymlinks,
+    max_depth: Option<usize>,
+    gitignore: bool,
+}
+
+impl WalkOptions {
+    /// Creates the default options.
+    pub fn new() -> WalkOptions {
+        WalkOptions::default()
+    }
+
+    /// Which symbolic links to directories to walk into.
+    pub fn follow(&mut self, follow: FollowSymlinks) -> &mut WalkOptions {
+        self.follow = follow;
+        self
+    }
+
+    /// Only go `depth` levels down; `1` yields the root and its direct children.
+    pub fn max_depth(&mut self, depth: usize) -> &mut WalkOptions {
+        self.max_depth = Some(depth);
+        self
+    }
+
+    /// Skip what `.gitignore` and `.ignore` files say to ignore, the way
+    /// ripgrep does.
+    ///
+    /// The ignore files of each directory apply below it, with `.ignore`
+    /// taking precedence over `.gitignore` and deeper files over those above
+    /// them. Ignore files in the directories above the root count too, up to
+    /// the git repository the root is in, as do the repository's
+    /// `.git/info/exclude` and git's global excludes file, `core.excludesFile`
+    /// or `~/.config/git/ignore`. An ignored directory is not walked into.
+    /// `.git` is always skipped and the root never is.
+    ///
+    /// ## Usage:
+    ///
+    /// ```
+    /// use fsutils::WalkOptions;
+    ///
+    /// fsutils::mkdir("walk_gitignore_dir/target/debug");
+    /// fsutils::mkdir("walk_gitignore_dir/src");
+    /// fsutils::write_file("walk_gitignore_dir/.gitignore", "target/\n*.log\n!keep.log\n");
+    /// fsutils::write_file("walk_gitignore_dir/src/main.rs", "");
+    /// fsutils::write_file("walk_gitignore_dir/src/debug.log", "");
+    /// fsutils::write_file("walk_gitignore_dir/src/keep.log", "");
+    ///
+    /// let names: Vec<_> = fsutils::walk_with("walk_gitignore_dir", WalkOptions::new().gitignore(true))
+    ///     .map(|entry| entry.unwrap().path.file_name().unwrap().to_owned())
+    ///     .collect();
+    /// assert_eq!(names, ["walk_gitignore_dir", ".gitignore", "src", "keep.log", "main.rs"]);
+    ///
+    /// # // Cleanup
+    /// # fsutils::rm_r("walk_gitignore_dir");
+    /// ```
+    pub fn gitignore(&mut self, gitignore: bool) -> &mut WalkOptions {
+        self.gitignore = gitignore;
+        self
+    }
+}
+
+/// The iterator returned by `fsutils::walk`, yielding each entry of a tree.
+#[derive(Debug)]
+pub struct Walk {
+    opts: WalkOptions,
+    root: Option<PathBuf>,
+    /// The entries left in each directory being walked, the next one last,
+    /// with the depth they are at.
+    levels: Vec<(usize, Vec<DirEntry>)>,
+    ancestors: Ancestors,
+    /// The ignore rules in effect, if they are respected.
+    ignores: Option<Ignores>,
+    /// An error reading the directory just yielded, to yield next.
+    failure: Option<FsError>,
+    /// Whether the entry yielded last is a directory being walked into.
+    entered: bool,
+}
+
+impl Walk {
+    fn new(root: &Path, opts: &WalkOptions) -> Walk {
+        Walk {
+            opts: opts.clone(),
+            root: Some(root.to_path_buf()),
+            levels: Vec::new(),
+            ancestors: Ancestors::new(opts.follow),
+            ignores: if opts.gitignore { Some(Ignores::new(root)) } else { None },
+            failure: None,
+            entered: false,
+        }
+    }
+
+    /// Does not walk into the directory yielded last, so none of the entries
+    /// below it are yielded. Does nothing if the last entry was not a
+    /// directory.
+    ///
+    /// ## Usage:
+    ///
+    /// ```
+    /// fsutils::mkdir("walk_skip_dir/node_modules/left-pad");
+    /// fsutils::write_file("walk_skip_dir/index.js", "");
+    ///
+    /// let mut walk = fsutils::walk("walk_skip_dir");
+    /// let mut found = Vec::new();
+    /// while let Some(entry) = walk.next() {
+    ///     let entry = entry.unwrap();
+    ///     if entry.path.ends_with("node_modules") {
+    ///         walk.skip_dir();
+    ///     }
+    ///     found.push(entry.path);
+    /// }
+    /// assert_eq!(found.len(), 3);
+    ///
+    /// # // Cleanup
+    /// # fsutils::rm_r("walk_skip_dir");
+    /// ```
+    pub fn skip_dir(&mut self) {
+        if self.entered {
+            self.entered = false;
+            self.leave_dir();
+        }
+        self.failure = None;
+    }
+
+    /// Yields the entry at `path` and walks into it if it is a directory,
+    /// or returns `None` if it is ignored.
+    fn visit(&mut self, path: PathBuf, file_type: FileType, depth: usize) -> Result<Option<WalkEntry>> {
+        let mut kind = FileKind::of_type(file_type);
+        if file_type.is_symlink() && self.opts.follow.follows(depth == 0) {
+            kind = FileKind::of(&self.opts.follow.metadata(&path, depth == 0).at(&path)?);
+        }
+        if depth > 0 && self.ignores.as_ref().is_some_and(|ignores| ignores.is_ignored(&path, kind == FileKind::Dir)) {
+            return Ok(None);
+        }
+        if kind == FileKind::Dir && self.opts.max_depth.is_none_or(|max| depth < max) {
+            match self.read_dir(&path) {
+                Ok(Some(mut entries)) => {
+                    entries.sort_by_key(|entry| std::cmp::Reverse(entry.file_name()));
+                    self.levels.push((depth + 1, entries));
+                    if let Some(ignores) = &mut self.ignores {
+                        ignores.enter(&path);
+                    }
+                    self.entered = true;
+                }
+                Ok(None) => {}
+                Err(e) => self.failure = Some(e),
+            }
+        }
+        Ok(Some(WalkEntry { path, kind, is_symlink: file_type.is_symlink(), depth }))
+    }
+
+    /// Stops walking the directory walked into last.
+    fn leave_dir(&mut self) {
+        self.levels.pop();
+        self.ancestors.leave();
+        if let Some(ignores) = &mut self.ignores {
+            ignores.leave();
+        }
+    }
+
+    /// The entries of `dir`, or `None` if walking into it would be a loop.
+    fn read_dir(&mut self, dir: &Path) -> Result<Option<Vec<DirEntry>>> {
+        if !self.ancestors.enter(dir)? {
+            return Ok(None);
+        }
+        let entries = fs::read_dir(dir).and_then(|entries| entries.collect::<std::io::Result<Vec<_>>>());
+        if entries.is_err() {
+            self.ancestors.leave();
+        }
+        entries.at(dir).map(Some)
+    }
+}
+
+impl Iterator for Walk {
+    type Item = Result<WalkEntry>;
+
+    fn next(&mut self) -> Option<Result<WalkEntry>> {
+        self.entered = false;
+        if let Some(e) = self.failure.take() {
+            return Some(Err(e));
+        }
+        if let Some(root) = self.root.take() {
+            let file_type = match root.symlink_metadata() {
+                Ok(meta) => meta.file_type(),
+                Err(e) => return Some(Err(FsError::from_io(&root, e))),
+            };
+            return self.visit(root, file_type, 0).transpose();
+        }
+        loop {
+            let (depth, entries) = self.levels.last_mut()?;
+            let depth = *depth;
+            match entries.pop() {
+                Some(entry) => {
+                    let path = entry.path();
+                    match entry.file_type() {
+                        Ok(file_type) => {
+                            if let Some(visited) = self.visit(path, file_type, depth).transpose() {
+                                return Some(visited);
+                            }
+                        }
+                        Err(e) => return Some(Err(FsError::from_io(&path, e))),
+                    }
+                }
+                None => self.leave_dir(),
+            }
+        }
+    }
+}
+
+/// Walks the tree at `root`, yielding the root and then every entry below
+/// it, depth first and in name order within each directory.
+///
+/// See `fsutils::walk_with` for the details.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::FileKind;
+///
+/// fsutils::mkdir("walk_dir/src/bin");
+/// fsutils::write_file("walk_dir/src/lib.rs", "");
+/// fsutils::write_file("walk_dir/src/bin/main.rs", "");
+///
+/// let files: Vec<_> = fsutils::walk("walk_dir")
+///     .filter_map(Result::ok)
+///     .filter(|entry| entry.kind == FileKind::File)
+///     .map(|entry| (entry.path, entry.depth))
+///     .collect();
+/// # #[cfg(unix)]
+/// assert_eq!(files, [("walk_dir/src/bin/main.rs".into(), 3), ("walk_dir/src/lib.rs".into(), 2)]);
+///
+/// # // Cleanup
+/// # fsutils::rm_r("walk_dir");
+/// ```
+pub fn walk<P: AsRef<Path>>(root: P) -> Walk {
+    walk_with(root, &WalkOptions::new())
+}
+
+/// Walks the tree at `root` according to `opts`, yielding the root and then
+/// every entry below it, depth first and in name order within each directory.
+///
+/// Entries are yielded as the walk goes instead of being collected first,
+/// and each directory is read as the walk reaches it. An entry that cannot
+/// be read, such as a directory without permission, is yielded as an error
+/// and the walk goes on with the rest of the tree; a missing root is a single
+/// error. A symbolic link that is not followed is yielded as a link and not
+/// walked into, and one that would lead back into a directory being walked
+/// is never walked into.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::WalkOptions;
+///
+/// fsutils::mkdir("walk_with_dir/a/b/c");
+///
+/// let depths: Vec<_> = fsutils::walk_with("walk_with_dir", WalkOptions::new().max_depth(2))
+///     .map(|entry| entry.unwrap().depth)
+///     .collect();
+/// assert_eq!(depths, [0, 1, 2]);
+/// assert!(fsutils::walk("a_very_1234_unlikely_9876_dirname").next().unwrap().is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm_r("walk_with_dir");
+/// ```
+pub fn walk_with<P: AsRef<Path>>(root: P, opts: &WalkOptions) -> Walk {
+    Walk::new(root.as_ref(), opts)
+}
+
+/// Walks the tree at `root` on one thread per CPU, yielding the root and
+/// then every entry below it in no particular order.
+///
+/// See `fsutils::par_walk_with` for the details.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::FileKind;
+///
+/// fsutils::mkdir("par_walk_dir/a/b");
+/// fsutils::write_file("par_walk_dir/a/one.txt", "");
+/// fsutils::write_file("par_walk_dir/a/b/two.txt", "");
+///
+/// let files = fsutils::par_walk("par_walk_dir").filter_map(Result::ok).filter(|e| e.kind == FileKind::File).count();
+/// assert_eq!(files, 2);
+///
+/// # // Cleanup
+/// # fsutils::rm_r("par_walk_dir");
+/// ```
+pub fn par_walk<P: AsRef<Path>>(root: P) -> ParWalk {
+    par_walk_with(root, &WalkOptions::new(), 0)
+}
+
+/// Walks the tree at `root` according to `opts` on `threads` threads, or one
+/// per CPU for `0`, yielding the root and then every entry below it in no
+/// particular order.
+///
+/// Each directory is read by whichever thread is free, and the entries are
+/// handed over through a channel as they are found, so very large trees are
+/// scanned much faster than with `fsutils::walk_with` on filesystems that
+/// serve several requests at once. The root comes first; the entries of a
+/// directory always come after the directory itself, but otherwise in any
+/// order. Errors, symbolic links, loops, ignore files and `max_depth` work
+/// as they do for `fsutils::walk_with`. Dropping the iterator stops the walk.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::WalkOptions;
+///
+/// fsutils::mkdir("par_walk_with_dir");
+/// for i in 0..50 {
+///     fsutils::mkdir(format!("par_walk_with_dir/{}/nested", i));
+/// }
+///
+/// let mut depths: Vec<_> = fsutils::par_walk_with("par_walk_with_dir", WalkOptions::new().max_depth(1), 4)
+///     .map(|entry| entry.unwrap().depth)
+///     .collect();
+/// depths.sort();
+/// assert_eq!(depths.len(), 51);
+/// assert_eq!(depths[0], 0);
+/// assert!(fsutils::par_walk("a_very_1234_unlikely_9876_dirname").next().unwrap().is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm_r("par_walk_with_dir");
+/// ```
+pub fn par_walk_with<P: AsRef<Path>>(root: P, opts: &WalkOptions, threads: usize) -> ParWalk {
+    let root = root.as_ref();
+    let threads = match threads {
+        0 => thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n,
+    };
+    let shared = Arc::new(Shared { opts: opts.clone(), queue: Mutex::new(Queue::default()), ready: Condvar::new() });
+    let (sender, entries) = mpsc::sync_channel(CHANNEL_CAPACITY);
+    if let Some(job) = start(root, &shared.opts, &sender) {
+        shared.lock().dirs.push(job);
+    }
+    let workers = (0..threads)
+        .map(|_| {
+            let shared = Arc::clone(&shared);
+            let sender = sender.clone();
+            thread::spawn(move || shared.work(&sender))
+        })
+        .collect();
+    ParWalk { entries: Some(entries), shared, workers }
+}
+
+/// How many entries the threads of a `ParWalk` may find ahead of the caller.
+const CHANNEL_CAPACITY: usize = 4096;
+
+/// The iterator returned by `fsutils::par_walk`, yielding each entry of a
+/// tree as the threads walking it find them.
+#[derive(Debug)]
+pub struct ParWalk {
+    /// Taken on drop, so threads waiting to hand over an entry give up.
+    entries: Option<mpsc::Receiver<Result<WalkEntry>>>,
+    shared: Arc<Shared>,
+    workers: Vec<thread::JoinHandle<()>>,
+}
+
+impl Iterator for ParWalk {
+    type Item = Result<WalkEntry>;
+
+    fn next(&mut self) -> Option<Result<WalkEntry>> {
+        self.entries.as_ref()?.recv().ok()
+    }
+}
+
+impl Drop for ParWalk {
+    fn drop(&mut self) {
+        self.shared.lock().stopped = true;
+        self.shared.ready.notify_all();
+        self.entries = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// The state the threads of a `ParWalk` share.
+#[derive(Debug)]
+struct Shared {
+    opts: WalkOptions,
+    queue: Mutex<Queue>,
+    /// Signalled when a directory is queued or the walk is over.
+    ready: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct Queue {
+    /// The directories left to read, the next one last so that the walk
+    /// stays depth first and the queue short.
+    dirs: Vec<Job>,
+    /// How many threads are reading a directory, and may queue more.
+    busy: usize,
+    stopped: bool,
+}
+
+/// A directory to read, with what is known about the way to it.
+#[derive(Debug)]
+struct Job {
+    path: PathBuf,
+    depth: usize,
+    ancestors: Ancestors,
+    ignores: Option<Ignores>,
+}
+
+impl Shared {
+    fn lock(&self) -> std::sync::MutexGuard<'_, Queue> {
+        self.queue.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Reads directories until there are none left or the walk is dropped.
+    fn work(&self, sender: &mpsc::SyncSender<Result<WalkEntry>>) {
+        loop {
+            let job = {
+                let mut queue = self.lock();
+                loop {
+                    if queue.stopped {
+                        return;
+                    }
+                    if let Some(job) = queue.dirs.pop() {
+                        queue.busy += 1;
+                        break job;
+                    }
+                    if queue.busy == 0 {
+                        self.ready.notify_all();
+                        return;
+                    }
+                    queue = self.ready.wait(queue).unwrap_or_else(|e| e.into_inner());
+                }
+            };
+            let handed_over = self.read(job, sender);
+            let mut queue = self.lock();
+            queue.busy -= 1;
+            if !handed_over {
+                queue.stopped = true;
+            }
+            if queue.stopped || (queue.busy == 0 && queue.dirs.is_empty()) {
+                self.ready.notify_all();
+            }
+        }
+    }
+
+    /// Sends the entries of the directory of `job` and queues the
+    /// directories among them, or returns `false` if nobody is listening.
+    fn read(&self, job: Job, sender: &mpsc::SyncSender<Result<WalkEntry>>) -> bool {
+        let entries = match fs::read_dir(&job.path) {
+            Ok(entries) => entries,
+            Err(e) => return sender.send(Err(FsError::from_io(&job.path, e))).is_ok(),
+        };
+        let depth = job.depth + 1;
+        for entry in entries {
+            let found = entry.at(&job.path).and_then(|entry| {
+                let path = entry.path();
+                let file_type = entry.file_type().at(&path)?;
+                self.child(path, file_type, depth, &job)
+            });
+            let found = match found {
+                Ok(Some(found)) => found,
+                Ok(None) => continue,
+                Err(e) => {
+                    if sender.send(Err(e)).is_err() {
+                        return false;
+                    }
+                    continue;
+                }
+            };
+            let (entry, child) = found;
+            // Sent first, so that it comes before anything below it
+            if sender.send(Ok(entry)).is_err() {
+                return false;
+            }
+            if let Some(child) = child {
+                self.lock().dirs.push(child);
+                self.ready.notify_one();
+            }
+        }
+        true
+    }
+
+    /// The entry at `path` and a job for it if it is a directory to walk
+    /// into, or `None` if it is ignored.
+    fn child(
+        &self,
+        path: PathBuf,
+        file_type: FileType,
+        depth: usize,
+        parent: &Job,
+    ) -> Result<Option<(WalkEntry, Option<Job>)>> {
+        let opts = &self.opts;
+        let mut kind = FileKind::of_type(file_type);
+        if file_type.is_symlink() && opts.follow.follows(false) {
+            kind = FileKind::of(&opts.follow.metadata(&path, false).at(&path)?);
+        }
+        if parent.ignores.as_ref().is_some_and(|ignores| ignores.is_ignored(&path, kind == FileKind::Dir)) {
+            return Ok(None);
+        }
+        let mut job = None;
+        if kind == FileKind::Dir && opts.max_depth.is_none_or(|max| depth < max) {
+            let mut ancestors = parent.ancestors.clone();
+            if ancestors.enter(&path)? {
+                let mut ignores = parent.ignores.clone();
+                if let Some(ignores) = &mut ignores {
+                    ignores.enter(&path);
+                }
+                job = Some(Job { path: path.clone(), depth, ancestors, ignores });
+            }
+        }
+        Ok(Some((WalkEntry { path, kind, is_symlink: file_type.is_symlink(), depth }, job)))
+    }
+}
+
+/// Sends the entry for `root` and returns the job for reading it, if it is a
+/// directory to walk into.
+fn start(root: &Path, opts: &WalkOptions, sender: &mpsc::SyncSender<Result<WalkEntry>>) -> Option<Job> {
+    let found = (|| {
+        let file_type = root.symlink_metadata().at(root)?.file_type();
+        let mut kind = FileKind::of_type(file_type);
+        if file_type.is_symlink() && opts.follow.follows(true) {
+            kind = FileKind::of(&opts.follow.metadata(root, true).at(root)?);
+        }
+        let mut job = None;
+        if kind == FileKind::Dir && opts.max_depth.is_none_or(|max| max > 0) {
+            let mut ancestors = Ancestors::new(opts.follow);
+            ancestors.enter(root)?;
+            let ignores = opts.gitignore.then(|| {
+                let mut ignores = Ignores::new(root);
+                ignores.enter(root);
+                ignores
+            });
+            job = Some(Job { path: root.to_path_buf(), depth: 0, ancestors, ignores });
+        }
+        Ok((WalkEntry { path: root.to_path_buf(), kind, is_symlink: file_type.is_symlink(), depth: 0 }, job))
+    })();
+    match found {
+        Ok((entry, job)) => {
+            let _ = sender.send(Ok(entry));
+            job
+        }
+        Err(e) => {
+            let _ = sender.send(Err(e));
+            None
+        }
+    }
+}
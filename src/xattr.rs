@@ -0,0 +1,232 @@
+// Copyright 2020 Jared Forth.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Extended attributes on Linux and macOS, behind the `xattr` feature.
+
+use std::ffi::{OsStr, OsString};
+use std::path::Path;
+
+use crate::error::{IoResultExt, Result};
+use crate::op::Op;
+use crate::sys;
+
+/// Returns the value of an extended attribute of a file, or `None` if it is
+/// not set or cannot be read.
+///
+/// Requires the `xattr` feature, on Linux or macOS.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("xattr_get.txt", "text");
+///
+/// assert_eq!(fsutils::xattr_get("xattr_get.txt", "user.a_very_1234_unlikely_9876_name"), None);
+///
+/// # // Cleanup
+/// # fsutils::rm("xattr_get.txt");
+/// ```
+pub fn xattr_get<P: AsRef<Path>, N: AsRef<OsStr>>(path: P, name: N) -> Option<Vec<u8>> {
+    match try_xattr_get(path, name) {
+        Ok(value) => value,
+        Err(e) => {
+            error!("Cannot read extended attribute {}", e);
+            None
+        }
+    }
+}
+
+/// Returns the value of an extended attribute of a file, such as
+/// `user.checksum` on Linux or `com.apple.quarantine` on macOS, or `None` if
+/// the file does not have it.
+///
+/// Symbolic links are followed. On Linux, attributes that users may set
+/// themselves must start with `user.`, and not every filesystem supports
+/// them, which is an error of kind `io::ErrorKind::Unsupported`.
+///
+/// Requires the `xattr` feature, on Linux or macOS.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("try_xattr_get.txt", "text");
+///
+/// if fsutils::try_xattr_set("try_xattr_get.txt", "user.origin", b"tests").is_ok() {
+///     let value = fsutils::try_xattr_get("try_xattr_get.txt", "user.origin").unwrap();
+///     assert_eq!(value.as_deref(), Some(&b"tests"[..]));
+/// }
+/// assert!(fsutils::try_xattr_get("a_very_1234_unlikely_9876_filename", "user.origin").is_err());
+///
+/// # // Cleanup
+/// # fsutils::rm("try_xattr_get.txt");
+/// ```
+pub fn try_xattr_get<P: AsRef<Path>, N: AsRef<OsStr>>(path: P, name: N) -> Result<Option<Vec<u8>>> {
+    let path = path.as_ref();
+    sys::getxattr(path, name.as_ref()).at(path)
+}
+
+/// Sets an extended attribute of a file
+/// and returns a boolean based on success or failure.
+///
+/// Requires the `xattr` feature, on Linux or macOS.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("xattr_set.txt", "text");
+///
+/// if fsutils::xattr_set("xattr_set.txt", "user.checksum", b"d41d8cd9") {
+///     assert_eq!(fsutils::xattr_get("xattr_set.txt", "user.checksum").unwrap(), b"d41d8cd9");
+/// }
+///
+/// # // Cleanup
+/// # fsutils::rm("xattr_set.txt");
+/// ```
+pub fn xattr_set<P: AsRef<Path>, N: AsRef<OsStr>>(path: P, name: N, value: &[u8]) -> bool {
+    match try_xattr_set(path, name, value) {
+        Ok(_) => true,
+        Err(e) => {
+            error!("Cannot set extended attribute {}", e);
+            false
+        }
+    }
+}
+
+/// Sets an extended attribute of a file, creating it or replacing its value.
+///
+/// Requires the `xattr` feature, on Linux or macOS.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("try_xattr_set.txt", "text");
+///
+/// if fsutils::try_xattr_set("try_xattr_set.txt", "user.version", b"1").is_ok() {
+///     fsutils::try_xattr_set("try_xattr_set.txt", "user.version", b"2").unwrap();
+///     assert_eq!(fsutils::xattr_get("try_xattr_set.txt", "user.version").unwrap(), b"2");
+/// }
+///
+/// # // Cleanup
+/// # fsutils::rm("try_xattr_set.txt");
+/// ```
+pub fn try_xattr_set<P: AsRef<Path>, N: AsRef<OsStr>>(path: P, name: N, value: &[u8]) -> Result<()> {
+    let path = path.as_ref();
+    let name = name.as_ref();
+    Op::new("xattr_set", path).bytes(value.len() as u64).run((), || {
+        sys::setxattr(path, name, value).at(path)?;
+        info!("Set extended attribute {} of {}", name.to_string_lossy(), path.display());
+        Ok(())
+    })
+}
+
+/// Returns the names of the extended attributes of a file, or an empty `Vec`
+/// if they cannot be read.
+///
+/// Requires the `xattr` feature, on Linux or macOS.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("xattr_list.txt", "text");
+///
+/// for name in fsutils::xattr_list("xattr_list.txt") {
+///     println!("{}", name.to_string_lossy());
+/// }
+///
+/// # // Cleanup
+/// # fsutils::rm("xattr_list.txt");
+/// ```
+pub fn xattr_list<P: AsRef<Path>>(path: P) -> Vec<OsString> {
+    match try_xattr_list(path) {
+        Ok(names) => names,
+        Err(e) => {
+            error!("Cannot list extended attributes {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// Returns the names of the extended attributes of a file.
+///
+/// On Linux only the attributes the caller may read are listed, so there are
+/// usually only `user.` attributes unless running as root.
+///
+/// Requires the `xattr` feature, on Linux or macOS.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("try_xattr_list.txt", "text");
+///
+/// if fsutils::try_xattr_set("try_xattr_list.txt", "user.tag", b"blue").is_ok() {
+///     let names = fsutils::try_xattr_list("try_xattr_list.txt").unwrap();
+///     assert!(names.iter().any(|name| name == "user.tag"));
+/// }
+///
+/// # // Cleanup
+/// # fsutils::rm("try_xattr_list.txt");
+/// ```
+pub fn try_xattr_list<P: AsRef<Path>>(path: P) -> Result<Vec<OsString>> {
+    let path = path.as_ref();
+    sys::listxattr(path).at(path)
+}
+
+/// Removes an extended attribute from a file
+/// and returns a boolean based on success or failure.
+///
+/// Requires the `xattr` feature, on Linux or macOS.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("xattr_remove.txt", "text");
+///
+/// if fsutils::xattr_set("xattr_remove.txt", "user.temporary", b"") {
+///     assert!(fsutils::xattr_remove("xattr_remove.txt", "user.temporary"));
+/// }
+///
+/// # // Cleanup
+/// # fsutils::rm("xattr_remove.txt");
+/// ```
+pub fn xattr_remove<P: AsRef<Path>, N: AsRef<OsStr>>(path: P, name: N) -> bool {
+    match try_xattr_remove(path, name) {
+        Ok(_) => true,
+        Err(e) => {
+            error!("Cannot remove extended attribute {}", e);
+            false
+        }
+    }
+}
+
+/// Removes an extended attribute from a file. Removing one that the file does
+/// not have is an error.
+///
+/// Requires the `xattr` feature, on Linux or macOS.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("try_xattr_remove.txt", "text");
+///
+/// if fsutils::try_xattr_set("try_xattr_remove.txt", "user.stale", b"yes").is_ok() {
+///     fsutils::try_xattr_remove("try_xattr_remove.txt", "user.stale").unwrap();
+///     assert_eq!(fsutils::xattr_get("try_xattr_remove.txt", "user.stale"), None);
+///     assert!(fsutils::try_xattr_remove("try_xattr_remove.txt", "user.stale").is_err());
+/// }
+///
+/// # // Cleanup
+/// # fsutils::rm("try_xattr_remove.txt");
+/// ```
+pub fn try_xattr_remove<P: AsRef<Path>, N: AsRef<OsStr>>(path: P, name: N) -> Result<()> {
+    let path = path.as_ref();
+    let name = name.as_ref();
+    Op::new("xattr_remove", path).run((), || {
+        sys::removexattr(path, name).at(path)?;
+        info!("Removed extended attribute {} of {}", name.to_string_lossy(), path.display());
+        Ok(())
+    })
+}
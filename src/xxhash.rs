@@ -0,0 +1,204 @@
+// Copyright 2020 Jared Forth.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! The xxHash checksums that LZ4 and Zstandard frames carry.
+
+#[cfg(feature = "lz4")]
+const P32: [u32; 5] = [2_654_435_761, 2_246_822_519, 3_266_489_917, 668_265_263, 374_761_393];
+
+/// XXH32 with a seed of `0`, fed a piece at a time.
+#[cfg(feature = "lz4")]
+pub(crate) struct Xxh32 {
+    lanes: [u32; 4],
+    stripe: [u8; 16],
+    buffered: usize,
+    len: u64,
+}
+
+#[cfg(feature = "lz4")]
+impl Xxh32 {
+    pub(crate) fn new() -> Xxh32 {
+        Xxh32 {
+            lanes: [P32[0].wrapping_add(P32[1]), P32[1], 0, 0u32.wrapping_sub(P32[0])],
+            stripe: [0; 16],
+            buffered: 0,
+            len: 0,
+        }
+    }
+
+    fn round(lane: u32, input: &[u8]) -> u32 {
+        let input = u32::from_le_bytes([input[0], input[1], input[2], input[3]]);
+        lane.wrapping_add(input.wrapping_mul(P32[1])).rotate_left(13).wrapping_mul(P32[0])
+    }
+
+    fn consume(&mut self, stripe: &[u8]) {
+        for (i, lane) in self.lanes.iter_mut().enumerate() {
+            *lane = Xxh32::round(*lane, &stripe[4 * i..]);
+        }
+    }
+
+    pub(crate) fn update(&mut self, mut data: &[u8]) {
+        self.len += data.len() as u64;
+        if self.buffered > 0 {
+            let n = (16 - self.buffered).min(data.len());
+            self.stripe[self.buffered..self.buffered + n].copy_from_slice(&data[..n]);
+            self.buffered += n;
+            data = &data[n..];
+            if self.buffered < 16 {
+                return;
+            }
+            let stripe = self.stripe;
+            self.consume(&stripe);
+            self.buffered = 0;
+        }
+        while data.len() >= 16 {
+            self.consume(&data[..16]);
+            data = &data[16..];
+        }
+        self.stripe[..data.len()].copy_from_slice(data);
+        self.buffered = data.len();
+    }
+
+    pub(crate) fn finish(&self) -> u32 {
+        let [a, b, c, d] = self.lanes;
+        let mut hash = if self.len >= 16 {
+            let ab = a.rotate_left(1).wrapping_add(b.rotate_left(7));
+            ab.wrapping_add(c.rotate_left(12)).wrapping_add(d.rotate_left(18))
+        } else {
+            P32[4]
+        };
+        hash = hash.wrapping_add(self.len as u32);
+        let mut rest = &self.stripe[..self.buffered];
+        while rest.len() >= 4 {
+            let input = u32::from_le_bytes([rest[0], rest[1], rest[2], rest[3]]);
+            hash = hash.wrapping_add(input.wrapping_mul(P32[2])).rotate_left(17).wrapping_mul(P32[3]);
+            rest = &rest[4..];
+        }
+        for &byte in rest {
+            hash = hash.wrapping_add(u32::from(byte).wrapping_mul(P32[4])).rotate_left(11).wrapping_mul(P32[0]);
+        }
+        hash ^= hash >> 15;
+        hash = hash.wrapping_mul(P32[1]);
+        hash ^= hash >> 13;
+        hash = hash.wrapping_mul(P32[2]);
+        hash ^ hash >> 16
+    }
+}
+
+/// The XXH32 of `data`.
+#[cfg(feature = "lz4")]
+pub(crate) fn xxh32(data: &[u8]) -> u32 {
+    let mut hasher = Xxh32::new();
+    hasher.update(data);
+    hasher.finish()
+}
+
+#[cfg(feature = "zstd")]
+const P64: [u64; 5] = [
+    11_400_714_785_074_694_791,
+    14_029_467_366_897_019_727,
+    1_609_587_929_392_839_161,
+    9_650_029_242_287_828_579,
+    2_870_177_450_012_600_261,
+];
+
+/// XXH64 with a seed of `0`, fed a piece at a time.
+#[cfg(feature = "zstd")]
+#[derive(Clone)]
+pub(crate) struct Xxh64 {
+    lanes: [u64; 4],
+    stripe: [u8; 32],
+    buffered: usize,
+    len: u64,
+}
+
+#[cfg(feature = "zstd")]
+impl Xxh64 {
+    pub(crate) fn new() -> Xxh64 {
+        Xxh64 {
+            lanes: [P64[0].wrapping_add(P64[1]), P64[1], 0, 0u64.wrapping_sub(P64[0])],
+            stripe: [0; 32],
+            buffered: 0,
+            len: 0,
+        }
+    }
+
+    fn round(lane: u64, input: u64) -> u64 {
+        lane.wrapping_add(input.wrapping_mul(P64[1])).rotate_left(31).wrapping_mul(P64[0])
+    }
+
+    fn read(input: &[u8]) -> u64 {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(&input[..8]);
+        u64::from_le_bytes(bytes)
+    }
+
+    fn consume(&mut self, stripe: &[u8]) {
+        for (i, lane) in self.lanes.iter_mut().enumerate() {
+            *lane = Xxh64::round(*lane, Xxh64::read(&stripe[8 * i..]));
+        }
+    }
+
+    pub(crate) fn update(&mut self, mut data: &[u8]) {
+        self.len += data.len() as u64;
+        if self.buffered > 0 {
+            let n = (32 - self.buffered).min(data.len());
+            self.stripe[self.buffered..self.buffered + n].copy_from_slice(&data[..n]);
+            self.buffered += n;
+            data = &data[n..];
+            if self.buffered < 32 {
+                return;
+            }
+            let stripe = self.stripe;
+            self.consume(&stripe);
+            self.buffered = 0;
+        }
+        while data.len() >= 32 {
+            self.consume(&data[..32]);
+            data = &data[32..];
+        }
+        self.stripe[..data.len()].copy_from_slice(data);
+        self.buffered = data.len();
+    }
+
+    pub(crate) fn finish(&self) -> u64 {
+        let mut hash = if self.len >= 32 {
+            let [a, b, c, d] = self.lanes;
+            let ab = a.rotate_left(1).wrapping_add(b.rotate_left(7));
+            let mut hash = ab.wrapping_add(c.rotate_left(12)).wrapping_add(d.rotate_left(18));
+            for lane in self.lanes {
+                hash = (hash ^ Xxh64::round(0, lane)).wrapping_mul(P64[0]).wrapping_add(P64[3]);
+            }
+            hash
+        } else {
+            P64[4]
+        };
+        hash = hash.wrapping_add(self.len);
+        let mut rest = &self.stripe[..self.buffered];
+        while rest.len() >= 8 {
+            hash ^= Xxh64::round(0, Xxh64::read(rest));
+            hash = hash.rotate_left(27).wrapping_mul(P64[0]).wrapping_add(P64[3]);
+            rest = &rest[8..];
+        }
+        if rest.len() >= 4 {
+            let input = u64::from(u32::from_le_bytes([rest[0], rest[1], rest[2], rest[3]]));
+            hash ^= input.wrapping_mul(P64[0]);
+            hash = hash.rotate_left(23).wrapping_mul(P64[1]).wrapping_add(P64[2]);
+            rest = &rest[4..];
+        }
+        for &byte in rest {
+            hash ^= u64::from(byte).wrapping_mul(P64[4]);
+            hash = hash.rotate_left(11).wrapping_mul(P64[0]);
+        }
+        hash ^= hash >> 33;
+        hash = hash.wrapping_mul(P64[1]);
+        hash ^= hash >> 29;
+        hash = hash.wrapping_mul(P64[2]);
+        hash ^ hash >> 32
+    }
+}
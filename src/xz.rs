@@ -0,0 +1,587 @@
+// Copyright 2020 Jared Forth.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Decompression of the xz format, with the LZMA2 filter `xz` compresses with.
+//!
+//! Streams and their blocks are read in full, the index of each stream is
+//! checked against its blocks, and CRC32, CRC64 and SHA-256 checks are
+//! verified. Blocks with other filters, such as the BCJ filters for
+//! executables, are not supported.
+
+use std::io;
+
+use crate::deflate::crc32;
+use crate::hash::Sha256;
+
+pub(crate) const MAGIC: [u8; 6] = [0xfd, b'7', b'z', b'X', b'Z', 0];
+const FOOTER_MAGIC: [u8; 2] = *b"YZ";
+const LZMA2: u64 = 0x21;
+/// The size of each type of check, including those that are not verified.
+const CHECK_SIZES: [usize; 16] = [0, 4, 4, 4, 8, 8, 8, 16, 16, 16, 32, 32, 32, 64, 64, 64];
+const CRC32: u8 = 1;
+const CRC64: u8 = 4;
+const SHA256: u8 = 10;
+
+/// What every probability starts at, which is one half.
+const PROB_INIT: u16 = 1 << 10;
+const LITERAL_PROBS: usize = 0x300;
+/// The first distance slot whose low bits are not in `dist_special`.
+const END_POS_MODEL: u32 = 14;
+
+fn corrupt(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("corrupt xz stream: {}", reason))
+}
+
+fn unsupported(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, format!("unsupported xz stream: {}", reason))
+}
+
+/// The CRC-64 of `data`, as xz uses it.
+fn crc64(data: &[u8]) -> u64 {
+    static TABLE: std::sync::OnceLock<[u64; 256]> = std::sync::OnceLock::new();
+    let table = TABLE.get_or_init(|| {
+        let mut table = [0; 256];
+        for (i, entry) in table.iter_mut().enumerate() {
+            let mut c = i as u64;
+            for _ in 0..8 {
+                c = if c & 1 != 0 { 0xc96c_5795_d787_0f42 ^ (c >> 1) } else { c >> 1 };
+            }
+            *entry = c;
+        }
+        table
+    });
+    !data.iter().fold(!0, |c, &b| table[((c ^ u64::from(b)) & 0xff) as usize] ^ (c >> 8))
+}
+
+/// Reads through the bytes of a stream in order.
+struct Input<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Input<'a> {
+    fn take(&mut self, count: usize) -> io::Result<&'a [u8]> {
+        let bytes = self.data.get(self.pos..self.pos + count).ok_or_else(|| corrupt("truncated"))?;
+        self.pos += count;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn be16(&mut self) -> io::Result<usize> {
+        let bytes = self.take(2)?;
+        Ok(usize::from(bytes[0]) << 8 | usize::from(bytes[1]))
+    }
+
+    fn le32(&mut self) -> io::Result<u32> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// Reads a variable-length integer, seven bits to a byte, least
+    /// significant first.
+    fn vli(&mut self) -> io::Result<u64> {
+        let mut value = 0;
+        for i in 0..9 {
+            let byte = self.byte()?;
+            if i > 0 && byte == 0 {
+                return Err(corrupt("bad variable-length integer"));
+            }
+            value |= u64::from(byte & 0x7f) << (7 * i);
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(corrupt("bad variable-length integer"))
+    }
+
+    /// Skips the zero bytes that pad what started at `start` to a multiple
+    /// of four bytes.
+    fn padding(&mut self, start: usize) -> io::Result<()> {
+        while !(self.pos - start).is_multiple_of(4) {
+            if self.byte()? != 0 {
+                return Err(corrupt("bad padding"));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Decodes the bits of one LZMA chunk.
+struct RangeDecoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+    range: u32,
+    code: u32,
+}
+
+impl<'a> RangeDecoder<'a> {
+    fn new(data: &'a [u8]) -> io::Result<RangeDecoder<'a>> {
+        if data.len() < 5 || data[0] != 0 {
+            return Err(corrupt("bad range coder start"));
+        }
+        let code = u32::from_be_bytes([data[1], data[2], data[3], data[4]]);
+        Ok(RangeDecoder { data, pos: 5, range: u32::MAX, code })
+    }
+
+    /// Reads another byte once the range gets too narrow. Reading past the
+    /// end gives zeros, and is caught by `finished`.
+    fn normalize(&mut self) {
+        if self.range < 1 << 24 {
+            let byte = self.data.get(self.pos).copied().unwrap_or(0);
+            self.pos += 1;
+            self.range <<= 8;
+            self.code = self.code << 8 | u32::from(byte);
+        }
+    }
+
+    /// Decodes a bit with the probability `prob` of it being `0`, and moves
+    /// `prob` towards what it was.
+    fn bit(&mut self, prob: &mut u16) -> u32 {
+        self.normalize();
+        let bound = (self.range >> 11) * u32::from(*prob);
+        if self.code < bound {
+            self.range = bound;
+            *prob += ((1 << 11) - *prob) >> 5;
+            0
+        } else {
+            self.range -= bound;
+            self.code -= bound;
+            *prob -= *prob >> 5;
+            1
+        }
+    }
+
+    /// Decodes `bits` bits, most significant first, with `probs` indexed by
+    /// the bits so far behind a leading one.
+    fn bittree(&mut self, probs: &mut [u16], bits: u32) -> u32 {
+        let mut symbol = 1;
+        for _ in 0..bits {
+            symbol = symbol << 1 | self.bit(&mut probs[symbol as usize]);
+        }
+        symbol - (1 << bits)
+    }
+
+    /// Decodes `bits` bits, least significant first, like `bittree`.
+    fn reverse_bittree(&mut self, probs: &mut [u16], bits: u32) -> u32 {
+        let (mut symbol, mut value) = (1, 0);
+        for i in 0..bits {
+            let bit = self.bit(&mut probs[symbol as usize]);
+            symbol = symbol << 1 | bit;
+            value |= bit << i;
+        }
+        value
+    }
+
+    /// Decodes `count` bits that are as likely to be `0` as `1`.
+    fn direct(&mut self, count: u32) -> u32 {
+        let mut value = 0;
+        for _ in 0..count {
+            self.normalize();
+            self.range >>= 1;
+            let bit = u32::from(self.code >= self.range);
+            if bit == 1 {
+                self.code -= self.range;
+            }
+            value = value << 1 | bit;
+        }
+        value
+    }
+
+    /// Whether the chunk ended exactly where the encoder flushed it, once
+    /// the last bit has been normalized.
+    fn finished(&mut self) -> bool {
+        self.normalize();
+        self.pos == self.data.len() && self.code == 0
+    }
+}
+
+/// The probabilities for the length of a match.
+struct LenDecoder {
+    choice: u16,
+    choice2: u16,
+    low: [[u16; 8]; 16],
+    mid: [[u16; 8]; 16],
+    high: [u16; 256],
+}
+
+impl LenDecoder {
+    fn new() -> LenDecoder {
+        LenDecoder {
+            choice: PROB_INIT,
+            choice2: PROB_INIT,
+            low: [[PROB_INIT; 8]; 16],
+            mid: [[PROB_INIT; 8]; 16],
+            high: [PROB_INIT; 256],
+        }
+    }
+
+    fn decode(&mut self, rc: &mut RangeDecoder, pos_state: usize) -> usize {
+        let len = if rc.bit(&mut self.choice) == 0 {
+            rc.bittree(&mut self.low[pos_state], 3)
+        } else if rc.bit(&mut self.choice2) == 0 {
+            8 + rc.bittree(&mut self.mid[pos_state], 3)
+        } else {
+            16 + rc.bittree(&mut self.high, 8)
+        };
+        2 + len as usize
+    }
+}
+
+/// The state of the LZMA decoder, which carries over from one chunk to the
+/// next until a chunk resets it.
+struct Lzma {
+    lc: u32,
+    lp: u32,
+    pb: u32,
+    /// What the last few symbols were, from `0` to `11`, with `0` to `6`
+    /// ending in a literal.
+    state: usize,
+    /// The distances of the last four matches, less one.
+    reps: [u32; 4],
+    is_match: [[u16; 16]; 12],
+    is_rep: [u16; 12],
+    is_rep0: [u16; 12],
+    is_rep1: [u16; 12],
+    is_rep2: [u16; 12],
+    is_rep0_long: [[u16; 16]; 12],
+    dist_slot: [[u16; 64]; 4],
+    /// The low bits of distances in slots `4` to `13`, each slot's from
+    /// where its distances start less the slot.
+    dist_special: [u16; 115],
+    dist_align: [u16; 16],
+    match_len: LenDecoder,
+    rep_len: LenDecoder,
+    literal: Vec<u16>,
+}
+
+impl Lzma {
+    fn new(lc: u32, lp: u32, pb: u32) -> Lzma {
+        Lzma {
+            lc,
+            lp,
+            pb,
+            state: 0,
+            reps: [0; 4],
+            is_match: [[PROB_INIT; 16]; 12],
+            is_rep: [PROB_INIT; 12],
+            is_rep0: [PROB_INIT; 12],
+            is_rep1: [PROB_INIT; 12],
+            is_rep2: [PROB_INIT; 12],
+            is_rep0_long: [[PROB_INIT; 16]; 12],
+            dist_slot: [[PROB_INIT; 64]; 4],
+            dist_special: [PROB_INIT; 115],
+            dist_align: [PROB_INIT; 16],
+            match_len: LenDecoder::new(),
+            rep_len: LenDecoder::new(),
+            literal: vec![PROB_INIT; LITERAL_PROBS << (lc + lp)],
+        }
+    }
+
+    /// A decoder for the properties byte of a chunk, `(pb * 5 + lp) * 9 + lc`.
+    fn from_props(props: u8) -> io::Result<Lzma> {
+        let props = u32::from(props);
+        let (lc, lp, pb) = (props % 9, props / 9 % 5, props / 45);
+        if lc + lp > 4 || pb > 4 {
+            return Err(corrupt("bad LZMA properties"));
+        }
+        Ok(Lzma::new(lc, lp, pb))
+    }
+
+    fn reset(&mut self) {
+        *self = Lzma::new(self.lc, self.lp, self.pb);
+    }
+
+    /// Decodes a distance, less one, for a match of `len` bytes.
+    fn distance(&mut self, rc: &mut RangeDecoder, len: usize) -> u32 {
+        let slot = rc.bittree(&mut self.dist_slot[(len - 2).min(3)], 6);
+        if slot < 4 {
+            return slot;
+        }
+        let bits = (slot >> 1) - 1;
+        let dist = (2 | (slot & 1)) << bits;
+        if slot < END_POS_MODEL {
+            dist + rc.reverse_bittree(&mut self.dist_special[(dist - slot) as usize..], bits)
+        } else {
+            dist + (rc.direct(bits - 4) << 4) + rc.reverse_bittree(&mut self.dist_align, 4)
+        }
+    }
+
+    /// Decodes a chunk of `size` bytes onto the end of `out`, whose
+    /// dictionary starts at `dict_start`.
+    fn decode(
+        &mut self,
+        chunk: &[u8],
+        out: &mut Vec<u8>,
+        dict_start: usize,
+        dict_size: u32,
+        size: usize,
+    ) -> io::Result<()> {
+        let mut rc = RangeDecoder::new(chunk)?;
+        let end = out.len() + size;
+        out.reserve(size);
+        let pb_mask = (1 << self.pb) - 1;
+        let lp_mask = (1 << self.lp) - 1;
+        while out.len() < end {
+            let pos = out.len() - dict_start;
+            let pos_state = pos & pb_mask;
+            let state = self.state;
+            if rc.bit(&mut self.is_match[state][pos_state]) == 0 {
+                let prev = if pos > 0 { usize::from(out[out.len() - 1]) } else { 0 };
+                let base = LITERAL_PROBS * (((pos & lp_mask) << self.lc) + (prev >> (8 - self.lc)));
+                let probs = &mut self.literal[base..base + LITERAL_PROBS];
+                let byte = if state < 7 {
+                    rc.bittree(probs, 8)
+                } else {
+                    let rep0 = self.reps[0] as usize;
+                    if rep0 >= pos {
+                        return Err(corrupt("match starts before the data"));
+                    }
+                    // Each bit is decoded as the bit of the byte at the last
+                    // distance would be, until one differs
+                    let mut match_byte = u32::from(out[out.len() - rep0 - 1]) << 1;
+                    let (mut symbol, mut offset) = (1, 0x100);
+                    while symbol < 0x100 {
+                        let match_bit = match_byte & offset;
+                        match_byte <<= 1;
+                        let bit = rc.bit(&mut probs[(offset + match_bit + symbol) as usize]);
+                        symbol = symbol << 1 | bit;
+                        offset &= if bit == 1 { match_bit } else { !match_bit };
+                    }
+                    symbol - 0x100
+                };
+                out.push(byte as u8);
+                self.state = match state {
+                    0..=3 => 0,
+                    4..=9 => state - 3,
+                    _ => state - 6,
+                };
+                continue;
+            }
+
+            let len = if rc.bit(&mut self.is_rep[state]) == 0 {
+                self.state = if state < 7 { 7 } else { 10 };
+                let len = self.match_len.decode(&mut rc, pos_state);
+                let dist = self.distance(&mut rc, len);
+                self.reps = [dist, self.reps[0], self.reps[1], self.reps[2]];
+                len
+            } else if rc.bit(&mut self.is_rep0[state]) == 0 {
+                if rc.bit(&mut self.is_rep0_long[state][pos_state]) == 0 {
+                    self.state = if state < 7 { 9 } else { 11 };
+                    1
+                } else {
+                    self.state = if state < 7 { 8 } else { 11 };
+                    self.rep_len.decode(&mut rc, pos_state)
+                }
+            } else {
+                let index = if rc.bit(&mut self.is_rep1[state]) == 0 {
+                    1
+                } else if rc.bit(&mut self.is_rep2[state]) == 0 {
+                    2
+                } else {
+                    3
+                };
+                self.reps[..=index].rotate_right(1);
+                self.state = if state < 7 { 8 } else { 11 };
+                self.rep_len.decode(&mut rc, pos_state)
+            };
+
+            let rep0 = self.reps[0] as usize;
+            if rep0 >= pos || u64::from(self.reps[0]) >= u64::from(dict_size) {
+                return Err(corrupt("match starts before the data"));
+            }
+            if len > end - out.len() {
+                return Err(corrupt("match runs past the chunk"));
+            }
+            let start = out.len() - rep0 - 1;
+            if rep0 >= len {
+                out.extend_from_within(start..start + len);
+            } else {
+                for i in start..start + len {
+                    let byte = out[i];
+                    out.push(byte);
+                }
+            }
+        }
+        if !rc.finished() {
+            return Err(corrupt("chunk size does not match"));
+        }
+        Ok(())
+    }
+}
+
+/// The dictionary size a block header's LZMA2 properties give.
+fn dict_size(props: u8) -> io::Result<u32> {
+    match props {
+        0..=39 => Ok((2 | u32::from(props & 1)) << (props / 2 + 11)),
+        40 => Ok(u32::MAX),
+        _ => Err(corrupt("bad dictionary size")),
+    }
+}
+
+/// Decodes the LZMA2 chunks of a block onto the end of `out`.
+fn decode_lzma2(input: &mut Input, out: &mut Vec<u8>, dict_size: u32) -> io::Result<()> {
+    let mut dict_start = None;
+    let mut lzma: Option<Lzma> = None;
+    loop {
+        let control = input.byte()?;
+        if control == 0 {
+            return Ok(());
+        }
+        if control == 1 || control >= 0xe0 {
+            // A new dictionary needs new properties as well
+            dict_start = Some(out.len());
+            lzma = None;
+        }
+        let dict_start = dict_start.ok_or_else(|| corrupt("first chunk does not reset the dictionary"))?;
+        if control < 0x80 {
+            if control > 2 {
+                return Err(corrupt("bad chunk type"));
+            }
+            let size = input.be16()? + 1;
+            out.extend_from_slice(input.take(size)?);
+            continue;
+        }
+        let size = (usize::from(control & 0x1f) << 16) + input.be16()? + 1;
+        let packed = input.be16()? + 1;
+        if control >= 0xc0 {
+            lzma = Some(Lzma::from_props(input.byte()?)?);
+        }
+        let lzma = lzma.as_mut().ok_or_else(|| corrupt("chunk without LZMA properties"))?;
+        if (0xa0..0xc0).contains(&control) {
+            lzma.reset();
+        }
+        lzma.decode(input.take(packed)?, out, dict_start, dict_size, size)?;
+    }
+}
+
+/// Decodes a block, whose header size byte has been read, onto the end of
+/// `out`, and returns its unpadded and uncompressed sizes for the index.
+fn decode_block(input: &mut Input, check: u8, out: &mut Vec<u8>) -> io::Result<(u64, u64)> {
+    let start = input.pos - 1;
+    let header_size = (usize::from(input.data[start]) + 1) * 4;
+    let header = input.take(header_size - 5)?;
+    if input.le32()? != crc32(0, &input.data[start..start + header_size - 4]) {
+        return Err(corrupt("block header checksum mismatch"));
+    }
+    let mut header = Input { data: header, pos: 0 };
+    let flags = header.byte()?;
+    if flags & 0x3c != 0 {
+        return Err(unsupported("unknown block flags"));
+    }
+    let compressed_size = if flags & 0x40 != 0 { Some(header.vli()?) } else { None };
+    let uncompressed_size = if flags & 0x80 != 0 { Some(header.vli()?) } else { None };
+    let filter = header.vli()?;
+    if flags & 3 != 0 || filter != LZMA2 {
+        return Err(unsupported("only the LZMA2 filter on its own is supported"));
+    }
+    if header.vli()? != 1 {
+        return Err(corrupt("bad LZMA2 properties"));
+    }
+    let dict_size = dict_size(header.byte()?)?;
+    if header.data[header.pos..].iter().any(|&b| b != 0) {
+        return Err(corrupt("bad block header padding"));
+    }
+
+    let (data_start, out_start) = (input.pos, out.len());
+    decode_lzma2(input, out, dict_size)?;
+    let compressed = (input.pos - data_start) as u64;
+    let uncompressed = (out.len() - out_start) as u64;
+    if compressed_size.is_some_and(|size| size != compressed)
+        || uncompressed_size.is_some_and(|size| size != uncompressed)
+    {
+        return Err(corrupt("block size does not match its header"));
+    }
+    input.padding(data_start)?;
+
+    let size = CHECK_SIZES[usize::from(check)];
+    let expected = input.take(size)?;
+    let data = &out[out_start..];
+    let matches = match check {
+        CRC32 => crc32(0, data).to_le_bytes() == expected,
+        CRC64 => crc64(data).to_le_bytes() == expected,
+        SHA256 => {
+            let mut digest = Sha256::new();
+            digest.update(data);
+            digest.finish() == expected
+        }
+        // No check, or one that cannot be verified, as `xz -d` allows
+        _ => true,
+    };
+    if !matches {
+        return Err(corrupt("checksum mismatch"));
+    }
+    Ok((header_size as u64 + compressed + size as u64, uncompressed))
+}
+
+/// Decodes one stream onto the end of `out`.
+fn decode_stream(input: &mut Input, out: &mut Vec<u8>) -> io::Result<()> {
+    if input.take(6)? != MAGIC {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not an xz file"));
+    }
+    let flags = input.take(2)?;
+    if input.le32()? != crc32(0, flags) {
+        return Err(corrupt("stream header checksum mismatch"));
+    }
+    if flags[0] != 0 || flags[1] > 0x0f {
+        return Err(unsupported("unknown stream flags"));
+    }
+    let check = flags[1];
+
+    let mut blocks = Vec::new();
+    while input.byte()? != 0 {
+        blocks.push(decode_block(input, check, out)?);
+    }
+
+    let index_start = input.pos - 1;
+    if input.vli()? != blocks.len() as u64 {
+        return Err(corrupt("index does not match the blocks"));
+    }
+    for &(unpadded, uncompressed) in &blocks {
+        if input.vli()? != unpadded || input.vli()? != uncompressed {
+            return Err(corrupt("index does not match the blocks"));
+        }
+    }
+    input.padding(index_start)?;
+    if input.le32()? != crc32(0, &input.data[index_start..input.pos - 4]) {
+        return Err(corrupt("index checksum mismatch"));
+    }
+    let index_size = (input.pos - index_start) as u64;
+
+    let footer = input.take(12)?;
+    if footer[10..] != FOOTER_MAGIC || footer[8..10] != *flags {
+        return Err(corrupt("bad stream footer"));
+    }
+    if u32::from_le_bytes([footer[0], footer[1], footer[2], footer[3]]) != crc32(0, &footer[4..10]) {
+        return Err(corrupt("stream footer checksum mismatch"));
+    }
+    if (u64::from(u32::from_le_bytes([footer[4], footer[5], footer[6], footer[7]])) + 1) * 4 != index_size {
+        return Err(corrupt("index size does not match the footer"));
+    }
+    Ok(())
+}
+
+/// Decompresses every stream in `data`, one after another, allowing the
+/// padding `xz` allows between them.
+pub(crate) fn decompress(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut input = Input { data, pos: 0 };
+    let mut out = Vec::new();
+    loop {
+        decode_stream(&mut input, &mut out)?;
+        let padding = data[input.pos..].iter().take_while(|&&b| b == 0).count();
+        if !padding.is_multiple_of(4) {
+            return Err(corrupt("bad stream padding"));
+        }
+        input.pos += padding;
+        if input.pos == data.len() {
+            return Ok(out);
+        }
+    }
+}
@@ -0,0 +1,649 @@
+// Copyright 2020 Jared Forth.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Creating and extracting zip archives.
+
+use std::convert::TryFrom;
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::archive::{
+    check_links, collect, create_file, create_symlink, declined, entry_name, finish_dirs, leaves_destination,
+    mode_of, read_name, safe_relative, set_mode, Source, MODE_DIR, MODE_SYMLINK, MODE_TYPE,
+};
+use crate::atomic::replace_file;
+use crate::deflate;
+use crate::error::{FsError, IoResultExt, Result};
+use crate::glob::Pattern;
+use crate::meta::{file_id, FileKind};
+use crate::op::Op;
+
+const LOCAL_HEADER: u32 = 0x0403_4b50;
+const CENTRAL_HEADER: u32 = 0x0201_4b50;
+const END_OF_CENTRAL_DIRECTORY: u32 = 0x0605_4b50;
+/// The extended timestamp field, which holds the modification time in Unix
+/// seconds.
+const EXTENDED_TIMESTAMP: u16 = 0x5455;
+/// Made by Unix, so the external attributes hold the mode, with version 2.0
+/// of the format.
+const VERSION_MADE_BY: u16 = 3 << 8 | 20;
+const VERSION_NEEDED: u16 = 20;
+/// Names are UTF-8.
+const FLAG_UTF8: u16 = 1 << 11;
+const STORED: u16 = 0;
+const DEFLATED: u16 = 8;
+
+/// Options for `fsutils::zip_with`.
+///
+/// By default every file is compressed and nothing is excluded. Patterns use
+/// shell wildcards (`*`, `?`, `**`, `[...]`) and are matched against paths
+/// relative to each source, or just the file name for patterns without a
+/// `/`, as for `fsutils::CopyOptions`.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::ZipOptions;
+///
+/// let mut opts = ZipOptions::new();
+/// opts.exclude("target").unwrap().exclude("*.log").unwrap().store(true);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct ZipOptions {
+    exclude: Vec<Pattern>,
+    store: bool,
+}
+
+impl ZipOptions {
+    /// Creates the default options.
+    pub fn new() -> ZipOptions {
+        ZipOptions::default()
+    }
+
+    /// Leave out files and directories matching `pattern`. May be given
+    /// several times.
+    pub fn exclude(&mut self, pattern: &str) -> Result<&mut ZipOptions> {
+        self.exclude.push(Pattern::new(pattern)?);
+        Ok(self)
+    }
+
+    /// Store files as they are instead of compressing them, which is faster
+    /// for contents that are already compressed, such as images.
+    pub fn store(&mut self, store: bool) -> &mut ZipOptions {
+        self.store = store;
+        self
+    }
+}
+
+/// An entry written to the archive, for the central directory.
+struct Member {
+    name: String,
+    mode: u32,
+    mtime: u32,
+    method: u16,
+    crc: u32,
+    compressed_size: u32,
+    size: u32,
+    offset: u32,
+}
+
+/// Packs files and directory trees into a zip archive, and returns whether
+/// that succeeded.
+///
+/// Requires the `zip` feature.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::mkdir("zip_dir");
+/// fsutils::write_file("zip_dir/a.txt", "a");
+///
+/// assert!(fsutils::zip(&["zip_dir"], "zip.zip"));
+/// assert!(!fsutils::zip(&["a_very_1234_unlikely_9876_dirname"], "zip.zip"));
+///
+/// # // Cleanup
+/// # fsutils::rm_r("zip_dir");
+/// # fsutils::rm("zip.zip");
+/// ```
+pub fn zip<P: AsRef<Path>, Q: AsRef<Path>>(sources: &[P], archive: Q) -> bool {
+    match try_zip(sources, archive) {
+        Ok(_) => true,
+        Err(e) => {
+            error!("Cannot create zip archive {}", e);
+            false
+        }
+    }
+}
+
+/// Packs files and directory trees into a zip archive, like `zip -r -y`,
+/// and returns the number of entries in it.
+///
+/// A file is stored under its name and a directory under its name with
+/// everything below it, so `zip(&["site"], ...)` gives entries such as
+/// `site/index.html`. A source without a name of its own, such as `.`,
+/// contributes only what is below it. Symbolic links inside directories are
+/// stored as links, Unix permissions and modification times are kept, and
+/// the archive replaces `archive` once it is complete. Requires the `zip`
+/// feature.
+///
+/// Each file is compressed in memory, and archives are limited to 65535
+/// entries of up to 4 GiB, as ZIP64 is not supported. Names and link
+/// targets are stored as UTF-8, with the flag that marks them so, and a file
+/// whose name is not valid UTF-8 fails with `FsError::InvalidPath` naming it
+/// before the archive is written, rather than being stored under a different
+/// name.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::mkdir("try_zip_dir/src");
+/// fsutils::write_file("try_zip_dir/src/lib.rs", "fn f() {}");
+/// fsutils::write_file("try_zip_readme.md", "# Readme");
+///
+/// assert_eq!(fsutils::try_zip(&["try_zip_dir", "try_zip_readme.md"], "try_zip.zip").unwrap(), 4);
+/// fsutils::try_unzip("try_zip.zip", "try_zip_out").unwrap();
+/// assert_eq!(fsutils::read_file("try_zip_out/try_zip_dir/src/lib.rs"), "fn f() {}");
+/// assert_eq!(fsutils::read_file("try_zip_out/try_zip_readme.md"), "# Readme");
+///
+/// # #[cfg(target_os = "linux")]
+/// # {
+/// use std::ffi::OsStr;
+/// use std::os::unix::ffi::OsStrExt;
+/// use fsutils::FsError;
+///
+/// fsutils::create_file(std::path::Path::new("try_zip_dir").join(OsStr::from_bytes(b"bad\xffname")));
+/// let result = fsutils::try_zip(&["try_zip_dir"], "try_zip_bad.zip");
+/// assert!(matches!(result, Err(FsError::InvalidPath { .. })));
+/// assert!(!fsutils::path_exists("try_zip_bad.zip"));
+/// # }
+///
+/// # // Cleanup
+/// # fsutils::rm_r("try_zip_dir");
+/// # fsutils::rm_r("try_zip_out");
+/// # fsutils::rm("try_zip_readme.md");
+/// # fsutils::rm("try_zip.zip");
+/// ```
+pub fn try_zip<P: AsRef<Path>, Q: AsRef<Path>>(sources: &[P], archive: Q) -> Result<usize> {
+    zip_with(sources, archive, &ZipOptions::new())
+}
+
+/// Packs files and directory trees into a zip archive like `fsutils::try_zip`,
+/// according to `opts`.
+///
+/// ## Usage:
+///
+/// ```
+/// use fsutils::ZipOptions;
+///
+/// fsutils::mkdir("zip_with_dir/target");
+/// fsutils::write_file("zip_with_dir/main.rs", "fn main() {}");
+/// fsutils::write_file("zip_with_dir/target/main", "");
+/// fsutils::write_file("zip_with_dir/build.log", "");
+///
+/// let opts = ZipOptions::new().exclude("target").unwrap().exclude("*.log").unwrap().clone();
+/// assert_eq!(fsutils::zip_with(&["zip_with_dir"], "zip_with.zip", &opts).unwrap(), 2);
+///
+/// # // Cleanup
+/// # fsutils::rm_r("zip_with_dir");
+/// # fsutils::rm("zip_with.zip");
+/// ```
+pub fn zip_with<P: AsRef<Path>, Q: AsRef<Path>>(sources: &[P], archive: Q, opts: &ZipOptions) -> Result<usize> {
+    let archive = archive.as_ref();
+    Op::new("zip", archive).destructive(archive.exists()).run(0, || {
+        let archive_id = file_id(archive).ok();
+        let mut found = Vec::new();
+        for source in sources {
+            collect(source.as_ref(), &opts.exclude, archive_id, &mut found)?;
+        }
+        if found.len() > usize::from(u16::MAX) {
+            return Err(too_large(archive, "more than 65535 entries"));
+        }
+        replace_file(archive, |file| {
+            let mut writer = ZipWriter { out: BufWriter::new(file), archive, offset: 0, members: Vec::new() };
+            for source in &found {
+                writer.add(source, opts)?;
+            }
+            writer.finish()
+        })?;
+        info!("Zipped {} entries into {}", found.len(), archive.display());
+        Ok(found.len())
+    })
+}
+
+fn too_large(archive: &Path, what: &str) -> FsError {
+    let message = format!("{} needs ZIP64, which is not supported", what);
+    FsError::from_io(archive, io::Error::new(io::ErrorKind::InvalidInput, message))
+}
+
+struct ZipWriter<'a, W: Write> {
+    out: W,
+    archive: &'a Path,
+    offset: u64,
+    members: Vec<Member>,
+}
+
+impl<W: Write> ZipWriter<'_, W> {
+    fn write(&mut self, bytes: &[u8]) -> Result<()> {
+        self.out.write_all(bytes).at(self.archive)?;
+        self.offset += bytes.len() as u64;
+        Ok(())
+    }
+
+    fn add(&mut self, source: &Source, opts: &ZipOptions) -> Result<()> {
+        let path = &source.path;
+        let meta = if source.kind == FileKind::Symlink { fs::symlink_metadata(path) } else { fs::metadata(path) };
+        let meta = meta.at(path)?;
+        let contents = match source.kind {
+            FileKind::File => fs::read(path).at(path)?,
+            FileKind::Symlink => entry_name(path, &fs::read_link(path).at(path)?)?.into_bytes(),
+            _ => Vec::new(),
+        };
+        let (method, data) = if source.kind == FileKind::File && !opts.store && !contents.is_empty() {
+            let compressed = deflate::compress(&contents);
+            if compressed.len() < contents.len() {
+                (DEFLATED, compressed)
+            } else {
+                (STORED, contents.clone())
+            }
+        } else {
+            (STORED, contents.clone())
+        };
+        let size = u32::try_from(contents.len()).map_err(|_| too_large(path, "a file over 4 GiB"))?;
+        let offset = u32::try_from(self.offset).map_err(|_| too_large(self.archive, "an archive over 4 GiB"))?;
+        let mtime = meta.modified().ok().and_then(|t| t.duration_since(UNIX_EPOCH).ok()).map_or(0, |d| d.as_secs());
+        let member = Member {
+            name: source.name.clone(),
+            mode: mode_of(&meta, source.kind),
+            mtime: u32::try_from(mtime).unwrap_or(u32::MAX),
+            method,
+            crc: deflate::crc32(0, &contents),
+            compressed_size: data.len() as u32,
+            size,
+            offset,
+        };
+
+        let (time, date) = dos_time(member.mtime);
+        let mut header = Vec::with_capacity(30 + member.name.len() + 9);
+        header.extend_from_slice(&LOCAL_HEADER.to_le_bytes());
+        for value in [VERSION_NEEDED, FLAG_UTF8, member.method, time, date] {
+            header.extend_from_slice(&value.to_le_bytes());
+        }
+        for value in [member.crc, member.compressed_size, member.size] {
+            header.extend_from_slice(&value.to_le_bytes());
+        }
+        header.extend_from_slice(&(member.name.len() as u16).to_le_bytes());
+        header.extend_from_slice(&9u16.to_le_bytes());
+        header.extend_from_slice(member.name.as_bytes());
+        header.extend_from_slice(&timestamp_field(member.mtime));
+        self.write(&header)?;
+        self.write(&data)?;
+        self.members.push(member);
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<()> {
+        let start = self.offset;
+        let members = std::mem::take(&mut self.members);
+        for member in &members {
+            let (time, date) = dos_time(member.mtime);
+            let mut header = Vec::with_capacity(46 + member.name.len() + 9);
+            header.extend_from_slice(&CENTRAL_HEADER.to_le_bytes());
+            for value in [VERSION_MADE_BY, VERSION_NEEDED, FLAG_UTF8, member.method, time, date] {
+                header.extend_from_slice(&value.to_le_bytes());
+            }
+            for value in [member.crc, member.compressed_size, member.size] {
+                header.extend_from_slice(&value.to_le_bytes());
+            }
+            // Name and extra field lengths, then no comment, disk 0 and no
+            // internal attributes
+            for value in [member.name.len() as u16, 9, 0, 0, 0] {
+                header.extend_from_slice(&value.to_le_bytes());
+            }
+            let dos_attributes = if member.mode & MODE_TYPE == MODE_DIR { 0x10 } else { 0 };
+            header.extend_from_slice(&(member.mode << 16 | dos_attributes).to_le_bytes());
+            header.extend_from_slice(&member.offset.to_le_bytes());
+            header.extend_from_slice(member.name.as_bytes());
+            header.extend_from_slice(&timestamp_field(member.mtime));
+            self.write(&header)?;
+        }
+        let size = u32::try_from(self.offset - start).map_err(|_| too_large(self.archive, "an archive over 4 GiB"))?;
+        let start = u32::try_from(start).map_err(|_| too_large(self.archive, "an archive over 4 GiB"))?;
+        let mut end = Vec::with_capacity(22);
+        end.extend_from_slice(&END_OF_CENTRAL_DIRECTORY.to_le_bytes());
+        let count = members.len() as u16;
+        for value in [0, 0, count, count] {
+            end.extend_from_slice(&value.to_le_bytes());
+        }
+        end.extend_from_slice(&size.to_le_bytes());
+        end.extend_from_slice(&start.to_le_bytes());
+        end.extend_from_slice(&0u16.to_le_bytes());
+        self.write(&end)?;
+        self.out.flush().at(self.archive)
+    }
+}
+
+/// The extended timestamp field holding `mtime`.
+fn timestamp_field(mtime: u32) -> [u8; 9] {
+    let mut field = [0; 9];
+    field[..2].copy_from_slice(&EXTENDED_TIMESTAMP.to_le_bytes());
+    field[2..4].copy_from_slice(&5u16.to_le_bytes());
+    field[4] = 1;
+    field[5..].copy_from_slice(&mtime.to_le_bytes());
+    field
+}
+
+/// The MS-DOS time and date of Unix seconds, taken as UTC and limited to the
+/// years DOS dates can hold.
+fn dos_time(secs: u32) -> (u16, u16) {
+    let secs = i64::from(secs);
+    let (year, month, day) = civil_from_days(secs.div_euclid(86400));
+    if year < 1980 {
+        return (0, 1 << 5 | 1);
+    }
+    let year = year.min(2107);
+    let seconds = secs.rem_euclid(86400);
+    let time = (seconds / 3600) << 11 | (seconds % 3600 / 60) << 5 | (seconds % 60 / 2);
+    let date = (year - 1980) << 9 | i64::from(month) << 5 | i64::from(day);
+    (time as u16, date as u16)
+}
+
+/// Unix seconds of an MS-DOS time and date, taken as UTC.
+fn from_dos_time(time: u16, date: u16) -> u64 {
+    let (year, month, day) = (i64::from(date >> 9) + 1980, u32::from(date >> 5 & 0xf), u32::from(date & 0x1f));
+    let days = days_from_civil(year, month.clamp(1, 12), day.clamp(1, 31));
+    let seconds = i64::from(time >> 11) * 3600 + i64::from(time >> 5 & 0x3f) * 60 + i64::from(time & 0x1f) * 2;
+    (days * 86400 + seconds).max(0) as u64
+}
+
+/// The year, month and day of a count of days since 1970-01-01.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 } as u32;
+    (year_of_era + era * 400 + i64::from(month <= 2), month, day)
+}
+
+/// The count of days since 1970-01-01 of a year, month and day.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let shifted_month = i64::from(if month > 2 { month - 3 } else { month + 9 });
+    let day_of_year = (153 * shifted_month + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// An entry read from the central directory of an archive.
+struct Entry {
+    name: String,
+    kind: FileKind,
+    /// The permission bits, if the archive was made on Unix.
+    mode: Option<u32>,
+    mtime: u64,
+    method: u16,
+    crc: u32,
+    compressed_size: usize,
+    size: usize,
+    offset: usize,
+}
+
+fn corrupt(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("invalid zip archive: {}", reason))
+}
+
+fn u16_at(data: &[u8], at: usize) -> io::Result<u16> {
+    data.get(at..at + 2).map(|b| u16::from_le_bytes([b[0], b[1]])).ok_or_else(|| corrupt("truncated"))
+}
+
+fn u32_at(data: &[u8], at: usize) -> io::Result<u32> {
+    data.get(at..at + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]])).ok_or_else(|| corrupt("truncated"))
+}
+
+/// Reads the entries listed in the central directory.
+fn read_entries(data: &[u8]) -> io::Result<Vec<Entry>> {
+    // The end record is last, followed by a comment of up to 65535 bytes
+    let earliest = data.len().saturating_sub(22 + usize::from(u16::MAX));
+    let end = (earliest..=data.len().saturating_sub(22))
+        .rev()
+        .find(|&at| u32_at(data, at).ok() == Some(END_OF_CENTRAL_DIRECTORY))
+        .ok_or_else(|| corrupt("no end of central directory"))?;
+    let count = u16_at(data, end + 10)?;
+    let mut at = u32_at(data, end + 16)? as usize;
+    if count == u16::MAX || at == u32::MAX as usize {
+        return Err(io::Error::new(io::ErrorKind::Unsupported, "ZIP64 archives are not supported"));
+    }
+    let mut entries = Vec::with_capacity(usize::from(count));
+    for _ in 0..count {
+        if u32_at(data, at)? != CENTRAL_HEADER {
+            return Err(corrupt("bad central directory header"));
+        }
+        let made_by_unix = u16_at(data, at + 4)? >> 8 == 3;
+        if u16_at(data, at + 8)? & 1 != 0 {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, "encrypted entries are not supported"));
+        }
+        let name_len = usize::from(u16_at(data, at + 28)?);
+        let extra_len = usize::from(u16_at(data, at + 30)?);
+        let comment_len = usize::from(u16_at(data, at + 32)?);
+        let attributes = u32_at(data, at + 38)?;
+        let name_bytes = data.get(at + 46..at + 46 + name_len).ok_or_else(|| corrupt("truncated"))?;
+        let name = read_name(name_bytes)?;
+        let extra = data.get(at + 46 + name_len..at + 46 + name_len + extra_len).ok_or_else(|| corrupt("truncated"))?;
+        let mode = if made_by_unix && attributes >> 16 != 0 { Some(attributes >> 16) } else { None };
+        let kind = match mode.map(|mode| mode & MODE_TYPE) {
+            Some(MODE_SYMLINK) => FileKind::Symlink,
+            Some(MODE_DIR) => FileKind::Dir,
+            _ if name.ends_with('/') || attributes & 0x10 != 0 => FileKind::Dir,
+            _ => FileKind::File,
+        };
+        let dos = from_dos_time(u16_at(data, at + 12)?, u16_at(data, at + 14)?);
+        entries.push(Entry {
+            name,
+            kind,
+            mode: mode.map(|mode| mode & 0o7777),
+            mtime: extended_mtime(extra).unwrap_or(dos),
+            method: u16_at(data, at + 10)?,
+            crc: u32_at(data, at + 16)?,
+            compressed_size: u32_at(data, at + 20)? as usize,
+            size: u32_at(data, at + 24)? as usize,
+            offset: u32_at(data, at + 42)? as usize,
+        });
+        at += 46 + name_len + extra_len + comment_len;
+    }
+    Ok(entries)
+}
+
+/// The modification time in an extended timestamp field, if `extra` has one.
+fn extended_mtime(mut extra: &[u8]) -> Option<u64> {
+    while extra.len() >= 4 {
+        let id = u16::from_le_bytes([extra[0], extra[1]]);
+        let len = usize::from(u16::from_le_bytes([extra[2], extra[3]]));
+        let field = extra.get(4..4 + len)?;
+        if id == EXTENDED_TIMESTAMP && field.len() >= 5 && field[0] & 1 != 0 {
+            return Some(u64::from(u32::from_le_bytes([field[1], field[2], field[3], field[4]])));
+        }
+        extra = &extra[4 + len..];
+    }
+    None
+}
+
+/// The contents of an entry, checked against its CRC-32.
+fn contents(data: &[u8], entry: &Entry) -> io::Result<Vec<u8>> {
+    if u32_at(data, entry.offset)? != LOCAL_HEADER {
+        return Err(corrupt("bad local header"));
+    }
+    let name_len = usize::from(u16_at(data, entry.offset + 26)?);
+    let extra_len = usize::from(u16_at(data, entry.offset + 28)?);
+    let start = entry.offset + 30 + name_len + extra_len;
+    let stored = data.get(start..start + entry.compressed_size).ok_or_else(|| corrupt("truncated"))?;
+    let contents = match entry.method {
+        STORED => stored.to_vec(),
+        DEFLATED => deflate::decompress(stored)?.0,
+        method => {
+            let message = format!("compression method {} is not supported", method);
+            return Err(io::Error::new(io::ErrorKind::Unsupported, message));
+        }
+    };
+    if contents.len() != entry.size || deflate::crc32(0, &contents) != entry.crc {
+        return Err(corrupt("checksum mismatch"));
+    }
+    Ok(contents)
+}
+
+/// Extracts a zip archive into `dst`, and returns whether that succeeded.
+///
+/// Requires the `zip` feature.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::write_file("unzip.txt", "a");
+/// fsutils::zip(&["unzip.txt"], "unzip.zip");
+///
+/// assert!(fsutils::unzip("unzip.zip", "unzip_out"));
+/// assert!(!fsutils::unzip("a_very_1234_unlikely_9876_filename", "unzip_out"));
+///
+/// # // Cleanup
+/// # fsutils::rm("unzip.txt");
+/// # fsutils::rm("unzip.zip");
+/// # fsutils::rm_r("unzip_out");
+/// ```
+pub fn unzip<P: AsRef<Path>, Q: AsRef<Path>>(archive: P, dst: Q) -> bool {
+    match try_unzip(archive, dst) {
+        Ok(_) => true,
+        Err(e) => {
+            error!("Cannot extract zip archive {}", e);
+            false
+        }
+    }
+}
+
+/// Extracts a zip archive into `dst`, creating it if needed, and returns the
+/// number of entries extracted.
+///
+/// Entries whose names would land outside `dst`, such as `../evil` or
+/// `/etc/passwd`, fail the extraction before anything is written, and so do
+/// symbolic links pointing outside it, including those that would lead out
+/// through another link in the archive. Links are created after everything
+/// else, so no entry is ever written through one. Existing files and links
+/// are replaced, but the confirm hook set with `fsutils::set_confirm` is
+/// first asked about each of them, and those it declines are left as they
+/// are and not counted. Permission bits are restored from archives made on
+/// Unix, without setuid, setgid and sticky bits, and modification times from
+/// all archives. Entries must be stored or deflated, and their names valid
+/// UTF-8, or the extraction fails with an error of kind
+/// `io::ErrorKind::InvalidData`. Requires the `zip` feature.
+///
+/// ## Usage:
+///
+/// ```
+/// fsutils::mkdir("try_unzip_dir/docs");
+/// fsutils::write_file("try_unzip_dir/docs/guide.md", "# Guide");
+/// fsutils::zip(&["try_unzip_dir"], "try_unzip.zip");
+///
+/// assert_eq!(fsutils::try_unzip("try_unzip.zip", "try_unzip_out").unwrap(), 3);
+/// assert_eq!(fsutils::read_file("try_unzip_out/try_unzip_dir/docs/guide.md"), "# Guide");
+/// assert!(fsutils::try_unzip("a_very_1234_unlikely_9876_filename", "try_unzip_out").is_err());
+///
+/// // Files the confirm hook declines to replace are kept
+/// fsutils::write_file("try_unzip_out/try_unzip_dir/docs/guide.md", "# Mine");
+/// fsutils::set_confirm(|_, _| false);
+/// assert_eq!(fsutils::try_unzip("try_unzip.zip", "try_unzip_out").unwrap(), 2);
+/// fsutils::clear_confirm();
+/// assert_eq!(fsutils::read_file("try_unzip_out/try_unzip_dir/docs/guide.md"), "# Mine");
+///
+/// # #[cfg(unix)]
+/// # {
+/// // `c` only leads out of the destination through the link `a/b`
+/// fsutils::mkdir("try_unzip_evil/a");
+/// fsutils::ln_s("..", "try_unzip_evil/a/b");
+/// fsutils::ln_s("a/b/..", "try_unzip_evil/c");
+/// fsutils::cd("try_unzip_evil");
+/// fsutils::zip(&["."], "../try_unzip_evil.zip");
+/// fsutils::cd("..");
+///
+/// assert!(fsutils::try_unzip("try_unzip_evil.zip", "try_unzip_evil_out").is_err());
+/// assert!(!fsutils::path_exists("try_unzip_evil_out/c"));
+/// # }
+///
+/// # // Cleanup
+/// # fsutils::rm_r("try_unzip_dir");
+/// # fsutils::rm_r("try_unzip_out");
+/// # fsutils::rm("try_unzip.zip");
+/// # fsutils::rm_r("try_unzip_evil");
+/// # fsutils::rm_r("try_unzip_evil_out");
+/// # fsutils::rm("try_unzip_evil.zip");
+/// ```
+pub fn try_unzip<P: AsRef<Path>, Q: AsRef<Path>>(archive: P, dst: Q) -> Result<usize> {
+    let (archive, dst) = (archive.as_ref(), dst.as_ref());
+    Op::new("unzip", archive).to(dst).run(0, || {
+        let data = fs::read(archive).at(archive)?;
+        let mut entries = Vec::new();
+        for entry in read_entries(&data).at(archive)? {
+            let relative = safe_relative(&entry.name).ok_or_else(|| leaves_destination(dst, &entry.name))?;
+            if relative.as_os_str().is_empty() {
+                continue;
+            }
+            entries.push((relative, entry));
+        }
+        let mut links = Vec::new();
+        for (relative, entry) in &entries {
+            if entry.kind == FileKind::Symlink {
+                let target = PathBuf::from(read_name(&contents(&data, entry).at(archive)?).at(archive)?);
+                links.push((relative.clone(), target));
+            }
+        }
+        check_links(dst, &links)?;
+        let replaced: Vec<PathBuf> = entries
+            .iter()
+            .filter(|(_, entry)| entry.kind != FileKind::Dir)
+            .map(|(relative, _)| dst.join(relative))
+            .collect();
+        let declined = declined("unzip", &replaced);
+
+        fs::create_dir_all(dst).at(dst)?;
+        let mut dirs = Vec::new();
+        for (relative, entry) in &entries {
+            let path = dst.join(relative);
+            match entry.kind {
+                FileKind::Dir => {
+                    fs::create_dir_all(&path).at(&path)?;
+                    dirs.push((path, time_of(entry), entry.mode));
+                }
+                FileKind::File if !declined.contains(&path) => {
+                    create_file(&path, &contents(&data, entry).at(archive)?, time_of(entry))?;
+                    if let Some(mode) = entry.mode {
+                        set_mode(&path, mode)?;
+                    }
+                }
+                _ => {}
+            }
+        }
+        for (relative, target) in &links {
+            let path = dst.join(relative);
+            if !declined.contains(&path) {
+                create_symlink(&path, target)?;
+            }
+        }
+        finish_dirs(dirs)?;
+        let extracted = entries.len() - declined.len();
+        info!("Extracted {} entries from {} into {}", extracted, archive.display(), dst.display());
+        Ok(extracted)
+    })
+}
+
+fn time_of(entry: &Entry) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(entry.mtime)
+}
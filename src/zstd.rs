@@ -0,0 +1,1410 @@
+// Copyright 2020 Jared Forth.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! The Zstandard format of RFC 8878.
+//!
+//! Compression cuts the input into frames of up to 8 MiB, each holding its
+//! size and checksum, and uses Huffman-coded literals with FSE-coded
+//! sequences. Decompression reads any frame without a dictionary.
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::io::{self, Read, Write};
+
+use crate::cmp::fill;
+use crate::compress::{read_u32, read_u32_or_end, read_vec};
+use crate::lz::{copy_match, Matcher, Params, Sequence};
+use crate::xxhash::Xxh64;
+
+pub(crate) const MAGIC: u32 = 0xfd2f_b528;
+pub(crate) const DEFAULT_LEVEL: u32 = 3;
+pub(crate) const MAX_LEVEL: u32 = 22;
+
+const BLOCK_MAX: usize = 128 << 10;
+const FRAME_SIZE: usize = 8 << 20;
+/// The largest window accepted when decompressing, as for `zstd --long=31`.
+const WINDOW_MAX: u64 = 1 << 31;
+
+const LL_BASE: [u32; 36] = [
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 18, 20, 22, 24, 28, 32, 40, 48, 64, 128, 256, 512, 1024,
+    2048, 4096, 8192, 16384, 32768, 65536,
+];
+const LL_BITS: [u8; 36] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 3, 3, 4, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16,
+];
+const ML_BASE: [u32; 53] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31, 32, 33,
+    34, 35, 37, 39, 41, 43, 47, 51, 59, 67, 83, 99, 131, 259, 515, 1027, 2051, 4099, 8195, 16387, 32771, 65539,
+];
+const ML_BITS: [u8; 53] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 3,
+    3, 4, 4, 5, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16,
+];
+const OF_MAX: usize = 31;
+
+const LL_DEFAULT: [i16; 36] = [
+    4, 3, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2, 2, 3, 2, 1, 1, 1, 1, 1, -1, -1, -1, -1,
+];
+const ML_DEFAULT: [i16; 53] = [
+    1, 4, 3, 2, 2, 2, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, -1, -1, -1, -1, -1, -1, -1,
+];
+const OF_DEFAULT: [i16; 29] = [
+    1, 1, 1, 1, 1, 1, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, -1, -1, -1, -1, -1,
+];
+
+/// The three kinds of sequence codes, with their predefined distributions
+/// and the largest accuracy their tables may have.
+#[derive(Clone, Copy)]
+enum Kind {
+    Literals,
+    Offsets,
+    Matches,
+}
+
+impl Kind {
+    fn default_table(self) -> (&'static [i16], u32) {
+        match self {
+            Kind::Literals => (&LL_DEFAULT, 6),
+            Kind::Offsets => (&OF_DEFAULT, 5),
+            Kind::Matches => (&ML_DEFAULT, 6),
+        }
+    }
+
+    fn max_log(self) -> u32 {
+        match self {
+            Kind::Offsets => 8,
+            _ => 9,
+        }
+    }
+
+    fn max_symbol(self) -> usize {
+        match self {
+            Kind::Literals => LL_BASE.len() - 1,
+            Kind::Offsets => OF_MAX,
+            Kind::Matches => ML_BASE.len() - 1,
+        }
+    }
+}
+
+fn corrupt(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("corrupt zstd frame: {}", reason))
+}
+
+fn highbit(value: u32) -> u32 {
+    31 - value.leading_zeros()
+}
+
+/// Writes bits from the lowest up, which is how both the forward streams of
+/// table descriptions and the streams read backwards are laid out.
+struct BitWriter {
+    out: Vec<u8>,
+    bits: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    fn new() -> BitWriter {
+        BitWriter { out: Vec::new(), bits: 0, count: 0 }
+    }
+
+    fn add(&mut self, value: u64, count: u32) {
+        self.bits |= (value & ((1 << count) - 1)) << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.out.push(self.bits as u8);
+            self.bits >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Ends a forward stream on a byte boundary.
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.out.push(self.bits as u8);
+        }
+        self.out
+    }
+
+    /// Ends a stream to be read backwards with the marker bit it starts
+    /// from.
+    fn close(mut self) -> Vec<u8> {
+        self.add(1, 1);
+        self.finish()
+    }
+}
+
+/// Reads bits from the lowest up.
+struct ForwardReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl ForwardReader<'_> {
+    fn peek(&self, count: u32) -> u32 {
+        let mut value = 0u64;
+        let first = self.pos / 8;
+        for (i, &byte) in self.data.iter().skip(first).take(5).enumerate() {
+            value |= u64::from(byte) << (8 * i);
+        }
+        ((value >> (self.pos % 8)) & ((1 << count) - 1)) as u32
+    }
+
+    fn read(&mut self, count: u32) -> io::Result<u32> {
+        if self.pos + count as usize > self.data.len() * 8 {
+            return Err(corrupt("truncated table description"));
+        }
+        let value = self.peek(count);
+        self.pos += count as usize;
+        Ok(value)
+    }
+}
+
+/// Reads a stream backwards from its marker bit, giving zeros past its
+/// start and remembering that it overflowed, as the reference decoder does.
+struct BackwardReader<'a> {
+    data: &'a [u8],
+    /// Bits left to read, below zero once the stream has overflowed.
+    left: isize,
+}
+
+impl<'a> BackwardReader<'a> {
+    fn new(data: &'a [u8]) -> io::Result<BackwardReader<'a>> {
+        let last = *data.last().ok_or_else(|| corrupt("empty bitstream"))?;
+        if last == 0 {
+            return Err(corrupt("bitstream has no end marker"));
+        }
+        Ok(BackwardReader { data, left: (data.len() * 8) as isize - (last.leading_zeros() as isize + 1) })
+    }
+
+    /// `count` bits, at most 56, from bit `start` up.
+    fn bits_at(&self, start: usize, count: u32) -> u64 {
+        let mut value = 0u64;
+        for (i, &byte) in self.data.iter().skip(start / 8).take(8).enumerate() {
+            value |= u64::from(byte) << (8 * i);
+        }
+        (value >> (start % 8)) & ((1 << count) - 1)
+    }
+
+    fn peek(&self, count: u32) -> u64 {
+        if count == 0 || self.left <= 0 {
+            return 0;
+        }
+        let start = self.left - count as isize;
+        if start >= 0 {
+            self.bits_at(start as usize, count)
+        } else {
+            self.bits_at(0, self.left as u32) << -start
+        }
+    }
+
+    fn read(&mut self, count: u32) -> u64 {
+        let value = self.peek(count);
+        self.left -= count as isize;
+        value
+    }
+
+    fn overflowed(&self) -> bool {
+        self.left < 0
+    }
+}
+
+/// Scales counts to a distribution over `1 << log` states, giving every
+/// symbol that occurs at least one.
+fn normalize(counts: &[u32], log: u32) -> Vec<i16> {
+    let size = 1u64 << log;
+    let total: u64 = counts.iter().map(|&c| u64::from(c)).sum();
+    let used = counts.iter().filter(|&&c| c > 0).count() as u64;
+    let spare = size - used;
+    let mut norm: Vec<i16> = Vec::with_capacity(counts.len());
+    let mut remainders = Vec::new();
+    let mut assigned = 0;
+    for (symbol, &count) in counts.iter().enumerate() {
+        if count == 0 {
+            norm.push(0);
+            continue;
+        }
+        let scaled = u64::from(count) * spare;
+        let share = scaled / total;
+        norm.push(1 + share as i16);
+        assigned += 1 + share;
+        remainders.push((Reverse(scaled % total), symbol));
+    }
+    remainders.sort();
+    for &(_, symbol) in remainders.iter().take((size - assigned) as usize) {
+        norm[symbol] += 1;
+    }
+    norm
+}
+
+/// The accuracy for a table of `total` symbols up to `max_symbol`, as the
+/// reference encoder picks it.
+fn table_log(max_log: u32, total: usize, max_symbol: usize) -> u32 {
+    let total = total.max(2) as u32;
+    let from_size = highbit(total - 1).saturating_sub(2);
+    let least = (highbit(total) + 1).min(highbit(max_symbol.max(1) as u32) + 2);
+    let mut log = max_log.min(from_size);
+    log = log.max(least);
+    log.clamp(5, max_log)
+}
+
+/// Writes the header describing a distribution.
+fn write_ncount(norm: &[i16], log: u32) -> Vec<u8> {
+    let mut out = BitWriter::new();
+    out.add(u64::from(log - 5), 4);
+    let mut remaining = (1i32 << log) + 1;
+    let mut threshold = 1i32 << log;
+    let mut bits = log + 1;
+    let mut symbol = 0;
+    let mut previous_zero = false;
+    while symbol < norm.len() && remaining > 1 {
+        if previous_zero {
+            let mut start = symbol;
+            while symbol < norm.len() && norm[symbol] == 0 {
+                symbol += 1;
+            }
+            while symbol >= start + 3 {
+                start += 3;
+                out.add(3, 2);
+            }
+            out.add((symbol - start) as u64, 2);
+        }
+        let count = i32::from(norm[symbol]);
+        symbol += 1;
+        let max = 2 * threshold - 1 - remaining;
+        remaining -= count.abs();
+        let mut value = count + 1;
+        if value >= threshold {
+            value += max;
+        }
+        out.add(value as u64, if value < max { bits - 1 } else { bits });
+        previous_zero = value == 1;
+        while remaining < threshold {
+            bits -= 1;
+            threshold >>= 1;
+        }
+    }
+    out.finish()
+}
+
+/// Reads the header describing a distribution, and returns it with its
+/// accuracy and how many bytes it took.
+fn read_ncount(data: &[u8], max_log: u32, max_symbol: usize) -> io::Result<(Vec<i16>, u32, usize)> {
+    let mut input = ForwardReader { data, pos: 0 };
+    let log = input.read(4)? + 5;
+    if log > max_log {
+        return Err(corrupt("table accuracy too high"));
+    }
+    let mut remaining = (1i32 << log) + 1;
+    let mut threshold = 1i32 << log;
+    let mut bits = log + 1;
+    let mut norm = Vec::new();
+    let mut previous_zero = false;
+    while remaining > 1 {
+        if previous_zero {
+            loop {
+                let repeat = input.read(2)?;
+                norm.extend(std::iter::repeat_n(0, repeat as usize));
+                if repeat != 3 {
+                    break;
+                }
+            }
+        }
+        if norm.len() > max_symbol {
+            return Err(corrupt("too many symbols in table"));
+        }
+        let max = 2 * threshold - 1 - remaining;
+        let low = input.peek(bits - 1) as i32;
+        let mut value;
+        if low < max {
+            value = low;
+            input.read(bits - 1)?;
+        } else {
+            value = input.read(bits)? as i32;
+            if value >= threshold {
+                value -= max;
+            }
+        }
+        let count = value - 1;
+        remaining -= count.abs();
+        if remaining < 1 {
+            return Err(corrupt("bad table distribution"));
+        }
+        norm.push(count as i16);
+        previous_zero = count == 0;
+        while remaining < threshold {
+            bits -= 1;
+            threshold >>= 1;
+        }
+    }
+    Ok((norm, log, input.pos.div_ceil(8)))
+}
+
+/// Lays the symbols of a distribution out over the states, with the
+/// rarest at the end.
+fn spread(norm: &[i16], log: u32) -> io::Result<Vec<u8>> {
+    let size = 1usize << log;
+    let mut symbols = vec![0; size];
+    let mut high = size - 1;
+    for (symbol, &count) in norm.iter().enumerate() {
+        if count == -1 {
+            symbols[high] = symbol as u8;
+            high = high.checked_sub(1).ok_or_else(|| corrupt("bad table distribution"))?;
+        }
+    }
+    let step = (size >> 1) + (size >> 3) + 3;
+    let mut pos = 0;
+    for (symbol, &count) in norm.iter().enumerate() {
+        for _ in 0..count.max(0) {
+            symbols[pos] = symbol as u8;
+            pos = (pos + step) & (size - 1);
+            while pos > high {
+                pos = (pos + step) & (size - 1);
+            }
+        }
+    }
+    if pos != 0 {
+        return Err(corrupt("bad table distribution"));
+    }
+    Ok(symbols)
+}
+
+#[derive(Clone, Copy)]
+struct FseEntry {
+    symbol: u8,
+    bits: u8,
+    base: u16,
+}
+
+#[derive(Clone)]
+struct FseTable {
+    log: u32,
+    entries: Vec<FseEntry>,
+}
+
+impl FseTable {
+    fn new(norm: &[i16], log: u32) -> io::Result<FseTable> {
+        let size = 1u32 << log;
+        let symbols = spread(norm, log)?;
+        let mut next: Vec<u32> = norm.iter().map(|&count| if count == -1 { 1 } else { count.max(0) as u32 }).collect();
+        let entries = symbols
+            .iter()
+            .map(|&symbol| {
+                let state = next[symbol as usize];
+                next[symbol as usize] += 1;
+                let bits = log - highbit(state);
+                FseEntry { symbol, bits: bits as u8, base: ((state << bits) - size) as u16 }
+            })
+            .collect();
+        Ok(FseTable { log, entries })
+    }
+
+    fn rle(symbol: u8) -> FseTable {
+        FseTable { log: 0, entries: vec![FseEntry { symbol, bits: 0, base: 0 }] }
+    }
+
+    fn init(&self, input: &mut BackwardReader) -> usize {
+        input.read(self.log) as usize
+    }
+
+    fn symbol(&self, state: usize) -> u8 {
+        self.entries[state].symbol
+    }
+
+    fn update(&self, state: usize, input: &mut BackwardReader) -> usize {
+        let entry = self.entries[state];
+        usize::from(entry.base) + input.read(u32::from(entry.bits)) as usize
+    }
+}
+
+/// Encodes symbols with a distribution, in the reverse of the order they
+/// are decoded.
+struct FseEncoder {
+    log: u32,
+    states: Vec<u16>,
+    /// For each symbol, how to find its next state and how many bits leave.
+    transforms: Vec<(i32, u32)>,
+}
+
+impl FseEncoder {
+    fn new(norm: &[i16], log: u32) -> FseEncoder {
+        let size = 1u32 << log;
+        let symbols = spread(norm, log).expect("normalized distributions spread");
+        let mut cumulative = vec![0u32; norm.len() + 1];
+        for (symbol, &count) in norm.iter().enumerate() {
+            cumulative[symbol + 1] = cumulative[symbol] + if count == -1 { 1 } else { count.max(0) as u32 };
+        }
+        let mut states = vec![0u16; size as usize];
+        for (u, &symbol) in symbols.iter().enumerate() {
+            let slot = &mut cumulative[symbol as usize];
+            states[*slot as usize] = (size + u as u32) as u16;
+            *slot += 1;
+        }
+        let mut total = 0i32;
+        let transforms = norm
+            .iter()
+            .map(|&count| match count {
+                0 => (0, ((log + 1) << 16) - size),
+                -1 | 1 => {
+                    total += 1;
+                    (total - 2, (log << 16) - size)
+                }
+                count => {
+                    let count = count as u32;
+                    let max_bits = log - highbit(count - 1);
+                    let find = total - count as i32;
+                    total += count as i32;
+                    (find, (max_bits << 16) - (count << max_bits))
+                }
+            })
+            .collect();
+        FseEncoder { log, states, transforms }
+    }
+
+    fn init(&self, symbol: u8) -> u32 {
+        let (find, delta_bits) = self.transforms[symbol as usize];
+        let bits = (delta_bits + (1 << 15)) >> 16;
+        let value = (bits << 16).wrapping_sub(delta_bits);
+        u32::from(self.states[((value >> bits) as i32 + find) as usize])
+    }
+
+    fn encode(&self, out: &mut BitWriter, state: &mut u32, symbol: u8) {
+        let (find, delta_bits) = self.transforms[symbol as usize];
+        let bits = (*state + delta_bits) >> 16;
+        out.add(u64::from(*state), bits);
+        *state = u32::from(self.states[((*state >> bits) as i32 + find) as usize]);
+    }
+
+    fn flush(&self, out: &mut BitWriter, state: u32) {
+        out.add(u64::from(state), self.log);
+    }
+}
+
+/// The bits each estimated symbol costs with a distribution, in 1/256ths
+/// of a bit, or `None` if it cannot encode one of them.
+fn cost(counts: &[u32], norm: &[i16], log: u32) -> Option<u64> {
+    let mut total = 0.0;
+    for (symbol, &count) in counts.iter().enumerate() {
+        if count == 0 {
+            continue;
+        }
+        let share = match norm.get(symbol) {
+            Some(-1) => 1.0,
+            Some(&n) if n > 0 => f64::from(n),
+            _ => return None,
+        };
+        total += f64::from(count) * (f64::from(log) - share.log2());
+    }
+    Some((total * 256.0) as u64)
+}
+
+/// How the codes of one kind are encoded in a block.
+enum Coding {
+    Predefined(FseEncoder),
+    Rle(u8),
+    Compressed(FseEncoder, Vec<u8>),
+}
+
+impl Coding {
+    fn choose(kind: Kind, codes: &[u8]) -> Coding {
+        let max = *codes.iter().max().unwrap_or(&0) as usize;
+        let mut counts = vec![0u32; max + 1];
+        for &code in codes {
+            counts[code as usize] += 1;
+        }
+        if counts.iter().filter(|&&c| c > 0).count() == 1 {
+            return Coding::Rle(max as u8);
+        }
+        let (default, default_log) = kind.default_table();
+        let predefined = cost(&counts, default, default_log);
+        let log = table_log(kind.max_log(), codes.len(), max);
+        let norm = normalize(&counts, log);
+        let header = write_ncount(&norm, log);
+        let compressed = cost(&counts, &norm, log).map(|bits| bits + header.len() as u64 * 8 * 256);
+        match (predefined, compressed) {
+            (Some(predefined), Some(compressed)) if predefined <= compressed => {
+                Coding::Predefined(FseEncoder::new(default, default_log))
+            }
+            _ => Coding::Compressed(FseEncoder::new(&norm, log), header),
+        }
+    }
+
+    fn mode(&self) -> u8 {
+        match self {
+            Coding::Predefined(_) => 0,
+            Coding::Rle(_) => 1,
+            Coding::Compressed(..) => 2,
+        }
+    }
+
+    fn write_description(&self, out: &mut Vec<u8>) {
+        match self {
+            Coding::Predefined(_) => {}
+            Coding::Rle(symbol) => out.push(*symbol),
+            Coding::Compressed(_, header) => out.extend_from_slice(header),
+        }
+    }
+
+    fn init(&self, symbol: u8) -> u32 {
+        match self {
+            Coding::Predefined(fse) | Coding::Compressed(fse, _) => fse.init(symbol),
+            Coding::Rle(_) => 0,
+        }
+    }
+
+    fn encode(&self, out: &mut BitWriter, state: &mut u32, symbol: u8) {
+        if let Coding::Predefined(fse) | Coding::Compressed(fse, _) = self {
+            fse.encode(out, state, symbol);
+        }
+    }
+
+    fn flush(&self, out: &mut BitWriter, state: u32) {
+        if let Coding::Predefined(fse) | Coding::Compressed(fse, _) = self {
+            fse.flush(out, state);
+        }
+    }
+}
+
+/// Code lengths of at most `limit` bits for `counts`, forming a complete
+/// prefix code.
+fn huffman_lengths(counts: &[u32], limit: u32) -> Vec<u32> {
+    let mut lengths = vec![0; counts.len()];
+    let mut heap: BinaryHeap<_> =
+        counts.iter().enumerate().filter(|(_, &c)| c > 0).map(|(s, &c)| Reverse((u64::from(c), s))).collect();
+    // Nodes past the symbols are internal, each with the parent of every node
+    let mut parent: Vec<usize> = vec![usize::MAX; counts.len()];
+    while heap.len() > 1 {
+        let Reverse((a, x)) = heap.pop().unwrap();
+        let Reverse((b, y)) = heap.pop().unwrap();
+        let node = parent.len();
+        parent.push(usize::MAX);
+        parent[x] = node;
+        parent[y] = node;
+        heap.push(Reverse((a + b, node)));
+    }
+    for symbol in 0..counts.len() {
+        if counts[symbol] == 0 {
+            continue;
+        }
+        let (mut node, mut depth) = (symbol, 0);
+        while parent[node] != usize::MAX {
+            node = parent[node];
+            depth += 1;
+        }
+        lengths[symbol] = depth;
+    }
+
+    // Clamp to the limit, then lengthen the longest codes still under it
+    // until the code fits, and shorten the longest until it is complete
+    let full = 1u64 << limit;
+    let kraft = |lengths: &[u32]| -> u64 { lengths.iter().filter(|&&l| l > 0).map(|&l| full >> l).sum() };
+    for length in lengths.iter_mut() {
+        *length = (*length).min(limit);
+    }
+    let mut order: Vec<usize> = (0..counts.len()).filter(|&s| counts[s] > 0).collect();
+    order.sort_by_key(|&s| Reverse(counts[s]));
+    while kraft(&lengths) > full {
+        let symbol = *order.iter().rev().find(|&&s| lengths[s] < limit).expect("a code under the limit");
+        lengths[symbol] += 1;
+    }
+    loop {
+        let deficit = full - kraft(&lengths);
+        if deficit == 0 {
+            break;
+        }
+        let fits = |&s: &usize| lengths[s] > 1 && full >> lengths[s] <= deficit;
+        let symbol = order.iter().copied().filter(fits).max_by_key(|&s| lengths[s]);
+        match symbol {
+            Some(symbol) => lengths[symbol] -= 1,
+            None => break,
+        }
+    }
+    lengths
+}
+
+/// Assigns codes and a decoding table to Huffman weights, which are
+/// `max_bits + 1 - length` for each symbol that occurs.
+struct Huffman {
+    max_bits: u32,
+    /// The code and its length for each symbol.
+    codes: Vec<(u32, u32)>,
+    /// The symbol and code length for each value of the next `max_bits`
+    /// bits.
+    table: Vec<(u8, u8)>,
+}
+
+impl Huffman {
+    fn from_weights(weights: &[u8]) -> io::Result<Huffman> {
+        let total: u32 = weights.iter().filter(|&&w| w > 0).map(|&w| 1 << (w - 1)).sum();
+        if total == 0 || !total.is_power_of_two() {
+            return Err(corrupt("bad Huffman weights"));
+        }
+        let max_bits = highbit(total);
+        if max_bits > 11 {
+            return Err(corrupt("Huffman codes too long"));
+        }
+        let mut starts = vec![0u32; max_bits as usize + 2];
+        for &weight in weights {
+            if weight > 0 {
+                starts[weight as usize] += 1 << (weight - 1);
+            }
+        }
+        let mut position = 0;
+        for start in starts.iter_mut().skip(1) {
+            let count = *start;
+            *start = position;
+            position += count;
+        }
+        let mut codes = vec![(0, 0); weights.len()];
+        let mut table = vec![(0, 0); 1 << max_bits];
+        for (symbol, &weight) in weights.iter().enumerate() {
+            if weight == 0 {
+                continue;
+            }
+            let span = 1 << (weight - 1);
+            let start = starts[weight as usize];
+            starts[weight as usize] += span;
+            let bits = max_bits + 1 - u32::from(weight);
+            codes[symbol] = (start >> (weight - 1), bits);
+            for entry in &mut table[start as usize..(start + span) as usize] {
+                *entry = (symbol as u8, bits as u8);
+            }
+        }
+        Ok(Huffman { max_bits, codes, table })
+    }
+
+    /// Reads a table description, and returns the table with how many
+    /// bytes it took.
+    fn read(data: &[u8]) -> io::Result<(Huffman, usize)> {
+        let header = *data.first().ok_or_else(|| corrupt("truncated Huffman table"))? as usize;
+        let (mut weights, used) = if header >= 128 {
+            let count = header - 127;
+            let bytes = data.get(1..1 + count.div_ceil(2)).ok_or_else(|| corrupt("truncated Huffman table"))?;
+            let weights = (0..count).map(|i| if i % 2 == 0 { bytes[i / 2] >> 4 } else { bytes[i / 2] & 15 });
+            (weights.collect(), 1 + bytes.len())
+        } else {
+            let data = data.get(1..1 + header).ok_or_else(|| corrupt("truncated Huffman table"))?;
+            (read_fse_weights(data)?, 1 + header)
+        };
+        if weights.iter().any(|&w| w > 11) {
+            return Err(corrupt("bad Huffman weights"));
+        }
+        // The last weight is left out, as it is whatever completes the code
+        let total: u32 = weights.iter().filter(|&&w| w > 0).map(|&w| 1 << (w - 1)).sum();
+        if total == 0 || weights.len() > 255 {
+            return Err(corrupt("bad Huffman weights"));
+        }
+        let rest = (1 << (highbit(total) + 1)) - total;
+        if !rest.is_power_of_two() {
+            return Err(corrupt("bad Huffman weights"));
+        }
+        weights.push(highbit(rest) as u8 + 1);
+        Ok((Huffman::from_weights(&weights)?, used))
+    }
+
+    fn decode_stream(&self, data: &[u8], count: usize, out: &mut Vec<u8>) -> io::Result<()> {
+        let mut input = BackwardReader::new(data)?;
+        for _ in 0..count {
+            let (symbol, bits) = self.table[input.peek(self.max_bits) as usize];
+            input.read(u32::from(bits));
+            out.push(symbol);
+        }
+        if input.left != 0 {
+            return Err(corrupt("bad Huffman stream"));
+        }
+        Ok(())
+    }
+
+    fn encode_stream(&self, literals: &[u8]) -> Vec<u8> {
+        let mut out = BitWriter::new();
+        for &literal in literals.iter().rev() {
+            let (code, bits) = self.codes[literal as usize];
+            out.add(u64::from(code), bits);
+        }
+        out.close()
+    }
+}
+
+/// Decodes Huffman weights compressed with two interleaved FSE states.
+fn read_fse_weights(data: &[u8]) -> io::Result<Vec<u8>> {
+    let (norm, log, used) = read_ncount(data, 6, 255)?;
+    let table = FseTable::new(&norm, log)?;
+    let mut input = BackwardReader::new(&data[used..])?;
+    let (mut even, mut odd) = (table.init(&mut input), table.init(&mut input));
+    let mut weights = Vec::new();
+    loop {
+        weights.push(table.symbol(even));
+        even = table.update(even, &mut input);
+        if input.overflowed() {
+            weights.push(table.symbol(odd));
+            break;
+        }
+        weights.push(table.symbol(odd));
+        odd = table.update(odd, &mut input);
+        if input.overflowed() {
+            weights.push(table.symbol(even));
+            break;
+        }
+        if weights.len() > 255 {
+            return Err(corrupt("too many Huffman weights"));
+        }
+    }
+    Ok(weights)
+}
+
+/// Compresses Huffman weights with two interleaved FSE states, or returns
+/// `None` if that does not give them back exactly.
+fn write_fse_weights(weights: &[u8]) -> Option<Vec<u8>> {
+    if weights.len() < 3 {
+        return None;
+    }
+    let max = *weights.iter().max()? as usize;
+    let mut counts = vec![0u32; max + 1];
+    for &weight in weights {
+        counts[weight as usize] += 1;
+    }
+    let log = table_log(6, weights.len(), max);
+    let norm = normalize(&counts, log);
+    let fse = FseEncoder::new(&norm, log);
+    let mut out = BitWriter::new();
+    let mut at = weights.len();
+    let (mut even, mut odd);
+    if weights.len() % 2 == 1 {
+        even = fse.init(weights[at - 1]);
+        odd = fse.init(weights[at - 2]);
+        fse.encode(&mut out, &mut even, weights[at - 3]);
+        at -= 3;
+    } else {
+        odd = fse.init(weights[at - 1]);
+        even = fse.init(weights[at - 2]);
+        at -= 2;
+    }
+    while at > 0 {
+        fse.encode(&mut out, &mut odd, weights[at - 1]);
+        fse.encode(&mut out, &mut even, weights[at - 2]);
+        at -= 2;
+    }
+    fse.flush(&mut out, odd);
+    fse.flush(&mut out, even);
+    let mut description = write_ncount(&norm, log);
+    description.extend_from_slice(&out.close());
+    match read_fse_weights(&description) {
+        Ok(decoded) if decoded == weights => Some(description),
+        _ => None,
+    }
+}
+
+/// The header of a literals section of `kind` with `size` literals.
+fn raw_literals_header(kind: u8, size: usize) -> Vec<u8> {
+    if size < 32 {
+        vec![kind | (size << 3) as u8]
+    } else if size < 4096 {
+        vec![kind | 1 << 2 | (size << 4) as u8, (size >> 4) as u8]
+    } else {
+        let value = u32::from(kind) | 3 << 2 | (size as u32) << 4;
+        value.to_le_bytes()[..3].to_vec()
+    }
+}
+
+fn encode_literals(literals: &[u8]) -> Vec<u8> {
+    let mut raw = raw_literals_header(0, literals.len());
+    raw.extend_from_slice(literals);
+    if literals.is_empty() {
+        return raw;
+    }
+    if literals.iter().all(|&b| b == literals[0]) {
+        let mut rle = raw_literals_header(1, literals.len());
+        rle.push(literals[0]);
+        return rle;
+    }
+    if literals.len() < 64 {
+        return raw;
+    }
+    let mut counts = vec![0u32; 256];
+    for &literal in literals {
+        counts[literal as usize] += 1;
+    }
+    let last = counts.iter().rposition(|&c| c > 0).unwrap_or(0);
+    counts.truncate(last + 1);
+    let lengths = huffman_lengths(&counts, 11);
+    let max_bits = *lengths.iter().max().unwrap_or(&0);
+    let weights: Vec<u8> = lengths.iter().map(|&l| if l == 0 { 0 } else { (max_bits + 1 - l) as u8 }).collect();
+    let huffman = Huffman::from_weights(&weights).expect("complete codes have valid weights");
+
+    // The last weight is implied
+    let stored = &weights[..last];
+    let mut description = match write_fse_weights(stored) {
+        Some(fse) if fse.len() < 128 => Some([&[fse.len() as u8][..], &fse].concat()),
+        _ => None,
+    };
+    if stored.len() <= 128 {
+        let mut direct = vec![127 + stored.len() as u8];
+        direct.extend(stored.chunks(2).map(|pair| pair[0] << 4 | pair.get(1).copied().unwrap_or(0)));
+        if description.as_ref().is_none_or(|fse| direct.len() <= fse.len()) {
+            description = Some(direct);
+        }
+    }
+    let Some(mut body) = description else {
+        return raw;
+    };
+
+    let single = literals.len() <= 1023;
+    if single {
+        body.extend_from_slice(&huffman.encode_stream(literals));
+    } else {
+        let segment = literals.len().div_ceil(4);
+        let streams: Vec<Vec<u8>> = literals.chunks(segment).map(|chunk| huffman.encode_stream(chunk)).collect();
+        for stream in &streams[..3] {
+            body.extend_from_slice(&(stream.len() as u16).to_le_bytes());
+        }
+        for stream in &streams {
+            body.extend_from_slice(stream);
+        }
+    }
+    let (regenerated, compressed) = (literals.len() as u64, body.len() as u64);
+    let mut section = if single && compressed < 1024 {
+        (2 | regenerated << 4 | compressed << 14).to_le_bytes()[..3].to_vec()
+    } else if regenerated < 1024 && compressed < 1024 {
+        (2 | 1 << 2 | regenerated << 4 | compressed << 14).to_le_bytes()[..3].to_vec()
+    } else if regenerated < 16384 && compressed < 16384 {
+        (2 | 2 << 2 | regenerated << 4 | compressed << 18).to_le_bytes()[..4].to_vec()
+    } else {
+        (2 | 3 << 2 | regenerated << 4 | compressed << 22).to_le_bytes()[..5].to_vec()
+    };
+    if single && compressed >= 1024 {
+        return raw;
+    }
+    section.extend_from_slice(&body);
+    if section.len() < raw.len() {
+        section
+    } else {
+        raw
+    }
+}
+
+/// The code for a value, given the smallest value of each code.
+fn code_of(value: u32, bases: &[u32]) -> u8 {
+    (bases.partition_point(|&base| base <= value) - 1) as u8
+}
+
+/// The offset value of a match, using the repeated offsets it can, and
+/// updates them as the decoder will.
+fn offset_value(offset: usize, literals: usize, repeats: &mut [usize; 3]) -> u32 {
+    let [first, second, third] = *repeats;
+    if literals > 0 && offset == first {
+        1
+    } else if offset == second {
+        *repeats = [second, first, third];
+        if literals > 0 {
+            2
+        } else {
+            1
+        }
+    } else if offset == third {
+        *repeats = [third, first, second];
+        if literals > 0 {
+            3
+        } else {
+            2
+        }
+    } else {
+        *repeats = [offset, first, second];
+        offset as u32 + 3
+    }
+}
+
+fn encode_sequences(sequences: &[Sequence], repeats: &mut [usize; 3], out: &mut Vec<u8>) {
+    let count = sequences.len();
+    if count < 128 {
+        out.push(count as u8);
+    } else if count < 0x7f00 {
+        out.extend_from_slice(&[(count >> 8) as u8 + 128, count as u8]);
+    } else {
+        out.extend_from_slice(&[255, (count - 0x7f00) as u8, ((count - 0x7f00) >> 8) as u8]);
+    }
+    if count == 0 {
+        return;
+    }
+
+    let mut codes = Vec::with_capacity(count);
+    for sequence in sequences {
+        let offset = offset_value(sequence.offset, sequence.literals, repeats);
+        let offset_code = highbit(offset) as u8;
+        let literals = sequence.literals as u32;
+        let len = sequence.len as u32;
+        codes.push((code_of(literals, &LL_BASE), offset_code, code_of(len, &ML_BASE), literals, offset, len));
+    }
+    let kinds: Vec<Vec<u8>> = vec![
+        codes.iter().map(|c| c.0).collect(),
+        codes.iter().map(|c| c.1).collect(),
+        codes.iter().map(|c| c.2).collect(),
+    ];
+    let literals = Coding::choose(Kind::Literals, &kinds[0]);
+    let offsets = Coding::choose(Kind::Offsets, &kinds[1]);
+    let matches = Coding::choose(Kind::Matches, &kinds[2]);
+    out.push(literals.mode() << 6 | offsets.mode() << 4 | matches.mode() << 2);
+    literals.write_description(out);
+    offsets.write_description(out);
+    matches.write_description(out);
+
+    let extra = |out: &mut BitWriter, &(ll_code, of_code, ml_code, ll, of, ml): &(u8, u8, u8, u32, u32, u32)| {
+        out.add(u64::from(ll - LL_BASE[ll_code as usize]), u32::from(LL_BITS[ll_code as usize]));
+        out.add(u64::from(ml - ML_BASE[ml_code as usize]), u32::from(ML_BITS[ml_code as usize]));
+        out.add(u64::from(of - (1 << of_code)), u32::from(of_code));
+    };
+    let mut bits = BitWriter::new();
+    let last = &codes[count - 1];
+    let mut ml_state = matches.init(last.2);
+    let mut of_state = offsets.init(last.1);
+    let mut ll_state = literals.init(last.0);
+    extra(&mut bits, last);
+    for code in codes[..count - 1].iter().rev() {
+        offsets.encode(&mut bits, &mut of_state, code.1);
+        matches.encode(&mut bits, &mut ml_state, code.2);
+        literals.encode(&mut bits, &mut ll_state, code.0);
+        extra(&mut bits, code);
+    }
+    matches.flush(&mut bits, ml_state);
+    offsets.flush(&mut bits, of_state);
+    literals.flush(&mut bits, ll_state);
+    out.extend_from_slice(&bits.close());
+}
+
+/// How hard each level looks for matches.
+fn params(level: u32) -> Params {
+    let level = level.clamp(1, MAX_LEVEL) as usize;
+    const CHAIN: [usize; 22] =
+        [1, 2, 4, 6, 8, 12, 16, 16, 24, 32, 32, 48, 64, 64, 96, 128, 128, 192, 256, 256, 384, 512];
+    Params {
+        window_log: match level {
+            1..=2 => 19,
+            3..=5 => 21,
+            6..=12 => 22,
+            _ => 23,
+        },
+        hash_log: match level {
+            1 => 16,
+            2..=5 => 17,
+            6..=12 => 18,
+            _ => 20,
+        },
+        chain: CHAIN[level - 1],
+        nice: match level {
+            1..=5 => 32,
+            6..=12 => 128,
+            13..=18 => 512,
+            _ => 4096,
+        },
+        lazy: level >= 3,
+        fast: level == 1,
+    }
+}
+
+fn block_header(last: bool, kind: u32, size: usize) -> [u8; 3] {
+    let value = u32::from(last) | kind << 1 | (size as u32) << 3;
+    let bytes = value.to_le_bytes();
+    [bytes[0], bytes[1], bytes[2]]
+}
+
+fn write_frame<W: Write>(data: &[u8], params: Params, out: &mut W) -> io::Result<()> {
+    // One segment, so the window is the content, with its size and checksum
+    let size = data.len() as u64;
+    let mut header = MAGIC.to_le_bytes().to_vec();
+    if size < 256 {
+        header.extend_from_slice(&[0x24, size as u8]);
+    } else if size < 65536 + 256 {
+        header.push(0x64);
+        header.extend_from_slice(&((size - 256) as u16).to_le_bytes());
+    } else {
+        header.push(0xa4);
+        header.extend_from_slice(&(size as u32).to_le_bytes());
+    }
+    out.write_all(&header)?;
+    if data.is_empty() {
+        out.write_all(&block_header(true, 0, 0))?;
+    }
+
+    let mut matcher = Matcher::new(data, params);
+    let mut repeats = [1, 4, 8];
+    let mut start = 0;
+    while start < data.len() {
+        let end = (start + BLOCK_MAX).min(data.len());
+        let last = end == data.len();
+        let block = &data[start..end];
+        let (sequences, _) = matcher.parse(start, end, end, end);
+        let mut literals = Vec::with_capacity(block.len());
+        let mut at = start;
+        for sequence in &sequences {
+            literals.extend_from_slice(&data[at..at + sequence.literals]);
+            at += sequence.literals + sequence.len;
+        }
+        literals.extend_from_slice(&data[at..end]);
+
+        let mut updated = repeats;
+        let mut body = encode_literals(&literals);
+        encode_sequences(&sequences, &mut updated, &mut body);
+        if block.iter().all(|&b| b == block[0]) && block.len() > 1 {
+            out.write_all(&block_header(last, 1, block.len()))?;
+            out.write_all(&block[..1])?;
+        } else if body.len() < block.len() {
+            // Repeated offsets carry on only through compressed blocks
+            repeats = updated;
+            out.write_all(&block_header(last, 2, body.len()))?;
+            out.write_all(&body)?;
+        } else {
+            out.write_all(&block_header(last, 0, block.len()))?;
+            out.write_all(block)?;
+        }
+        start = end;
+    }
+    let mut checksum = Xxh64::new();
+    checksum.update(data);
+    out.write_all(&(checksum.finish() as u32).to_le_bytes())
+}
+
+/// Compresses everything from `input` into `output` as Zstandard frames at
+/// `level`, from `1` to `22`.
+pub(crate) fn compress<R: Read, W: Write>(mut input: R, output: &mut W, level: u32) -> io::Result<()> {
+    let params = params(level);
+    let mut buf = vec![0; FRAME_SIZE];
+    let mut first = true;
+    loop {
+        let n = fill(&mut input, &mut buf)?;
+        if n == 0 && !first {
+            return Ok(());
+        }
+        write_frame(&buf[..n], params, output)?;
+        first = false;
+        if n < FRAME_SIZE {
+            return Ok(());
+        }
+    }
+}
+
+/// What carries over from one block of a frame to the next.
+struct FrameState {
+    window: usize,
+    /// The output not yet dropped from the window.
+    out: Vec<u8>,
+    flushed: usize,
+    repeats: [usize; 3],
+    huffman: Option<Huffman>,
+    tables: [Option<FseTable>; 3],
+}
+
+/// Reads a literals section, and returns the literals with how many bytes
+/// it took.
+fn decode_literals(block: &[u8], state: &mut FrameState) -> io::Result<(Vec<u8>, usize)> {
+    let truncated = || corrupt("truncated literals");
+    let byte = |at: usize| block.get(at).map(|&b| usize::from(b)).ok_or_else(truncated);
+    let first = byte(0)?;
+    let (kind, format) = (first & 3, first >> 2 & 3);
+    if kind < 2 {
+        let (size, header) = match format {
+            0 | 2 => (first >> 3, 1),
+            1 => (first >> 4 | byte(1)? << 4, 2),
+            _ => (first >> 4 | byte(1)? << 4 | byte(2)? << 12, 3),
+        };
+        if size > BLOCK_MAX {
+            return Err(corrupt("too many literals"));
+        }
+        return if kind == 0 {
+            let literals = block.get(header..header + size).ok_or_else(truncated)?;
+            Ok((literals.to_vec(), header + size))
+        } else {
+            Ok((vec![byte(header)? as u8; size], header + 1))
+        };
+    }
+
+    let header = match format {
+        0 | 1 => 3,
+        2 => 4,
+        _ => 5,
+    };
+    let mut value = 0u64;
+    for i in 0..header {
+        value |= (byte(i)? as u64) << (8 * i);
+    }
+    let bits = match format {
+        0 | 1 => 10,
+        2 => 14,
+        _ => 18,
+    };
+    let regenerated = (value >> 4 & ((1 << bits) - 1)) as usize;
+    let compressed = (value >> (4 + bits) & ((1 << bits) - 1)) as usize;
+    if regenerated > BLOCK_MAX {
+        return Err(corrupt("too many literals"));
+    }
+    let mut data = block.get(header..header + compressed).ok_or_else(truncated)?;
+    if kind == 2 {
+        let (huffman, used) = Huffman::read(data)?;
+        state.huffman = Some(huffman);
+        data = &data[used..];
+    }
+    let huffman = state.huffman.as_ref().ok_or_else(|| corrupt("literals reuse a missing Huffman table"))?;
+    let mut literals = Vec::with_capacity(regenerated);
+    if format == 0 {
+        huffman.decode_stream(data, regenerated, &mut literals)?;
+    } else {
+        let jump = data.get(..6).ok_or_else(truncated)?;
+        let sizes: Vec<usize> = jump.chunks(2).map(|pair| usize::from(pair[0]) | usize::from(pair[1]) << 8).collect();
+        let segment = regenerated.div_ceil(4);
+        if 3 * segment > regenerated {
+            return Err(corrupt("too few literals for four streams"));
+        }
+        let mut at = 6;
+        for (i, &size) in sizes.iter().chain(std::iter::once(&0)).enumerate() {
+            let stream = if i < 3 { data.get(at..at + size) } else { data.get(at..) };
+            let stream = stream.ok_or_else(truncated)?;
+            let count = if i < 3 { segment } else { regenerated - 3 * segment };
+            huffman.decode_stream(stream, count, &mut literals)?;
+            at += size;
+        }
+    }
+    Ok((literals, header + compressed))
+}
+
+/// Reads the table for one kind of code in a sequences section, and
+/// returns how many bytes it took.
+fn read_table(kind: Kind, mode: u8, data: &[u8], slot: &mut Option<FseTable>) -> io::Result<usize> {
+    let (table, used) = match mode {
+        0 => {
+            let (norm, log) = kind.default_table();
+            (FseTable::new(norm, log)?, 0)
+        }
+        1 => {
+            let symbol = *data.first().ok_or_else(|| corrupt("truncated sequences"))?;
+            if symbol as usize > kind.max_symbol() {
+                return Err(corrupt("bad sequence code"));
+            }
+            (FseTable::rle(symbol), 1)
+        }
+        2 => {
+            let (norm, log, used) = read_ncount(data, kind.max_log(), kind.max_symbol())?;
+            (FseTable::new(&norm, log)?, used)
+        }
+        _ => return slot.as_ref().map(|_| 0).ok_or_else(|| corrupt("sequences reuse a missing table")),
+    };
+    *slot = Some(table);
+    Ok(used)
+}
+
+fn decode_block(block: &[u8], state: &mut FrameState) -> io::Result<()> {
+    let (literals, mut at) = decode_literals(block, state)?;
+    let byte = |at: usize| block.get(at).map(|&b| usize::from(b)).ok_or_else(|| corrupt("truncated sequences"));
+    let first = byte(at)?;
+    let count = match first {
+        0..=127 => {
+            at += 1;
+            first
+        }
+        128..=254 => {
+            at += 2;
+            (first - 128) << 8 | byte(at - 1)?
+        }
+        _ => {
+            at += 3;
+            byte(at - 2)? + (byte(at - 1)? << 8) + 0x7f00
+        }
+    };
+    let start = state.out.len();
+    let mut used_literals = 0;
+    if count > 0 {
+        let modes = byte(at)? as u8;
+        at += 1;
+        if modes & 3 != 0 {
+            return Err(corrupt("reserved bits set"));
+        }
+        let kinds = [Kind::Literals, Kind::Offsets, Kind::Matches];
+        for (i, kind) in kinds.iter().enumerate() {
+            let mode = modes >> (6 - 2 * i) & 3;
+            at += read_table(*kind, mode, block.get(at..).unwrap_or(&[]), &mut state.tables[i])?;
+        }
+        // Each was just read or reused, which fails when there is none
+        let [ll_table, of_table, ml_table] = state.tables.each_ref().map(|table| table.as_ref().unwrap());
+        let mut input = BackwardReader::new(block.get(at..).unwrap_or(&[]))?;
+        let mut ll_state = ll_table.init(&mut input);
+        let mut of_state = of_table.init(&mut input);
+        let mut ml_state = ml_table.init(&mut input);
+        for i in 0..count {
+            let ll_code = ll_table.symbol(ll_state) as usize;
+            let of_code = of_table.symbol(of_state) as u32;
+            let ml_code = ml_table.symbol(ml_state) as usize;
+            if ll_code >= LL_BASE.len() || ml_code >= ML_BASE.len() || of_code as usize > OF_MAX {
+                return Err(corrupt("bad sequence code"));
+            }
+            let offset_value = (1u64 << of_code) + input.read(of_code);
+            let len = (ML_BASE[ml_code] as u64 + input.read(u32::from(ML_BITS[ml_code]))) as usize;
+            let literal_len = (LL_BASE[ll_code] as u64 + input.read(u32::from(LL_BITS[ll_code]))) as usize;
+            let offset = resolve_offset(offset_value as usize, literal_len, &mut state.repeats)?;
+            if i + 1 < count {
+                ll_state = ll_table.update(ll_state, &mut input);
+                ml_state = ml_table.update(ml_state, &mut input);
+                of_state = of_table.update(of_state, &mut input);
+            }
+
+            let run = literals.get(used_literals..used_literals + literal_len);
+            let run = run.ok_or_else(|| corrupt("too few literals"))?;
+            state.out.extend_from_slice(run);
+            used_literals += literal_len;
+            if offset > state.out.len() || offset > state.window {
+                return Err(corrupt("match starts before the window"));
+            }
+            if state.out.len() - start + len > BLOCK_MAX {
+                return Err(corrupt("block too large"));
+            }
+            copy_match(&mut state.out, offset, len);
+        }
+        if input.left != 0 {
+            return Err(corrupt("bad sequences bitstream"));
+        }
+    }
+    state.out.extend_from_slice(&literals[used_literals..]);
+    if state.out.len() - start > BLOCK_MAX {
+        return Err(corrupt("block too large"));
+    }
+    Ok(())
+}
+
+/// The offset an offset value stands for, updating the repeated offsets.
+fn resolve_offset(value: usize, literals: usize, repeats: &mut [usize; 3]) -> io::Result<usize> {
+    let [first, second, third] = *repeats;
+    if value > 3 {
+        *repeats = [value - 3, first, second];
+        return Ok(value - 3);
+    }
+    let index = if literals == 0 { value } else { value - 1 };
+    let offset = match index {
+        0 => first,
+        1 => {
+            *repeats = [second, first, third];
+            second
+        }
+        2 => {
+            *repeats = [third, first, second];
+            third
+        }
+        _ => {
+            let offset = first.checked_sub(1).filter(|&o| o > 0).ok_or_else(|| corrupt("zero offset"))?;
+            *repeats = [offset, first, second];
+            offset
+        }
+    };
+    Ok(offset)
+}
+
+fn read_bytes<R: Read>(input: &mut R, count: usize) -> io::Result<Vec<u8>> {
+    read_vec(input, count, "zstd")
+}
+
+/// Decompresses one frame after its magic number, and returns its size.
+fn decompress_frame<R: Read, W: Write>(input: &mut R, output: &mut W) -> io::Result<u64> {
+    let descriptor = read_bytes(input, 1)?[0];
+    let single = descriptor & 0x20 != 0;
+    if descriptor & 8 != 0 {
+        return Err(corrupt("reserved bit set"));
+    }
+    let mut window = 0;
+    if !single {
+        let byte = read_bytes(input, 1)?[0];
+        let base = 1u64 << (10 + (byte >> 3));
+        window = base + base / 8 * u64::from(byte & 7);
+    }
+    let dictionary = read_bytes(input, [0, 1, 2, 4][usize::from(descriptor & 3)])?;
+    if dictionary.iter().any(|&b| b != 0) {
+        return Err(io::Error::new(io::ErrorKind::Unsupported, "zstd dictionaries are not supported"));
+    }
+    let size_bytes = match descriptor >> 6 {
+        0 => usize::from(single),
+        1 => 2,
+        2 => 4,
+        _ => 8,
+    };
+    let mut content_size = None;
+    if size_bytes > 0 {
+        let bytes = read_bytes(input, size_bytes)?;
+        let size = bytes.iter().rev().fold(0u64, |n, &b| n << 8 | u64::from(b));
+        content_size = Some(if size_bytes == 2 { size + 256 } else { size });
+    }
+    if single {
+        window = content_size.unwrap_or(0);
+    }
+    if window > WINDOW_MAX {
+        return Err(io::Error::new(io::ErrorKind::Unsupported, "zstd window is larger than 2 GiB"));
+    }
+
+    let window = window as usize;
+    let block_max = window.clamp(1, BLOCK_MAX);
+    let mut state = FrameState {
+        window,
+        out: Vec::new(),
+        flushed: 0,
+        repeats: [1, 4, 8],
+        huffman: None,
+        tables: [None, None, None],
+    };
+    let mut checksum = Xxh64::new();
+    let mut total = 0u64;
+    loop {
+        let header = read_bytes(input, 3)?;
+        let value = u32::from(header[0]) | u32::from(header[1]) << 8 | u32::from(header[2]) << 16;
+        let (last, kind, size) = (value & 1 != 0, value >> 1 & 3, (value >> 3) as usize);
+        match kind {
+            0 if size <= block_max => state.out.extend_from_slice(&read_bytes(input, size)?),
+            1 if size <= block_max => {
+                let byte = read_bytes(input, 1)?[0];
+                state.out.resize(state.out.len() + size, byte);
+            }
+            2 if size <= block_max => decode_block(&read_bytes(input, size)?, &mut state)?,
+            3 => return Err(corrupt("reserved block type")),
+            _ => return Err(corrupt("block too large")),
+        }
+        let produced = &state.out[state.flushed..];
+        output.write_all(produced)?;
+        checksum.update(produced);
+        total += produced.len() as u64;
+        state.flushed = state.out.len();
+        // Keep what later matches may reach back to
+        if state.out.len() >= 2 * window.max(BLOCK_MAX) {
+            let drop = state.out.len() - window;
+            state.out.drain(..drop);
+            state.flushed -= drop;
+        }
+        if last {
+            break;
+        }
+    }
+    if content_size.is_some_and(|size| size != total) {
+        return Err(corrupt("content size does not match"));
+    }
+    if descriptor & 4 != 0 && read_u32(input, "zstd")? != checksum.finish() as u32 {
+        return Err(corrupt("checksum mismatch"));
+    }
+    Ok(total)
+}
+
+/// Decompresses every frame from `input` into `output`, given the magic
+/// number of the first, and returns how many bytes they held.
+pub(crate) fn decompress<R: Read, W: Write>(magic: u32, mut input: R, output: &mut W) -> io::Result<u64> {
+    let mut next = Some(magic);
+    let mut total = 0;
+    while let Some(magic) = next {
+        if magic & 0xffff_fff0 == 0x184d_2a50 {
+            let size = u64::from(read_u32(&mut input, "zstd")?);
+            if io::copy(&mut input.by_ref().take(size), &mut io::sink())? != size {
+                return Err(corrupt("truncated skippable frame"));
+            }
+        } else if magic == MAGIC {
+            total += decompress_frame(&mut input, output)?;
+        } else {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a zstd frame"));
+        }
+        next = read_u32_or_end(&mut input)?;
+    }
+    Ok(total)
+}